@@ -14,26 +14,73 @@
 # See the License for the specific language governing permissions and
 # limitations under the License.
 
+"""Manage a cache of CMSIS-Packs and an index of the devices they describe.
+
+The Rust backend is reached through the cffi module that maturin builds
+from ``cmsis.h``; the classes here wrap it so callers never handle its
+pointers. Errors reported by the backend are raised as :class:`RustError`
+or one of its subclasses, chosen by the error code of the failed call.
+
+Tools that only need to update a pack store and look up devices can use
+the ``cmsis_pack_native`` module built with PyO3 from ``rust/cmsis-py``
+instead.
+"""
+
 import sys
 import time
 import collections
+from os import listdir
 from os.path import join, dirname, exists
 from shutil import rmtree
-from json import load
+from json import load, loads
 from zipfile import ZipFile
 from appdirs import user_data_dir
 from .cmsis_pack_manager import ffi, lib
 
 
+class RustError(Exception):
+    """An error reported by the Rust backend
+
+    Every backend error derives from this class, which is itself an
+    ``Exception`` as the plain exceptions raised before it were.
+    """
+
+
+class RustNotFoundError(RustError, LookupError):
+    """The Rust backend could not find a requested file or device"""
+
+
+class RustIOError(RustError, IOError):
+    """The Rust backend failed to read or write a file"""
+
+
+class RustPanic(RustError):
+    """The Rust backend panicked"""
+
+
+class RustCancelled(RustError):
+    """A Rust backend operation was cancelled before it completed"""
+
+
+_ERROR_CLASSES = {
+    lib.NullPointer: RustError,
+    lib.Io: RustIOError,
+    lib.NotFound: RustNotFoundError,
+    lib.Panic: RustPanic,
+    lib.Cancelled: RustCancelled,
+}
+
+
 class _RaiseRust(object):
     def __enter__(self):
         pass
 
     def __exit__(self, exc_type, exc_val, exe_tb):
+        code = lib.err_get_last_code()
         maybe_err = ffi.gc(lib.err_get_last_message(),
                            lib.err_last_message_free)
         if maybe_err:
-            raise Exception(ffi.string(maybe_err))
+            raise _ERROR_CLASSES.get(code, RustError)(ffi.string(maybe_err))
 
 
 class CmsisPackRef(collections.namedtuple(
@@ -149,6 +196,36 @@ class Cache (object):
                 return {}
         return self._index
 
+    def device(self, device_name):
+        """Look up a single device by parsing the cached PDSC files.
+
+        Unlike :attr:`index`, this does not require an index to have been
+        generated beforehand.
+
+        :param device_name: The exact name of a device
+        :type device_name: str
+        :return: The same representation of the device as in :attr:`index`
+        :rtype: dict
+        :raises RustNotFoundError: if no cached PDSC describes the device
+        """
+        pdsc_index = ffi.gc(
+            lib.update_pdsc_index_new(),
+            lib.update_pdsc_index_free
+        )
+        if exists(self.data_path):
+            for name in listdir(self.data_path):
+                if name.endswith(".pdsc"):
+                    pdsc_path = join(self.data_path, name)
+                    cpdsc_path = ffi.new("char[]", pdsc_path.encode("utf-8"))
+                    with _RaiseRust():
+                        lib.update_pdsc_index_push(pdsc_index, cpdsc_path)
+        parsed_packs = self._call_rust_parse(pdsc_index)
+        cname = ffi.new("char[]", device_name.encode("utf-8"))
+        with _RaiseRust():
+            dumped = ffi.gc(lib.device_lookup_json(parsed_packs, cname),
+                            lib.cstring_free)
+        return loads(ffi.string(dumped))
+
     @property
     def aliases(self):
         """An index of the boards in all CMSIS Pack Descriptions.
@@ -217,7 +294,8 @@ class Cache (object):
         else:
             cdata_path = ffi.NULL
         with _RaiseRust():
-            poll_obj = lib.update_packs(cdata_path, parsed_packs)
+            poll_obj = ffi.gc(lib.update_packs(cdata_path, parsed_packs),
+                              lib.update_pdsc_poll_free)
         return self._poll_rust_update(poll_obj, on_tick_fn)
 
     def _verbose_on_tick_fn(self, total, current):
@@ -240,7 +318,8 @@ class Cache (object):
         else:
             cvidx_path = ffi.NULL
         with _RaiseRust():
-            poll_obj = lib.update_pdsc_index(cdata_path, cvidx_path)
+            poll_obj = ffi.gc(lib.update_pdsc_index(cdata_path, cvidx_path),
+                              lib.update_pdsc_poll_free)
         return self._poll_rust_update(poll_obj, on_tick_fn)
 
     def _poll_rust_update(self, poll_obj, on_tick_fn):
@@ -249,7 +328,14 @@ class Cache (object):
             current_downloads = 0
             while not lib.update_pdsc_poll(poll_obj):
                 prev_downloads = current_downloads
-                time.sleep(1/2)
+                try:
+                    time.sleep(1/2)
+                except KeyboardInterrupt:
+                    # Let the backend clean up its partial downloads
+                    lib.update_pdsc_cancel(poll_obj)
+                    while not lib.update_pdsc_poll(poll_obj):
+                        time.sleep(1/10)
+                    raise
                 message = ffi.gc(
                     lib.update_pdsc_get_status(poll_obj),
                     lib.update_pdsc_status_free
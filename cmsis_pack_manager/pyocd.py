@@ -0,0 +1,122 @@
+# CMSIS Pack Manager
+# Copyright (c) 2017-2021 Arm Limited
+# Copyright (c) 2021 Chris Reed
+#
+# Licensed under the Apache License, Version 2.0 (the "License");
+# you may not use this file except in compliance with the License.
+# You may obtain a copy of the License at
+#
+#     http://www.apache.org/licenses/LICENSE-2.0
+#
+# Unless required by applicable law or agreed to in writing, software
+# distributed under the License is distributed on an "AS IS" BASIS,
+# WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+# See the License for the specific language governing permissions and
+# limitations under the License.
+
+"""Pack access shaped like pyOCD's ``pyocd.target.pack.cmsis_pack`` module.
+
+The pack description is parsed by the Rust backend; files referenced by the
+description (flash algorithms, SVDs) are read from the pack archive.
+"""
+
+import collections
+from io import BytesIO
+from json import loads
+from zipfile import ZipFile, is_zipfile
+
+from . import _RaiseRust
+from .cmsis_pack_manager import ffi, lib
+
+
+FlashAlgorithm = collections.namedtuple(
+    "FlashAlgorithm",
+    "file_name start size is_default ram_start ram_size")
+
+
+class MemoryRegion(collections.namedtuple(
+        "MemoryRegion",
+        "type name start length access is_boot_memory is_default pname "
+        "algorithms")):
+    """A region of a device's memory map"""
+
+    @property
+    def end(self):
+        return self.start + self.length - 1
+
+    @property
+    def algo(self):
+        """The default flash algorithm of a flash region, if any"""
+        for algo in self.algorithms:
+            if algo.is_default:
+                return algo
+        return self.algorithms[0] if self.algorithms else None
+
+
+class CmsisPackDevice(object):
+    """A single device described by a pack"""
+
+    def __init__(self, pack, info):
+        self._pack = pack
+        self.part_number = info["part_number"]
+        self.vendor = info["vendor"]
+        self.families = info["families"]
+        self.default_reset_type = info["default_reset_type"]
+        self._svd = info["svd"]
+        self.memory_map = [
+            MemoryRegion(
+                region["type"], region["name"], region["start"],
+                region["length"], region["access"], region["is_boot_memory"],
+                region["is_default"], region["pname"],
+                [FlashAlgorithm(**algo) for algo in region["algorithms"]])
+            for region in info["memory_map"]
+        ]
+
+    @property
+    def pack(self):
+        return self._pack
+
+    @property
+    def svd(self):
+        """A file-like object holding the SVD, or None"""
+        if self._svd is None:
+            return None
+        return self._pack.get_file(self._svd)
+
+    def get_flash_algorithm(self, algo):
+        """The ELF image of a flash algorithm, as bytes"""
+        return self._pack.get_file(algo.file_name).read()
+
+
+class CmsisPack(object):
+    """A CMSIS pack archive or a bare PDSC file
+
+    :param path: Path of a ``.pack`` archive or of a ``.pdsc`` file
+    """
+
+    def __init__(self, path):
+        self._path = path
+        self._archive = ZipFile(path) if is_zipfile(path) else None
+        cpath = ffi.new("char[]", path.encode("utf-8"))
+        with _RaiseRust():
+            dumped = ffi.gc(lib.pyocd_targets_json(cpath), lib.cstring_free)
+        self._devices = [
+            CmsisPackDevice(self, info) for info in loads(ffi.string(dumped))
+        ]
+
+    @property
+    def filename(self):
+        return self._path
+
+    @property
+    def devices(self):
+        return self._devices
+
+    def get_file(self, filename):
+        """Open a file of the pack archive
+
+        :raises LookupError: for a bare PDSC, which has no other files
+        """
+        if self._archive is None:
+            raise LookupError("{} is not a pack archive".format(self._path))
+        return BytesIO(self._archive.read(filename.replace("\\", "/")))
@@ -7,10 +7,10 @@
 #ifndef _CMSIS_H_
 #define _CMSIS_H_
 
-/* Generated with cbindgen:0.8.7 */
+/* Generated with cbindgen:0.29.4 */
 
 /* Warning, this file is autogenerated by cbindgen. Don't modify this manually. 
- * To regenerate use `rustup run nightly cbindgen . > cmsis.h` in this directory.
+ * To regenerate use `RUSTC_BOOTSTRAP=1 cbindgen . -o cmsis.h` in this directory.
  */
 
 
@@ -19,6 +19,29 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Version of this library; matches the header it was generated with
+ */
+#define CMSIS_CFFI_VERSION_MAJOR 0
+
+#define CMSIS_CFFI_VERSION_MINOR 6
+
+#define CMSIS_CFFI_VERSION_PATCH 3
+
+/**
+ * Classification of the last error, so that callers can react to a failure
+ * without parsing the message.
+ */
+typedef enum ErrorCode {
+        NoError = 0,
+        Unknown = 1,
+        NullPointer = 2,
+        Io = 3,
+        NotFound = 4,
+        Panic = 5,
+        Cancelled = 6,
+} ErrorCode;
+
 typedef struct ParsedPacks ParsedPacks;
 
 typedef struct UpdatePoll UpdatePoll;
@@ -30,42 +53,70 @@ typedef struct DownloadUpdate {
         uintptr_t size;
 } DownloadUpdate;
 
-void cstring_free(char *ptr);
-
-void dump_pdsc_json(ParsedPacks *packs,
-                    const char *devices_dest,
-                    const char *boards_dest);
+/**
+ * The version of the loaded library, to compare against the
+ * `CMSIS_CFFI_VERSION_*` constants of the header. The string is static and
+ * must not be freed.
+ */
+const char *cmsis_cffi_version(void);
 
-const char *dumps_components(ParsedPacks *ptr);
+/**
+ * Does not consume the last error; call this before `err_get_last_message`.
+ */
+enum ErrorCode err_get_last_code(void);
 
 const char *err_get_last_message(void);
 
 void err_last_message_free(char *ptr);
 
-UpdateReturn *pack_from_path(const char *ptr);
+struct UpdatePoll *update_packs(const char *pack_store,
+                                struct ParsedPacks *parsed_packs);
 
-ParsedPacks *parse_packs(UpdateReturn *ptr);
+struct UpdatePoll *update_pdsc_index(const char *pack_store,
+                                     const char *vidx_list);
 
-void parse_packs_free(ParsedPacks *ptr);
+bool update_pdsc_poll(struct UpdatePoll *ptr);
 
-UpdatePoll *update_packs(const char *pack_store, ParsedPacks *parsed_packs);
+bool update_pdsc_cancel(struct UpdatePoll *ptr);
 
-DownloadUpdate *update_pdsc_get_status(UpdatePoll *ptr);
+struct DownloadUpdate *update_pdsc_get_status(struct UpdatePoll *ptr);
 
-UpdatePoll *update_pdsc_index(const char *pack_store, const char *vidx_list);
+void update_pdsc_status_free(struct DownloadUpdate *ptr);
+
+struct UpdateReturn *update_pdsc_result(struct UpdatePoll *ptr);
+
+/**
+ * Release an update, cancelling it and waiting for it to stop when it
+ * is still running
+ */
+void update_pdsc_poll_free(struct UpdatePoll *ptr);
+
+struct UpdateReturn *update_pdsc_index_new(void);
+
+const char *update_pdsc_index_next(struct UpdateReturn *ptr);
+
+void update_pdsc_index_push(struct UpdateReturn *ptr, char *cstr);
+
+void cstring_free(char *ptr);
+
+void update_pdsc_index_free(struct UpdateReturn *ptr);
+
+void dump_pdsc_json(struct ParsedPacks *packs,
+                    const char *devices_dest,
+                    const char *boards_dest);
 
-void update_pdsc_index_free(UpdateReturn *ptr);
+struct UpdateReturn *pack_from_path(const char *ptr);
 
-UpdateReturn *update_pdsc_index_new(void);
+struct ParsedPacks *parse_packs(struct UpdateReturn *ptr);
 
-const char *update_pdsc_index_next(UpdateReturn *ptr);
+void parse_packs_free(struct ParsedPacks *ptr);
 
-void update_pdsc_index_push(UpdateReturn *ptr, char *cstr);
+const char *dumps_components(struct ParsedPacks *ptr);
 
-bool update_pdsc_poll(UpdatePoll *ptr);
+const char *dumps_devices(struct ParsedPacks *ptr);
 
-UpdateReturn *update_pdsc_result(UpdatePoll *ptr);
+const char *device_lookup_json(struct ParsedPacks *packs, const char *name);
 
-void update_pdsc_status_free(DownloadUpdate *ptr);
+const char *pyocd_targets_json(const char *path);
 
-#endif /* _CMSIS_H_ */
+#endif  /* _CMSIS_H_ */
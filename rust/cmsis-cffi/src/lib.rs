@@ -18,17 +18,39 @@ macro_rules! with_from_raw {
         #[allow(unused_unsafe)]
         let $boxed = unsafe { Box::from_raw($ptr) };
         let ret = $block;
-        Box::into_raw($boxed);
+        let _ = Box::into_raw($boxed);
         ret
     }};
     (let mut $boxed:ident = $ptr:ident, $block:block) => {{
         #[allow(unused_unsafe)]
         let mut $boxed = unsafe { Box::from_raw($ptr) };
         let ret = $block;
-        Box::into_raw($boxed);
+        let _ = Box::into_raw($boxed);
         ret
     }};
 }
+/// Version of this library; matches the header it was generated with
+pub const CMSIS_CFFI_VERSION_MAJOR: u32 = 0;
+pub const CMSIS_CFFI_VERSION_MINOR: u32 = 6;
+pub const CMSIS_CFFI_VERSION_PATCH: u32 = 3;
+
+const fn parse_version(digits: &str) -> u32 {
+    let digits = digits.as_bytes();
+    let mut value = 0;
+    let mut i = 0;
+    while i < digits.len() {
+        value = value * 10 + (digits[i] - b'0') as u32;
+        i += 1;
+    }
+    value
+}
+
+// The constants above end up in cmsis.h, so they must be literals; keep them
+// in step with Cargo.toml
+const _: () = assert!(CMSIS_CFFI_VERSION_MAJOR == parse_version(env!("CARGO_PKG_VERSION_MAJOR")));
+const _: () = assert!(CMSIS_CFFI_VERSION_MINOR == parse_version(env!("CARGO_PKG_VERSION_MINOR")));
+const _: () = assert!(CMSIS_CFFI_VERSION_PATCH == parse_version(env!("CARGO_PKG_VERSION_PATCH")));
+
 #[macro_use]
 pub mod utils;
 
@@ -5,11 +5,14 @@ use std::sync::mpsc::channel;
 use std::sync::Arc;
 use std::thread;
 
+use anyhow::Error;
+
 use crate::config::ConfigBuilder;
-use cmsis_pack::update::install;
+use cmsis_pack::update::{install, CancellationToken};
 
 use crate::pack_index::{DownloadSender, RunningUpdateContext, UpdatePoll, UpdateReturn};
 use crate::pdsc::ParsedPacks;
+use crate::utils::NullPointer;
 
 cffi! {
     fn update_packs(
@@ -27,6 +30,8 @@ cffi! {
         let (send, recv) = channel();
         let done_flag = Arc::new(AtomicBool::new(false));
         let threads_done_flag = done_flag.clone();
+        let cancel = CancellationToken::new();
+        let threads_cancel = cancel.clone();
         if !parsed_packs.is_null() {
             with_from_raw!(let mut packs = parsed_packs, {
                 let size = packs.0.len();
@@ -37,8 +42,9 @@ cffi! {
                         let res = install(
                             &conf,
                             packs.iter(),
-                            DownloadSender::from_sender(send)
-                        ).map(UpdateReturn);
+                            DownloadSender::from_sender(send),
+                            threads_cancel
+                        ).map(UpdateReturn).map_err(Error::from);
                         threads_done_flag.store(true, Ordering::Release);
                         res
                     })?;
@@ -46,10 +52,11 @@ cffi! {
                     thread_handle: thread,
                     done_flag,
                     result_stream: recv,
+                    cancel,
                 }))))
             })
         } else {
-            Err(anyhow::anyhow!("update packs received a Null pointer"))
+            Err(NullPointer("update_packs").into())
         }
     }
 }
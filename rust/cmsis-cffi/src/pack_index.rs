@@ -13,9 +13,9 @@ use std::thread;
 use anyhow::{anyhow, Error};
 
 use crate::config::{read_vidx_list, ConfigBuilder, DEFAULT_VIDX_LIST};
-use crate::utils::set_last_error;
+use crate::utils::{set_last_error, NullPointer, Panicked};
 use cmsis_pack::update::update;
-use cmsis_pack::update::DownloadProgress;
+use cmsis_pack::update::{CancellationToken, DownloadProgress, Observer};
 
 pub struct UpdateReturn(pub(crate) Vec<PathBuf>);
 
@@ -23,6 +23,7 @@ pub struct RunningUpdateContext {
     pub(crate) thread_handle: thread::JoinHandle<Result<UpdateReturn, Error>>,
     pub(crate) done_flag: Arc<AtomicBool>,
     pub(crate) result_stream: Receiver<DownloadUpdate>,
+    pub(crate) cancel: CancellationToken,
 }
 
 #[repr(C)]
@@ -39,6 +40,8 @@ impl DownloadSender {
     }
 }
 
+impl Observer for DownloadSender {}
+
 impl DownloadProgress for DownloadSender {
     fn size(&self, size: usize) {
         let _ = self.0.send(DownloadUpdate {
@@ -103,14 +106,17 @@ cffi! {
         let (send, recv) = channel();
         let done_flag = Arc::new(AtomicBool::new(false));
         let threads_done_flag = done_flag.clone();
+        let cancel = CancellationToken::new();
+        let threads_cancel = cancel.clone();
         let thread = thread::Builder::new()
             .name("update".to_string())
             .spawn(move || {
                 let res = update(
                     &conf,
                     vidx_list,
-                    DownloadSender::from_sender(send)
-                ).map(UpdateReturn);
+                    DownloadSender::from_sender(send),
+                    threads_cancel
+                ).map(|report| UpdateReturn(report.pdsc_files())).map_err(Error::from);
                 threads_done_flag.store(true, Ordering::Release);
                 res
             })?;
@@ -118,6 +124,7 @@ cffi! {
             thread_handle: thread,
             done_flag,
             result_stream: recv,
+            cancel,
         }))))
     }
 }
@@ -134,7 +141,7 @@ pub unsafe extern "C" fn update_pdsc_poll(ptr: *mut UpdatePoll) -> bool {
                         let response = cont.thread_handle.join();
                         let response = match response {
                             Ok(inner) => inner,
-                            Err(_) => Err(anyhow!("thread paniced"))
+                            Err(_) => Err(Panicked("thread paniced".to_string()).into())
                         };
                         (true, UpdatePoll::Complete(response))
                     } else {
@@ -150,6 +157,25 @@ pub unsafe extern "C" fn update_pdsc_poll(ptr: *mut UpdatePoll) -> bool {
     }
 }
 
+/* Cancellation is asynchronous: keep polling until the update completes,
+ * at which point its result is a cancellation error */
+#[no_mangle]
+pub unsafe extern "C" fn update_pdsc_cancel(ptr: *mut UpdatePoll) -> bool {
+    if !ptr.is_null() {
+        with_from_raw!(let boxed = ptr,{
+            match boxed.borrow() {
+                UpdatePoll::Running(ref cont) => {
+                    cont.cancel.cancel();
+                    true
+                }
+                _ => false
+            }
+        })
+    } else {
+        false
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn update_pdsc_get_status(ptr: *mut UpdatePoll) -> *mut DownloadUpdate {
     if !ptr.is_null() {
@@ -204,6 +230,18 @@ pub unsafe extern "C" fn update_pdsc_result(ptr: *mut UpdatePoll) -> *mut Update
     }
 }
 
+/// Release an update, cancelling it and waiting for it to stop when it
+/// is still running
+#[no_mangle]
+pub unsafe extern "C" fn update_pdsc_poll_free(ptr: *mut UpdatePoll) {
+    if !ptr.is_null() {
+        if let UpdatePoll::Running(cont) = *Box::from_raw(ptr) {
+            cont.cancel.cancel();
+            let _ = cont.thread_handle.join();
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn update_pdsc_index_new() -> *mut UpdateReturn {
     Box::into_raw(Box::new(UpdateReturn(Vec::new())))
@@ -225,7 +263,7 @@ cffi! {
                 }
             })
         } else {
-            Err(anyhow!("update pdsc index next called with null"))
+            Err(NullPointer("update_pdsc_index_next").into())
         }
     }
 }
@@ -239,7 +277,7 @@ cffi! {
                 Ok(())
             })
         } else {
-            Err(anyhow!("update pdsc index push called with null"))
+            Err(NullPointer("update_pdsc_index_push").into())
         }
     }
 }
@@ -1,13 +1,15 @@
+#![allow(clippy::missing_safety_doc)]
 use std::borrow::Cow;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 
-use cmsis_pack::pdsc::{dump_devices, Package};
-use cmsis_pack::utils::FromElem;
+use anyhow::Error;
+use cmsis_pack::pdsc::{dump_devices, parse_packages, Package};
 use cmsis_pack::utils::ResultLogExt;
 
 use crate::pack_index::UpdateReturn;
+use crate::utils::{NotFound, NullPointer};
 
 cffi! {
     fn dump_pdsc_json(
@@ -53,10 +55,10 @@ cffi! {
             if pathbuf.exists() {
                 Ok(Box::into_raw(Box::new(UpdateReturn::from_vec(vec![pathbuf]))))
             } else {
-                Err(anyhow::anyhow!(format!("Could not find file {:?}", &pathbuf)))
+                Err(NotFound(format!("file {:?}", &pathbuf)).into())
             }
         } else {
-            Err(anyhow::anyhow!("Null passed into pack_from_path"))
+            Err(NullPointer("pack_from_path").into())
         }
     }
 }
@@ -65,14 +67,15 @@ cffi! {
     fn parse_packs(ptr: *mut UpdateReturn) -> Result<*mut ParsedPacks>{
         if !ptr.is_null() {
             with_from_raw!(let boxed = ptr,{
-                let pdsc_files = boxed.iter();
+                let pdsc_files = boxed.iter().cloned();
                 Ok(Box::into_raw(Box::new(ParsedPacks(
-                    pdsc_files
-                        .filter_map(|input| Package::from_path(Path::new(input)).ok_warn())
+                    parse_packages(pdsc_files)
+                        .into_iter()
+                        .filter_map(|(_, pkg)| pkg.ok_warn())
                         .collect()))))
             })
         } else {
-            Err(anyhow::anyhow!("Null Passed into parse packs."))
+            Err(NullPointer("parse_packs").into())
         }
     }
 }
@@ -94,3 +97,51 @@ cffi! {
         })
     }
 }
+
+cffi! {
+    fn dumps_devices(ptr: *mut ParsedPacks) -> Result<*const c_char> {
+        if !ptr.is_null() {
+            with_from_raw!(let boxed = ptr, {
+                let dumped_devices = cmsis_pack::pdsc::dumps_devices(boxed.iter())?;
+                Ok(CString::new(dumped_devices)?.into_raw())
+            })
+        } else {
+            Err(NullPointer("dumps_devices").into())
+        }
+    }
+}
+
+cffi! {
+    unsafe fn device_lookup_json(packs: *mut ParsedPacks, name: *const c_char) -> Result<*const c_char> {
+        if !packs.is_null() && !name.is_null() {
+            let name = CStr::from_ptr(name).to_string_lossy();
+            with_from_raw!(let boxed = packs, {
+                match cmsis_pack::pdsc::dumps_device(boxed.iter(), &name) {
+                    Ok(Some(dumped)) => CString::new(dumped)
+                        .map(|cstr| cstr.into_raw() as *const c_char)
+                        .map_err(Error::from),
+                    Ok(None) => Err(NotFound(format!("device {}", name)).into()),
+                    Err(e) => Err(e),
+                }
+            })
+        } else {
+            Err(NullPointer("device_lookup_json").into())
+        }
+    }
+}
+
+cffi! {
+    unsafe fn pyocd_targets_json(path: *const c_char) -> Result<*const c_char> {
+        if !path.is_null() {
+            let path = PathBuf::from(CStr::from_ptr(path).to_string_lossy().into_owned());
+            if !path.exists() {
+                return Err(NotFound(format!("file {:?}", &path)).into());
+            }
+            let pack = Package::from_pack_path(&path)?;
+            let dumped = cmsis_pack::export::pyocd::dumps_pyocd_targets(Some(&pack))?;
+            Ok(CString::new(dumped)?.into_raw())
+        } else {
+            Err(NullPointer("pyocd_targets_json").into())
+        }
+    }
+}
@@ -1,6 +1,8 @@
 #![allow(clippy::missing_safety_doc)]
 use std::cell::RefCell;
 use std::ffi::CString;
+use std::fmt;
+use std::io;
 use std::mem;
 use std::os::raw::c_char;
 use std::panic;
@@ -13,12 +15,97 @@ thread_local! {
     pub static LAST_ERROR: RefCell<Option<Error>> = RefCell::new(None);
 }
 
+/// Classification of the last error, so that callers can react to a failure
+/// without parsing the message.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError = 0,
+    Unknown = 1,
+    NullPointer = 2,
+    Io = 3,
+    NotFound = 4,
+    Panic = 5,
+    Cancelled = 6,
+}
+
+#[derive(Debug)]
+pub(crate) struct NullPointer(pub(crate) &'static str);
+
+impl fmt::Display for NullPointer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} received a Null pointer", self.0)
+    }
+}
+
+impl std::error::Error for NullPointer {}
+
+#[derive(Debug)]
+pub(crate) struct NotFound(pub(crate) String);
+
+impl fmt::Display for NotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Could not find {}", self.0)
+    }
+}
+
+impl std::error::Error for NotFound {}
+
+#[derive(Debug)]
+pub(crate) struct Panicked(pub(crate) String);
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Panicked {}
+
 pub(crate) fn set_last_error(err: Error) {
     LAST_ERROR.with(|e| {
         *e.borrow_mut() = Some(err);
     });
 }
 
+fn error_code(err: &Error) -> ErrorCode {
+    for cause in err.chain() {
+        if cause.is::<NullPointer>() {
+            return ErrorCode::NullPointer;
+        } else if cause.is::<NotFound>() {
+            return ErrorCode::NotFound;
+        } else if cause.is::<Panicked>() {
+            return ErrorCode::Panic;
+        } else if let Some(err) = cause.downcast_ref::<cmsis_pack::Error>() {
+            match err {
+                cmsis_pack::Error::Cancelled => return ErrorCode::Cancelled,
+                cmsis_pack::Error::Io { .. } => return ErrorCode::Io,
+                _ => (),
+            }
+        } else if cause.is::<io::Error>() {
+            return ErrorCode::Io;
+        }
+    }
+    ErrorCode::Unknown
+}
+
+/// The version of the loaded library, to compare against the
+/// `CMSIS_CFFI_VERSION_*` constants of the header. The string is static and
+/// must not be freed.
+#[no_mangle]
+pub extern "C" fn cmsis_cffi_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Does not consume the last error; call this before `err_get_last_message`.
+#[no_mangle]
+pub extern "C" fn err_get_last_code() -> ErrorCode {
+    LAST_ERROR.with(|e| match *e.borrow() {
+        Some(ref err) => error_code(err),
+        None => ErrorCode::NoError,
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn err_get_last_message() -> *const c_char {
     LAST_ERROR.with(|e| {
@@ -64,7 +151,7 @@ pub unsafe fn set_panic_hook() {
             None => format!("thread '{}' panicked with '{}'", thread, message),
         };
 
-        set_last_error(anyhow::anyhow!(description))
+        set_last_error(Panicked(description).into())
     }));
 }
 
@@ -78,7 +165,13 @@ where
             set_last_error(err);
             mem::zeroed()
         }
-        Err(_) => mem::zeroed(),
+        Err(_) => {
+            // The panic hook may already have recorded a more precise message
+            if LAST_ERROR.with(|e| e.borrow().is_none()) {
+                set_last_error(Panicked("Rust code panicked".to_string()).into());
+            }
+            mem::zeroed()
+        }
     }
 }
 
@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error};
+use clap::{App, ArgMatches, SubCommand};
+use serde::Serialize;
+
+use cmsis_pack::pdsc::{with_parse_threads, DeviceDatabase};
+
+use crate::config::Config;
+use crate::events::Event;
+use crate::{parse_packages, pdsc_paths};
+
+/// Timings of the index operations over the pack store
+///
+/// Field names are stable, so reports of different releases can be compared
+/// line by line or, with `--json`, by tools.
+#[derive(Serialize)]
+pub struct Report {
+    version: &'static str,
+    pdsc_files: usize,
+    pdsc_bytes: u64,
+    packages: usize,
+    devices: usize,
+    parse_seconds: f64,
+    parse_mib_per_second: f64,
+    build_seconds: f64,
+    exact_lookup_p50_us: f64,
+    exact_lookup_p99_us: f64,
+    prefix_lookup_p50_us: f64,
+    prefix_lookup_p99_us: f64,
+}
+
+impl Report {
+    fn print(&self) {
+        println!("{:<24} {}", "version", self.version);
+        println!("{:<24} {}", "pdsc_files", self.pdsc_files);
+        println!("{:<24} {}", "pdsc_bytes", self.pdsc_bytes);
+        println!("{:<24} {}", "packages", self.packages);
+        println!("{:<24} {}", "devices", self.devices);
+        println!("{:<24} {:.3}", "parse_seconds", self.parse_seconds);
+        println!(
+            "{:<24} {:.1}",
+            "parse_mib_per_second", self.parse_mib_per_second
+        );
+        println!("{:<24} {:.3}", "build_seconds", self.build_seconds);
+        println!(
+            "{:<24} {:.2}",
+            "exact_lookup_p50_us", self.exact_lookup_p50_us
+        );
+        println!(
+            "{:<24} {:.2}",
+            "exact_lookup_p99_us", self.exact_lookup_p99_us
+        );
+        println!(
+            "{:<24} {:.2}",
+            "prefix_lookup_p50_us", self.prefix_lookup_p50_us
+        );
+        println!(
+            "{:<24} {:.2}",
+            "prefix_lookup_p99_us", self.prefix_lookup_p99_us
+        );
+    }
+}
+
+/// The median and 99th percentile of `samples`, in microseconds
+fn percentiles(mut samples: Vec<Duration>) -> (f64, f64) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    samples.sort();
+    let at = |q: f64| {
+        let index = ((samples.len() - 1) as f64 * q).round() as usize;
+        samples[index].as_secs_f64() * 1e6
+    };
+    (at(0.5), at(0.99))
+}
+
+fn time_each<T, F: FnMut(&T)>(items: &[T], mut op: F) -> Vec<Duration> {
+    items
+        .iter()
+        .map(|item| {
+            let start = Instant::now();
+            op(item);
+            start.elapsed()
+        })
+        .collect()
+}
+
+pub fn bench_args() -> App<'static, 'static> {
+    SubCommand::with_name("bench")
+        .about("Measure parsing, index building and device queries over the pack store")
+        .version("0.1.0")
+}
+
+pub fn bench_command<'a>(conf: &Config, _: &ArgMatches<'a>) -> Result<(), Error> {
+    let paths = pdsc_paths(conf);
+    if paths.is_empty() {
+        return Err(anyhow!(
+            "No PDSC files in {:?}; run update first",
+            conf.pack_store
+        ));
+    }
+    let pdsc_bytes = paths
+        .iter()
+        .filter_map(|path| path.metadata().ok())
+        .map(|meta| meta.len())
+        .sum();
+
+    let start = Instant::now();
+    let packages = with_parse_threads(conf.parse_jobs, || parse_packages(paths.clone()));
+    let parse = start.elapsed();
+
+    let start = Instant::now();
+    let database = DeviceDatabase::from_packages(&packages);
+    let build = start.elapsed();
+
+    let names: Vec<String> = database.devices.keys().cloned().collect();
+    let exact = time_each(&names, |name| {
+        assert!(database.lookup(&name.to_lowercase()).is_some());
+    });
+    let prefixes: Vec<String> = names
+        .iter()
+        .map(|name| {
+            name.chars()
+                .take(name.chars().count().div_ceil(2))
+                .collect()
+        })
+        .collect();
+    let prefix = time_each(&prefixes, |prefix| {
+        assert!(database.with_prefix(prefix).next().is_some());
+    });
+    let (exact_p50, exact_p99) = percentiles(exact);
+    let (prefix_p50, prefix_p99) = percentiles(prefix);
+
+    let report = Report {
+        version: env!("CARGO_PKG_VERSION"),
+        pdsc_files: paths.len(),
+        pdsc_bytes,
+        packages: packages.len(),
+        devices: names.len(),
+        parse_seconds: parse.as_secs_f64(),
+        parse_mib_per_second: pdsc_bytes as f64 / (1 << 20) as f64 / parse.as_secs_f64(),
+        build_seconds: build.as_secs_f64(),
+        exact_lookup_p50_us: exact_p50,
+        exact_lookup_p99_us: exact_p99,
+        prefix_lookup_p50_us: prefix_p50,
+        prefix_lookup_p99_us: prefix_p99,
+    };
+    if conf.json {
+        Event::Bench(&report).emit();
+        Event::summary("bench", report.pdsc_files).emit();
+    } else {
+        report.print();
+    }
+    Ok(())
+}
@@ -1,24 +1,170 @@
 extern crate cmsis_pack;
 
+use std::collections::HashMap;
 use std::fs::{create_dir_all, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Error;
 
-use cmsis_pack::update::DownloadConfig;
+use cmsis_pack::pdsc::ConflictPolicy;
+use cmsis_pack::update::{
+    Credentials, DownloadConfig, Mirror, NetworkProfile, RateLimit, StoreLock, Timeouts,
+    VanishedPolicy, VendorFilter, DEFAULT_VIDX_LIST,
+};
 
 use directories::ProjectDirs;
 
+/// Locations of the pack store and the vendor index list
+///
+/// Creating a config only resolves paths; neither file is touched until a
+/// command reads the list or scans the store.
+#[derive(Clone)]
 pub struct Config {
     pub pack_store: PathBuf,
     pub vidx_list: PathBuf,
+    pub network_profile: NetworkProfile,
+    /// Downloads in flight at once, overriding the network profile
+    pub jobs: Option<usize>,
+    /// Time a download may receive nothing, overriding the network profile
+    pub read_timeout: Option<Duration>,
+    /// Time allowed for a whole download
+    pub timeout: Option<Duration>,
+    /// Proxy URL for every download, overriding HTTP_PROXY and HTTPS_PROXY
+    pub proxy: Option<String>,
+    /// Credentials for the hosts that require them, keyed by host name
+    pub credentials: HashMap<String, Credentials>,
+    /// PEM files of root certificates to trust besides those of the system
+    pub ca_certificates: Vec<PathBuf>,
+    /// Accept any certificate of HTTPS servers
+    pub insecure_skip_verify: bool,
+    /// Mirrors tried when files are missing or their server is unreachable
+    pub mirrors: Vec<Mirror>,
+    /// Requests per second to each host and to all of them
+    pub rate_limit: RateLimit,
+    /// Directory of downloads shared with the other pack stores of the machine
+    pub shared_cache: Option<PathBuf>,
+    /// Forbid network access
+    pub offline: bool,
+    /// Download files again even when they are already in the pack store
+    pub refresh: bool,
+    /// Extract pack archives after installing them
+    pub extract: bool,
+    /// Install the packs that packs installed by their spec require
+    pub install_dependencies: bool,
+    /// Threads parsing PDSC files at once, instead of one per CPU core
+    pub parse_jobs: Option<usize>,
+    /// How devices defined by several packs are resolved
+    pub conflict_policy: ConflictPolicy,
+    /// Warn about files served from another origin than the declared one
+    pub warn_origins: bool,
+    /// What happens to the PDSC files of packs that vanished upstream
+    pub vanished_policy: VanishedPolicy,
+    /// Fail on fetched documents that are not valid UTF-8
+    pub strict_utf8: bool,
+    /// Download PDSC files older than the newest one in the pack store
+    pub allow_downgrade: bool,
+    /// The vendors whose PDSC files are downloaded
+    pub vendor_filter: VendorFilter,
+    /// Refuse downloads without a checksum to verify them against
+    pub require_checksum: bool,
+    /// Wait for other processes to unlock the pack store instead of failing
+    pub wait_for_lock: bool,
+    /// Report progress and results as newline-delimited JSON events
+    pub json: bool,
 }
 
 impl DownloadConfig for Config {
     fn pack_store(&self) -> PathBuf {
         self.pack_store.clone()
     }
+
+    fn network_profile(&self) -> NetworkProfile {
+        self.network_profile
+    }
+
+    fn concurrency(&self) -> usize {
+        self.jobs
+            .unwrap_or_else(|| self.network_profile.concurrency())
+    }
+
+    fn timeouts(&self) -> Timeouts {
+        let profile = self.network_profile.timeouts();
+        Timeouts {
+            read: self.read_timeout.unwrap_or(profile.read),
+            total: self.timeout.or(profile.total),
+            ..profile
+        }
+    }
+
+    fn proxy(&self) -> Option<String> {
+        self.proxy.clone()
+    }
+
+    fn credentials(&self) -> HashMap<String, Credentials> {
+        self.credentials.clone()
+    }
+
+    fn ca_certificates(&self) -> Vec<PathBuf> {
+        self.ca_certificates.clone()
+    }
+
+    fn insecure_skip_verify(&self) -> bool {
+        self.insecure_skip_verify
+    }
+
+    fn mirrors(&self) -> Vec<Mirror> {
+        self.mirrors.clone()
+    }
+
+    fn rate_limit(&self) -> RateLimit {
+        self.rate_limit
+    }
+
+    fn shared_cache(&self) -> Option<PathBuf> {
+        self.shared_cache.clone()
+    }
+
+    fn offline(&self) -> bool {
+        self.offline
+    }
+
+    fn refresh(&self) -> bool {
+        self.refresh
+    }
+
+    fn extract_packs(&self) -> bool {
+        self.extract
+    }
+
+    fn install_dependencies(&self) -> bool {
+        self.install_dependencies
+    }
+
+    fn origin_warnings(&self) -> bool {
+        self.warn_origins
+    }
+
+    fn vanished_policy(&self) -> VanishedPolicy {
+        self.vanished_policy
+    }
+
+    fn allow_downgrade(&self) -> bool {
+        self.allow_downgrade
+    }
+
+    fn vendor_filter(&self) -> VendorFilter {
+        self.vendor_filter.clone()
+    }
+
+    fn strict_utf8(&self) -> bool {
+        self.strict_utf8
+    }
+
+    fn require_checksum(&self) -> bool {
+        self.require_checksum
+    }
 }
 
 impl Config {
@@ -35,9 +181,53 @@ impl Config {
         Ok(Config {
             pack_store,
             vidx_list,
+            network_profile: NetworkProfile::default(),
+            jobs: None,
+            read_timeout: None,
+            timeout: None,
+            proxy: None,
+            credentials: HashMap::new(),
+            ca_certificates: Vec::new(),
+            insecure_skip_verify: false,
+            mirrors: Vec::new(),
+            rate_limit: RateLimit::default(),
+            shared_cache: None,
+            offline: false,
+            refresh: false,
+            extract: false,
+            install_dependencies: true,
+            parse_jobs: None,
+            conflict_policy: ConflictPolicy::default(),
+            warn_origins: false,
+            vanished_policy: VanishedPolicy::default(),
+            strict_utf8: false,
+            allow_downgrade: false,
+            vendor_filter: VendorFilter::default(),
+            require_checksum: false,
+            wait_for_lock: false,
+            json: false,
         })
     }
 
+    /// Lock the pack store for a command that changes it
+    pub fn lock_store(&self) -> Result<StoreLock, cmsis_pack::Error> {
+        StoreLock::acquire(&self.pack_store, self.wait_for_lock)
+    }
+
+    /// Replace the vendor index list with `urls`
+    pub fn write_vidx_list(&self, urls: &[String]) -> Result<(), Error> {
+        if let Some(par) = self.vidx_list.parent() {
+            create_dir_all(par)?;
+        }
+        let mut fd = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.vidx_list)?;
+        fd.write_all(urls.join("\n").as_bytes())?;
+        Ok(())
+    }
+
     pub fn read_vidx_list(&self) -> Vec<String> {
         let fd = OpenOptions::new().read(true).open(&self.vidx_list);
         match fd.map_err(Error::from) {
@@ -45,17 +235,20 @@ impl Config {
                 .lines()
                 .enumerate()
                 .flat_map(|(linenum, line)| {
-                    line.map_err(|e| log::error!("Could not parse line #{}: {}", linenum, e))
+                    line.map_err(|e| tracing::error!("Could not parse line #{}: {}", linenum, e))
                         .into_iter()
                 })
                 .collect(),
             Err(_) => {
-                log::warn!("Failed to open vendor index list read only. Recreating.");
-                let new_content = vec![String::from("http://www.keil.com/pack/index.pidx")];
+                tracing::warn!("Failed to open vendor index list read only. Recreating.");
+                let new_content: Vec<String> = DEFAULT_VIDX_LIST
+                    .iter()
+                    .map(|url| url.to_string())
+                    .collect();
                 match self.vidx_list.parent() {
                     Some(par) => {
                         create_dir_all(par).unwrap_or_else(|e| {
-                            log::error!(
+                            tracing::error!(
                                 "Could not create parent directory for vendor index list.\
                                  Error: {}",
                                 e
@@ -63,7 +256,7 @@ impl Config {
                         });
                     }
                     None => {
-                        log::error!("Could not get parent directory for vendors.list");
+                        tracing::error!("Could not get parent directory for vendors.list");
                     }
                 }
                 match OpenOptions::new()
@@ -74,11 +267,11 @@ impl Config {
                     Ok(mut fd) => {
                         let lines = new_content.join("\n");
                         fd.write_all(lines.as_bytes()).unwrap_or_else(|e| {
-                            log::error!("Could not create vendor list file: {}", e);
+                            tracing::error!("Could not create vendor list file: {}", e);
                         });
                     }
                     Err(e) => {
-                        log::error!("Could not open vendors index list file for writing {}", e)
+                        tracing::error!("Could not open vendors index list file for writing {}", e)
                     }
                 }
                 new_content
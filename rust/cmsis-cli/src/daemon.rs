@@ -0,0 +1,756 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Serialize;
+
+use cmsis_pack::update::{install, update, CancellationToken, DownloadProgress, Observer};
+use cmsis_pack::Error as PackError;
+
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::{installed_database, iter_installed_packages};
+
+#[derive(Clone, Default, Serialize)]
+struct JobState {
+    kind: Option<&'static str>,
+    running: bool,
+    total: usize,
+    completed: usize,
+    failed: usize,
+    updated: Option<usize>,
+    error: Option<String>,
+    #[serde(skip)]
+    cancel: CancellationToken,
+}
+
+type Shared = Arc<Mutex<JobState>>;
+type SharedMetrics = Arc<Mutex<Metrics>>;
+
+struct DaemonProgress(Shared, SharedMetrics);
+
+impl DaemonProgress {
+    fn metrics<F: FnOnce(&mut Metrics)>(&self, record: F) {
+        if let Ok(mut metrics) = self.1.lock() {
+            record(&mut metrics);
+        }
+    }
+}
+
+impl Observer for DaemonProgress {
+    fn download_started(&self, url: &str) {
+        self.metrics(|m| m.download_started(url));
+    }
+    fn pdsc_downloaded(&self, url: &str, _: &Path) {
+        self.metrics(|m| m.download_succeeded(url));
+    }
+    fn pack_installed(&self, url: &str, _: &Path) {
+        self.metrics(|m| m.download_succeeded(url));
+    }
+    fn download_failed(&self, url: &str, error: &Error) {
+        if let Ok(mut state) = self.0.lock() {
+            state.failed += 1;
+        }
+        self.metrics(|m| m.download_failed(url, PackError::code_of(error)));
+    }
+}
+
+impl DownloadProgress for DaemonProgress {
+    fn size(&self, files: usize) {
+        if let Ok(mut state) = self.0.lock() {
+            state.total = files;
+        }
+    }
+    fn progress(&self, bytes: usize) {
+        self.metrics(|m| m.bytes(bytes));
+    }
+    fn complete(&self) {
+        if let Ok(mut state) = self.0.lock() {
+            state.completed += 1;
+        }
+    }
+    fn for_file(&self, _: &str) -> Self {
+        DaemonProgress(self.0.clone(), self.1.clone())
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    host: Option<String>,
+    origin: Option<String>,
+    /// The size of the body, drained unless above [`MAX_BODY`]
+    content_length: usize,
+}
+
+/// The largest request body read; the API takes its arguments from the
+/// query string, so bodies are only drained
+const MAX_BODY: usize = 64 * 1024;
+
+/// The longest request line or header line read
+const MAX_LINE: u64 = 8 * 1024;
+
+/// The most header lines read from one request
+const MAX_HEADERS: usize = 64;
+
+/// How long a connection may stay silent while its request is read, or
+/// stall while a response is written
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A request refused while it was read, with the status to answer it with
+#[derive(Debug)]
+struct Rejected {
+    status: &'static str,
+    message: &'static str,
+}
+
+impl std::fmt::Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// The error of a read that failed, as a [`Rejected`] request when the
+/// client went silent
+fn read_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Rejected {
+            status: "408 Request Timeout",
+            message: "Request not received in time",
+        }
+        .into(),
+        _ => err.into(),
+    }
+}
+
+/// Read one line of at most [`MAX_LINE`] bytes; longer lines are refused
+/// with `too_long`
+fn read_line<R: BufRead>(reader: &mut R, too_long: Rejected) -> Result<String, Error> {
+    let mut line = String::new();
+    reader
+        .take(MAX_LINE)
+        .read_line(&mut line)
+        .map_err(read_error)?;
+    if !line.ends_with('\n') && line.len() as u64 >= MAX_LINE {
+        return Err(too_long.into());
+    }
+    Ok(line)
+}
+
+/// Whether `host`, with or without a port, names the loopback interface
+fn is_loopback(host: &str) -> bool {
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+    name.eq_ignore_ascii_case("localhost")
+        || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+impl Request {
+    /// Whether the request comes from a page or name other than the loopback
+    /// interface, as a web page the user opens sends through their browser
+    /// or a DNS rebinding attack
+    fn is_foreign(&self) -> bool {
+        let foreign_host = self.host.as_deref().is_some_and(|host| !is_loopback(host));
+        let foreign_origin = self.origin.as_deref().is_some_and(|origin| {
+            let host = origin
+                .strip_prefix("http://")
+                .or_else(|| origin.strip_prefix("https://"));
+            !host.is_some_and(is_loopback)
+        });
+        foreign_host || foreign_origin
+    }
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let hex = (bytes[i + 1] as char)
+                    .to_digit(16)
+                    .zip((bytes[i + 2] as char).to_digit(16));
+                match hex {
+                    Some((high, low)) => {
+                        out.push((high * 16 + low) as u8);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn read_request<R: Read>(stream: R) -> Result<Request, Error> {
+    let mut reader = BufReader::new(stream);
+    let line = read_line(
+        &mut reader,
+        Rejected {
+            status: "414 URI Too Long",
+            message: "Request line too long",
+        },
+    )?;
+    let mut parts = line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| anyhow!("Empty request"))?
+        .to_string();
+    let target = parts
+        .next()
+        .ok_or_else(|| anyhow!("Request without a target"))?;
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (target, ""),
+    };
+    let query = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (percent_decode(key), percent_decode(value)),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect();
+    let mut content_length = 0;
+    let mut host = None;
+    let mut origin = None;
+    for read in 0.. {
+        let too_large = Rejected {
+            status: "431 Request Header Fields Too Large",
+            message: "Request headers too large",
+        };
+        if read == MAX_HEADERS {
+            return Err(too_large.into());
+        }
+        let header = read_line(&mut reader, too_large)?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse()?;
+            } else if name.eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            } else if name.eq_ignore_ascii_case("origin") {
+                origin = Some(value.trim().to_string());
+            }
+        }
+    }
+    if content_length <= MAX_BODY {
+        io::copy(&mut reader.take(content_length as u64), &mut io::sink()).map_err(read_error)?;
+    }
+    Ok(Request {
+        method,
+        path: percent_decode(path),
+        query,
+        host,
+        origin,
+        content_length,
+    })
+}
+
+fn respond_with_type(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    respond_with_type(stream, status, "application/json", body)
+}
+
+fn respond_json<T: Serialize>(stream: &mut TcpStream, value: &T) -> Result<(), Error> {
+    respond(stream, "200 OK", &serde_json::to_string(value)?)?;
+    Ok(())
+}
+
+fn respond_error(stream: &mut TcpStream, status: &str, message: &str) -> Result<(), Error> {
+    let body = serde_json::json!({ "error": message });
+    respond(stream, status, &body.to_string())?;
+    Ok(())
+}
+
+/// Run `job` on a background thread unless another job is still running
+fn start_job<F>(state: &Shared, metrics: &SharedMetrics, kind: &'static str, job: F) -> bool
+where
+    F: FnOnce(DaemonProgress, CancellationToken) -> Result<Vec<PathBuf>, Error> + Send + 'static,
+{
+    let cancel = CancellationToken::new();
+    {
+        let mut guard = match state.lock() {
+            Ok(guard) => guard,
+            Err(_) => return false,
+        };
+        if guard.running {
+            return false;
+        }
+        *guard = JobState {
+            kind: Some(kind),
+            running: true,
+            cancel: cancel.clone(),
+            ..JobState::default()
+        };
+    }
+    let state = state.clone();
+    let metrics = metrics.clone();
+    thread::spawn(move || {
+        let started = Instant::now();
+        let res = job(DaemonProgress(state.clone(), metrics.clone()), cancel);
+        if let Ok(mut metrics) = metrics.lock() {
+            metrics.job_finished(kind, started.elapsed());
+        }
+        if let Ok(mut guard) = state.lock() {
+            guard.running = false;
+            match res {
+                Ok(updated) => guard.updated = Some(updated.len()),
+                Err(e) => guard.error = Some(e.to_string()),
+            }
+        }
+    });
+    true
+}
+
+/// Start an update unless one is already running, which the caller then
+/// shares instead of starting another; fails while another kind of job runs
+fn start_update(conf: &Arc<Config>, state: &Shared, metrics: &SharedMetrics) -> bool {
+    let job_conf = conf.clone();
+    start_job(state, metrics, "update", move |progress, cancel| {
+        let _lock = job_conf.lock_store()?;
+        Ok(update(&*job_conf, job_conf.read_vidx_list(), progress, cancel)?.pdsc_files())
+    }) || state
+        .lock()
+        .is_ok_and(|guard| guard.running && guard.kind == Some("update"))
+}
+
+/// Parse an interval such as `24h`, `30m`, `90s` or `1d`; a bare number is
+/// in seconds
+fn parse_interval(interval: &str) -> Result<Duration, Error> {
+    let (number, unit) = interval.split_at(
+        interval
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(interval.len()),
+    );
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => 0,
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 && scale > 0 => Ok(Duration::from_secs(number * scale)),
+        _ => Err(anyhow!(
+            "--interval expects a duration such as 24h, 30m or 90s, got {}",
+            interval
+        )),
+    }
+}
+
+/// Update the pack store now and then every `interval`, skipping a round
+/// while another job runs
+fn schedule_updates(conf: Arc<Config>, state: Shared, metrics: SharedMetrics, interval: Duration) {
+    thread::spawn(move || loop {
+        let idle = state.lock().is_ok_and(|guard| !guard.running);
+        if idle && start_update(&conf, &state, &metrics) {
+            tracing::info!("Started the scheduled update");
+        } else {
+            tracing::info!("Skipped the scheduled update, as a job is running");
+        }
+        thread::sleep(interval);
+    });
+}
+
+fn stream_progress(stream: &mut TcpStream, state: &Shared) -> Result<(), Error> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut last = String::new();
+    loop {
+        let snapshot = state
+            .lock()
+            .map(|guard| guard.clone())
+            .map_err(|_| anyhow!("Progress state poisoned"))?;
+        let event = serde_json::to_string(&snapshot)?;
+        if event != last {
+            write!(stream, "data: {}\n\n", event)?;
+            stream.flush()?;
+            last = event;
+        }
+        if !snapshot.running {
+            return Ok(());
+        }
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+#[derive(Serialize)]
+struct PackSummary {
+    vendor: String,
+    name: String,
+    version: String,
+    description: String,
+}
+
+fn handle(
+    conf: &Arc<Config>,
+    state: &Shared,
+    metrics: &SharedMetrics,
+    mut stream: TcpStream,
+) -> Result<(), Error> {
+    let request = match read_request(&stream) {
+        Ok(request) => request,
+        Err(err) => match err.downcast_ref::<Rejected>() {
+            Some(rejected) => {
+                respond_error(&mut stream, rejected.status, rejected.message)?;
+                // Closing with unread input resets the connection, which
+                // can discard the answer before the client reads it
+                stream.shutdown(Shutdown::Write)?;
+                let _ = io::copy(&mut (&stream).take(MAX_BODY as u64), &mut io::sink());
+                return Ok(());
+            }
+            None => return Err(err),
+        },
+    };
+    if request.content_length > MAX_BODY {
+        return respond_error(
+            &mut stream,
+            "413 Payload Too Large",
+            "Request body too large",
+        );
+    }
+    if request.is_foreign() {
+        return respond_error(
+            &mut stream,
+            "403 Forbidden",
+            "Only local clients are served",
+        );
+    }
+    tracing::debug!(method = %request.method, path = %request.path, "Daemon request");
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/packs") => {
+            let packs: Vec<_> = iter_installed_packages(conf)
+                .map(|pack| PackSummary {
+                    version: pack.releases.latest_release().version.clone(),
+                    vendor: pack.vendor,
+                    name: pack.name,
+                    description: pack.description,
+                })
+                .collect();
+            respond_json(&mut stream, &packs)
+        }
+        ("GET", "/devices") => {
+            let search = request
+                .query
+                .get("search")
+                .map(|s| s.to_lowercase())
+                .unwrap_or_default();
+            let database = match installed_database(conf) {
+                Ok(database) => database,
+                Err(err) => {
+                    return respond_error(&mut stream, "409 Conflict", &err.to_string());
+                }
+            };
+            let names: Vec<&str> = match request.query.get("prefix") {
+                Some(prefix) => database.with_prefix(prefix).collect(),
+                None => database.devices.keys().map(String::as_str).collect(),
+            };
+            let devices: BTreeMap<_, _> = names
+                .into_iter()
+                .filter(|name| name.to_lowercase().contains(&search))
+                .filter_map(|name| Some((name, database.dump_device(name)?)))
+                .collect();
+            respond_json(&mut stream, &devices)
+        }
+        ("POST", "/update") => {
+            if start_update(conf, state, metrics) {
+                respond(&mut stream, "202 Accepted", "{}")?;
+                Ok(())
+            } else {
+                respond_error(&mut stream, "409 Conflict", "A job is already running")
+            }
+        }
+        ("POST", "/install") => {
+            let wanted = match request.query.get("pack") {
+                Some(pack) => pack.clone(),
+                None => return respond_error(&mut stream, "400 Bad Request", "Missing pack"),
+            };
+            let conf = conf.clone();
+            if start_job(state, metrics, "install", move |progress, cancel| {
+                let packs: Vec<_> = iter_installed_packages(&conf)
+                    .filter(|pack| {
+                        format!("{}.{}", pack.vendor, pack.name).eq_ignore_ascii_case(&wanted)
+                    })
+                    .collect();
+                if packs.is_empty() {
+                    return Err(anyhow!("No PDSC found for {}", wanted));
+                }
+                let _lock = conf.lock_store()?;
+                Ok(install(&*conf, packs.iter(), progress, cancel)?)
+            }) {
+                respond(&mut stream, "202 Accepted", "{}")?;
+                Ok(())
+            } else {
+                respond_error(&mut stream, "409 Conflict", "A job is already running")
+            }
+        }
+        ("POST", "/cancel") => {
+            let running = state
+                .lock()
+                .map(|guard| {
+                    guard.cancel.cancel();
+                    guard.running
+                })
+                .map_err(|_| anyhow!("Progress state poisoned"))?;
+            if running {
+                respond(&mut stream, "202 Accepted", "{}")?;
+                Ok(())
+            } else {
+                respond_error(&mut stream, "409 Conflict", "No job is running")
+            }
+        }
+        ("GET", "/progress") => {
+            let snapshot = state
+                .lock()
+                .map(|guard| guard.clone())
+                .map_err(|_| anyhow!("Progress state poisoned"))?;
+            respond_json(&mut stream, &snapshot)
+        }
+        ("GET", "/progress/events") => stream_progress(&mut stream, state),
+        ("GET", "/metrics") => {
+            let running = state.lock().map(|guard| guard.running).unwrap_or(false);
+            let body = metrics
+                .lock()
+                .map(|metrics| metrics.render(&conf.pack_store, running))
+                .map_err(|_| anyhow!("Metrics poisoned"))?;
+            respond_with_type(&mut stream, "200 OK", "text/plain; version=0.0.4", &body)?;
+            Ok(())
+        }
+        _ => respond_error(&mut stream, "404 Not Found", "Unknown endpoint"),
+    }
+}
+
+pub fn daemon_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("daemon")
+        .about("Serve a local HTTP API for querying and updating the pack store")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .takes_value(true)
+                .default_value("127.0.0.1:8421")
+                .help("Address to listen on"),
+        )
+        .arg(
+            Arg::with_name("interval")
+                .long("interval")
+                .takes_value(true)
+                .value_name("DURATION")
+                .help("Updates the pack store at start and then this often, such as 24h or 30m"),
+        )
+}
+
+pub fn daemon_command(conf: Config, args: &ArgMatches) -> Result<(), Error> {
+    let addr = args.value_of("listen").unwrap();
+    let interval = args.value_of("interval").map(parse_interval).transpose()?;
+    let listener = TcpListener::bind(addr)?;
+    tracing::info!("Listening on http://{}", listener.local_addr()?);
+    let conf = Arc::new(conf);
+    let state = Shared::default();
+    let metrics = SharedMetrics::default();
+    if let Some(interval) = interval {
+        schedule_updates(conf.clone(), state.clone(), metrics.clone(), interval);
+    }
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        {
+            tracing::warn!("Could not set connection timeouts: {}", e);
+            continue;
+        }
+        let conf = conf.clone();
+        let state = state.clone();
+        let metrics = metrics.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle(&conf, &state, &metrics, stream) {
+                tracing::warn!("Daemon request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(host: Option<&str>, origin: Option<&str>) -> Request {
+        Request {
+            method: "POST".to_string(),
+            path: "/update".to_string(),
+            query: HashMap::new(),
+            host: host.map(String::from),
+            origin: origin.map(String::from),
+            content_length: 0,
+        }
+    }
+
+    fn rejection(raw: &[u8]) -> &'static str {
+        let err = read_request(raw).err().unwrap();
+        err.downcast_ref::<Rejected>().unwrap().status
+    }
+
+    #[test]
+    fn intervals_are_parsed() {
+        assert_eq!(parse_interval("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_interval("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_interval("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(
+            parse_interval("24h").unwrap(),
+            Duration::from_secs(24 * 60 * 60)
+        );
+        assert_eq!(
+            parse_interval("1d").unwrap(),
+            Duration::from_secs(24 * 60 * 60)
+        );
+        for bad in ["", "0", "0h", "h", "5w", "1.5h", "-1h", "1h30m"] {
+            assert!(parse_interval(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn query_strings_are_percent_decoded() {
+        assert_eq!(percent_decode("Keil.STM32F4xx_DFP"), "Keil.STM32F4xx_DFP");
+        assert_eq!(percent_decode("a%20b+c%2Fd"), "a b c/d");
+        assert_eq!(percent_decode("%e2%82%ac"), "€");
+        // Malformed escapes are kept as they are
+        assert_eq!(percent_decode("100%zz"), "100%zz");
+        assert_eq!(percent_decode("50%"), "50%");
+    }
+
+    #[test]
+    fn only_loopback_hosts_are_local() {
+        for local in [
+            "localhost",
+            "LOCALHOST:8421",
+            "127.0.0.1",
+            "127.0.0.1:8421",
+            "127.1.2.3:80",
+            "[::1]",
+            "[::1]:8421",
+        ] {
+            assert!(is_loopback(local), "{}", local);
+        }
+        for foreign in [
+            "example.com",
+            "localhost.example.com",
+            "127.0.0.1.example.com",
+            "192.168.1.2:8421",
+            "[::2]:8421",
+            "",
+        ] {
+            assert!(!is_loopback(foreign), "{}", foreign);
+        }
+    }
+
+    #[test]
+    fn rebound_and_cross_origin_requests_are_foreign() {
+        assert!(!request(None, None).is_foreign());
+        assert!(!request(Some("127.0.0.1:8421"), None).is_foreign());
+        assert!(!request(Some("localhost:8421"), Some("http://localhost:8421")).is_foreign());
+        assert!(!request(Some("[::1]:8421"), Some("https://[::1]:8421")).is_foreign());
+        // A name rebound to 127.0.0.1 still sends its own Host
+        assert!(request(Some("attacker.example:8421"), None).is_foreign());
+        // A page elsewhere posting to the daemon
+        assert!(request(Some("127.0.0.1:8421"), Some("http://attacker.example")).is_foreign());
+        assert!(request(Some("127.0.0.1:8421"), Some("null")).is_foreign());
+        assert!(request(Some("127.0.0.1:8421"), Some("file://")).is_foreign());
+    }
+
+    #[test]
+    fn requests_are_read_within_limits() {
+        let raw = b"POST /install?pack=Keil.STM32F4xx%5FDFP HTTP/1.1\r\n\
+                    Host: localhost:8421\r\nContent-Length: 4\r\n\r\nbody";
+        let request = read_request(&raw[..]).unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("POST", "/install")
+        );
+        assert_eq!(request.query["pack"], "Keil.STM32F4xx_DFP");
+        assert_eq!(request.host.as_deref(), Some("localhost:8421"));
+        assert_eq!(request.content_length, 4);
+
+        let long_target = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        assert_eq!(rejection(long_target.as_bytes()), "414 URI Too Long");
+        let long_header = format!(
+            "GET / HTTP/1.1\r\nX: {}\r\n\r\n",
+            "a".repeat(MAX_LINE as usize)
+        );
+        assert_eq!(
+            rejection(long_header.as_bytes()),
+            "431 Request Header Fields Too Large"
+        );
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X: a\r\n".repeat(MAX_HEADERS));
+        assert_eq!(
+            rejection(many_headers.as_bytes()),
+            "431 Request Header Fields Too Large"
+        );
+    }
+
+    /// A client that sends the start of a request, then goes silent until
+    /// the read timeout of the connection runs out
+    struct Silent(&'static [u8]);
+
+    impl Read for Silent {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.read(buf)? {
+                0 => Err(io::ErrorKind::WouldBlock.into()),
+                read => Ok(read),
+            }
+        }
+    }
+
+    #[test]
+    fn silent_clients_time_out() {
+        let err = read_request(Silent(b"GET /progress HTTP/1.1\r\nHost: loc"))
+            .err()
+            .unwrap();
+        let rejected = err.downcast_ref::<Rejected>().unwrap();
+        assert_eq!(rejected.status, "408 Request Timeout");
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use cmsis_pack::pdsc::{Algorithm, Board, DumpDevice, PackMatch};
+use cmsis_pack::update::{Outdated, PdscProblem, PlannedDownload, StoreUsage};
+use serde::Serialize;
+
+use crate::bench::Report;
+
+/// A line of the `--json` output, tagged with its kind in `event`
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A PDSC file was downloaded into the pack store
+    Downloaded { url: &'a str, path: &'a Path },
+    /// A pack archive was downloaded into the pack store
+    Installed { url: &'a str, path: &'a Path },
+    /// A pack archive was verified and extracted
+    Extracted { url: &'a str, path: &'a Path },
+    /// The install directory of a pack named on the command line
+    PackDir { pack: String, path: &'a Path },
+    /// A pack an install resolved, with the pack that requires it
+    Resolved {
+        pack: String,
+        required_by: Option<String>,
+    },
+    /// A PDSC file vanished upstream; `local` are its files in the store
+    Vanished { url: &'a str, local: &'a [PathBuf] },
+    /// A download failed, and the command went on with the other files
+    Failed {
+        url: &'a str,
+        code: &'static str,
+        error: String,
+    },
+    /// A file or directory was deleted from the pack store
+    Removed { path: &'a Path },
+    /// A PDSC file of the pack store is broken
+    Broken {
+        path: &'a Path,
+        problem: &'a PdscProblem,
+    },
+    /// A pack matched a search
+    Found(&'a PackMatch),
+    /// An installed pack has a newer version or a deprecation notice
+    Outdated(&'a Outdated),
+    /// A dry run would download this file
+    Planned(&'a PlannedDownload),
+    /// The SVD file of a device
+    Svd { device: &'a str, path: &'a Path },
+    /// A flash algorithm of a device, with the path of its extracted file
+    FlashAlgorithm {
+        device: &'a str,
+        algorithm: &'a Algorithm,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<&'a Path>,
+    },
+    /// A board of the pack store, with its mounted devices and features
+    Board(&'a Board),
+    /// The disk space the pack store takes up, by vendor
+    Usage(&'a StoreUsage),
+    /// A device of the pack store, with its memories, cores and algorithms
+    Device(&'a DumpDevice<'a>),
+    /// The binary device index was brought up to date
+    Indexed {
+        devices: usize,
+        boards: usize,
+        components: usize,
+        path: &'a Path,
+    },
+    /// A document an export command wrote, in `format`
+    Exported {
+        format: &'static str,
+        document: &'a str,
+    },
+    /// A configuration value
+    Setting { key: &'a str, value: &'a Path },
+    /// The timings of a benchmark run
+    Bench(&'a Report),
+    /// A shell completion script
+    Completions { shell: &'a str, script: &'a str },
+    /// The outcome of the command, always its last line unless it failed
+    Summary {
+        command: &'a str,
+        files: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        broken: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bytes: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        failed: Option<usize>,
+    },
+    /// The command failed
+    Error { code: &'static str, error: String },
+}
+
+impl Event<'_> {
+    /// A summary of `files` files handled by `command`
+    pub fn summary(command: &str, files: usize) -> Event<'_> {
+        Event::Summary {
+            command,
+            files,
+            broken: None,
+            bytes: None,
+            failed: None,
+        }
+    }
+
+    /// Write the event to stdout as one line of JSON
+    pub fn emit(&self) {
+        if let Ok(line) = serde_json::to_string(self) {
+            let _ = writeln!(std::io::stdout().lock(), "{}", line);
+        }
+    }
+}
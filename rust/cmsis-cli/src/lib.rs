@@ -1,27 +1,84 @@
 extern crate clap;
 extern crate pbr;
 
-use anyhow::Error;
-use clap::{App, Arg, ArgMatches, SubCommand};
+use anyhow::{anyhow, Error};
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand};
 use pbr::ProgressBar;
+use std::collections::HashMap;
+use std::fs::File;
 use std::io::Stdout;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 extern crate cmsis_pack;
-use cmsis_pack::pdsc::{dump_devices, Component, FileRef, Package};
-use cmsis_pack::update::{install, update, DownloadProgress};
+use cmsis_pack::export::inventory::dumps_inventory;
+use cmsis_pack::export::mbed::{dumps_mbed_targets, dumps_pack_manager_index};
+use cmsis_pack::pdsc::{
+    self, iter_packages, search_packages, Algorithm, Board, Component, DeviceDatabase, FileRef,
+    Package,
+};
+use cmsis_pack::update::{
+    capture_snapshot, check_store, clean_store, collect_garbage, dry_run_update,
+    flash_algorithm_paths, install, install_pack, measure_downloads, outdated_packs, plan_install,
+    plan_install_pack, remove_pack, resolve_pack, restore_snapshot, store_usage, svd_path, update,
+    vanished_packs, BrokenPdsc, CancellationToken, CleanScope, Deprecation, DownloadProgress,
+    Observer, PackSpec, PlannedDownload, Reclaimed, ResolvedPack, StoreSnapshot, VanishedPolicy,
+    VendorFilter,
+};
 use cmsis_pack::utils::FromElem;
 
+mod bench;
 mod config;
+mod daemon;
+mod events;
+mod metrics;
+mod rpc;
 
+pub use bench::{bench_args, bench_command};
 pub use config::Config;
+pub use daemon::{daemon_args, daemon_command};
+pub use events::Event;
+pub use rpc::rpc_command;
 
-struct CliProgress(Arc<Mutex<ProgressBar<Stdout>>>);
+/// Progress shown as a bar, or as events on stdout in `--json` mode
+struct CliProgress(Option<Arc<Mutex<ProgressBar<Stdout>>>>);
+
+impl Observer for CliProgress {
+    fn pdsc_downloaded(&self, url: &str, dest: &Path) {
+        if self.0.is_none() {
+            Event::Downloaded { url, path: dest }.emit();
+        }
+    }
+    fn download_failed(&self, url: &str, error: &Error) {
+        if self.0.is_none() {
+            Event::Failed {
+                url,
+                code: cmsis_pack::Error::code_of(error),
+                error: error.to_string(),
+            }
+            .emit();
+        }
+    }
+    fn pack_installed(&self, url: &str, dest: &Path) {
+        if self.0.is_none() {
+            Event::Installed { url, path: dest }.emit();
+        }
+    }
+    fn pack_extracted(&self, url: &str, dir: &Path) {
+        if self.0.is_none() {
+            Event::Extracted { url, path: dir }.emit();
+        }
+    }
+    fn pack_vanished(&self, url: &str, local: &[PathBuf]) {
+        if self.0.is_none() {
+            Event::Vanished { url, local }.emit();
+        }
+    }
+}
 
 impl DownloadProgress for CliProgress {
     fn size(&self, files: usize) {
-        if let Ok(mut inner) = self.0.lock() {
+        if let Some(Ok(mut inner)) = self.0.as_ref().map(|bar| bar.lock()) {
             inner.total = files as u64;
             inner.show_speed = false;
             inner.show_bar = true;
@@ -29,7 +86,7 @@ impl DownloadProgress for CliProgress {
     }
     fn progress(&self, _: usize) {}
     fn complete(&self) {
-        if let Ok(mut inner) = self.0.lock() {
+        if let Some(Ok(mut inner)) = self.0.as_ref().map(|bar| bar.lock()) {
             inner.inc();
         }
     }
@@ -39,13 +96,16 @@ impl DownloadProgress for CliProgress {
 }
 
 impl CliProgress {
-    fn new() -> Self {
+    fn new(conf: &Config) -> Self {
+        if conf.json {
+            return CliProgress(None);
+        }
         let mut progress = ProgressBar::new(363);
         progress.show_speed = false;
         progress.show_time_left = false;
         progress.format("[#> ]");
         progress.message("Downloading Packs ");
-        CliProgress(Arc::new(Mutex::new(progress)))
+        CliProgress(Some(Arc::new(Mutex::new(progress))))
     }
 }
 
@@ -58,58 +118,426 @@ pub fn install_args() -> App<'static, 'static> {
                 .required(true)
                 .takes_value(true)
                 .index(1)
-                .multiple(true),
+                .multiple(true)
+                .help(
+                    "PDSC files, or packs in the pack store written Vendor::Pack[@version], \
+                     where version may be a requirement such as ^5.7",
+                ),
+        )
+        .arg(
+            Arg::with_name("extract")
+                .long("extract")
+                .help("Verify and extract each pack next to its archive"),
         )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("List the packs that would be downloaded, with their sizes, and stop"),
+        )
+        .arg(
+            Arg::with_name("no-deps")
+                .long("no-deps")
+                .help("Install packs named by their spec without the packs they require"),
+        )
+}
+
+/// Report the packs resolved for a spec, before any of them is downloaded
+fn print_resolved(conf: &Config, resolved: &[ResolvedPack]) {
+    for pack in resolved {
+        if conf.json {
+            Event::Resolved {
+                pack: pack.spec.to_string(),
+                required_by: pack.required_by.as_ref().map(PackSpec::to_string),
+            }
+            .emit();
+        } else if let Some(by) = &pack.required_by {
+            tracing::info!("{} requires {}", by, pack.spec);
+        }
+    }
 }
 
 pub fn install_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
-    let pdsc_list: Vec<_> = args
+    let conf = &Config {
+        extract: args.is_present("extract"),
+        install_dependencies: !args.is_present("no-deps"),
+        ..conf.clone()
+    };
+    let (specs, paths): (Vec<&str>, Vec<&str>) = args
         .values_of("PDSC")
         .unwrap()
+        .partition(|input| input.contains("::"));
+    if args.is_present("dry-run") {
+        let mut planned = Vec::new();
+        for spec in specs {
+            let resolved = resolve_pack(conf, &spec.parse()?)?;
+            print_resolved(conf, &resolved);
+            for pack in resolved {
+                planned.push(plan_install_pack(conf, &pack.spec)?);
+            }
+        }
+        let pdsc_list: Vec<_> = paths
+            .into_iter()
+            .map(|input| Package::from_path(Path::new(input)))
+            .collect::<Result<_, _>>()?;
+        planned.extend(plan_install(conf, pdsc_list.iter()));
+        print_planned(conf, "install", &measure_downloads(conf, planned)?);
+        return Ok(());
+    }
+    let _lock = conf.lock_store()?;
+    // Packs named by their spec are always extracted, and their install
+    // directories printed
+    for spec in specs {
+        let spec: PackSpec = spec.parse()?;
+        print_resolved(conf, &resolve_pack(conf, &spec)?);
+        let dir = install_pack(
+            conf,
+            &spec,
+            CliProgress::new(conf),
+            CancellationToken::new(),
+        )?;
+        if conf.json {
+            Event::PackDir {
+                pack: spec.to_string(),
+                path: &dir,
+            }
+            .emit();
+        } else {
+            println!("{}", dir.display());
+        }
+    }
+    if paths.is_empty() {
+        return Ok(());
+    }
+    let pdsc_list: Vec<_> = paths
+        .into_iter()
         .filter_map(|input| Package::from_path(Path::new(input)).ok())
         .collect();
-    let progress = CliProgress::new();
-    let updated = install(conf, pdsc_list.iter(), progress)?;
+    let progress = CliProgress::new(conf);
+    let updated = install(conf, pdsc_list.iter(), progress, CancellationToken::new())?;
+    if conf.json {
+        Event::summary("install", updated.len()).emit();
+    }
     let num_updated = updated.iter().map(|_| 1).sum::<u32>();
     match num_updated {
         0 => {
-            log::info!("Already up to date");
+            tracing::info!("Already up to date");
         }
         1 => {
-            log::info!("Updated 1 package");
+            tracing::info!("Updated 1 package");
         }
         _ => {
-            log::info!("Updated {} package", num_updated);
+            tracing::info!("Updated {} package", num_updated);
         }
     }
     Ok(())
 }
 
+pub fn remove_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("remove")
+        .about("Remove packs and their PDSC files from the pack store")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("PACK")
+                .required(true)
+                .index(1)
+                .multiple(true)
+                .help("Packs written Vendor::Pack, or Vendor::Pack@version for one version"),
+        )
+}
+
+/// Print the files a dry run would download and their total size
+fn print_planned(conf: &Config, command: &str, planned: &[PlannedDownload]) {
+    let downloads: Vec<_> = planned.iter().filter(|p| p.reason.downloads()).collect();
+    let bytes = downloads.iter().filter_map(|p| p.size).sum();
+    let unknown = downloads.iter().filter(|p| p.size.is_none()).count();
+    if conf.json {
+        for download in downloads.iter() {
+            Event::Planned(download).emit();
+        }
+        Event::Summary {
+            command,
+            files: downloads.len(),
+            broken: None,
+            bytes: Some(bytes),
+            failed: None,
+        }
+        .emit();
+        return;
+    }
+    for download in downloads.iter() {
+        match download.size {
+            Some(size) => println!("{}  {:.1} kB", download.url, size as f64 / 1_000.0),
+            None => println!("{}  size unknown", download.url),
+        }
+    }
+    println!(
+        "Would download {} files, {:.1} MB{}",
+        downloads.len(),
+        bytes as f64 / 1_000_000.0,
+        match unknown {
+            0 => String::new(),
+            _ => format!(" and {} of unknown size", unknown),
+        }
+    );
+}
+
+/// Print the deleted paths and the disk space they took up
+fn print_reclaimed(conf: &Config, command: &str, reclaimed: &Reclaimed) {
+    if conf.json {
+        for path in reclaimed.paths.iter() {
+            Event::Removed { path }.emit();
+        }
+        Event::Summary {
+            command,
+            files: reclaimed.paths.len(),
+            broken: None,
+            bytes: Some(reclaimed.bytes),
+            failed: None,
+        }
+        .emit();
+        return;
+    }
+    for path in reclaimed.paths.iter() {
+        println!("Removed {}", path.display());
+    }
+    println!(
+        "Reclaimed {:.1} MB in {} files and directories",
+        reclaimed.bytes as f64 / 1_000_000.0,
+        reclaimed.paths.len()
+    );
+}
+
+pub fn remove_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let _lock = conf.lock_store()?;
+    let mut reclaimed = Reclaimed::default();
+    for spec in args.values_of("PACK").unwrap() {
+        let spec: PackSpec = spec.parse()?;
+        let removed = remove_pack(&conf.pack_store, &spec)?;
+        reclaimed.paths.extend(removed.paths);
+        reclaimed.bytes += removed.bytes;
+    }
+    print_reclaimed(conf, "remove", &reclaimed);
+    Ok(())
+}
+
+pub fn gc_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("gc")
+        .about("Prune superseded PDSC files and temporary files left by interrupted runs")
+        .version("0.1.0")
+}
+
+pub fn gc_command<'a>(conf: &Config, _: &ArgMatches<'a>) -> Result<(), Error> {
+    let _lock = conf.lock_store()?;
+    print_reclaimed(conf, "gc", &collect_garbage(&conf.pack_store)?);
+    Ok(())
+}
+
 pub fn update_args<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("update")
         .about("Update CMSIS PDSC files for indexing")
         .version("0.1.0")
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .help("Download PDSC files again, rewriting only those that changed"),
+        )
+        .arg(
+            Arg::with_name("delete-vanished")
+                .long("delete-vanished")
+                .help("Delete the PDSC files of packs whose PDSC URL returns 404"),
+        )
+        .arg(
+            Arg::with_name("allow-downgrade")
+                .long("allow-downgrade")
+                .help("Download PDSC files older than the newest version in the pack store"),
+        )
+        .arg(
+            Arg::with_name("vendor")
+                .long("vendor")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("VENDOR")
+                .help("Download only the PDSC files of this vendor; give it once per vendor"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("List the PDSC files that would be downloaded, with their sizes, and stop"),
+        )
+        .arg(
+            Arg::with_name("exclude-vendor")
+                .long("exclude-vendor")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("VENDOR")
+                .help("Skip the PDSC files of this vendor; give it once per vendor"),
+        )
+        .arg(
+            Arg::with_name("jobs")
+                .long("jobs")
+                .short("j")
+                .takes_value(true)
+                .value_name("N")
+                .help("Download N indexes or PDSC files at once, instead of the profile's number"),
+        )
+        .arg(
+            Arg::with_name("fail-on-error")
+                .long("fail-on-error")
+                .help("Exit with an error when any index or PDSC file failed to download"),
+        )
 }
 
-pub fn update_command<'a>(conf: &Config, _: &ArgMatches<'a>) -> Result<(), Error> {
+pub fn update_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let vanished_policy = if args.is_present("delete-vanished") {
+        VanishedPolicy::Delete
+    } else {
+        VanishedPolicy::Keep
+    };
+    let jobs = match args.value_of("jobs") {
+        Some(jobs) => match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => Some(jobs),
+            _ => return Err(anyhow!("--jobs expects a positive number, got {}", jobs)),
+        },
+        None => conf.jobs,
+    };
+    let vendors = |name: &str| -> Vec<String> {
+        args.values_of(name)
+            .into_iter()
+            .flatten()
+            .map(String::from)
+            .collect()
+    };
+    let conf = &Config {
+        jobs,
+        refresh: args.is_present("force"),
+        vanished_policy,
+        allow_downgrade: args.is_present("allow-downgrade"),
+        vendor_filter: VendorFilter {
+            allow: vendors("vendor"),
+            deny: vendors("exclude-vendor"),
+        },
+        ..conf.clone()
+    };
+    if args.is_present("dry-run") {
+        let planned = dry_run_update(conf, conf.read_vidx_list())?;
+        print_planned(conf, "update", &planned);
+        return Ok(());
+    }
+    let _lock = conf.lock_store()?;
     let vidx_list = conf.read_vidx_list();
     for url in vidx_list.iter() {
-        log::info!("Updating registry from `{}`", url);
+        tracing::debug!("Updating registry from `{}`", url);
     }
-    let progress = CliProgress::new();
-    let updated = update(conf, vidx_list, progress)?;
-    let num_updated = updated.iter().map(|_| 1).sum::<u32>();
-    match num_updated {
+    let progress = CliProgress::new(conf);
+    let report = update(conf, vidx_list, progress, CancellationToken::new())?;
+    let failing = args.is_present("fail-on-error") && !report.failed.is_empty();
+    if conf.json && !failing {
+        Event::Summary {
+            command: "update",
+            files: report.downloaded.len() + report.skipped.len(),
+            broken: None,
+            bytes: None,
+            failed: Some(report.failed.len()),
+        }
+        .emit();
+    }
+    match report.downloaded.len() {
         0 => {
-            log::info!("Already up to date");
+            tracing::info!("Already up to date");
         }
         1 => {
-            log::info!("Updated 1 package");
+            tracing::info!("Updated 1 package");
         }
-        _ => {
-            log::info!("Updated {} package", num_updated);
+        num_updated => {
+            tracing::info!("Updated {} package", num_updated);
+        }
+    }
+    if !report.failed.is_empty() {
+        tracing::warn!(
+            "{} downloads failed, {} PDSC files were already up to date",
+            report.failed.len(),
+            report.skipped.len()
+        );
+        for failed in report.failed.iter() {
+            tracing::warn!("{}: {}", failed.url, failed.error);
+        }
+    }
+    for pack in outdated_packs(&conf.pack_store) {
+        if let Some(notice) = &pack.deprecation {
+            tracing::warn!(
+                "Installed pack {}::{} is {}",
+                pack.vendor,
+                pack.name,
+                deprecation_text(notice)
+            );
         }
     }
+    for (pack, vanished) in vanished_packs(&conf.pack_store) {
+        let state = if vanished.deleted { "deleted" } else { "stale" };
+        tracing::warn!(
+            "{} vanished from {}; local files {}: {}",
+            pack,
+            vanished.url,
+            state,
+            vanished.files.join(", ")
+        );
+    }
+    if failing {
+        return Err(anyhow!("{} downloads failed", report.failed.len()));
+    }
+    Ok(())
+}
+
+pub fn snapshot_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("snapshot")
+        .about("Record the vendor index list, PDSC files and installed packs")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("OUTPUT")
+                .help("File to write the snapshot to")
+                .required(true)
+                .index(1),
+        )
+}
+
+pub fn snapshot_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let snapshot = capture_snapshot(conf, conf.read_vidx_list())?;
+    snapshot.to_writer(File::create(args.value_of("OUTPUT").unwrap())?)?;
+    if conf.json {
+        Event::summary("snapshot", snapshot.index.len() + snapshot.installed.len()).emit();
+    }
+    tracing::info!(
+        "Recorded {} PDSC files and {} packs",
+        snapshot.index.len(),
+        snapshot.installed.len()
+    );
+    Ok(())
+}
+
+pub fn restore_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("restore")
+        .about("Reproduce the pack store recorded by the snapshot command")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Snapshot file to restore")
+                .required(true)
+                .index(1),
+        )
+}
+
+pub fn restore_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let snapshot = StoreSnapshot::from_reader(File::open(args.value_of("INPUT").unwrap())?)?;
+    let _lock = conf.lock_store()?;
+    conf.write_vidx_list(&snapshot.sources)?;
+    let progress = CliProgress::new(conf);
+    let restored = restore_snapshot(conf, &snapshot, progress, CancellationToken::new())?;
+    if conf.json {
+        Event::summary("restore", restored.len()).emit();
+    }
+    tracing::info!("Restored {} files", restored.len());
     Ok(())
 }
 
@@ -120,6 +548,7 @@ pub fn dump_devices_args<'a, 'b>() -> App<'a, 'b> {
         .arg(
             Arg::with_name("devices")
                 .short("d")
+                .long("out")
                 .takes_value(true)
                 .help("Dump JSON in the specified file"),
         )
@@ -129,6 +558,13 @@ pub fn dump_devices_args<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(true)
                 .help("Dump JSON in the specified file"),
         )
+        .arg(
+            Arg::with_name("filter")
+                .long("filter")
+                .takes_value(true)
+                .value_name("vendor=VENDOR")
+                .help("Only dump devices whose vendor starts with VENDOR, ignoring case"),
+        )
         .arg(
             Arg::with_name("INPUT")
                 .help("Input file to dump devices from")
@@ -136,51 +572,701 @@ pub fn dump_devices_args<'a, 'b>() -> App<'a, 'b> {
         )
 }
 
-pub fn dump_devices_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
-    let files = args
-        .value_of("INPUT")
-        .map(|input| vec![Box::new(Path::new(input)).to_path_buf()]);
-    let filenames = files
-        .or_else(|| {
-            c.pack_store.read_dir().ok().map(|rd| {
-                rd.flat_map(|dirent| dirent.into_iter().map(|p| p.path()))
-                    .collect()
-            })
-        })
-        .unwrap();
-    let pdscs = filenames
+fn parse_packages<I: IntoIterator<Item = PathBuf>>(filenames: I) -> Vec<Package> {
+    pdsc::parse_packages(filenames)
         .into_iter()
-        .flat_map(|filename| match Package::from_path(&filename) {
+        .flat_map(|(filename, pkg)| match pkg {
             Ok(c) => Some(c),
             Err(e) => {
-                log::error!("parsing {:?}: {}", filename, e);
+                tracing::error!("parsing {:?}: {}", filename, e);
                 None
             }
         })
-        .collect::<Vec<Package>>();
-    let to_ret = dump_devices(&pdscs, args.value_of("devices"), args.value_of("boards"));
-    log::debug!("exiting");
+        .collect()
+}
+
+/// Paths of every PDSC file in the pack store
+pub(crate) fn pdsc_paths(c: &Config) -> Vec<PathBuf> {
+    c.pack_store
+        .read_dir()
+        .map(|rd| {
+            rd.flat_map(|dirent| dirent.into_iter().map(|p| p.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "pdsc"))
+                .collect()
+        })
+        .unwrap_or_else(|_| Vec::new())
+}
+
+/// Parse every PDSC file in the pack store
+pub(crate) fn installed_packages(c: &Config) -> Vec<Package> {
+    pdsc::with_parse_threads(c.parse_jobs, || parse_packages(pdsc_paths(c)))
+}
+
+/// Where the device database of the pack store is cached
+fn database_cache(c: &Config) -> PathBuf {
+    c.pack_store.join(".device-cache.bin")
+}
+
+/// The devices, boards and components of the pack store, from its binary
+/// cache, in which only the PDSC files that changed are parsed again
+///
+/// Devices defined by several packs are resolved by the conflict policy of
+/// `c`, and each conflict is logged with the packs involved.
+pub(crate) fn installed_database(c: &Config) -> Result<DeviceDatabase, Error> {
+    let database = pdsc::with_parse_threads(c.parse_jobs, || {
+        DeviceDatabase::load_or_build_with_policy(
+            &pdsc_paths(c),
+            &database_cache(c),
+            &c.conflict_policy,
+        )
+    })?;
+    for conflict in database.conflict_messages() {
+        tracing::warn!("{}", conflict);
+    }
+    Ok(database)
+}
+
+/// Parse the PDSC files in the pack store one at a time, skipping the ones
+/// that fail to parse
+pub(crate) fn iter_installed_packages(c: &Config) -> impl Iterator<Item = Package> {
+    iter_packages(pdsc_paths(c)).flat_map(|(filename, pkg)| match pkg {
+        Ok(c) => Some(c),
+        Err(e) => {
+            tracing::error!("parsing {:?}: {}", filename, e);
+            None
+        }
+    })
+}
+
+/// The vendor prefix of a `vendor=VENDOR` filter
+fn vendor_filter(filter: &str) -> Result<String, Error> {
+    match filter.split_once('=') {
+        Some(("vendor", vendor)) => Ok(vendor.to_lowercase()),
+        _ => Err(anyhow!(
+            "Unsupported filter {}, expected vendor=VENDOR",
+            filter
+        )),
+    }
+}
+
+pub fn dump_devices_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    if c.json && (args.is_present("devices") || args.is_present("boards")) {
+        return Err(anyhow!(
+            "With --json, devices and boards are events on stdout instead of files"
+        ));
+    }
+    let vendor = args.value_of("filter").map(vendor_filter).transpose()?;
+    let mut database = match args.value_of("INPUT") {
+        Some(input) => DeviceDatabase::with_policy(
+            &parse_packages(vec![PathBuf::from(input)]),
+            &c.conflict_policy,
+        )?,
+        None => installed_database(c)?,
+    };
+    if let Some(vendor) = vendor {
+        database
+            .devices
+            .retain(|_, device| device.vendor().to_lowercase().starts_with(&vendor));
+    }
+    if c.json {
+        let devices: Vec<_> = database
+            .devices
+            .keys()
+            .filter_map(|name| database.dump_device(name))
+            .collect();
+        for device in devices.iter() {
+            Event::Device(device).emit();
+        }
+        for board in database.boards.values() {
+            Event::Board(board).emit();
+        }
+        Event::summary("dump-devices", devices.len()).emit();
+        return Ok(());
+    }
+    let to_ret = database.dump(args.value_of("devices"), args.value_of("boards"));
+    tracing::debug!("exiting");
     to_ret
 }
 
+pub fn search_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("search")
+        .about("Search the installed packs by name, description, keyword or device")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("QUERY")
+                .help("Text to look for, ignoring case")
+                .required(true)
+                .index(1),
+        )
+}
+
+pub fn search_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let pdscs: Vec<Package> = iter_installed_packages(c).collect();
+    let matches = search_packages(&pdscs, args.value_of("QUERY").unwrap());
+    if c.json {
+        for found in matches.iter() {
+            Event::Found(found).emit();
+        }
+        Event::summary("search", matches.len()).emit();
+        return Ok(());
+    }
+    for found in matches {
+        println!(
+            "{}::{} {}  {}",
+            found.vendor, found.name, found.version, found.description
+        );
+    }
+    Ok(())
+}
+
+pub fn outdated_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("outdated")
+        .about("List installed packs with newer versions or deprecation notices")
+        .version("0.1.0")
+}
+
+/// How a deprecation notice reads in warnings and listings
+fn deprecation_text(notice: &Deprecation) -> String {
+    match &notice.replacement {
+        Some(replacement) => format!("deprecated ({}), replaced by {}", notice.since, replacement),
+        None => format!("deprecated ({})", notice.since),
+    }
+}
+
+pub fn outdated_command<'a>(c: &Config, _: &ArgMatches<'a>) -> Result<(), Error> {
+    let outdated = outdated_packs(&c.pack_store);
+    if c.json {
+        for pack in outdated.iter() {
+            Event::Outdated(pack).emit();
+        }
+        Event::summary("outdated", outdated.len()).emit();
+        return Ok(());
+    }
+    for pack in outdated {
+        let mut line = format!("{}::{} {}", pack.vendor, pack.name, pack.installed);
+        if let Some(latest) = &pack.latest {
+            line.push_str(&format!(" -> {}", latest));
+        }
+        if let Some(notice) = &pack.deprecation {
+            line.push_str(&format!("  {}", deprecation_text(notice)));
+        }
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+pub fn svd_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("svd")
+        .about("Print the path of the SVD file of a device, extracting it from its pack if needed")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("DEVICE")
+                .help("Name of the device, ignoring case")
+                .required(true)
+                .index(1),
+        )
+}
+
+pub fn svd_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let device = args.value_of("DEVICE").unwrap();
+    let path = svd_path(&c.pack_store, device)?;
+    if c.json {
+        Event::Svd {
+            device,
+            path: &path,
+        }
+        .emit();
+        Event::summary("svd", 1).emit();
+        return Ok(());
+    }
+    println!("{}", path.display());
+    Ok(())
+}
+
+pub fn flash_algo_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("flash-algo")
+        .about("List the flash algorithms of a device")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("extract")
+                .long("extract")
+                .help("Extract their .FLM files from the installed pack and print their paths"),
+        )
+        .arg(
+            Arg::with_name("DEVICE")
+                .help("Name of the device, ignoring case")
+                .required(true)
+                .index(1),
+        )
+}
+
+/// How a flash algorithm reads in listings
+fn algorithm_text(algo: &Algorithm) -> String {
+    let mut line = format!(
+        "{} {:#010x} {:#x}",
+        algo.file_name.display(),
+        algo.start,
+        algo.size
+    );
+    if let (Some(start), Some(size)) = (algo.ram_start, algo.ram_size) {
+        line.push_str(&format!(" ram {:#010x} {:#x}", start, size));
+    }
+    if algo.default {
+        line.push_str(" default");
+    }
+    line
+}
+
+pub fn flash_algo_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let device = args.value_of("DEVICE").unwrap();
+    let algorithms: Vec<(Algorithm, Option<PathBuf>)> = if args.is_present("extract") {
+        flash_algorithm_paths(&c.pack_store, device)?
+            .into_iter()
+            .map(|(algo, path)| (algo, Some(path)))
+            .collect()
+    } else {
+        installed_database(c)?
+            .flash_algorithms(device)
+            .ok_or_else(|| anyhow!("No PDSC file of the pack store defines {}", device))?
+            .iter()
+            .map(|algo| (algo.clone(), None))
+            .collect()
+    };
+    if c.json {
+        for (algorithm, path) in algorithms.iter() {
+            Event::FlashAlgorithm {
+                device,
+                algorithm,
+                path: path.as_deref(),
+            }
+            .emit();
+        }
+        Event::summary("flash-algo", algorithms.len()).emit();
+        return Ok(());
+    }
+    for (algo, path) in algorithms {
+        match path {
+            Some(path) => println!("{}  {}", algorithm_text(&algo), path.display()),
+            None => println!("{}", algorithm_text(&algo)),
+        }
+    }
+    Ok(())
+}
+
+pub fn list_boards_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("list-boards")
+        .about("List the boards of the PDSC files of the pack store")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("device")
+                .long("device")
+                .takes_value(true)
+                .value_name("DEVICE")
+                .help("Only list boards with a device starting with DEVICE mounted, ignoring case"),
+        )
+}
+
+/// How a board reads in listings
+fn board_text(board: &Board) -> String {
+    let mut line = board.name.clone();
+    if let Some(revision) = &board.revision {
+        line.push_str(&format!(" ({})", revision));
+    }
+    line.push_str(&format!("  {}", board.mounted_devices.join(", ")));
+    if !board.debug_interfaces.is_empty() {
+        line.push_str(&format!("  debug {}", board.debug_interfaces.join(", ")));
+    }
+    line
+}
+
+pub fn list_boards_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let database = installed_database(c)?;
+    let boards: Vec<&Board> = match args.value_of("device") {
+        Some(device) => database.boards_with_device(device).collect(),
+        None => database.boards.values().collect(),
+    };
+    if c.json {
+        for board in boards.iter() {
+            Event::Board(board).emit();
+        }
+        Event::summary("list-boards", boards.len()).emit();
+        return Ok(());
+    }
+    for board in boards {
+        println!("{}", board_text(board));
+    }
+    Ok(())
+}
+
+pub fn index_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("index")
+        .about("Build the binary index of the devices, boards and components of the pack store")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("rebuild")
+                .long("rebuild")
+                .help("Parse every PDSC file again instead of only the changed ones"),
+        )
+}
+
+pub fn index_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    if args.is_present("rebuild") {
+        let _ = std::fs::remove_file(database_cache(c));
+    }
+    let database = installed_database(c)?;
+    let path = database_cache(c);
+    if c.json {
+        Event::Indexed {
+            devices: database.devices.len(),
+            boards: database.boards.len(),
+            components: database.components.len(),
+            path: &path,
+        }
+        .emit();
+        Event::summary("index", database.devices.len()).emit();
+        return Ok(());
+    }
+    println!(
+        "Indexed {} devices, {} boards and {} components into {}",
+        database.devices.len(),
+        database.boards.len(),
+        database.components.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Print the document of an export command, or report it as an event in
+/// `--json` mode
+fn print_export(conf: &Config, command: &str, format: &'static str, document: &str) {
+    if conf.json {
+        Event::Exported { format, document }.emit();
+        Event::summary(command, 1).emit();
+    } else if document.ends_with('\n') {
+        print!("{}", document);
+    } else {
+        println!("{}", document);
+    }
+}
+
+pub fn export_mbed_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("export-mbed")
+        .about("Export devices as Mbed OS targets.json entries")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("detect-codes")
+                .long("detect-codes")
+                .takes_value(true)
+                .help("JSON file mapping device names to lists of detect codes"),
+        )
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to export devices from")
+                .index(1),
+        )
+}
+
+pub fn export_mbed_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let pdscs = match args.value_of("INPUT") {
+        Some(input) => parse_packages(vec![PathBuf::from(input)]),
+        None => installed_packages(c),
+    };
+    let detect_codes: HashMap<String, Vec<String>> = match args.value_of("detect-codes") {
+        Some(path) => serde_json::from_reader(File::open(path)?)?,
+        None => HashMap::new(),
+    };
+    print_export(
+        c,
+        "export-mbed",
+        "json",
+        &dumps_mbed_targets(&pdscs, &detect_codes)?,
+    );
+    Ok(())
+}
+
+pub fn export_mbed_targets_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("export-mbed-targets")
+        .about("Export devices as the index.json of arm-pack-manager, for Mbed tools")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to export devices from")
+                .index(1),
+        )
+}
+
+pub fn export_mbed_targets_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let database = match args.value_of("INPUT") {
+        Some(input) => DeviceDatabase::with_policy(
+            &parse_packages(vec![PathBuf::from(input)]),
+            &c.conflict_policy,
+        )?,
+        None => installed_database(c)?,
+    };
+    print_export(
+        c,
+        "export-mbed-targets",
+        "json",
+        &dumps_pack_manager_index(&database)?,
+    );
+    Ok(())
+}
+
+pub fn export_inventory_args<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("export-inventory")
+        .about("Export boards and SoCs as a YAML inventory")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("INPUT")
+                .help("Input file to export boards and SoCs from")
+                .index(1),
+        )
+}
+
+pub fn export_inventory_command<'a>(c: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let pdscs = match args.value_of("INPUT") {
+        Some(input) => parse_packages(vec![PathBuf::from(input)]),
+        None => installed_packages(c),
+    };
+    print_export(c, "export-inventory", "yaml", &dumps_inventory(&pdscs)?);
+    Ok(())
+}
+
+pub fn config_args() -> App<'static, 'static> {
+    SubCommand::with_name("config")
+        .about("Show the configuration")
+        .version("0.1.0")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Print a configuration value")
+                .arg(
+                    Arg::with_name("KEY")
+                        .required(true)
+                        .possible_values(&["pack-store", "vidx-list"])
+                        .index(1),
+                ),
+        )
+}
+
+/// Print a configuration value, or report it as an event in `--json` mode
+fn print_setting(conf: &Config, key: &str, value: &Path) {
+    if conf.json {
+        Event::Setting { key, value }.emit();
+    } else {
+        println!("{}", value.display());
+    }
+}
+
+pub fn config_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    match args.subcommand() {
+        ("get", Some(sub_m)) => {
+            let key = sub_m.value_of("KEY").unwrap();
+            let value = match key {
+                "pack-store" => &conf.pack_store,
+                _ => &conf.vidx_list,
+            };
+            print_setting(conf, key, value);
+            Ok(())
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+pub fn cache_args() -> App<'static, 'static> {
+    SubCommand::with_name("cache")
+        .about("Locate, measure and clean the pack store")
+        .version("0.1.0")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(SubCommand::with_name("dir").about("Print the directory of the pack store"))
+        .subcommand(
+            SubCommand::with_name("size")
+                .about("Print the disk space the pack store takes up, by vendor"),
+        )
+        .subcommand(
+            SubCommand::with_name("clean")
+                .about("Delete the PDSC files and caches, the packs, or all of the pack store")
+                .arg(
+                    Arg::with_name("PART")
+                        .required(true)
+                        .possible_values(CleanScope::NAMES)
+                        .index(1),
+                ),
+        )
+}
+
+fn megabytes(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1_000_000.0)
+}
+
+pub fn cache_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    match args.subcommand() {
+        ("dir", _) => {
+            print_setting(conf, "pack-store", &conf.pack_store);
+            Ok(())
+        }
+        ("size", _) => {
+            let usage = store_usage(&conf.pack_store);
+            if conf.json {
+                Event::Usage(&usage).emit();
+                Event::Summary {
+                    command: "cache size",
+                    files: usage.vendors.len(),
+                    broken: None,
+                    bytes: Some(usage.total()),
+                    failed: None,
+                }
+                .emit();
+                return Ok(());
+            }
+            for (vendor, vendor_usage) in usage.vendors.iter() {
+                println!(
+                    "{:<24} {:>10} index {:>10} packs",
+                    vendor,
+                    megabytes(vendor_usage.index),
+                    megabytes(vendor_usage.packs)
+                );
+            }
+            println!("{:<24} {:>10}", "Caches", megabytes(usage.caches));
+            println!(
+                "{:<24} {:>10}",
+                "Temporary files",
+                megabytes(usage.temporary)
+            );
+            println!("{:<24} {:>10}", "Total", megabytes(usage.total()));
+            Ok(())
+        }
+        ("clean", Some(sub_m)) => {
+            let scope: CleanScope = sub_m.value_of("PART").unwrap().parse()?;
+            let _lock = conf.lock_store()?;
+            print_reclaimed(conf, "cache clean", &clean_store(&conf.pack_store, scope)?);
+            Ok(())
+        }
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+pub fn completions_args() -> App<'static, 'static> {
+    SubCommand::with_name("completions")
+        .about("Print a shell completion script")
+        .version("0.1.0")
+        .arg(
+            Arg::with_name("SHELL")
+                .required(true)
+                .possible_values(&Shell::variants())
+                .index(1),
+        )
+}
+
+pub fn completions_command<'a>(mut app: App<'a, 'a>, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let name = args.value_of("SHELL").unwrap();
+    let shell: Shell = name.parse().map_err(|e: String| anyhow!(e))?;
+    if args.is_present("json") {
+        let mut script = Vec::new();
+        app.gen_completions_to("cmsis-cli", shell, &mut script);
+        Event::Completions {
+            shell: name,
+            script: &String::from_utf8_lossy(&script),
+        }
+        .emit();
+        Event::summary("completions", 1).emit();
+        return Ok(());
+    }
+    app.gen_completions_to("cmsis-cli", shell, &mut std::io::stdout());
+    Ok(())
+}
+
 pub fn check_args<'a, 'b>() -> App<'a, 'b> {
     SubCommand::with_name("check")
-        .about("Check a project or pack for correct usage of the CMSIS standard")
+        .about(
+            "Check a project or pack for correct usage of the CMSIS standard, \
+             or the PDSC files of the pack store",
+        )
         .version("0.1.0")
         .arg(
             Arg::with_name("INPUT")
-                .help("Input file to check")
-                .required(true)
+                .help("Input file to check; the pack store when omitted")
                 .index(1),
         )
+        .arg(
+            Arg::with_name("fix")
+                .long("fix")
+                .conflicts_with("INPUT")
+                .help("Removes broken PDSC files and downloads them again"),
+        )
+}
+
+fn print_broken(conf: &Config, broken: &[BrokenPdsc]) {
+    for broken in broken {
+        if conf.json {
+            Event::Broken {
+                path: &broken.path,
+                problem: &broken.problem,
+            }
+            .emit();
+        } else {
+            println!("{}: {}", broken.path.display(), broken.problem);
+        }
+    }
+}
+
+/// Check the PDSC files of the pack store, failing when any is broken
+fn check_store_command(conf: &Config, fix: bool) -> Result<(), Error> {
+    let check = check_store(&conf.pack_store);
+    print_broken(conf, &check.broken);
+    if conf.json {
+        Event::Summary {
+            command: "check",
+            files: check.checked,
+            broken: Some(check.broken.len()),
+            bytes: None,
+            failed: None,
+        }
+        .emit();
+    } else {
+        println!(
+            "Checked {} PDSC files, {} broken",
+            check.checked,
+            check.broken.len()
+        );
+    }
+    if check.broken.is_empty() {
+        return Ok(());
+    }
+    if !fix {
+        return Err(anyhow!(
+            "{} broken PDSC files; run check --fix to download them again",
+            check.broken.len()
+        ));
+    }
+    let _lock = conf.lock_store()?;
+    for broken in check.broken.iter() {
+        std::fs::remove_file(&broken.path)?;
+    }
+    let progress = CliProgress::new(conf);
+    update(
+        conf,
+        conf.read_vidx_list(),
+        progress,
+        CancellationToken::new(),
+    )?;
+    let remaining = check_store(&conf.pack_store).broken;
+    print_broken(conf, &remaining);
+    match remaining.len() {
+        0 => Ok(()),
+        broken => Err(anyhow!("{} PDSC files are still broken", broken)),
+    }
 }
 
-pub fn check_command<'a>(_: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
-    let filename = args.value_of("INPUT").unwrap();
+pub fn check_command<'a>(conf: &Config, args: &ArgMatches<'a>) -> Result<(), Error> {
+    let filename = match args.value_of("INPUT") {
+        Some(filename) => filename,
+        None => return check_store_command(conf, args.is_present("fix")),
+    };
     match Package::from_path(Path::new(filename)) {
         Ok(c) => {
-            log::info!("Parsing succedded");
-            log::info!("{} Valid Conditions", c.conditions.0.iter().count());
+            tracing::info!("Parsing succedded");
+            tracing::info!("{} Valid Conditions", c.conditions.0.iter().count());
             let cond_lookup = c.make_condition_lookup();
             let mut num_components = 0;
             let mut num_files = 0;
@@ -196,7 +1282,7 @@ pub fn check_command<'a>(_: &Config, args: &ArgMatches<'a>) -> Result<(), Error>
                 num_files += files.iter().count();
                 if let Some(ref cond_name) = condition {
                     if cond_lookup.get(cond_name.as_str()).is_none() {
-                        log::warn!(
+                        tracing::warn!(
                             "Component {}::{} references an unknown condition '{}'",
                             class,
                             group,
@@ -212,7 +1298,7 @@ pub fn check_command<'a>(_: &Config, args: &ArgMatches<'a>) -> Result<(), Error>
                 {
                     if let Some(ref cond_name) = condition {
                         if cond_lookup.get(cond_name.as_str()).is_none() {
-                            log::warn!(
+                            tracing::warn!(
                                 "File {:?} Component {}::{} references an unknown condition '{}'",
                                 path,
                                 class,
@@ -223,14 +1309,14 @@ pub fn check_command<'a>(_: &Config, args: &ArgMatches<'a>) -> Result<(), Error>
                     }
                 }
             }
-            log::info!("{} Valid Devices", c.devices.0.len());
-            log::info!("{} Valid Software Components", num_components);
-            log::info!("{} Valid Files References", num_files);
+            tracing::info!("{} Valid Devices", c.devices.0.len());
+            tracing::info!("{} Valid Software Components", num_components);
+            tracing::info!("{} Valid Files References", num_files);
         }
         Err(e) => {
-            log::error!("parsing {}: {}", filename, e);
+            tracing::error!("parsing {}: {}", filename, e);
         }
     }
-    log::debug!("exiting");
+    tracing::debug!("exiting");
     Ok(())
 }
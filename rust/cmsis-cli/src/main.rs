@@ -1,66 +1,464 @@
 extern crate clap;
 
-use anyhow::Error;
-use clap::{App, Arg};
+use anyhow::{anyhow, Error};
+use clap::{App, Arg, ArgMatches};
 use cmsis_cli::{
-    check_args, check_command, dump_devices_args, dump_devices_command, install_args,
-    install_command, update_args, update_command, Config,
+    bench_args, bench_command, cache_args, cache_command, check_args, check_command,
+    completions_args, completions_command, config_args, config_command, daemon_args,
+    daemon_command, dump_devices_args, dump_devices_command, export_inventory_args,
+    export_inventory_command, export_mbed_args, export_mbed_command, export_mbed_targets_args,
+    export_mbed_targets_command, flash_algo_args, flash_algo_command, gc_args, gc_command,
+    index_args, index_command, install_args, install_command, list_boards_args,
+    list_boards_command, outdated_args, outdated_command, remove_args, remove_command,
+    restore_args, restore_command, rpc_command, search_args, search_command, snapshot_args,
+    snapshot_command, svd_args, svd_command, update_args, update_command, Config, Event,
 };
+use cmsis_pack::update::{NetworkProfile, RateLimit};
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
 
-fn main() {
-    // Note: This argument parser should do nothing more than handle
-    let matches = App::new("CMSIS Pack manager")
+/// Directives overriding `-v` and `-q`, such as `cmsis_pack::network=debug,warn`
+const LOG_VAR: &str = "CMSIS_PACK_LOG";
+
+fn app() -> App<'static, 'static> {
+    App::new("CMSIS Pack manager")
         .arg(
             Arg::with_name("verbose")
                 .short("v")
-                .help("Sets the level of verbosity"),
+                .long("verbose")
+                .multiple(true)
+                .global(true)
+                .conflicts_with("quiet")
+                .help("Logs more: debug messages, including URLs, and with -vv trace messages"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .multiple(true)
+                .global(true)
+                .help("Logs less: warnings and errors only, and with -qq errors only"),
+        )
+        .arg(
+            Arg::with_name("rpc")
+                .long("rpc")
+                .help("Serve JSON-RPC requests on stdin and stdout"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .global(true)
+                .help("Reports progress and results as JSON lines on stdout, logs on stderr"),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text")
+                .help("Sets the format of log messages"),
+        )
+        .arg(
+            Arg::with_name("network-profile")
+                .long("network-profile")
+                .takes_value(true)
+                .possible_values(NetworkProfile::NAMES)
+                .default_value("balanced")
+                .help("Sets concurrency, retries and timeouts of downloads"),
+        )
+        .arg(
+            Arg::with_name("parse-jobs")
+                .long("parse-jobs")
+                .takes_value(true)
+                .value_name("N")
+                .help("Parses N PDSC files at once instead of one per CPU core"),
+        )
+        .arg(
+            Arg::with_name("read-timeout")
+                .long("read-timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Fails downloads that receive nothing for this long"),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .takes_value(true)
+                .value_name("SECONDS")
+                .help("Fails downloads that take longer than this in all"),
+        )
+        .arg(
+            Arg::with_name("rate-limit")
+                .long("rate-limit")
+                .takes_value(true)
+                .value_name("PER_SECOND")
+                .help("Sends at most this many requests per second to any one host"),
+        )
+        .arg(
+            Arg::with_name("global-rate-limit")
+                .long("global-rate-limit")
+                .takes_value(true)
+                .value_name("PER_SECOND")
+                .help("Sends at most this many requests per second to all hosts together"),
+        )
+        .arg(
+            Arg::with_name("shared-cache")
+                .long("shared-cache")
+                .takes_value(true)
+                .value_name("DIR")
+                .help("Shares downloaded files with other pack stores through this directory"),
+        )
+        .arg(
+            Arg::with_name("offline")
+                .long("offline")
+                .help("Forbids network access; commands that need a download fail"),
+        )
+        .arg(
+            Arg::with_name("warn-origins")
+                .long("warn-origins")
+                .help("Warns about files a redirect fetched from another origin"),
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .long("proxy")
+                .takes_value(true)
+                .value_name("URL")
+                .help("Sends downloads through this proxy instead of HTTP_PROXY/HTTPS_PROXY"),
+        )
+        .arg(
+            Arg::with_name("ca-cert")
+                .long("ca-cert")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PEM")
+                .help(
+                    "Trusts the root certificates in this PEM file, such as a mirror's internal CA",
+                ),
+        )
+        .arg(
+            Arg::with_name("insecure-skip-verify")
+                .long("insecure-skip-verify")
+                .help("Accepts any TLS certificate; leaves downloads open to interception"),
+        )
+        .arg(
+            Arg::with_name("auth")
+                .long("auth")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("HOST=CREDENTIALS")
+                .help(
+                    "Authorizes downloads from HOST with bearer:TOKEN or basic:USER:PASSWORD; \
+                     env:VAR reads the token or password from the environment",
+                ),
+        )
+        .arg(
+            Arg::with_name("mirror")
+                .long("mirror")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("PREFIX=MIRROR")
+                .help(
+                    "Fetches files under PREFIX from MIRROR when they are missing or their \
+                     server is unreachable; tried in the order given",
+                ),
+        )
+        .arg(
+            Arg::with_name("strict-utf8")
+                .long("strict-utf8")
+                .help("Fails on fetched documents that are not valid UTF-8"),
+        )
+        .arg(
+            Arg::with_name("wait")
+                .long("wait")
+                .help("Waits for other processes to unlock the pack store instead of failing"),
+        )
+        .arg(
+            Arg::with_name("require-checksum")
+                .long("require-checksum")
+                .help("Refuses downloads without a published checksum to verify them against"),
+        )
+        .arg(
+            Arg::with_name("on-conflict")
+                .long("on-conflict")
+                .takes_value(true)
+                .default_value("newest")
+                .help(
+                    "Which definition to use when packs define the same device: \
+                     newest, vendor:NAME or error",
+                ),
         )
         .subcommand(update_args())
         .subcommand(check_args())
         .subcommand(dump_devices_args())
+        .subcommand(index_args())
+        .subcommand(search_args())
+        .subcommand(outdated_args())
+        .subcommand(svd_args())
+        .subcommand(flash_algo_args())
+        .subcommand(list_boards_args())
+        .subcommand(export_mbed_args())
+        .subcommand(export_mbed_targets_args())
+        .subcommand(export_inventory_args())
         .subcommand(install_args())
-        .get_matches();
+        .subcommand(remove_args())
+        .subcommand(gc_args())
+        .subcommand(snapshot_args())
+        .subcommand(restore_args())
+        .subcommand(daemon_args())
+        .subcommand(bench_args())
+        .subcommand(config_args())
+        .subcommand(cache_args())
+        .subcommand(completions_args())
+}
+
+/// The positive number of seconds of the option `name`, if given
+fn seconds(matches: &ArgMatches, name: &str) -> Result<Option<Duration>, Error> {
+    match matches.value_of(name) {
+        Some(value) => match value.parse::<u64>() {
+            Ok(seconds) if seconds > 0 => Ok(Some(Duration::from_secs(seconds))),
+            _ => Err(anyhow!(
+                "--{} expects a positive number, got {}",
+                name,
+                value
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The positive rate of the option `name`, if given
+fn rate(matches: &ArgMatches, name: &str) -> Result<Option<f64>, Error> {
+    match matches.value_of(name) {
+        Some(value) => match value.parse::<f64>() {
+            Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(Some(rate)),
+            _ => Err(anyhow!(
+                "--{} expects a positive number, got {}",
+                name,
+                value
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+fn config(matches: &ArgMatches) -> Result<Config, Error> {
+    let mut config = Config::new()?;
+    if let Some(profile) = matches.value_of("network-profile") {
+        config.network_profile = profile.parse()?;
+    }
+    if let Some(jobs) = matches.value_of("parse-jobs") {
+        match jobs.parse::<usize>() {
+            Ok(jobs) if jobs > 0 => config.parse_jobs = Some(jobs),
+            _ => {
+                return Err(anyhow!(
+                    "--parse-jobs expects a positive number, got {}",
+                    jobs
+                ))
+            }
+        }
+    }
+    config.read_timeout = seconds(matches, "read-timeout")?;
+    config.timeout = seconds(matches, "timeout")?;
+    config.rate_limit = RateLimit {
+        per_host: rate(matches, "rate-limit")?,
+        global: rate(matches, "global-rate-limit")?,
+    };
+    config.shared_cache = matches.value_of("shared-cache").map(PathBuf::from);
+    config.warn_origins = matches.is_present("warn-origins");
+    config.strict_utf8 = matches.is_present("strict-utf8");
+    config.require_checksum = matches.is_present("require-checksum");
+    config.wait_for_lock = matches.is_present("wait");
+    config.offline = matches.is_present("offline");
+    config.json = matches.is_present("json");
+    if let Some(proxy) = matches.value_of("proxy") {
+        config.proxy = Some(proxy.to_string());
+    }
+    config.ca_certificates = matches
+        .values_of("ca-cert")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .collect();
+    config.insecure_skip_verify = matches.is_present("insecure-skip-verify");
+    for auth in matches.values_of("auth").into_iter().flatten() {
+        let (host, credentials) = auth
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected HOST=CREDENTIALS, got {}", auth))?;
+        config
+            .credentials
+            .insert(host.to_string(), credentials.parse()?);
+    }
+    config.mirrors = matches
+        .values_of("mirror")
+        .into_iter()
+        .flatten()
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+    if let Some(policy) = matches.value_of("on-conflict") {
+        config.conflict_policy = policy.parse()?;
+    }
+    Ok(config)
+}
+
+/// The levels `-v` and `-q` select for the cmsis crates, with other crates
+/// kept at info at most, or the directives of `CMSIS_PACK_LOG` when it is set
+fn log_filter(matches: &ArgMatches) -> Targets {
+    let level = match (
+        matches.occurrences_of("quiet"),
+        matches.occurrences_of("verbose"),
+    ) {
+        (0, 0) => Level::INFO,
+        (0, 1) => Level::DEBUG,
+        (0, _) => Level::TRACE,
+        (1, _) => Level::WARN,
+        (_, _) => Level::ERROR,
+    };
+    let filter = Targets::new()
+        .with_default(level.min(Level::INFO))
+        .with_target("cmsis_pack", level)
+        .with_target("cmsis_cli", level);
+    match std::env::var(LOG_VAR) {
+        Ok(directives) => directives.parse().unwrap_or_else(|err| {
+            eprintln!("Ignoring {}: {}", LOG_VAR, err);
+            filter
+        }),
+        Err(_) => filter,
+    }
+}
+
+fn main() {
+    // Note: This argument parser should do nothing more than handle
+    // arguments; the source list and the pack store are only read by the
+    // commands that need them
+    let matches = app().get_matches();
+
+    // In RPC and JSON modes stdout carries the protocol or the events, so
+    // logs go to stderr
+    let rpc = matches.is_present("rpc");
+    let json = matches.is_present("json");
+    let filter = log_filter(&matches);
+    let subscriber = tracing_subscriber::fmt().with_max_level(Level::TRACE);
+    match (matches.value_of("log-format"), rpc || json) {
+        (Some("json"), true) => subscriber
+            .json()
+            .with_writer(io::stderr)
+            .finish()
+            .with(filter)
+            .init(),
+        (Some("json"), false) => subscriber.json().finish().with(filter).init(),
+        (_, true) => subscriber
+            .with_writer(io::stderr)
+            .finish()
+            .with(filter)
+            .init(),
+        (_, false) => subscriber.finish().with(filter).init(),
+    }
+    tracing::debug!("Logging ready.");
 
-    simplelog::TermLogger::init(
-        simplelog::LevelFilter::Info,
-        simplelog::Config::default(),
-        simplelog::TerminalMode::Mixed,
-        simplelog::ColorChoice::Auto,
-    )
-    .unwrap();
-    log::debug!("Logging ready.");
+    if rpc {
+        config(&matches)
+            .and_then(|config| rpc_command(&config))
+            .unwrap();
+        return;
+    }
 
-    match matches.subcommand() {
+    let res = match matches.subcommand() {
         ("update", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
-                .and_then(|config| update_command(&config, sub_m))
-                .unwrap();
+            config(&matches).and_then(|config| update_command(&config, sub_m))
         }
         ("install", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
-                .and_then(|config| install_command(&config, sub_m))
-                .unwrap();
+            config(&matches).and_then(|config| install_command(&config, sub_m))
         }
-        ("check", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
-                .and_then(|config| check_command(&config, sub_m))
-                .unwrap();
+        ("remove", Some(sub_m)) => {
+            config(&matches).and_then(|config| remove_command(&config, sub_m))
         }
+        ("gc", Some(sub_m)) => config(&matches).and_then(|config| gc_command(&config, sub_m)),
+        ("check", Some(sub_m)) => config(&matches).and_then(|config| check_command(&config, sub_m)),
         ("dump-devices", Some(sub_m)) => {
-            Config::new()
-                .map_err(Error::from)
-                .and_then(|config| dump_devices_command(&config, sub_m))
-                .unwrap();
+            config(&matches).and_then(|config| dump_devices_command(&config, sub_m))
+        }
+        ("index", Some(sub_m)) => config(&matches).and_then(|config| index_command(&config, sub_m)),
+        ("outdated", Some(sub_m)) => {
+            config(&matches).and_then(|config| outdated_command(&config, sub_m))
+        }
+        ("svd", Some(sub_m)) => config(&matches).and_then(|config| svd_command(&config, sub_m)),
+        ("flash-algo", Some(sub_m)) => {
+            config(&matches).and_then(|config| flash_algo_command(&config, sub_m))
+        }
+        ("list-boards", Some(sub_m)) => {
+            config(&matches).and_then(|config| list_boards_command(&config, sub_m))
+        }
+        ("search", Some(sub_m)) => {
+            config(&matches).and_then(|config| search_command(&config, sub_m))
+        }
+        ("export-mbed", Some(sub_m)) => {
+            config(&matches).and_then(|config| export_mbed_command(&config, sub_m))
+        }
+        ("export-mbed-targets", Some(sub_m)) => {
+            config(&matches).and_then(|config| export_mbed_targets_command(&config, sub_m))
         }
+        ("export-inventory", Some(sub_m)) => {
+            config(&matches).and_then(|config| export_inventory_command(&config, sub_m))
+        }
+        ("snapshot", Some(sub_m)) => {
+            config(&matches).and_then(|config| snapshot_command(&config, sub_m))
+        }
+        ("restore", Some(sub_m)) => {
+            config(&matches).and_then(|config| restore_command(&config, sub_m))
+        }
+        ("daemon", Some(sub_m)) => {
+            config(&matches).and_then(|config| daemon_command(config, sub_m))
+        }
+        ("bench", Some(sub_m)) => config(&matches).and_then(|config| bench_command(&config, sub_m)),
+        ("cache", Some(sub_m)) => config(&matches).and_then(|config| cache_command(&config, sub_m)),
+        ("config", Some(sub_m)) => {
+            config(&matches).and_then(|config| config_command(&config, sub_m))
+        }
+        ("completions", Some(sub_m)) => completions_command(app(), sub_m),
         (bad_command, Some(_)) => {
             println!("I did not understand the command {}", bad_command);
+            Ok(())
         }
         (_, None) => {
             println!("{}", matches.usage());
             println!("Try the help command for more information.");
+            Ok(())
+        }
+    };
+    match res {
+        Err(err) if json => {
+            Event::Error {
+                code: cmsis_pack::Error::code_of(&err),
+                error: err.to_string(),
+            }
+            .emit();
+            std::process::exit(1);
+        }
+        res => res.unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_is_global() {
+        for args in [
+            &["cmsis-cli", "--json", "update"][..],
+            &["cmsis-cli", "update", "--json"],
+            &["cmsis-cli", "search", "stm32", "--json"],
+            &["cmsis-cli", "config", "get", "pack-store", "--json"],
+        ] {
+            let matches = app().get_matches_from_safe(args).unwrap();
+            assert!(matches.is_present("json"), "{:?}", args);
         }
     }
 }
@@ -0,0 +1,207 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+const DOWNLOAD_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+const JOB_BUCKETS: &[f64] = &[1.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0];
+
+struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: Duration) {
+        let value = value.as_secs_f64();
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter_mut()) {
+            if value <= *bound {
+                *count += 1;
+            }
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let sep = if labels.is_empty() { "" } else { "," };
+        for (bound, count) in self.bounds.iter().zip(self.counts.iter()) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, sep, bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, sep, self.count
+        );
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/// Counters and histograms of the daemon, rendered in the Prometheus text
+/// exposition format
+pub(crate) struct Metrics {
+    downloads: u64,
+    failures: BTreeMap<&'static str, u64>,
+    bytes: u64,
+    download_seconds: Histogram,
+    job_seconds: BTreeMap<&'static str, Histogram>,
+    in_flight: HashMap<String, Instant>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            downloads: 0,
+            failures: BTreeMap::new(),
+            bytes: 0,
+            download_seconds: Histogram::new(DOWNLOAD_BUCKETS),
+            job_seconds: BTreeMap::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    pub(crate) fn download_started(&mut self, url: &str) {
+        self.in_flight.insert(url.to_string(), Instant::now());
+    }
+
+    fn download_finished(&mut self, url: &str) {
+        if let Some(started) = self.in_flight.remove(url) {
+            self.download_seconds.observe(started.elapsed());
+        }
+    }
+
+    pub(crate) fn download_succeeded(&mut self, url: &str) {
+        self.downloads += 1;
+        self.download_finished(url);
+    }
+
+    pub(crate) fn download_failed(&mut self, url: &str, class: &'static str) {
+        *self.failures.entry(class).or_insert(0) += 1;
+        self.download_finished(url);
+    }
+
+    pub(crate) fn bytes(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+
+    pub(crate) fn job_finished(&mut self, kind: &'static str, duration: Duration) {
+        self.job_seconds
+            .entry(kind)
+            .or_insert_with(|| Histogram::new(JOB_BUCKETS))
+            .observe(duration);
+    }
+
+    pub(crate) fn render(&self, pack_store: &Path, running: bool) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP cmsis_downloads_total Files downloaded into the pack store\n");
+        out.push_str("# TYPE cmsis_downloads_total counter\n");
+        let _ = writeln!(out, "cmsis_downloads_total {}", self.downloads);
+
+        out.push_str("# HELP cmsis_download_failures_total Failed downloads by class\n");
+        out.push_str("# TYPE cmsis_download_failures_total counter\n");
+        for (class, count) in &self.failures {
+            let _ = writeln!(
+                out,
+                "cmsis_download_failures_total{{class=\"{}\"}} {}",
+                class, count
+            );
+        }
+
+        out.push_str("# HELP cmsis_downloaded_bytes_total Bytes downloaded\n");
+        out.push_str("# TYPE cmsis_downloaded_bytes_total counter\n");
+        let _ = writeln!(out, "cmsis_downloaded_bytes_total {}", self.bytes);
+
+        out.push_str("# HELP cmsis_download_duration_seconds Time taken by single downloads\n");
+        out.push_str("# TYPE cmsis_download_duration_seconds histogram\n");
+        self.download_seconds
+            .render(&mut out, "cmsis_download_duration_seconds", "");
+
+        out.push_str("# HELP cmsis_job_duration_seconds Time taken by updates and installs\n");
+        out.push_str("# TYPE cmsis_job_duration_seconds histogram\n");
+        for (kind, histogram) in &self.job_seconds {
+            histogram.render(
+                &mut out,
+                "cmsis_job_duration_seconds",
+                &format!("kind=\"{}\"", kind),
+            );
+        }
+
+        out.push_str("# HELP cmsis_job_running Whether an update or install is running\n");
+        out.push_str("# TYPE cmsis_job_running gauge\n");
+        let _ = writeln!(out, "cmsis_job_running {}", running as u8);
+
+        if let Some(age) = index_age(pack_store) {
+            out.push_str("# HELP cmsis_index_age_seconds Time since a PDSC was last written\n");
+            out.push_str("# TYPE cmsis_index_age_seconds gauge\n");
+            let _ = writeln!(out, "cmsis_index_age_seconds {}", age.as_secs_f64());
+        }
+        out
+    }
+}
+
+/// Time since the newest PDSC file in the pack store was written
+fn index_age(pack_store: &Path) -> Option<Duration> {
+    let newest = pack_store
+        .read_dir()
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "pdsc"))
+        .filter_map(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .max()?;
+    SystemTime::now().duration_since(newest).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::new(&[0.5, 1.0, 5.0]);
+        histogram.observe(Duration::from_millis(250));
+        histogram.observe(Duration::from_millis(500));
+        histogram.observe(Duration::from_secs(2));
+        histogram.observe(Duration::from_secs(10));
+        let mut out = String::new();
+        histogram.render(&mut out, "job_seconds", "kind=\"update\"");
+        assert_eq!(
+            out,
+            "job_seconds_bucket{kind=\"update\",le=\"0.5\"} 2\n\
+             job_seconds_bucket{kind=\"update\",le=\"1\"} 2\n\
+             job_seconds_bucket{kind=\"update\",le=\"5\"} 3\n\
+             job_seconds_bucket{kind=\"update\",le=\"+Inf\"} 4\n\
+             job_seconds_sum{kind=\"update\"} 12.75\n\
+             job_seconds_count{kind=\"update\"} 4\n"
+        );
+
+        let mut out = String::new();
+        Histogram::new(&[1.0]).render(&mut out, "empty", "");
+        assert_eq!(
+            out,
+            "empty_bucket{le=\"1\"} 0\nempty_bucket{le=\"+Inf\"} 0\nempty_sum 0\nempty_count 0\n"
+        );
+    }
+}
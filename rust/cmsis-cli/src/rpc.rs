@@ -0,0 +1,213 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use anyhow::Error;
+use serde_json::{json, Value};
+
+use cmsis_pack::update::{install, update, CancellationToken, DownloadProgress, Observer};
+
+use crate::config::Config;
+use crate::{installed_database, iter_installed_packages};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+fn send(message: &Value) {
+    let stdout = io::stdout();
+    let mut lock = stdout.lock();
+    let _ = writeln!(lock, "{}", message);
+    let _ = lock.flush();
+}
+
+fn notify(method: &str, params: Value) {
+    send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+}
+
+/// Forwards download events to the client as `progress` notifications
+struct RpcProgress;
+
+impl Observer for RpcProgress {
+    fn source_fetched(&self, url: &str) {
+        notify("progress", json!({ "event": "source_fetched", "url": url }));
+    }
+    fn pdsc_downloaded(&self, url: &str, dest: &Path) {
+        notify(
+            "progress",
+            json!({ "event": "pdsc_downloaded", "url": url, "path": dest }),
+        );
+    }
+    fn download_failed(&self, url: &str, error: &Error) {
+        notify(
+            "progress",
+            json!({ "event": "download_failed", "url": url, "error": error.to_string() }),
+        );
+    }
+    fn pack_installed(&self, url: &str, dest: &Path) {
+        notify(
+            "progress",
+            json!({ "event": "pack_installed", "url": url, "path": dest }),
+        );
+    }
+}
+
+impl DownloadProgress for RpcProgress {
+    fn size(&self, files: usize) {
+        notify("progress", json!({ "event": "size", "files": files }));
+    }
+    fn progress(&self, _: usize) {}
+    fn complete(&self) {
+        notify("progress", json!({ "event": "complete" }));
+    }
+    fn for_file(&self, _: &str) -> Self {
+        RpcProgress
+    }
+}
+
+fn string_param(params: &Value, name: &str) -> Result<String, (i64, String)> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .map(String::from)
+        .ok_or_else(|| (INVALID_PARAMS, format!("Missing string parameter {}", name)))
+}
+
+fn call(conf: &Config, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+    let server_error = |e: Error| (SERVER_ERROR, e.to_string());
+    match method {
+        "search" => {
+            let query = string_param(params, "query")?.to_lowercase();
+            let database = installed_database(conf).map_err(server_error)?;
+            let names: Vec<_> = if params.get("prefix").and_then(Value::as_bool) == Some(true) {
+                database.with_prefix(&query).collect()
+            } else {
+                database
+                    .devices
+                    .keys()
+                    .map(String::as_str)
+                    .filter(|name| name.to_lowercase().contains(&query))
+                    .collect()
+            };
+            Ok(json!(names))
+        }
+        "lookup" => {
+            let name = string_param(params, "device")?;
+            let database = installed_database(conf).map_err(server_error)?;
+            match database.dump_device(&name) {
+                Some(device) => serde_json::to_value(device).map_err(|e| server_error(e.into())),
+                None => Err((SERVER_ERROR, format!("Unknown device {}", name))),
+            }
+        }
+        "update" => {
+            let _lock = conf.lock_store().map_err(|e| server_error(e.into()))?;
+            let report = update(
+                conf,
+                conf.read_vidx_list(),
+                RpcProgress,
+                CancellationToken::new(),
+            )
+            .map_err(|e| server_error(e.into()))?;
+            Ok(json!({ "updated": report.pdsc_files(), "failed": report.failed }))
+        }
+        "install" => {
+            let wanted = string_param(params, "pack")?;
+            let packs: Vec<_> = iter_installed_packages(conf)
+                .filter(|pack| {
+                    format!("{}.{}", pack.vendor, pack.name).eq_ignore_ascii_case(&wanted)
+                })
+                .collect();
+            if packs.is_empty() {
+                return Err((SERVER_ERROR, format!("No PDSC found for {}", wanted)));
+            }
+            let _lock = conf.lock_store().map_err(|e| server_error(e.into()))?;
+            let installed = install(conf, packs.iter(), RpcProgress, CancellationToken::new())
+                .map_err(|e| server_error(e.into()))?;
+            Ok(json!({ "installed": installed }))
+        }
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method))),
+    }
+}
+
+/// The response to one line of input; `None` for notifications, which
+/// carry no id
+fn respond(conf: &Config, line: &str) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => {
+            return Some(json!({
+                "jsonrpc": "2.0",
+                "id": Value::Null,
+                "error": { "code": PARSE_ERROR, "message": e.to_string() },
+            }))
+        }
+    };
+    let id = request.get("id").cloned();
+    let method = request.get("method").and_then(Value::as_str).unwrap_or("");
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let result = call(conf, method, &params);
+    match result {
+        Ok(result) => Some(json!({ "jsonrpc": "2.0", "id": id?, "result": result })),
+        Err((code, message)) => Some(json!({
+            "jsonrpc": "2.0",
+            "id": id?,
+            "error": { "code": code, "message": message },
+        })),
+    }
+}
+
+/// Serve JSON-RPC 2.0 requests, one per line, on stdin and stdout
+///
+/// Logs must not be written to stdout while this runs.
+pub fn rpc_command(conf: &Config) -> Result<(), Error> {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = respond(conf, &line) {
+            send(&response);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_are_framed_as_json_rpc() {
+        let conf = Config::new().unwrap();
+
+        let response = respond(
+            &conf,
+            "{\"jsonrpc\": \"2.0\", \"id\": 7, \"method\": \"nope\"}",
+        );
+        assert_eq!(
+            response,
+            Some(json!({
+                "jsonrpc": "2.0",
+                "id": 7,
+                "error": { "code": METHOD_NOT_FOUND, "message": "Unknown method nope" },
+            }))
+        );
+
+        let response = respond(
+            &conf,
+            "{\"id\": \"a\", \"method\": \"lookup\", \"params\": {}}",
+        );
+        assert_eq!(response.unwrap()["error"]["code"], INVALID_PARAMS);
+
+        // Notifications get no response, even when they fail
+        assert_eq!(
+            respond(&conf, "{\"jsonrpc\": \"2.0\", \"method\": \"nope\"}"),
+            None
+        );
+
+        let response = respond(&conf, "{\"id\": 1, \"method\":").unwrap();
+        assert_eq!(response["id"], Value::Null);
+        assert_eq!(response["error"]["code"], PARSE_ERROR);
+    }
+}
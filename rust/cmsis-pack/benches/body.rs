@@ -0,0 +1,67 @@
+//! Throughput of reading a body through the concrete `Body::Chunks` variant,
+//! against the same chunks behind a boxed, error-mapping stream as bodies
+//! were read before `Body` existed
+//!
+//! Run with `cargo bench --bench body`.
+
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use cmsis_pack::update::{Body, ByteStream};
+use futures::executor::block_on;
+use futures::prelude::*;
+
+const CHUNKS: usize = 1 << 20;
+const ROUNDS: usize = 10;
+
+fn chunks() -> Vec<Bytes> {
+    let chunk = Bytes::from_static(&[0u8; 16]);
+    vec![chunk; CHUNKS]
+}
+
+fn drain(mut body: Body) -> usize {
+    block_on(async {
+        let mut size = 0;
+        while let Some(chunk) = body.chunk().await.unwrap() {
+            size += chunk.len();
+        }
+        size
+    })
+}
+
+fn drain_stream(mut body: ByteStream) -> usize {
+    block_on(async {
+        let mut size = 0;
+        while let Some(chunk) = body.next().await {
+            size += chunk.unwrap().len();
+        }
+        size
+    })
+}
+
+fn measure<B>(name: &str, make: impl Fn() -> B, drain: impl Fn(B) -> usize) {
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let body = make();
+        let start = Instant::now();
+        assert_eq!(drain(body), CHUNKS * 16);
+        best = best.min(start.elapsed());
+    }
+    println!(
+        "{:<8} {:>8.1} Mchunks/s",
+        name,
+        CHUNKS as f64 / best.as_secs_f64() / 1e6
+    );
+}
+
+fn main() {
+    measure("chunks", || Body::from(chunks()), drain);
+    measure(
+        "boxed",
+        || -> ByteStream {
+            let chunks = chunks().into_iter().map(Ok::<_, std::io::Error>);
+            Box::pin(stream::iter(chunks).map_err(anyhow::Error::from))
+        },
+        drain_stream,
+    );
+}
@@ -0,0 +1,281 @@
+//! Errors returned by the public API
+//!
+//! Internally the crate uses `anyhow`; at the API boundary failures are
+//! classified into [`Error`], which carries the URL, path or pack involved
+//! and keeps the underlying error available through
+//! [`std::error::Error::source`]. Match on its variants, or compare its
+//! [`code`](Error::code), to tell network, checksum, parse, I/O and
+//! configuration failures apart.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+pub type BoxError = Box<dyn StdError + Send + Sync + 'static>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading or writing a file failed
+    Io {
+        path: Option<PathBuf>,
+        source: io::Error,
+    },
+    /// Fetching a URL failed
+    Download { url: String, source: BoxError },
+    /// A download did not match its checksum, or had none to be verified
+    /// against when one was required
+    Checksum { url: String, source: BoxError },
+    /// A document or archive could not be parsed
+    Parse {
+        path: Option<PathBuf>,
+        source: BoxError,
+    },
+    /// A pack required by the operation is unavailable
+    Pack { pack: String, source: BoxError },
+    /// The operation was cancelled through its `CancellationToken`
+    Cancelled,
+    /// The operation needs to download `url`, which the
+    /// [`offline`](crate::update::DownloadConfig::offline) mode forbids
+    Offline { url: String },
+    /// Another process holds the lock file `path` of the pack store
+    Locked { path: PathBuf, pid: Option<u32> },
+    /// A setting of the [`DownloadConfig`](crate::update::DownloadConfig)
+    /// is invalid, such as a proxy URL or a certificate file
+    Config(BoxError),
+    /// Any failure not covered by the other variants
+    Other(BoxError),
+}
+
+impl Error {
+    /// A stable, machine-readable identifier of the kind of failure
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io { .. } => "io",
+            Error::Download { .. } => "download",
+            Error::Checksum { .. } => "checksum",
+            Error::Parse { .. } => "parse",
+            Error::Pack { .. } => "pack",
+            Error::Cancelled => "cancelled",
+            Error::Offline { .. } => "offline",
+            Error::Locked { .. } => "locked",
+            Error::Config(_) => "config",
+            Error::Other(_) => "other",
+        }
+    }
+
+    /// The [`code`](Error::code) `err` has once converted into an [`Error`]
+    ///
+    /// Useful to classify the failures reported to an `Observer`.
+    pub fn code_of(err: &anyhow::Error) -> &'static str {
+        if let Some(err) = err.downcast_ref::<Error>() {
+            return err.code();
+        }
+        if err.is::<io::Error>() {
+            return "io";
+        }
+        #[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+        if err.is::<reqwest::Error>()
+            || err.is::<crate::update::HttpStatus>()
+            || err.is::<crate::update::RedirectError>()
+        {
+            return "download";
+        }
+        #[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+        if err.is::<crate::update::ChecksumMismatch>() || err.is::<crate::update::Unverified>() {
+            return "checksum";
+        }
+        "other"
+    }
+
+    /// Whether a download failed because a connect, read or total timeout
+    /// expired, so that callers can retry it later or skip it
+    pub fn is_timeout(&self) -> bool {
+        let first: Option<&(dyn StdError + 'static)> = match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Download { source, .. }
+            | Error::Checksum { source, .. }
+            | Error::Parse { source, .. }
+            | Error::Pack { source, .. }
+            | Error::Config(source)
+            | Error::Other(source) => Some(source.as_ref()),
+            Error::Cancelled | Error::Offline { .. } | Error::Locked { .. } => None,
+        };
+        std::iter::successors(first, |&err| err.source()).any(timed_out)
+    }
+
+    /// Whether `err` is a timeout, as [`is_timeout`](Error::is_timeout) tells
+    /// once it is converted into an [`Error`]
+    pub fn is_timeout_of(err: &anyhow::Error) -> bool {
+        err.chain()
+            .any(|cause| match cause.downcast_ref::<Error>() {
+                Some(err) => err.is_timeout(),
+                None => timed_out(cause),
+            })
+    }
+
+    /// The URL of the failed download, if any
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            Error::Download { url, .. } | Error::Checksum { url, .. } | Error::Offline { url } => {
+                Some(url)
+            }
+            _ => None,
+        }
+    }
+
+    /// The file involved in the failure, if any
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            Error::Io { path, .. } | Error::Parse { path, .. } => path.as_ref(),
+            Error::Locked { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The pack involved in the failure, as `Vendor.Name`, if any
+    pub fn pack(&self) -> Option<&str> {
+        match self {
+            Error::Pack { pack, .. } => Some(pack),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io {
+                path: Some(path),
+                source,
+            } => write!(f, "{:?}: {}", path, source),
+            Error::Io { path: None, source } => write!(f, "{}", source),
+            Error::Download { url, source } => write!(f, "Download of {} failed: {}", url, source),
+            Error::Checksum { url, source } if url.is_empty() => write!(f, "{}", source),
+            Error::Checksum { url, source } => write!(f, "{}: {}", url, source),
+            Error::Parse {
+                path: Some(path),
+                source,
+            } => write!(f, "Could not parse {:?}: {}", path, source),
+            Error::Parse { path: None, source } => write!(f, "Could not parse: {}", source),
+            Error::Pack { pack, source } => write!(f, "Pack {}: {}", pack, source),
+            Error::Cancelled => f.write_str("Operation cancelled"),
+            Error::Offline { url } => write!(f, "Cannot download {} in offline mode", url),
+            Error::Locked {
+                path,
+                pid: Some(pid),
+            } => write!(
+                f,
+                "The pack store is locked by process {} ({:?})",
+                pid, path
+            ),
+            Error::Locked { path, pid: None } => {
+                write!(
+                    f,
+                    "The pack store is locked by another process ({:?})",
+                    path
+                )
+            }
+            Error::Config(source) => write!(f, "Invalid configuration: {}", source),
+            Error::Other(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Download { source, .. }
+            | Error::Checksum { source, .. }
+            | Error::Parse { source, .. }
+            | Error::Pack { source, .. }
+            | Error::Config(source) => Some(source.as_ref()),
+            Error::Cancelled | Error::Offline { .. } | Error::Locked { .. } => None,
+            Error::Other(source) => source.source(),
+        }
+    }
+}
+
+#[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+fn timed_out(err: &(dyn StdError + 'static)) -> bool {
+    match err.downcast_ref::<reqwest::Error>() {
+        Some(err) => err.is_timeout(),
+        None => err.is::<crate::update::TimedOut>(),
+    }
+}
+
+#[cfg(not(all(feature = "network", not(target_arch = "wasm32"))))]
+fn timed_out(_: &(dyn StdError + 'static)) -> bool {
+    false
+}
+
+impl From<io::Error> for Error {
+    fn from(source: io::Error) -> Self {
+        Error::Io { path: None, source }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<Error>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<io::Error>() {
+            Ok(source) => return Error::Io { path: None, source },
+            Err(err) => err,
+        };
+        #[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+        let err = match err.downcast::<reqwest::Error>() {
+            Ok(source) => {
+                return Error::Download {
+                    url: source.url().map(|url| url.to_string()).unwrap_or_default(),
+                    source: Box::new(source),
+                }
+            }
+            Err(err) => err,
+        };
+        #[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+        if err.is::<crate::update::ChecksumMismatch>() || err.is::<crate::update::Unverified>() {
+            return Error::Checksum {
+                url: String::new(),
+                source: err.into(),
+            };
+        }
+        Error::Other(err.into())
+    }
+}
+
+impl Error {
+    /// Attach `url` to a failed checksum, leaving other errors as they are
+    /// so that they are still retried or reported as vanished
+    #[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+    pub(crate) fn with_url(err: anyhow::Error, url: &str) -> anyhow::Error {
+        match Error::code_of(&err) {
+            "checksum" if !err.is::<Error>() => Error::Checksum {
+                url: url.to_string(),
+                source: err.into(),
+            }
+            .into(),
+            _ => err,
+        }
+    }
+
+    /// Attach `path` to an error about a file, turning it into [`Error::Parse`]
+    /// unless it is an I/O failure
+    pub(crate) fn with_path(err: anyhow::Error, path: PathBuf) -> Self {
+        match Error::from(err) {
+            Error::Io { source, .. } => Error::Io {
+                path: Some(path),
+                source,
+            },
+            Error::Other(source) => Error::Parse {
+                path: Some(path),
+                source,
+            },
+            err => err,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Board and SoC inventory
+//!
+//! A flat, YAML friendly catalogue of the devices and boards described by a
+//! set of packs, for board-catalog tooling outside the CMSIS ecosystem.
+
+use std::collections::BTreeMap;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::pdsc::{Device, Package};
+
+#[derive(Debug, Serialize)]
+pub struct InventoryMemory {
+    pub name: String,
+    pub start: u64,
+    pub size: u64,
+    pub access: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InventoryDebug {
+    pub core: String,
+    pub dp: u8,
+    pub ap: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InventorySoc {
+    pub vendor: Option<String>,
+    pub family: String,
+    pub pack: String,
+    pub cores: Vec<String>,
+    pub memories: Vec<InventoryMemory>,
+    pub debug: Vec<InventoryDebug>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InventoryBoard {
+    pub vendor: Option<String>,
+    pub pack: String,
+    pub socs: Vec<String>,
+    pub debug_interfaces: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Inventory {
+    pub socs: BTreeMap<String, InventorySoc>,
+    pub boards: BTreeMap<String, InventoryBoard>,
+}
+
+impl InventorySoc {
+    fn from_device(device: &Device, pack: String) -> Self {
+        let mut memories: Vec<_> = device
+            .memories
+            .0
+            .iter()
+            .map(|(name, mem)| {
+                let access = [
+                    (mem.access.read, 'r'),
+                    (mem.access.write, 'w'),
+                    (mem.access.execute, 'x'),
+                ];
+                InventoryMemory {
+                    name: name.clone(),
+                    start: mem.start,
+                    size: mem.size,
+                    access: access
+                        .iter()
+                        .filter(|(set, _)| *set)
+                        .map(|(_, c)| c)
+                        .collect(),
+                }
+            })
+            .collect();
+        memories.sort_by_key(|mem| mem.start);
+        Self {
+            vendor: device.vendor.clone(),
+            family: device.family.clone(),
+            pack,
+            cores: device
+                .processors
+                .iter()
+                .map(|p| p.core.to_string())
+                .collect(),
+            memories,
+            debug: device
+                .processors
+                .iter()
+                .map(|p| InventoryDebug {
+                    core: p.name.clone().unwrap_or_else(|| p.core.to_string()),
+                    dp: p.dp,
+                    ap: p.ap,
+                })
+                .collect(),
+        }
+    }
+}
+
+pub fn inventory<'a, I>(pdscs: I) -> Inventory
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    let mut inventory = Inventory::default();
+    for pdsc in pdscs {
+        let pack = format!("{}.{}", pdsc.vendor, pdsc.name);
+        for device in pdsc.devices.0.values() {
+            inventory.socs.insert(
+                device.name.clone(),
+                InventorySoc::from_device(device, pack.clone()),
+            );
+        }
+        for board in pdsc.boards.iter() {
+            inventory.boards.insert(
+                board.name.clone(),
+                InventoryBoard {
+                    vendor: board.vendor.clone(),
+                    pack: pack.clone(),
+                    socs: board.mounted_devices.clone(),
+                    debug_interfaces: board.debug_interfaces.clone(),
+                },
+            );
+        }
+    }
+    inventory
+}
+
+pub fn dumps_inventory<'a, I>(pdscs: I) -> Result<String, Error>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    Ok(serde_yaml::to_string(&inventory(pdscs))?)
+}
@@ -0,0 +1,303 @@
+//! Mbed OS `targets.json` fragments
+//!
+//! Mbed OS describes each target with its core, the region of flash and RAM
+//! the application may use and the DAPLink board IDs ("detect codes") that
+//! identify it. Everything except the detect codes is available in a pdsc;
+//! those are supplied by the caller, keyed by device name.
+//!
+//! The Mbed tools that predate this crate read devices from the `index.json`
+//! of arm-pack-manager instead, which [`pack_manager_index`] reproduces from
+//! a [`DeviceDatabase`].
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::log::PARSE;
+use crate::pdsc::{Core, DatabaseDevice, Device, DeviceDatabase, Memory, Package, FPU};
+
+#[derive(Debug, Serialize)]
+pub struct MbedTarget {
+    pub inherits: Vec<String>,
+    pub core: String,
+    pub device_name: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub detect_code: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mbed_rom_start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mbed_rom_size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mbed_ram_start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mbed_ram_size: Option<String>,
+}
+
+/// The Mbed OS name of a core, which folds in the presence of an FPU
+fn mbed_core(core: &Core, fpu: &FPU) -> Option<&'static str> {
+    let has_fpu = !matches!(fpu, FPU::None);
+    Some(match core {
+        Core::CortexM0 => "Cortex-M0",
+        Core::CortexM0Plus => "Cortex-M0+",
+        Core::CortexM1 => "Cortex-M1",
+        Core::CortexM3 => "Cortex-M3",
+        Core::CortexM4 if has_fpu => "Cortex-M4F",
+        Core::CortexM4 => "Cortex-M4",
+        Core::CortexM7 => match fpu {
+            FPU::None => "Cortex-M7",
+            FPU::SinglePrecision => "Cortex-M7F",
+            FPU::DoublePrecision => "Cortex-M7FD",
+        },
+        Core::CortexM23 => "Cortex-M23",
+        Core::CortexM33 if has_fpu => "Cortex-M33F",
+        Core::CortexM33 => "Cortex-M33",
+        Core::CortexM55 => "Cortex-M55",
+        _ => return None,
+    })
+}
+
+/// Pick the lowest addressed memory matching `pred`, preferring default ones
+fn pick_memory<F>(device: &Device, pred: F) -> Option<&Memory>
+where
+    F: Fn(&Memory) -> bool,
+{
+    device
+        .memories
+        .0
+        .values()
+        .filter(|mem| pred(mem))
+        .min_by_key(|mem| (!mem.default, mem.start))
+}
+
+fn hex(value: u64) -> String {
+    format!("0x{:X}", value)
+}
+
+/// Build the `targets.json` entry of a single device
+///
+/// Returns `None` for devices whose core Mbed OS does not support.
+pub fn mbed_target(device: &Device, detect_code: Vec<String>) -> Option<MbedTarget> {
+    let processor = device.processors.first()?;
+    let core = mbed_core(&processor.core, &processor.fpu)?;
+    let rom = device
+        .memories
+        .0
+        .values()
+        .find(|mem| mem.startup)
+        .or_else(|| pick_memory(device, |mem| mem.access.execute && !mem.access.write));
+    let ram = pick_memory(device, |mem| {
+        mem.access.write && !mem.access.peripheral && !mem.startup
+    });
+    Some(MbedTarget {
+        inherits: vec!["Target".to_string()],
+        core: core.to_string(),
+        device_name: device.name.clone(),
+        detect_code,
+        mbed_rom_start: rom.map(|mem| hex(mem.start)),
+        mbed_rom_size: rom.map(|mem| hex(mem.size)),
+        mbed_ram_start: ram.map(|mem| hex(mem.start)),
+        mbed_ram_size: ram.map(|mem| hex(mem.size)),
+    })
+}
+
+/// The Mbed OS target name of a device: upper case with `_` separators
+pub fn target_name(device: &str) -> String {
+    device
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+pub fn mbed_targets<'a, I>(
+    pdscs: I,
+    detect_codes: &HashMap<String, Vec<String>>,
+) -> BTreeMap<String, MbedTarget>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    pdscs
+        .into_iter()
+        .flat_map(|pdsc| pdsc.devices.0.values())
+        .filter_map(|device| {
+            let codes = detect_codes.get(&device.name).cloned().unwrap_or_default();
+            match mbed_target(device, codes) {
+                Some(target) => Some((target_name(&device.name), target)),
+                None => {
+                    tracing::debug!(target: PARSE, device = %device.name, "Core not supported by Mbed OS");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+pub fn dumps_mbed_targets<'a, I>(
+    pdscs: I,
+    detect_codes: &HashMap<String, Vec<String>>,
+) -> Result<String, Error>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    Ok(serde_json::to_string_pretty(&mbed_targets(
+        pdscs,
+        detect_codes,
+    ))?)
+}
+
+/// A region of the memory map of an arm-pack-manager device
+#[derive(Debug, Serialize)]
+pub struct PackManagerMemory {
+    pub start: String,
+    pub size: String,
+}
+
+/// A flash algorithm of an arm-pack-manager device
+#[derive(Debug, Serialize)]
+pub struct PackManagerAlgorithm {
+    pub start: String,
+    pub size: String,
+    pub ramstart: Option<String>,
+    pub ramsize: Option<String>,
+    pub default: u8,
+}
+
+/// A device as arm-pack-manager describes it in its `index.json`
+///
+/// Memories are keyed by their id, such as `IROM1`, and flash algorithms by
+/// their path inside the pack archive; addresses and sizes are hexadecimal.
+#[derive(Debug, Serialize)]
+pub struct PackManagerDevice {
+    pub pdsc_file: String,
+    pub pack_file: String,
+    pub memory: BTreeMap<String, PackManagerMemory>,
+    pub algorithm: BTreeMap<String, PackManagerAlgorithm>,
+    /// The SVD file of the first processor, inside the pack archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core: Option<String>,
+}
+
+fn pack_url(url: &str, file: String) -> String {
+    match url.ends_with('/') {
+        true => format!("{}{}", url, file),
+        false => format!("{}/{}", url, file),
+    }
+}
+
+/// Build the arm-pack-manager entry of a device of a database
+pub fn pack_manager_device(device: &DatabaseDevice) -> PackManagerDevice {
+    let DatabaseDevice { device, pack } = device;
+    let processor = device.processors.first();
+    PackManagerDevice {
+        pdsc_file: pack_url(&pack.url, format!("{}.{}.pdsc", pack.vendor, pack.name)),
+        pack_file: pack_url(
+            &pack.url,
+            format!("{}.{}.{}.pack", pack.vendor, pack.name, pack.version),
+        ),
+        memory: device
+            .memories
+            .0
+            .iter()
+            .map(|(id, mem)| {
+                let region = PackManagerMemory {
+                    start: hex(mem.start),
+                    size: hex(mem.size),
+                };
+                (id.clone(), region)
+            })
+            .collect(),
+        algorithm: device
+            .algorithms
+            .iter()
+            .map(|algo| {
+                let entry = PackManagerAlgorithm {
+                    start: hex(algo.start),
+                    size: hex(algo.size),
+                    ramstart: algo.ram_start.map(hex),
+                    ramsize: algo.ram_size.map(hex),
+                    default: algo.default.into(),
+                };
+                (algo.file_name.to_string_lossy().replace('\\', "/"), entry)
+            })
+            .collect(),
+        debug: processor.and_then(|p| p.svd.clone()),
+        core: processor.map(|p| p.core.to_string()),
+    }
+}
+
+/// The `index.json` of arm-pack-manager for the devices of `database`, keyed
+/// by device name
+pub fn pack_manager_index(database: &DeviceDatabase) -> BTreeMap<String, PackManagerDevice> {
+    database
+        .devices
+        .iter()
+        .map(|(name, device)| (name.clone(), pack_manager_device(device)))
+        .collect()
+}
+
+pub fn dumps_pack_manager_index(database: &DeviceDatabase) -> Result<String, Error> {
+    Ok(serde_json::to_string_pretty(&pack_manager_index(database))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pdsc::Devices;
+    use crate::utils::FromElem;
+
+    #[test]
+    fn mbed_target_from_device() {
+        let devices = Devices::from_string(
+            "<devices>
+               <family Dfamily=\"K60\" Dvendor=\"NXP:11\">
+                 <processor Dcore=\"Cortex-M4\" Dfpu=\"SP_FPU\"/>
+                 <device Dname=\"MK64FN1M0xxx12\">
+                   <memory id=\"IROM1\" start=\"0x00000000\" size=\"0x100000\" startup=\"1\" default=\"1\"/>
+                   <memory id=\"IRAM1\" start=\"0x20000000\" size=\"0x30000\" default=\"1\"/>
+                   <memory id=\"IRAM2\" start=\"0x1FFF0000\" size=\"0x10000\"/>
+                 </device>
+               </family>
+             </devices>",
+        )
+        .unwrap();
+        let device = &devices.0["MK64FN1M0xxx12"];
+        let target = mbed_target(device, vec!["0240".to_string()]).unwrap();
+        assert_eq!(target.core, "Cortex-M4F");
+        assert_eq!(target.mbed_rom_start.as_deref(), Some("0x0"));
+        assert_eq!(target.mbed_rom_size.as_deref(), Some("0x100000"));
+        assert_eq!(target.mbed_ram_start.as_deref(), Some("0x20000000"));
+        assert_eq!(target.mbed_ram_size.as_deref(), Some("0x30000"));
+        assert_eq!(target_name(&device.name), "MK64FN1M0XXX12");
+    }
+
+    #[test]
+    fn pack_manager_entry_from_database() {
+        let pdsc = Package::from_path(std::path::Path::new(
+            "../../tests/test-pack-index/MyVendor.MyPack.pdsc",
+        ))
+        .unwrap();
+        let database = DeviceDatabase::from_packages(&[pdsc]);
+        let index = pack_manager_index(&database);
+        let entry = &index["MyDevice"];
+        assert_eq!(
+            entry.pdsc_file,
+            "http://localhost:8001/tests/test-pack-index/MyVendor.MyPack.pdsc"
+        );
+        assert_eq!(
+            entry.pack_file,
+            "http://localhost:8001/tests/test-pack-index/MyVendor.MyPack.1.1.0.pack"
+        );
+        assert_eq!(entry.core.as_deref(), Some("Cortex-M0+"));
+        let algo = &entry.algorithm["flash/algo.FLM"];
+        assert_eq!((algo.start.as_str(), algo.size.as_str()), ("0x0", "0x1"));
+        assert!(entry.memory.is_empty() && entry.debug.is_none());
+    }
+}
@@ -0,0 +1,5 @@
+//! Conversions from parsed packs into the formats of other embedded tools
+
+pub mod inventory;
+pub mod mbed;
+pub mod pyocd;
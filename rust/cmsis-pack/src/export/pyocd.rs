@@ -0,0 +1,177 @@
+//! Target descriptions shaped like pyOCD's `cmsis_pack` module
+//!
+//! pyOCD builds a target from each device of a pack: its part number and
+//! families, a memory map whose flash regions reference flash algorithms,
+//! the SVD file and the default reset type. The flash algorithms themselves
+//! are ELF files inside the pack archive, referenced here by path.
+
+use std::path::PathBuf;
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::pdsc::{Algorithm, Device, Memory, Package};
+
+#[derive(Debug, Serialize)]
+pub struct PyocdAlgorithm {
+    pub file_name: PathBuf,
+    pub start: u64,
+    pub size: u64,
+    pub is_default: bool,
+    pub ram_start: Option<u64>,
+    pub ram_size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PyocdRegion {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub name: String,
+    pub start: u64,
+    pub length: u64,
+    pub access: String,
+    pub is_boot_memory: bool,
+    pub is_default: bool,
+    pub pname: Option<String>,
+    pub algorithms: Vec<PyocdAlgorithm>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PyocdTarget {
+    pub part_number: String,
+    pub vendor: Option<String>,
+    pub families: Vec<String>,
+    pub pack: String,
+    pub svd: Option<String>,
+    pub default_reset_type: Option<&'static str>,
+    pub memory_map: Vec<PyocdRegion>,
+}
+
+impl From<&Algorithm> for PyocdAlgorithm {
+    fn from(algo: &Algorithm) -> Self {
+        Self {
+            file_name: algo.file_name.clone(),
+            start: algo.start,
+            size: algo.size,
+            is_default: algo.default,
+            ram_start: algo.ram_start,
+            ram_size: algo.ram_size,
+        }
+    }
+}
+
+/// pyOCD's name for the reset sequence a debugger should use by default
+fn reset_type(sequence: &str) -> Option<&'static str> {
+    match sequence {
+        "ResetHardware" => Some("hw"),
+        "ResetSystem" => Some("sysresetreq"),
+        "ResetProcessor" => Some("vectreset"),
+        _ => None,
+    }
+}
+
+fn region(name: &str, mem: &Memory, algorithms: &[Algorithm]) -> PyocdRegion {
+    let end = mem.start + mem.size;
+    let algorithms: Vec<PyocdAlgorithm> = algorithms
+        .iter()
+        .filter(|algo| algo.start >= mem.start && algo.start < end)
+        .map(PyocdAlgorithm::from)
+        .collect();
+    let kind = if mem.access.peripheral {
+        "device"
+    } else if mem.access.write {
+        "ram"
+    } else if !algorithms.is_empty() {
+        "flash"
+    } else {
+        "rom"
+    };
+    let access = [
+        (mem.access.read, 'r'),
+        (mem.access.write, 'w'),
+        (mem.access.execute, 'x'),
+    ];
+    PyocdRegion {
+        kind,
+        name: name.to_string(),
+        start: mem.start,
+        length: mem.size,
+        access: access
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, c)| c)
+            .collect(),
+        is_boot_memory: mem.startup,
+        is_default: mem.default,
+        pname: mem.p_name.clone(),
+        algorithms,
+    }
+}
+
+pub fn pyocd_target(device: &Device, pack: String) -> PyocdTarget {
+    let mut memory_map: Vec<_> = device
+        .memories
+        .0
+        .iter()
+        .map(|(name, mem)| region(name, mem, &device.algorithms))
+        .collect();
+    memory_map.sort_by_key(|region| region.start);
+    let processor = device.processors.first();
+    PyocdTarget {
+        part_number: device.name.clone(),
+        vendor: device.vendor.clone(),
+        families: std::iter::once(device.family.clone())
+            .chain(device.sub_family.clone())
+            .collect(),
+        pack,
+        svd: processor.and_then(|p| p.svd.clone()),
+        default_reset_type: processor
+            .and_then(|p| p.default_reset_sequence.as_deref())
+            .and_then(reset_type),
+        memory_map,
+    }
+}
+
+pub fn pyocd_targets<'a, I>(pdscs: I) -> Vec<PyocdTarget>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    let mut targets: Vec<_> = pdscs
+        .into_iter()
+        .flat_map(|pdsc| {
+            let pack = format!("{}.{}", pdsc.vendor, pdsc.name);
+            pdsc.devices
+                .0
+                .values()
+                .map(move |device| pyocd_target(device, pack.clone()))
+        })
+        .collect();
+    targets.sort_by(|a, b| a.part_number.cmp(&b.part_number));
+    targets
+}
+
+pub fn dumps_pyocd_targets<'a, I>(pdscs: I) -> Result<String, Error>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    Ok(serde_json::to_string_pretty(&pyocd_targets(pdscs))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn pyocd_targets_from_pack() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../tests/test-pack-index");
+        let archived = Package::from_pack_path(&dir.join("MyVendor.MyPack.1.1.0.pack")).unwrap();
+        assert_eq!(archived.name, "MyPack");
+        let pack = Package::from_pack_path(&dir.join("MyVendor.MyPack.pdsc")).unwrap();
+        let targets = pyocd_targets(Some(&pack));
+        assert_eq!(targets.len(), 1);
+        assert_eq!(targets[0].part_number, "MyDevice");
+        assert_eq!(targets[0].pack, "MyVendor.MyPack");
+        assert_eq!(targets[0].families, vec!["MyFamily".to_string()]);
+    }
+}
@@ -1,13 +1,22 @@
+mod error;
+pub mod export;
+pub mod log;
 pub mod pack_index;
 pub mod pdsc;
+#[cfg(all(feature = "network", not(target_arch = "wasm32")))]
 pub mod update;
 #[macro_use]
 pub mod utils;
 
+pub use crate::error::{BoxError, Error};
+
+#[cfg(all(feature = "network", not(target_arch = "wasm32")))]
 extern crate futures;
-extern crate log;
 extern crate minidom;
+#[cfg(all(feature = "network", not(target_arch = "wasm32")))]
 extern crate reqwest;
 extern crate serde;
 extern crate serde_json;
+#[cfg(all(feature = "network", not(target_arch = "wasm32")))]
 extern crate tokio;
+extern crate tracing;
@@ -0,0 +1,12 @@
+//! Targets of the log events of the crate
+//!
+//! Events are emitted through `tracing` under one of these targets, by the
+//! area of the crate they come from, so that subscribers can filter them
+//! with directives such as `cmsis_pack::network=debug`.
+
+/// Requests, responses, retries, mirrors and rate limits of downloads
+pub const NETWORK: &str = "cmsis_pack::network";
+/// Parsing PDSC files, indexes and dates
+pub const PARSE: &str = "cmsis_pack::parse";
+/// Files of the pack store: claims, locks, caches and snapshots
+pub const STORE: &str = "cmsis_pack::store";
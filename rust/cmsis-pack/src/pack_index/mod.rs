@@ -1,8 +1,16 @@
+use crate::log::PARSE;
+use crate::utils::date::{parse_date, parse_timestamp};
 use crate::utils::prelude::*;
-use anyhow::Error;
+use crate::utils::Serialization;
+use anyhow::{format_err, Error};
+use chrono::{DateTime, NaiveDate, Utc};
+use minidom::quick_xml::events::{BytesStart, Event};
+use minidom::quick_xml::Reader;
 use minidom::Element;
+use serde::{Deserialize, Serialize};
+use std::io::BufRead;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PdscRef {
     pub url: String,
     pub vendor: String,
@@ -12,24 +20,88 @@ pub struct PdscRef {
     pub deprecated: Option<String>,
     pub replacement: Option<String>,
     pub size: Option<String>,
+    /// `date`, when it is a valid date
+    #[serde(default)]
+    pub released: Option<NaiveDate>,
+    /// `deprecated`, when it is a valid date
+    #[serde(default)]
+    pub deprecated_on: Option<NaiveDate>,
+    /// The digest of the PDSC file, as `sha256:HEX` or `sha1:HEX`
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// When the PDSC file last changed, as PackIndex 1.7 lists it
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    /// `timestamp`, when it is a valid timestamp
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pidx {
     pub url: String,
     pub vendor: String,
     pub date: Option<String>,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    /// `date`, when it is a valid date
+    #[serde(default)]
+    pub released: Option<NaiveDate>,
+    /// `timestamp`, when it is a valid timestamp
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Vidx {
     pub vendor: String,
     pub url: String,
+    /// The `schemaVersion` of the index, such as `1.7`
+    #[serde(default)]
+    pub schema_version: Option<String>,
     pub timestamp: Option<String>,
+    /// `timestamp`, when it is a valid timestamp
+    #[serde(default)]
+    pub updated: Option<DateTime<Utc>>,
     pub pdsc_index: Vec<PdscRef>,
     pub vendor_index: Vec<Pidx>,
 }
 
+/// Parse an optional value, logging the values that do not parse
+fn typed<T>(value: &Option<String>, parse: fn(&str) -> Option<T>) -> Option<T> {
+    let value = value.as_deref()?;
+    let parsed = parse(value);
+    if parsed.is_none() {
+        tracing::debug!(target: PARSE, value, "Ignoring a malformed date");
+    }
+    parsed
+}
+
+impl PdscRef {
+    fn with_dates(self) -> Self {
+        Self {
+            released: typed(&self.date, parse_date),
+            deprecated_on: typed(&self.deprecated, parse_date),
+            updated: typed(&self.timestamp, parse_timestamp),
+            ..self
+        }
+    }
+}
+
+impl Pidx {
+    fn with_dates(self) -> Self {
+        Self {
+            released: typed(&self.date, parse_date),
+            updated: typed(&self.timestamp, parse_timestamp),
+            ..self
+        }
+    }
+}
+
+impl Serialization for PdscRef {}
+impl Serialization for Pidx {}
+impl Serialization for Vidx {}
+
 impl FromElem for PdscRef {
     fn from_elem(e: &Element) -> Result<Self, Error> {
         assert_root_name(e, "pdsc")?;
@@ -42,7 +114,13 @@ impl FromElem for PdscRef {
             deprecated: attr_map(e, "deprecated", "pdsc").ok(),
             replacement: attr_map(e, "replacement", "pdsc").ok(),
             size: attr_map(e, "size", "pdsc").ok(),
-        })
+            released: None,
+            deprecated_on: None,
+            checksum: attr_map(e, "checksum", "pdsc").ok(),
+            timestamp: attr_map(e, "timestamp", "pdsc").ok(),
+            updated: None,
+        }
+        .with_dates())
     }
 }
 
@@ -53,19 +131,32 @@ impl FromElem for Pidx {
             url: attr_map(e, "url", "pidx")?,
             vendor: attr_map(e, "vendor", "pidx")?,
             date: attr_map(e, "date", "pidx").ok(),
-        })
+            timestamp: attr_map(e, "timestamp", "pidx").ok(),
+            released: None,
+            updated: None,
+        }
+        .with_dates())
     }
 }
 
 impl FromElem for Vidx {
+    /// Indexes list thousands of packs, so they are parsed from events
+    /// rather than through a DOM
+    fn from_reader<T: BufRead>(r: &mut Reader<T>) -> Result<Self, Error> {
+        vidx_from_events(r)
+    }
+
     fn from_elem(root: &Element) -> Result<Self, Error> {
         assert_root_name(root, "index")?;
         let vendor = child_text(root, "vendor", "index")?;
         let url = child_text(root, "url", "index")?;
+        let timestamp = get_child_no_ns(root, "timestamp").map(Element::text);
         Ok(Vidx {
             vendor,
             url,
-            timestamp: get_child_no_ns(root, "timestamp").map(Element::text),
+            schema_version: root.attr("schemaVersion").map(String::from),
+            updated: typed(&timestamp, parse_timestamp),
+            timestamp,
             vendor_index: get_child_no_ns(root, "vindex")
                 .map(|e| Pidx::vec_from_children(e.children()))
                 .unwrap_or_default(),
@@ -76,6 +167,217 @@ impl FromElem for Vidx {
     }
 }
 
+/// Streams the `<pdsc>` entries of a vidx or pidx document
+///
+/// Entries are parsed as they are read, without building the document in
+/// memory, so arbitrarily large indexes are handled in bounded memory.
+/// Entries missing a required attribute are logged and skipped, like
+/// [`Vidx`] does; malformed XML ends the iteration with an error.
+pub struct PdscRefs<R: BufRead> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl<R: BufRead> PdscRefs<R> {
+    pub fn new(reader: R) -> Self {
+        PdscRefs {
+            reader: Reader::from_reader(reader),
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+}
+
+impl PdscRefs<std::io::BufReader<std::fs::File>> {
+    pub fn from_path(p: &std::path::Path) -> Result<Self, Error> {
+        let fd = std::fs::File::open(p)?;
+        Ok(Self::new(std::io::BufReader::new(fd)))
+    }
+}
+
+/// The unescaped attributes of a start tag
+struct StartAttrs(Vec<(Vec<u8>, String)>);
+
+impl StartAttrs {
+    fn read<R: BufRead>(reader: &Reader<R>, e: &BytesStart) -> Result<Self, Error> {
+        let mut attrs = Vec::new();
+        for attr in e.attributes() {
+            let attr = attr?;
+            let value = attr.unescape_and_decode_value(reader)?;
+            attrs.push((attr.key.to_vec(), value));
+        }
+        Ok(StartAttrs(attrs))
+    }
+
+    fn get(&self, name: &str) -> Option<String> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name.as_bytes())
+            .map(|(_, value)| value.clone())
+    }
+
+    fn required(&self, name: &str, elemname: &str) -> Result<String, Error> {
+        self.get(name)
+            .ok_or_else(|| format_err!("{} not found in {} element", name, elemname))
+    }
+}
+
+fn pdsc_ref_from_start<R: BufRead>(reader: &Reader<R>, e: &BytesStart) -> Result<PdscRef, Error> {
+    let attrs = StartAttrs::read(reader, e)?;
+    Ok(PdscRef {
+        url: attrs.required("url", "pdsc")?,
+        vendor: attrs.required("vendor", "pdsc")?,
+        name: attrs.required("name", "pdsc")?,
+        version: attrs.required("version", "pdsc")?,
+        date: attrs.get("date"),
+        deprecated: attrs.get("deprecated"),
+        replacement: attrs.get("replacement"),
+        size: attrs.get("size"),
+        released: None,
+        deprecated_on: None,
+        checksum: attrs.get("checksum"),
+        timestamp: attrs.get("timestamp"),
+        updated: None,
+    }
+    .with_dates())
+}
+
+fn pidx_from_start<R: BufRead>(reader: &Reader<R>, e: &BytesStart) -> Result<Pidx, Error> {
+    let attrs = StartAttrs::read(reader, e)?;
+    Ok(Pidx {
+        url: attrs.required("url", "pidx")?,
+        vendor: attrs.required("vendor", "pidx")?,
+        date: attrs.get("date"),
+        timestamp: attrs.get("timestamp"),
+        released: None,
+        updated: None,
+    }
+    .with_dates())
+}
+
+/// Parse a vidx or pidx document from its events, without building a DOM
+///
+/// Behaves like [`Vidx::from_elem`]: the first `vendor`, `url` and
+/// `timestamp` children are kept, and invalid index entries are logged and
+/// skipped.
+fn vidx_from_events<R: BufRead>(reader: &mut Reader<R>) -> Result<Vidx, Error> {
+    let mut buf = Vec::new();
+    // Local names of the open elements, from the root down
+    let mut open: Vec<Vec<u8>> = Vec::new();
+    let mut text = String::new();
+    let (mut vendor, mut url, mut timestamp) = (None, None, None);
+    let mut schema_version = None;
+    let mut vendor_index = Vec::new();
+    let mut pdsc_index = Vec::new();
+    loop {
+        buf.clear();
+        let (start, is_empty) = match reader.read_event(&mut buf)? {
+            Event::Start(e) => (e.into_owned(), false),
+            Event::Empty(e) => (e.into_owned(), true),
+            Event::Text(e) | Event::CData(e) => {
+                if open.len() == 2 {
+                    text.push_str(&e.unescape_and_decode(reader)?);
+                }
+                continue;
+            }
+            Event::End(_) => {
+                if open.len() == 2 {
+                    let field = match open[1].as_slice() {
+                        b"vendor" => &mut vendor,
+                        b"url" => &mut url,
+                        b"timestamp" => &mut timestamp,
+                        _ => &mut None,
+                    };
+                    if field.is_none() {
+                        *field = Some(std::mem::take(&mut text));
+                    }
+                }
+                text.clear();
+                open.pop();
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+        let name = start.local_name().to_vec();
+        if open.is_empty() && name != b"index" {
+            return Err(format_err!(
+                "tried to parse element \"index\" from element \"{}\"",
+                String::from_utf8_lossy(&name)
+            ));
+        }
+        if open.is_empty() {
+            schema_version = StartAttrs::read(reader, &start)?.get("schemaVersion");
+        }
+        if open.len() == 2 && open[0] == b"index" {
+            match (open[1].as_slice(), name.as_slice()) {
+                (b"vindex", b"pidx") => {
+                    vendor_index.extend(pidx_from_start(reader, &start).ok_warn());
+                }
+                (b"pindex", b"pdsc") => {
+                    pdsc_index.extend(pdsc_ref_from_start(reader, &start).ok_warn());
+                }
+                _ => {}
+            }
+        }
+        if is_empty {
+            // An empty `<vendor/>` still has (empty) text
+            if open.len() == 1 {
+                let field = match name.as_slice() {
+                    b"vendor" => &mut vendor,
+                    b"url" => &mut url,
+                    b"timestamp" => &mut timestamp,
+                    _ => &mut None,
+                };
+                field.get_or_insert_with(String::new);
+            }
+        } else {
+            open.push(name);
+        }
+    }
+    if !open.is_empty() {
+        return Err(format_err!("unexpected end of the index document"));
+    }
+    let required = |value: Option<String>, name: &str| {
+        value
+            .ok_or_else(|| format_err!("child element \"{}\" not found in \"index\" element", name))
+    };
+    Ok(Vidx {
+        vendor: required(vendor, "vendor")?,
+        url: required(url, "url")?,
+        schema_version,
+        updated: typed(&timestamp, parse_timestamp),
+        timestamp,
+        vendor_index,
+        pdsc_index,
+    })
+}
+
+impl<R: BufRead> Iterator for PdscRefs<R> {
+    type Item = Result<PdscRef, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.buf.clear();
+            match self.reader.read_event(&mut self.buf) {
+                Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) if e.local_name() == b"pdsc" => {
+                    if let Some(pdsc) = pdsc_ref_from_start(&self.reader, e).ok_warn() {
+                        return Some(Ok(pdsc));
+                    }
+                }
+                Ok(Event::Eof) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+                Ok(_) => {}
+            }
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -118,6 +420,8 @@ mod test {
         assert_eq!(response.deprecated, Some(String::from("true")));
         assert_eq!(response.replacement, Some(String::from("Other")));
         assert_eq!(response.size, Some(String::from("8MB")));
+        assert_eq!(response.released, None);
+        assert_eq!(response.deprecated_on, None);
     }
 
     #[test]
@@ -153,7 +457,8 @@ mod test {
         assert_eq!(
             response.date,
             Some(String::from("Fri Sep  1 11:21:06 CDT 2017"))
-        )
+        );
+        assert_eq!(response.released, NaiveDate::from_ymd_opt(2017, 9, 1));
     }
 
     #[test]
@@ -200,5 +505,76 @@ mod test {
         let response = Vidx::from_string(good_string).unwrap();
         assert_eq!(response.vendor, String::from("Vendor"));
         assert_eq!(response.url, "Url");
+        let updated = response.updated.unwrap();
+        assert_eq!(updated.to_rfc3339(), "2017-09-01T18:26:41+00:00");
+    }
+
+    #[test]
+    fn pack_index_1_7_fields() {
+        let index = "<index schemaVersion=\"1.7\"><vendor>V</vendor><url>Url</url><pindex>
+                 <pdsc vendor=\"V\" url=\"Url\" name=\"A\" version=\"1.0.0\"
+                   timestamp=\"2023-05-02T10:30:00Z\" size=\"4096\" replacement=\"V.B\"/>
+               </pindex></index>";
+        let from_events = Vidx::from_string(index).unwrap();
+        let from_dom = Vidx::from_elem(&index.parse::<Element>().unwrap()).unwrap();
+        for vidx in [from_events, from_dom] {
+            assert_eq!(vidx.schema_version.as_deref(), Some("1.7"));
+            let pdsc = &vidx.pdsc_index[0];
+            assert_eq!(pdsc.timestamp.as_deref(), Some("2023-05-02T10:30:00Z"));
+            assert_eq!(
+                pdsc.updated.unwrap().to_rfc3339(),
+                "2023-05-02T10:30:00+00:00"
+            );
+            assert_eq!(pdsc.size.as_deref(), Some("4096"));
+            assert_eq!(pdsc.replacement.as_deref(), Some("V.B"));
+        }
+    }
+
+    #[test]
+    fn pdsc_refs_stream() {
+        let index = "<index><vendor>Vendor</vendor><url>Url</url>
+               <vindex><pidx vendor=\"Other\" url=\"Url\"/></vindex>
+               <pindex>
+                 <pdsc vendor=\"Vendor\" url=\"Url\" name=\"A\" version=\"1.0.0\"/>
+                 <pdsc vendor=\"Vendor\" url=\"Url\" name=\"Broken\"/>
+                 <pdsc vendor=\"Vendor\" url=\"Url\" name=\"B\" version=\"2.0.0\"></pdsc>
+               </pindex>
+             </index>";
+        let names: Vec<_> = PdscRefs::new(index.as_bytes())
+            .map(|pdsc| pdsc.unwrap().name)
+            .collect();
+        assert_eq!(names, ["A", "B"]);
+        let mut truncated = PdscRefs::new("<index><pindex></index>".as_bytes());
+        assert!(truncated.next().unwrap().is_err());
+        assert!(truncated.next().is_none());
+    }
+
+    #[test]
+    fn vidx_events_match_dom() {
+        let src = include_str!("../../benches/bench.vidx");
+        let from_events = Vidx::from_string(src).unwrap();
+        let from_dom = Vidx::from_elem(&src.parse::<Element>().unwrap()).unwrap();
+        assert_eq!(from_events.to_json().unwrap(), from_dom.to_json().unwrap());
+        assert!(!from_events.vendor_index.is_empty());
+        assert!(Vidx::from_string("<index><vendor>V</vendor><url>U</url>").is_err());
+    }
+
+    #[test]
+    fn vidx_serialization_round_trip() {
+        let good_string = "<index xmlns:xs=\"http://www.w3.org/2001/XMLSchema-instance\">
+               <vendor>Vendor</vendor>
+               <url>Url</url>
+               <pindex>
+                 <pdsc vendor=\"Vendor\" url=\"Url\" name=\"Name\" version=\"1.2.3\"/>
+               </pindex>
+             </index>";
+        let vidx = Vidx::from_string(good_string).unwrap();
+        let from_json = Vidx::from_json(&vidx.to_json().unwrap()).unwrap();
+        let from_yaml = Vidx::from_yaml(&vidx.to_yaml().unwrap()).unwrap();
+        for response in [from_json, from_yaml] {
+            assert_eq!(response.vendor, "Vendor");
+            assert_eq!(response.pdsc_index.len(), 1);
+            assert_eq!(response.pdsc_index[0].version, "1.2.3");
+        }
     }
 }
@@ -3,11 +3,12 @@ use std::str::FromStr;
 
 use anyhow::{format_err, Error};
 use minidom::Element;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
+use crate::log::PARSE;
 use crate::utils::prelude::*;
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum FileCategory {
     Doc,
     Header,
@@ -50,7 +51,7 @@ impl FromStr for FileCategory {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum FileAttribute {
     Config,
     Template,
@@ -67,15 +68,15 @@ impl FromStr for FileAttribute {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileRef {
     pub path: PathBuf,
-    category: FileCategory,
-    attr: Option<FileAttribute>,
+    pub category: FileCategory,
+    pub attr: Option<FileAttribute>,
     pub condition: Option<String>,
-    select: Option<String>,
-    src: Option<String>,
-    version: Option<String>,
+    pub select: Option<String>,
+    pub src: Option<String>,
+    pub version: Option<String>,
 }
 
 impl FromElem for FileRef {
@@ -96,6 +97,8 @@ impl FromElem for FileRef {
 #[derive(Debug, Clone, Serialize)]
 pub struct ComponentBuilder {
     pub vendor: Option<String>,
+    /// The `Cbundle` of the bundle the component belongs to
+    pub bundle: Option<String>,
     pub class: Option<String>,
     pub group: Option<String>,
     pub sub_group: Option<String>,
@@ -124,18 +127,20 @@ impl FromElem for ComponentBuilder {
         let sub_group_string = sub_group.clone().unwrap_or_else(|| "SubGroup".into());
         let files = get_child_no_ns(e, "files")
             .map(move |child| {
-                log::debug!(
-                    "Working on {}::{}::{}::{}",
-                    vendor_string,
-                    class_string,
-                    group_string,
-                    sub_group_string,
+                tracing::debug!(
+                    target: PARSE,
+                    vendor = %vendor_string,
+                    class = %class_string,
+                    group = %group_string,
+                    sub_group = %sub_group_string,
+                    "Working on component"
                 );
                 FileRef::vec_from_children(child.children())
             })
             .unwrap_or_default();
         Ok(Self {
             vendor,
+            bundle: None,
             class,
             group,
             sub_group,
@@ -155,31 +160,35 @@ impl FromElem for ComponentBuilder {
     }
 }
 
-#[derive(Debug, Serialize)]
+/// A bundle of components that are used together, sharing a class,
+/// version and vendor
+#[derive(Debug, Clone, Serialize)]
 pub struct Bundle {
-    name: String,
-    class: String,
-    version: String,
-    vendor: Option<String>,
-    description: String,
-    doc: String,
+    /// The `Cbundle` name
+    pub name: String,
+    pub class: String,
+    pub version: String,
+    pub vendor: Option<String>,
+    pub description: String,
+    pub doc: String,
+    #[serde(skip)]
     components: Vec<ComponentBuilder>,
 }
 
 impl Bundle {
-    pub fn into_components(self) -> Vec<ComponentBuilder> {
-        let class = self.class;
-        let version = self.version;
-        let vendor = self.vendor;
+    /// Move the components out of the bundle, filling in its class, version,
+    /// vendor and name where they leave them out
+    fn take_components(&mut self) -> Vec<ComponentBuilder> {
         if self.components.is_empty() {
-            log::warn!("Bundle should not be empty")
+            tracing::warn!(target: PARSE, "Bundle should not be empty")
         }
-        self.components
+        std::mem::take(&mut self.components)
             .into_iter()
             .map(|comp| ComponentBuilder {
-                class: comp.class.or_else(|| Some(class.clone())),
-                version: comp.version.or_else(|| Some(version.clone())),
-                vendor: comp.vendor.or_else(|| vendor.clone()),
+                class: comp.class.or_else(|| Some(self.class.clone())),
+                version: comp.version.or_else(|| Some(self.version.clone())),
+                vendor: comp.vendor.or_else(|| self.vendor.clone()),
+                bundle: Some(self.name.clone()),
                 ..comp
             })
             .collect()
@@ -217,41 +226,34 @@ impl FromElem for Bundle {
     }
 }
 
-fn child_to_component_iter(
-    e: &Element,
-) -> Result<Box<dyn Iterator<Item = ComponentBuilder>>, Error> {
-    match e.name() {
-        "bundle" => {
-            let bundle = Bundle::from_elem(e)?;
-            Ok(Box::new(bundle.into_components().into_iter()))
-        }
-        "component" => {
-            let component = ComponentBuilder::from_elem(e)?;
-            Ok(Box::new(Some(component).into_iter()))
-        }
-        _ => Err(format_err!(
-            "element of name {} is not allowed as a descendant of components",
-            e.name()
-        )),
-    }
-}
-
+/// The components of a PDSC file, with those of bundles moved out of them
 #[derive(Default)]
-pub struct ComponentBuilders(pub(crate) Vec<ComponentBuilder>);
+pub struct ComponentBuilders {
+    pub(crate) components: Vec<ComponentBuilder>,
+    pub(crate) bundles: Vec<Bundle>,
+}
 
 impl FromElem for ComponentBuilders {
     fn from_elem(e: &Element) -> Result<Self, Error> {
         assert_root_name(e, "components")?;
-        Ok(ComponentBuilders(
-            e.children()
-                .flat_map(move |c| match child_to_component_iter(c) {
-                    Ok(iter) => iter,
-                    Err(e) => {
-                        log::error!("when trying to parse component: {}", e);
-                        Box::new(None.into_iter())
-                    }
-                })
-                .collect(),
-        ))
+        let mut builders = ComponentBuilders::default();
+        for child in e.children() {
+            let res = match child.name() {
+                "bundle" => Bundle::from_elem(child).map(|mut bundle| {
+                    builders.components.extend(bundle.take_components());
+                    builders.bundles.push(bundle);
+                }),
+                "component" => ComponentBuilder::from_elem(child)
+                    .map(|component| builders.components.push(component)),
+                _ => Err(format_err!(
+                    "element of name {} is not allowed as a descendant of components",
+                    child.name()
+                )),
+            };
+            if let Err(err) = res {
+                tracing::error!(target: PARSE, "when trying to parse component: {}", err);
+            }
+        }
+        Ok(builders)
     }
 }
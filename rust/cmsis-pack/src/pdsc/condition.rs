@@ -1,28 +1,58 @@
 use anyhow::Error;
 use minidom::Element;
+use serde::Serialize;
 
+use crate::log::PARSE;
 use crate::utils::prelude::*;
 
+/// An `accept`, `deny` or `require` expression of a condition; it holds
+/// when all of the attributes it sets do
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct ConditionComponent {
     pub device_family: Option<String>,
     pub device_sub_family: Option<String>,
     pub device_variant: Option<String>,
     pub device_vendor: Option<String>,
     pub device_name: Option<String>,
+    pub processor: Option<String>,
+    pub core: Option<String>,
+    pub fpu: Option<String>,
+    pub mpu: Option<String>,
+    pub compiler: Option<String>,
+    /// Another condition that must hold
+    pub condition: Option<String>,
+    /// The component the condition depends on, by class, group, sub-group
+    /// and vendor
+    pub component_class: Option<String>,
+    pub component_group: Option<String>,
+    pub component_sub_group: Option<String>,
+    pub component_vendor: Option<String>,
 }
 
 impl FromElem for ConditionComponent {
     fn from_elem(e: &Element) -> Result<Self, Error> {
         Ok(ConditionComponent {
             device_family: attr_map(e, "Dfamily", "condition").ok(),
-            device_sub_family: attr_map(e, "Dsubfamily", "condition").ok(),
+            device_sub_family: attr_map(e, "DsubFamily", "condition").ok(),
             device_variant: attr_map(e, "Dvariant", "condition").ok(),
             device_vendor: attr_map(e, "Dvendor", "condition").ok(),
             device_name: attr_map(e, "Dname", "condition").ok(),
+            processor: attr_map(e, "Pname", "condition").ok(),
+            core: attr_map(e, "Dcore", "condition").ok(),
+            fpu: attr_map(e, "Dfpu", "condition").ok(),
+            mpu: attr_map(e, "Dmpu", "condition").ok(),
+            compiler: attr_map(e, "Tcompiler", "condition").ok(),
+            condition: attr_map(e, "condition", "condition").ok(),
+            component_class: attr_map(e, "Cclass", "condition").ok(),
+            component_group: attr_map(e, "Cgroup", "condition").ok(),
+            component_sub_group: attr_map(e, "Csub", "condition").ok(),
+            component_vendor: attr_map(e, "Cvendor", "condition").ok(),
         })
     }
 }
 
+/// A named condition of a PDSC file, which components and files refer to
+#[derive(Debug, Clone, Serialize)]
 pub struct Condition {
     pub id: String,
     pub accept: Vec<ConditionComponent>,
@@ -39,17 +69,17 @@ impl FromElem for Condition {
         for elem in e.children() {
             match elem.name() {
                 "accept" => {
-                    accept.push(ConditionComponent::from_elem(e)?);
+                    accept.push(ConditionComponent::from_elem(elem)?);
                 }
                 "deny" => {
-                    deny.push(ConditionComponent::from_elem(e)?);
+                    deny.push(ConditionComponent::from_elem(elem)?);
                 }
                 "require" => {
-                    require.push(ConditionComponent::from_elem(e)?);
+                    require.push(ConditionComponent::from_elem(elem)?);
                 }
                 "description" => {}
                 _ => {
-                    log::warn!("Found unkonwn element {} in components", elem.name());
+                    tracing::warn!(target: PARSE, "Found unkonwn element {} in conditions", elem.name());
                 }
             }
         }
@@ -62,7 +92,7 @@ impl FromElem for Condition {
     }
 }
 
-#[derive(Default)]
+#[derive(Debug, Default, Serialize)]
 pub struct Conditions(pub Vec<Condition>);
 
 impl FromElem for Conditions {
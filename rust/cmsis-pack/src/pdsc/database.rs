@@ -0,0 +1,689 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{rename, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{format_err, Error};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    parse_packages, write_dump, Algorithm, Board, Component, Device, DumpDevice, FromPack, Package,
+};
+use crate::log::STORE;
+use crate::utils::{compare_versions, pack_id, ResultLogExt};
+
+/// Bumped whenever the layout of [`DeviceDatabase`] changes, so that caches
+/// written by other versions are rebuilt instead of misread
+const CACHE_VERSION: u32 = 6;
+
+/// The pack a device of a [`DeviceDatabase`] comes from
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PackInfo {
+    pub vendor: String,
+    pub name: String,
+    pub version: String,
+    pub url: String,
+}
+
+impl fmt::Display for PackInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{} {}", self.vendor, self.name, self.version)
+    }
+}
+
+/// Which definition wins when several packs define a device of one name
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictPolicy {
+    /// The definition of the pack with the highest version
+    #[default]
+    PreferNewest,
+    /// The newest definition of a pack from this vendor, or the newest one
+    /// when no pack of the vendor defines the device
+    PreferVendor(String),
+    /// Refuse to build a database with conflicts
+    Error,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        match from {
+            "newest" => Ok(ConflictPolicy::PreferNewest),
+            "error" => Ok(ConflictPolicy::Error),
+            _ => match from.strip_prefix("vendor:") {
+                Some(vendor) if !vendor.is_empty() => {
+                    Ok(ConflictPolicy::PreferVendor(vendor.to_string()))
+                }
+                _ => Err(format_err!("Unknown conflict policy {}", from)),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DatabaseDevice {
+    pub device: Device,
+    pub pack: PackInfo,
+}
+
+/// The devices, boards and components of many packs, with devices and
+/// boards merged by name
+///
+/// Unlike [`Package`], a database can be cached in a compressed binary form,
+/// which answers queries without parsing any XML. Exact and prefix lookups
+/// of device names, ignoring case, are binary searches in a sorted table
+/// kept alongside the devices.
+///
+/// Only the highest version of each pack is used, whatever the order of
+/// its PDSC files. When several packs define a device of the same name, one
+/// definition is picked according to a [`ConflictPolicy`] and every
+/// colliding pack is listed in `conflicts`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DeviceDatabase {
+    pub devices: BTreeMap<String, DatabaseDevice>,
+    pub boards: BTreeMap<String, Board>,
+    /// The components of every pack, in the order of the packs
+    pub components: Vec<Component>,
+    /// The packs defining each device that is defined more than once
+    pub conflicts: BTreeMap<String, Vec<PackInfo>>,
+    /// Lowercase device names and the names they stand for, sorted
+    names: Vec<(String, String)>,
+}
+
+/// Name, modification time and size of a PDSC file a cache was built from
+type SourceKey = (String, u64, u32, u64);
+
+/// What a database keeps of a PDSC file
+#[derive(Clone, Serialize, Deserialize)]
+struct ParsedPack {
+    pack: PackInfo,
+    devices: Vec<(String, Device)>,
+    boards: Vec<Board>,
+    components: Vec<Component>,
+}
+
+impl ParsedPack {
+    fn new(pdsc: &Package) -> Self {
+        ParsedPack {
+            pack: PackInfo {
+                vendor: pdsc.vendor.clone(),
+                name: pdsc.name.clone(),
+                version: pdsc
+                    .releases
+                    .iter()
+                    .next()
+                    .map(|r| r.version.clone())
+                    .unwrap_or_default(),
+                url: pdsc.url.clone(),
+            },
+            devices: pdsc
+                .devices
+                .0
+                .iter()
+                .map(|(name, device)| (name.clone(), device.clone()))
+                .collect(),
+            boards: pdsc.boards.clone(),
+            components: pdsc.make_components(),
+        }
+    }
+}
+
+/// A cached database with the parsed contents of each of its PDSC files,
+/// which is rebuilt by parsing only the files that changed
+///
+/// Files that failed to parse are kept as `None`, so they are not parsed
+/// again until they change.
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    policy: ConflictPolicy,
+    packs: Vec<(SourceKey, Option<ParsedPack>)>,
+    database: DeviceDatabase,
+}
+
+fn source_keys(paths: &[PathBuf]) -> Vec<SourceKey> {
+    let mut keys: Vec<SourceKey> = paths
+        .iter()
+        .map(|path| {
+            let meta = path.metadata().ok();
+            let mtime = meta
+                .as_ref()
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            (
+                path.to_string_lossy().into_owned(),
+                mtime.as_secs(),
+                mtime.subsec_nanos(),
+                meta.map(|m| m.len()).unwrap_or_default(),
+            )
+        })
+        .collect();
+    keys.sort();
+    keys
+}
+
+impl DeviceDatabase {
+    /// The devices of `pdscs`, preferring the newest pack on conflicts
+    pub fn from_packages<'a, I>(pdscs: I) -> Self
+    where
+        I: IntoIterator<Item = &'a Package>,
+    {
+        Self::build(pdscs, &ConflictPolicy::default())
+    }
+
+    /// The devices of `pdscs`, resolving conflicts according to `policy`
+    pub fn with_policy<'a, I>(pdscs: I, policy: &ConflictPolicy) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = &'a Package>,
+    {
+        Self::build(pdscs, policy).check(policy)
+    }
+
+    fn check(self, policy: &ConflictPolicy) -> Result<Self, Error> {
+        if *policy == ConflictPolicy::Error && !self.conflicts.is_empty() {
+            let conflicts: Vec<String> = self.conflict_messages().collect();
+            return Err(format_err!("{}", conflicts.join("\n")));
+        }
+        Ok(self)
+    }
+
+    /// A line naming the colliding packs of each conflict
+    pub fn conflict_messages(&self) -> impl Iterator<Item = String> + '_ {
+        self.conflicts.iter().map(move |(name, packs)| {
+            let packs: Vec<String> = packs.iter().map(PackInfo::to_string).collect();
+            let used = self.devices.get(name).map(|device| device.pack.to_string());
+            format!(
+                "Device {} is defined by {}; using {}",
+                name,
+                packs.join(", "),
+                used.unwrap_or_default()
+            )
+        })
+    }
+
+    fn build<'a, I>(pdscs: I, policy: &ConflictPolicy) -> Self
+    where
+        I: IntoIterator<Item = &'a Package>,
+    {
+        let parsed: Vec<ParsedPack> = pdscs.into_iter().map(ParsedPack::new).collect();
+        Self::merge(&parsed, policy)
+    }
+
+    fn merge<'a, I>(packs: I, policy: &ConflictPolicy) -> Self
+    where
+        I: IntoIterator<Item = &'a ParsedPack>,
+    {
+        let packs: Vec<&ParsedPack> = packs.into_iter().collect();
+        let mut newest: HashMap<String, &str> = HashMap::new();
+        for parsed in packs.iter() {
+            let version = newest
+                .entry(pack_id(&parsed.pack.vendor, &parsed.pack.name))
+                .or_insert(&parsed.pack.version);
+            if compare_versions(&parsed.pack.version, version).is_gt() {
+                *version = &parsed.pack.version;
+            }
+        }
+        let mut database = DeviceDatabase::default();
+        let mut candidates: BTreeMap<String, Vec<DatabaseDevice>> = BTreeMap::new();
+        let mut seen = BTreeSet::new();
+        for parsed in packs {
+            let pack = &parsed.pack;
+            let id = pack_id(&pack.vendor, &pack.name);
+            if compare_versions(&pack.version, newest[&id]).is_lt() {
+                continue;
+            }
+            // The same release under another spelling of its vendor or name
+            // is not a conflict
+            if !seen.insert((id, pack.version.clone())) {
+                continue;
+            }
+            for (name, device) in &parsed.devices {
+                let device = DatabaseDevice {
+                    device: device.clone(),
+                    pack: pack.clone(),
+                };
+                candidates.entry(name.clone()).or_default().push(device);
+            }
+            for board in &parsed.boards {
+                database.boards.insert(board.name.clone(), board.clone());
+            }
+            database
+                .components
+                .extend(parsed.components.iter().cloned());
+        }
+        for (name, mut defs) in candidates {
+            if defs.len() > 1 {
+                let packs = defs.iter().map(|def| def.pack.clone()).collect();
+                database.conflicts.insert(name.clone(), packs);
+            }
+            // Stable, so that the last of equally recent packs wins
+            defs.sort_by(|a, b| compare_versions(&a.pack.version, &b.pack.version));
+            let preferred = match policy {
+                ConflictPolicy::PreferVendor(vendor) => defs
+                    .iter()
+                    .rposition(|def| def.pack.vendor.eq_ignore_ascii_case(vendor)),
+                _ => None,
+            };
+            let chosen = match preferred {
+                Some(at) => defs.swap_remove(at),
+                None => defs.pop().expect("every device has a definition"),
+            };
+            database.devices.insert(name, chosen);
+        }
+        database.names = database
+            .devices
+            .keys()
+            .map(|name| (name.to_lowercase(), name.clone()))
+            .collect();
+        database.names.sort();
+        database
+    }
+
+    /// The device called `name`, compared without regard to case when
+    /// there is no exact match
+    pub fn lookup(&self, name: &str) -> Option<&DatabaseDevice> {
+        self.devices.get(name).or_else(|| {
+            let lower = name.to_lowercase();
+            let at = self.names.partition_point(|(key, _)| *key < lower);
+            match self.names.get(at) {
+                Some((key, name)) if *key == lower => self.devices.get(name),
+                _ => None,
+            }
+        })
+    }
+
+    /// The flash algorithms of the device called `name`, as
+    /// [`lookup`](Self::lookup) finds it
+    ///
+    /// Their `file_name` is relative to the pack of the device.
+    pub fn flash_algorithms(&self, name: &str) -> Option<&[Algorithm]> {
+        self.lookup(name)
+            .map(|found| found.device.algorithms.as_slice())
+    }
+
+    /// The names of the devices starting with `prefix`, ignoring case, in
+    /// case-insensitive order
+    pub fn with_prefix(&self, prefix: &str) -> impl Iterator<Item = &str> {
+        let prefix = prefix.to_lowercase();
+        let at = self.names.partition_point(|(key, _)| *key < prefix);
+        self.names[at..]
+            .iter()
+            .take_while(move |(key, _)| key.starts_with(&prefix))
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// The devices of `vendor`, ignoring case, in order of their names
+    pub fn by_vendor<'a>(&'a self, vendor: &'a str) -> impl Iterator<Item = &'a DatabaseDevice> {
+        self.devices
+            .values()
+            .filter(move |device| device.vendor().eq_ignore_ascii_case(vendor))
+    }
+
+    /// The boards with a device mounted whose name starts with `device`,
+    /// ignoring case, in order of their names
+    pub fn boards_with_device<'a>(&'a self, device: &'a str) -> impl Iterator<Item = &'a Board> {
+        self.boards
+            .values()
+            .filter(move |board| board.mounts(device))
+    }
+
+    /// Load the database of `pdscs` from `cache`, or rewrite `cache` when
+    /// any of them changed since it was written, parsing only the changed
+    /// files
+    pub fn load_or_build(pdscs: &[PathBuf], cache: &Path) -> Self {
+        Self::load_or_build_cached(pdscs, cache, &ConflictPolicy::default())
+    }
+
+    /// Like [`load_or_build`](Self::load_or_build), resolving conflicts
+    /// according to `policy`
+    pub fn load_or_build_with_policy(
+        pdscs: &[PathBuf],
+        cache: &Path,
+        policy: &ConflictPolicy,
+    ) -> Result<Self, Error> {
+        Self::load_or_build_cached(pdscs, cache, policy).check(policy)
+    }
+
+    fn load_or_build_cached(pdscs: &[PathBuf], cache: &Path, policy: &ConflictPolicy) -> Self {
+        let sources = source_keys(pdscs);
+        let cached = CacheFile::load(cache).filter(|cached| cached.version == CACHE_VERSION);
+        let mut reusable: HashMap<SourceKey, Option<ParsedPack>> = HashMap::new();
+        if let Some(cached) = cached {
+            let cached_sources = cached.packs.iter().map(|(key, _)| key);
+            if cached_sources.eq(sources.iter()) && cached.policy == *policy {
+                return cached.database;
+            }
+            reusable.extend(cached.packs);
+        }
+
+        let changed = sources
+            .iter()
+            .filter(|key| !reusable.contains_key(*key))
+            .map(|(path, ..)| PathBuf::from(path));
+        let mut parsed: HashMap<PathBuf, Option<ParsedPack>> = parse_packages(changed)
+            .into_iter()
+            .map(|(path, pkg)| {
+                let pkg = pkg
+                    .map_err(|e| anyhow::format_err!("parsing {:?}: {}", path, e))
+                    .ok_warn();
+                (path, pkg.as_ref().map(ParsedPack::new))
+            })
+            .collect();
+        tracing::debug!(target: STORE, cache = ?cache, parsed = parsed.len(), "Rebuilding the device database");
+        let packs: Vec<(SourceKey, Option<ParsedPack>)> = sources
+            .into_iter()
+            .map(|key| {
+                let pack = match reusable.remove(&key) {
+                    Some(pack) => pack,
+                    None => parsed.remove(Path::new(&key.0)).flatten(),
+                };
+                (key, pack)
+            })
+            .collect();
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            policy: policy.clone(),
+            database: DeviceDatabase::merge(
+                packs.iter().filter_map(|(_, pack)| pack.as_ref()),
+                policy,
+            ),
+            packs,
+        };
+        if let Err(err) = file.save(cache) {
+            tracing::warn!(target: STORE, cache = ?cache, error = %err, "Could not write the device database");
+        }
+        file.database
+    }
+
+    /// A device in the format of [`dump_devices`](super::dump_devices)
+    pub fn dump_device(&self, name: &str) -> Option<DumpDevice<'_>> {
+        self.lookup(name).map(DatabaseDevice::dump)
+    }
+
+    /// Write the devices and boards like [`dump_devices`](super::dump_devices)
+    pub fn dump<P: AsRef<Path>>(
+        &self,
+        device_dest: Option<P>,
+        board_dest: Option<P>,
+    ) -> Result<(), Error> {
+        let devices: HashMap<_, _> = self
+            .devices
+            .iter()
+            .map(|(name, device)| (name.as_str(), device.dump()))
+            .collect();
+        let boards: HashMap<_, _> = self.boards.iter().collect();
+        write_dump(devices, boards, device_dest, board_dest)
+    }
+}
+
+impl DatabaseDevice {
+    /// The vendor of the device: the name part of its `Dvendor`, such as
+    /// `ARM` for `ARM:82`, or the vendor of its pack when it has none
+    pub fn vendor(&self) -> &str {
+        match &self.device.vendor {
+            Some(vendor) => vendor.split(':').next().unwrap_or(vendor),
+            None => &self.pack.vendor,
+        }
+    }
+
+    fn dump(&self) -> DumpDevice<'_> {
+        let from_pack = FromPack::new(
+            &self.pack.vendor,
+            &self.pack.name,
+            &self.pack.version,
+            &self.pack.url,
+        );
+        DumpDevice::from_device(&self.device, from_pack)
+    }
+}
+
+impl CacheFile {
+    fn load(cache: &Path) -> Option<Self> {
+        let fd = File::open(cache).ok()?;
+        bincode::deserialize_from(DeflateDecoder::new(BufReader::new(fd))).ok()
+    }
+
+    fn save(&self, cache: &Path) -> Result<(), Error> {
+        let temp = cache.with_extension("part");
+        let mut encoder =
+            DeflateEncoder::new(BufWriter::new(File::create(&temp)?), Compression::fast());
+        bincode::serialize_into(&mut encoder, self)?;
+        encoder.finish()?.flush()?;
+        rename(temp, cache)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pdsc::with_parse_threads;
+    use crate::utils::parse::FromElem;
+    use std::fs::{copy, create_dir_all};
+
+    #[test]
+    fn cache_is_reused_until_a_pdsc_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+        let pdsc = dir.join("MyVendor.MyPack.pdsc");
+        copy("../../tests/test-pack-index/MyVendor.MyPack.pdsc", &pdsc).unwrap();
+        let cache = dir.join("devices.bin");
+
+        let built = DeviceDatabase::load_or_build(std::slice::from_ref(&pdsc), &cache);
+        assert!(cache.exists());
+        assert!(!built.devices.is_empty());
+        let name = built.devices.keys().next().unwrap().clone();
+        assert!(built.dump_device(&name).is_some());
+
+        // A stale cache for the same sources is returned as is
+        let stale = CacheFile {
+            version: CACHE_VERSION,
+            policy: ConflictPolicy::default(),
+            packs: source_keys(std::slice::from_ref(&pdsc))
+                .into_iter()
+                .map(|key| (key, None))
+                .collect(),
+            database: DeviceDatabase::default(),
+        };
+        stale.save(&cache).unwrap();
+        let loaded = DeviceDatabase::load_or_build(std::slice::from_ref(&pdsc), &cache);
+        assert!(loaded.devices.is_empty());
+
+        // Until the pdsc is modified
+        let mtime = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+        File::options()
+            .write(true)
+            .open(&pdsc)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        let rebuilt = DeviceDatabase::load_or_build(&[pdsc], &cache);
+        assert_eq!(rebuilt.devices.len(), built.devices.len());
+    }
+
+    #[test]
+    fn only_changed_pdscs_are_parsed_again() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+        let source = std::fs::read_to_string("../../tests/test-pack-index/MyVendor.MyPack.pdsc")
+            .unwrap()
+            .replace(
+                "</package>",
+                "<components><component Cclass=\"Device\" Cgroup=\"Startup\">\
+                 <description>Startup</description></component></components></package>",
+            );
+        let first = dir.join("A.MyPack.pdsc");
+        let second = dir.join("B.MyPack.pdsc");
+        std::fs::write(&first, source.replace("MyVendor", "A")).unwrap();
+        std::fs::write(&second, source.replace("MyVendor", "B")).unwrap();
+        let pdscs = [first.clone(), second.clone()];
+        let cache = dir.join("devices.bin");
+        let built = DeviceDatabase::load_or_build(&pdscs, &cache);
+        assert!(!built.components.is_empty());
+
+        // Mark the cached contents of the first file, which is not parsed
+        // again when only the second one changes
+        let mut file = CacheFile::load(&cache).unwrap();
+        let (_, pack) = &mut file.packs[0];
+        pack.as_mut().unwrap().pack.url = "cached".to_string();
+        file.save(&cache).unwrap();
+        std::fs::write(&second, source.replace("MyVendor", "C")).unwrap();
+        let rebuilt = DeviceDatabase::load_or_build(&pdscs, &cache);
+        let packs: BTreeSet<_> = rebuilt
+            .conflicts
+            .values()
+            .flatten()
+            .map(|pack| (pack.vendor.as_str(), pack.url.as_str()))
+            .collect();
+        assert!(packs.contains(&("A", "cached")));
+        assert!(packs
+            .iter()
+            .any(|&(vendor, url)| vendor == "C" && url != "cached"));
+    }
+
+    #[test]
+    fn parse_threads_keep_the_order() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+        let source =
+            std::fs::read_to_string("../../tests/test-pack-index/MyVendor.MyPack.pdsc").unwrap();
+        let paths: Vec<PathBuf> = (0..8)
+            .map(|n| {
+                let vendor = format!("V{}", n);
+                let path = dir.join(format!("{}.MyPack.pdsc", vendor));
+                std::fs::write(&path, source.replace("MyVendor", &vendor)).unwrap();
+                path
+            })
+            .collect();
+        let vendors = |threads| -> Vec<String> {
+            with_parse_threads(threads, || parse_packages(paths.clone()))
+                .into_iter()
+                .map(|(_, pkg)| pkg.unwrap().vendor)
+                .collect()
+        };
+        let expected: Vec<String> = (0..8).map(|n| format!("V{}", n)).collect();
+        assert_eq!(vendors(None), expected);
+        assert_eq!(vendors(Some(1)), expected);
+        assert_eq!(vendors(Some(3)), expected);
+    }
+
+    #[test]
+    fn conflicts_follow_the_policy() {
+        let pdsc =
+            std::fs::read_to_string("../../tests/test-pack-index/MyVendor.MyPack.pdsc").unwrap();
+        let variant = |vendor: &str, version: &str| {
+            let pdsc = pdsc
+                .replace(
+                    "<vendor>MyVendor</vendor>",
+                    &format!("<vendor>{}</vendor>", vendor),
+                )
+                .replace("version=\"1.1.0\"", &format!("version=\"{}\"", version));
+            Package::from_string(&pdsc).unwrap()
+        };
+        let packs = [variant("A", "1.10.0"), variant("B", "1.9.0")];
+        let name = packs[0].devices.0.keys().next().unwrap().clone();
+
+        let newest = DeviceDatabase::from_packages(&packs);
+        assert_eq!(newest.devices[&name].pack.vendor, "A");
+        assert_eq!(newest.conflicts[&name].len(), 2);
+        let message = newest.conflict_messages().next().unwrap();
+        assert!(message.contains("A.MyPack 1.10.0") && message.contains("B.MyPack 1.9.0"));
+
+        let policy: ConflictPolicy = "vendor:B".parse().unwrap();
+        let vendor = DeviceDatabase::with_policy(&packs, &policy).unwrap();
+        assert_eq!(vendor.devices[&name].pack.vendor, "B");
+
+        let policy: ConflictPolicy = "error".parse().unwrap();
+        assert!(DeviceDatabase::with_policy(&packs, &policy).is_err());
+        assert!(DeviceDatabase::with_policy(&packs[..1], &policy).is_ok());
+
+        // Older versions of a pack are left out, whatever their order
+        let versions = [variant("A", "1.10.0"), variant("A", "1.9.0")];
+        let database = DeviceDatabase::with_policy(&versions, &policy).unwrap();
+        assert_eq!(database.devices[&name].pack.version, "1.10.0");
+    }
+
+    #[test]
+    fn lookups_ignore_case() {
+        let path = Path::new("../../tests/test-pack-index/MyVendor.MyPack.pdsc");
+        let pdsc = Package::from_path(path).unwrap();
+        let name = pdsc.devices.0.keys().next().unwrap().clone();
+        let database = DeviceDatabase::from_packages([&pdsc]);
+        assert!(database.lookup(&name.to_uppercase()).is_some());
+        assert!(database.lookup(&format!("{}x", name)).is_none());
+        let prefix = &name[..name.len() / 2];
+        let found: Vec<_> = database.with_prefix(&prefix.to_lowercase()).collect();
+        assert!(found.contains(&name.as_str()));
+        assert!(found
+            .iter()
+            .all(|found| found.to_lowercase().starts_with(&prefix.to_lowercase())));
+        assert_eq!(database.with_prefix("\u{10ffff}").count(), 0);
+
+        let algorithms = database.flash_algorithms(&name.to_lowercase()).unwrap();
+        assert_eq!(algorithms[0].file_name, Path::new("flash/algo.FLM"));
+        assert!(database.flash_algorithms(&format!("{}x", name)).is_none());
+    }
+
+    #[test]
+    fn boards_are_linked_to_devices() {
+        let pdsc = Package::from_string(
+            r#"<package><vendor>V</vendor><name>P</name><description/><url/>
+            <boards>
+              <board vendor="V" name="Disco" revision="Rev.C">
+                <description>A discovery kit</description>
+                <mountedDevice deviceIndex="0" Dvendor="STMicroelectronics:13" Dname="STM32F407VGTx"/>
+                <feature type="ODbg" n="1" name="On-board ST-LINK/V2"/>
+                <feature type="PWR" n="3.3"/>
+                <debugInterface adapter="ST-Link" connector="Mini-USB"/>
+              </board>
+              <board name="Nucleo">
+                <mountedDevice Dname="STM32F411RETx"/>
+              </board>
+            </boards></package>"#,
+        )
+        .unwrap();
+        let board = &pdsc.boards[0];
+        assert_eq!(board.revision.as_deref(), Some("Rev.C"));
+        assert_eq!(board.description.as_deref(), Some("A discovery kit"));
+        assert_eq!(board.debug_interfaces, vec!["ST-Link"]);
+        assert_eq!(board.features.len(), 2);
+        assert_eq!(board.features[0].kind, "ODbg");
+        assert_eq!(board.features[1].n, Some(3.3));
+
+        let database = DeviceDatabase::from_packages([&pdsc]);
+        let names = |device| -> Vec<_> {
+            database
+                .boards_with_device(device)
+                .map(|board| board.name.as_str())
+                .collect()
+        };
+        assert_eq!(names("stm32f407vg"), vec!["Disco"]);
+        assert_eq!(names("STM32F4"), vec!["Disco", "Nucleo"]);
+        assert!(names("STM32L4").is_empty());
+    }
+
+    #[test]
+    fn devices_by_vendor() {
+        let path = Path::new("../../tests/test-pack-index/MyVendor.MyPack.pdsc");
+        let pdsc = Package::from_path(path).unwrap();
+        let mut database = DeviceDatabase::from_packages([&pdsc]);
+        let count = database.devices.len();
+        assert_eq!(database.by_vendor("myvendor").count(), count);
+
+        let device = database.devices.values_mut().next().unwrap();
+        device.device.vendor = Some("ARM:82".to_string());
+        assert_eq!(device.vendor(), "ARM");
+        assert_eq!(database.by_vendor("arm").count(), 1);
+        assert_eq!(database.by_vendor("MyVendor").count(), count - 1);
+    }
+}
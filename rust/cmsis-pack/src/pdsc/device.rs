@@ -1,11 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 use crate::utils::prelude::*;
+use crate::utils::Serialization;
 use anyhow::{format_err, Error};
 use minidom::Element;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Core {
@@ -87,6 +89,47 @@ impl FromStr for Core {
     }
 }
 
+impl fmt::Display for Core {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Core::CortexM0 => "Cortex-M0",
+            Core::CortexM0Plus => "Cortex-M0+",
+            Core::CortexM1 => "Cortex-M1",
+            Core::CortexM3 => "Cortex-M3",
+            Core::CortexM4 => "Cortex-M4",
+            Core::CortexM7 => "Cortex-M7",
+            Core::CortexM23 => "Cortex-M23",
+            Core::CortexM33 => "Cortex-M33",
+            Core::CortexM35P => "Cortex-M35P",
+            Core::CortexM55 => "Cortex-M55",
+            Core::CortexM85 => "Cortex-M85",
+            Core::StarMC1 => "Star-MC1",
+            Core::SC000 => "SC000",
+            Core::SC300 => "SC300",
+            Core::ARMV8MBL => "ARMV8MBL",
+            Core::ARMV8MML => "ARMV8MML",
+            Core::ARMV81MML => "ARMV81MML",
+            Core::CortexR4 => "Cortex-R4",
+            Core::CortexR5 => "Cortex-R5",
+            Core::CortexR7 => "Cortex-R7",
+            Core::CortexR8 => "Cortex-R8",
+            Core::CortexA5 => "Cortex-A5",
+            Core::CortexA7 => "Cortex-A7",
+            Core::CortexA8 => "Cortex-A8",
+            Core::CortexA9 => "Cortex-A9",
+            Core::CortexA15 => "Cortex-A15",
+            Core::CortexA17 => "Cortex-A17",
+            Core::CortexA32 => "Cortex-A32",
+            Core::CortexA35 => "Cortex-A35",
+            Core::CortexA53 => "Cortex-A53",
+            Core::CortexA57 => "Cortex-A57",
+            Core::CortexA72 => "Cortex-A72",
+            Core::CortexA73 => "Cortex-A73",
+            Core::Any => "*",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FPU {
     None,
@@ -468,8 +511,13 @@ impl FromElem for MemElem {
     }
 }
 
+/// Memories by name, serialized in order of their names
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Memories(pub HashMap<String, Memory>);
+pub struct Memories(#[serde(serialize_with = "sorted")] pub HashMap<String, Memory>);
+
+fn sorted<S: Serializer>(memories: &HashMap<String, Memory>, ser: S) -> Result<S::Ok, S::Error> {
+    memories.iter().collect::<BTreeMap<_, _>>().serialize(ser)
+}
 
 fn merge_memories(lhs: Memories, rhs: &Memories) -> Memories {
     let rhs: Vec<_> = rhs
@@ -552,7 +600,7 @@ struct DeviceBuilder<'dom> {
     sub_family: Option<&'dom str>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Device {
     pub name: String,
     pub memories: Memories,
@@ -762,6 +810,12 @@ fn parse_family(e: &Element) -> Result<Vec<Device>, Error> {
                     .map(|prc| family_device.add_processor(prc));
                 Vec::new()
             }
+            "debug" => {
+                FromElem::from_elem(child)
+                    .ok_warn()
+                    .map(|debug| family_device.add_debug(debug));
+                Vec::new()
+            }
             _ => Vec::new(),
         })
         .collect::<Vec<_>>();
@@ -771,9 +825,12 @@ fn parse_family(e: &Element) -> Result<Vec<Device>, Error> {
         .collect()
 }
 
-#[derive(Default, Serialize)]
+#[derive(Default, Serialize, Deserialize)]
 pub struct Devices(pub HashMap<String, Device>);
 
+impl Serialization for Device {}
+impl Serialization for Devices {}
+
 impl FromElem for Devices {
     fn from_elem(e: &Element) -> Result<Self, Error> {
         e.children()
@@ -2,23 +2,38 @@ use minidom::Element;
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::Read;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+use crate::log::PARSE;
+use crate::utils::date::parse_date;
 use crate::utils::prelude::*;
+use crate::utils::{compare_versions, Serialization};
 use anyhow::{format_err, Error};
+use chrono::NaiveDate;
 
 mod component;
 mod condition;
+mod database;
 mod device;
-pub use component::{ComponentBuilders, FileRef};
-pub use condition::{Condition, Conditions};
-pub use device::{Algorithm, Core, Device, Devices, Memories, Processor};
+mod resolve;
+mod search;
+pub use component::{Bundle, ComponentBuilders, FileAttribute, FileCategory, FileRef};
+pub use condition::{Condition, ConditionComponent, Conditions};
+pub use database::{ConflictPolicy, DatabaseDevice, DeviceDatabase, PackInfo};
+pub use device::{Algorithm, Core, Device, Devices, Memories, Memory, Processor, FPU, MPU};
+pub use resolve::{resolve_components, ConditionResolver, TargetContext};
+pub use search::{search_packages, PackMatch};
 
 pub struct Release {
     pub version: String,
     pub text: String,
+    pub date: Option<NaiveDate>,
+    /// When the pack was deprecated
+    pub deprecated: Option<NaiveDate>,
+    /// The pack that replaces a deprecated one, as `Vendor.Name`
+    pub replacement: Option<String>,
 }
 
 impl FromElem for Release {
@@ -27,6 +42,9 @@ impl FromElem for Release {
         Ok(Self {
             version: attr_map(e, "version", "release")?,
             text: e.text(),
+            date: e.attr("date").and_then(parse_date),
+            deprecated: e.attr("deprecated").and_then(parse_date),
+            replacement: e.attr("replacement").map(str::to_string),
         })
     }
 }
@@ -38,6 +56,10 @@ impl Releases {
     pub fn latest_release(&self) -> &Release {
         &self.0[0]
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Release> {
+        self.0.iter()
+    }
 }
 
 impl FromElem for Releases {
@@ -107,11 +129,17 @@ pub struct Package {
     pub vendor: String,
     pub url: String,
     pub license: Option<String>,
+    /// The words of the `keywords` element, for searches
+    pub keywords: Vec<String>,
     components: ComponentBuilders,
     pub releases: Releases,
     pub conditions: Conditions,
     pub devices: Devices,
     pub boards: Vec<Board>,
+    /// The packs this one requires, from its `<requirements>`
+    pub requirements: Vec<PackRequirement>,
+    /// The component interfaces the pack defines, from its `<apis>`
+    pub apis: Vec<Api>,
 }
 
 impl FromElem for Package {
@@ -121,7 +149,8 @@ impl FromElem for Package {
         let description: String = child_text(e, "description", "package")?;
         let vendor: String = child_text(e, "vendor", "package")?;
         let url: String = child_text(e, "url", "package")?;
-        log::debug!("Working on {}::{}", vendor, name,);
+        let _span = tracing::debug_span!(target: PARSE, "package", vendor = %vendor, pack = %name)
+            .entered();
         let components = get_child_no_ns(e, "components")
             .and_then(|c| ComponentBuilders::from_elem(c).ok_warn())
             .unwrap_or_default();
@@ -137,6 +166,16 @@ impl FromElem for Package {
         let boards = get_child_no_ns(e, "boards")
             .map(|c| Board::vec_from_children(c.children()))
             .unwrap_or_default();
+        let keywords = get_child_no_ns(e, "keywords")
+            .map(|c| c.children().map(|keyword| keyword.text()).collect())
+            .unwrap_or_default();
+        let requirements = get_child_no_ns(e, "requirements")
+            .and_then(|c| get_child_no_ns(c, "packages"))
+            .map(|c| PackRequirement::vec_from_children(c.children()))
+            .unwrap_or_default();
+        let apis = get_child_no_ns(e, "apis")
+            .map(|c| Api::vec_from_children(c.children()))
+            .unwrap_or_default();
         Ok(Self {
             name,
             description,
@@ -144,24 +183,144 @@ impl FromElem for Package {
             url,
             components,
             license: child_text(e, "license", "package").ok(),
+            keywords,
             releases,
             conditions,
             devices,
             boards,
+            requirements,
+            apis,
+        })
+    }
+}
+
+/// A pack another one requires, with the range of versions it accepts
+///
+/// The `version` attribute is written `MIN` for that version or any newer
+/// one, or `MIN:MAX` for the versions between both, inclusive.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PackRequirement {
+    pub vendor: String,
+    pub name: String,
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl PackRequirement {
+    /// Whether the requirement accepts the release `version`
+    pub fn accepts(&self, version: &str) -> bool {
+        let range = match &self.version {
+            Some(range) => range,
+            None => return true,
+        };
+        let (min, max) = match range.split_once(':') {
+            Some((min, max)) => (min, Some(max).filter(|max| !max.is_empty())),
+            None => (range.as_str(), None),
+        };
+        compare_versions(version, min).is_ge()
+            && max.is_none_or(|max| compare_versions(version, max).is_le())
+    }
+}
+
+impl FromElem for PackRequirement {
+    fn from_elem(e: &Element) -> Result<Self, Error> {
+        assert_root_name(e, "package")?;
+        Ok(Self {
+            vendor: attr_map(e, "vendor", "package")?,
+            name: attr_map(e, "name", "package")?,
+            version: attr_map(e, "version", "package").ok(),
+        })
+    }
+}
+
+/// A component interface a pack defines, which components of other packs
+/// may implement
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Api {
+    pub class: String,
+    pub group: String,
+    pub version: String,
+    /// Whether only one component may implement the interface at once
+    pub exclusive: bool,
+    pub description: Option<String>,
+}
+
+impl FromElem for Api {
+    fn from_elem(e: &Element) -> Result<Self, Error> {
+        assert_root_name(e, "api")?;
+        Ok(Self {
+            class: attr_map(e, "Cclass", "api")?,
+            group: attr_map(e, "Cgroup", "api")?,
+            version: attr_map(e, "Capiversion", "api")?,
+            exclusive: !matches!(e.attr("exclusive"), Some("0" | "false")),
+            description: child_text(e, "description", "api").ok(),
         })
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// A development board described by a pack
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Board {
-    name: String,
-    mounted_devices: Vec<String>,
+    pub name: String,
+    #[serde(default)]
+    pub vendor: Option<String>,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// The names of the devices mounted on the board
+    pub mounted_devices: Vec<String>,
+    /// The debug adapters of the board, such as `ST-Link` or `CMSIS-DAP`
+    #[serde(default)]
+    pub debug_interfaces: Vec<String>,
+    #[serde(default)]
+    pub features: Vec<BoardFeature>,
+}
+
+/// A `<feature>` of a board, such as its on-board debugger or its LEDs
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct BoardFeature {
+    /// The kind of feature, such as `ODbg` or `LED`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// How many of the feature the board has, or its value, like a voltage
+    #[serde(default)]
+    pub n: Option<f64>,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+impl Board {
+    /// Whether a device whose name starts with `device`, ignoring case, is
+    /// mounted on the board
+    pub fn mounts(&self, device: &str) -> bool {
+        let device = device.to_lowercase();
+        self.mounted_devices
+            .iter()
+            .any(|mounted| mounted.to_lowercase().starts_with(&device))
+    }
+}
+
+impl Serialization for Board {}
+impl Serialization for Component {}
+
+impl FromElem for BoardFeature {
+    fn from_elem(e: &Element) -> Result<Self, Error> {
+        Ok(Self {
+            kind: attr_map(e, "type", "feature")?,
+            n: attr_parse(e, "n", "feature").ok(),
+            name: attr_map(e, "name", "feature").ok(),
+        })
+    }
 }
 
 impl FromElem for Board {
     fn from_elem(e: &Element) -> Result<Self, Error> {
         Ok(Self {
             name: attr_map(e, "name", "board")?,
+            vendor: attr_map(e, "vendor", "board").ok(),
+            revision: attr_map(e, "revision", "board").ok(),
+            description: child_text(e, "description", "board").ok(),
             mounted_devices: e
                 .children()
                 .flat_map(|c| match c.name() {
@@ -169,13 +328,28 @@ impl FromElem for Board {
                     _ => None,
                 })
                 .collect(),
+            debug_interfaces: e
+                .children()
+                .flat_map(|c| match c.name() {
+                    "debugInterface" => attr_map(c, "adapter", "debugInterface").ok(),
+                    _ => None,
+                })
+                .collect(),
+            features: e
+                .children()
+                .filter(|c| c.name() == "feature")
+                .flat_map(|c| BoardFeature::from_elem(c).ok_warn())
+                .collect(),
         })
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Component {
     pub vendor: String,
+    /// The bundle the component belongs to
+    #[serde(default)]
+    pub bundle: Option<String>,
     pub class: String,
     pub group: String,
     pub sub_group: Option<String>,
@@ -194,13 +368,39 @@ pub struct Component {
 type Components = Vec<Component>;
 
 impl Package {
+    /// Parse the description of a pack archive or of a bare pdsc file
+    ///
+    /// Archives (`.pack` or `.zip`) carry their pdsc at the top level.
+    pub fn from_pack_path(path: &Path) -> Result<Self, crate::Error> {
+        Self::read_pack(path).map_err(|err| crate::Error::with_path(err, path.to_path_buf()))
+    }
+
+    fn read_pack(path: &Path) -> Result<Self, Error> {
+        let is_archive = path
+            .extension()
+            .is_some_and(|ext| ext == "pack" || ext == "zip");
+        if !is_archive {
+            return Self::from_path(path);
+        }
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+        let pdsc_name = archive
+            .file_names()
+            .find(|name| name.ends_with(".pdsc") && !name.contains('/'))
+            .map(str::to_string)
+            .ok_or_else(|| format_err!("No pdsc found in {:?}", path))?;
+        let mut contents = String::new();
+        archive.by_name(&pdsc_name)?.read_to_string(&mut contents)?;
+        Self::from_string(&contents)
+    }
+
     pub fn make_components(&self) -> Components {
         self.components
-            .0
+            .components
             .clone()
             .into_iter()
             .map(|comp| Component {
                 vendor: comp.vendor.unwrap_or_else(|| self.vendor.clone()),
+                bundle: comp.bundle,
                 class: comp.class.unwrap(),
                 group: comp.group.unwrap(),
                 sub_group: comp.sub_group,
@@ -220,11 +420,17 @@ impl Package {
             .collect()
     }
 
+    /// The bundles of the pack; their components are among those of
+    /// [`make_components`](Package::make_components)
+    pub fn bundles(&self) -> &[Bundle] {
+        &self.components.bundles
+    }
+
     pub fn make_condition_lookup<'a>(&'a self) -> HashMap<&'a str, &'a Condition> {
         let mut map = HashMap::with_capacity(self.conditions.0.iter().count());
         for cond in self.conditions.0.iter() {
             if let Some(dup) = map.insert(cond.id.as_str(), cond) {
-                log::warn!("Duplicate Condition found {}", dup.id);
+                tracing::warn!(target: PARSE, "Duplicate Condition found {}", dup.id);
             }
         }
         map
@@ -244,6 +450,90 @@ impl Package {
             .collect()
     }
 }
+
+/// Parse PDSC files one at a time as the iterator is advanced
+///
+/// Only the current document is held in memory, which keeps long-running
+/// services from holding the whole pack store at once.
+pub fn iter_packages<I>(paths: I) -> impl Iterator<Item = (PathBuf, Result<Package, Error>)>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    paths.into_iter().map(|path| {
+        let pkg = Package::from_path(&path);
+        (path, pkg)
+    })
+}
+
+/// Parse many PDSC files, spreading the work across CPU cores
+///
+/// Results are in the order of `paths`. Without the `parallel` feature, and
+/// on wasm32, the files are parsed one after the other.
+pub fn parse_packages<I>(paths: I) -> Vec<(PathBuf, Result<Package, Error>)>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    let paths: Vec<PathBuf> = paths.into_iter().collect();
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    {
+        use rayon::prelude::*;
+        paths
+            .into_par_iter()
+            .map(|path| {
+                let pkg = Package::from_path(&path);
+                (path, pkg)
+            })
+            .collect()
+    }
+    #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+    {
+        iter_packages(paths).collect()
+    }
+}
+
+/// Run `f` with [`parse_packages`] spreading its work over `threads`
+/// threads instead of one per CPU core
+///
+/// `None` keeps the default. Results stay in the order of the paths
+/// whatever the number of threads. Without the `parallel` feature, and on
+/// wasm32, `f` simply runs.
+pub fn with_parse_threads<R, F>(threads: Option<usize>, f: F) -> R
+where
+    F: FnOnce() -> R + Send,
+    R: Send,
+{
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    if let Some(threads) = threads {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads.max(1))
+            .build();
+        match pool {
+            Ok(pool) => return pool.install(f),
+            Err(err) => {
+                tracing::warn!(target: PARSE, "Could not start {} parser threads: {}", threads, err)
+            }
+        }
+    }
+    #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+    let _ = threads;
+    f()
+}
+
+/// The devices of PDSC files, parsing each file when its devices are reached
+///
+/// Files that fail to parse are logged and skipped.
+pub fn iter_devices<I>(paths: I) -> impl Iterator<Item = Device>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    iter_packages(paths).flat_map(|(path, pkg)| {
+        pkg.map_err(|e| format_err!("parsing {:?}: {}", path, e))
+            .ok_warn()
+            .into_iter()
+            .flat_map(|pkg| pkg.devices.0.into_values())
+    })
+}
+
 pub fn dump_devices<'a, P: AsRef<Path>, I: IntoIterator<Item = &'a Package>>(
     pdscs: I,
     device_dest: Option<P>,
@@ -254,6 +544,22 @@ pub fn dump_devices<'a, P: AsRef<Path>, I: IntoIterator<Item = &'a Package>>(
         .iter()
         .flat_map(|pdsc| pdsc.make_dump_devices().into_iter())
         .collect::<HashMap<_, _>>();
+    let boards = pdscs
+        .iter()
+        .flat_map(|pdsc| pdsc.boards.iter())
+        .map(|b| (&b.name, b))
+        .collect::<HashMap<_, _>>();
+    write_dump(devices, boards, device_dest, board_dest)
+}
+
+/// Write devices and boards to their files, merged with the ones already
+/// there, or print the devices when no file is given
+fn write_dump<P: AsRef<Path>>(
+    devices: HashMap<&str, DumpDevice>,
+    boards: HashMap<&String, &Board>,
+    device_dest: Option<P>,
+    board_dest: Option<P>,
+) -> Result<(), Error> {
     match device_dest {
         Some(to_file) => {
             if !devices.is_empty() {
@@ -277,13 +583,11 @@ pub fn dump_devices<'a, P: AsRef<Path>, I: IntoIterator<Item = &'a Package>>(
                 }
             }
         }
-        None => println!("{}", &serde_json::to_string_pretty(&devices).unwrap()),
+        None => {
+            let devices: BTreeMap<_, _> = devices.iter().collect();
+            println!("{}", &serde_json::to_string_pretty(&devices).unwrap())
+        }
     }
-    let boards = pdscs
-        .iter()
-        .flat_map(|pdsc| pdsc.boards.iter())
-        .map(|b| (&b.name, b))
-        .collect::<HashMap<_, _>>();
     match board_dest {
         Some(to_file) => {
             let mut file_contents = Vec::new();
@@ -305,7 +609,10 @@ pub fn dump_devices<'a, P: AsRef<Path>, I: IntoIterator<Item = &'a Package>>(
                 println!("Could not open file {:?}", to_file.as_ref());
             }
         }
-        None => println!("{}", &serde_json::to_string_pretty(&devices).unwrap()),
+        None => {
+            let devices: BTreeMap<_, _> = devices.iter().collect();
+            println!("{}", &serde_json::to_string_pretty(&devices).unwrap())
+        }
     }
     Ok(())
 }
@@ -320,3 +627,30 @@ where
         .collect::<Vec<_>>();
     Ok(serde_json::to_string_pretty(&components)?)
 }
+
+/// The devices of `pdscs` as a JSON object keyed by device name, in the
+/// format of [`dump_devices`]
+pub fn dumps_devices<'a, I>(pdscs: I) -> Result<String, Error>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    let devices = pdscs
+        .into_iter()
+        .flat_map(|pdsc| pdsc.make_dump_devices().into_iter())
+        .collect::<BTreeMap<_, _>>();
+    Ok(serde_json::to_string_pretty(&devices)?)
+}
+
+pub fn dumps_device<'a, I>(pdscs: I, name: &str) -> Result<Option<String>, Error>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    let found = pdscs
+        .into_iter()
+        .flat_map(|pdsc| pdsc.make_dump_devices().into_iter())
+        .find(|(dev_name, _)| *dev_name == name);
+    match found {
+        Some((_, device)) => Ok(Some(serde_json::to_string_pretty(&device)?)),
+        None => Ok(None),
+    }
+}
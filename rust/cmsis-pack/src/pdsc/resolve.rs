@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+
+use super::{Component, Condition, ConditionComponent, Core, Device, Package, FPU, MPU};
+use crate::log::PARSE;
+
+/// The device, processor and compiler of a build, which the conditions of
+/// components and files are evaluated against
+///
+/// Attributes left `None` are unknown, and conditions on them are not
+/// checked.
+#[derive(Clone, Debug, Default)]
+pub struct TargetContext {
+    pub device_vendor: Option<String>,
+    pub device_family: Option<String>,
+    pub device_sub_family: Option<String>,
+    /// The name of the device, or of its variant
+    pub device_name: Option<String>,
+    pub processor: Option<String>,
+    pub core: Option<Core>,
+    pub fpu: Option<FPU>,
+    pub mpu: Option<MPU>,
+    /// The compiler as conditions name it: `GCC`, `ARMCC`, `IAR`, ...
+    pub compiler: Option<String>,
+}
+
+impl TargetContext {
+    /// The context of `device` and its first processor, built with `compiler`
+    pub fn for_device(device: &Device, compiler: Option<&str>) -> Self {
+        let processor = device.processors.first();
+        TargetContext {
+            device_vendor: device.vendor.clone(),
+            device_family: Some(device.family.clone()),
+            device_sub_family: device.sub_family.clone(),
+            device_name: Some(device.name.clone()),
+            processor: processor.and_then(|p| p.name.clone()),
+            core: processor.map(|p| p.core.clone()),
+            fpu: processor.map(|p| p.fpu.clone()),
+            mpu: processor.map(|p| p.mpu.clone()),
+            compiler: compiler.map(str::to_string),
+        }
+    }
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any text and `?`
+/// for any one character
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.chars().next() {
+        None => name.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+            name.char_indices()
+                .map(|(at, _)| at)
+                .chain(Some(name.len()))
+                .any(|at| wildcard_match(rest, &name[at..]))
+        }
+        Some(first) => {
+            let mut chars = name.chars();
+            match chars.next() {
+                Some(c) if first == '?' || c == first => {
+                    wildcard_match(&pattern[first.len_utf8()..], chars.as_str())
+                }
+                _ => false,
+            }
+        }
+    }
+}
+
+fn fpu_matches(want: &str, have: &FPU) -> bool {
+    matches!(
+        (want, have),
+        ("FPU", FPU::SinglePrecision | FPU::DoublePrecision)
+            | ("SP_FPU", FPU::SinglePrecision)
+            | ("DP_FPU", FPU::DoublePrecision)
+            | ("NO_FPU", FPU::None)
+    )
+}
+
+fn mpu_matches(want: &str, have: &MPU) -> bool {
+    matches!(
+        (want, have),
+        ("MPU", MPU::Present) | ("NO_MPU", MPU::NotPresent)
+    )
+}
+
+/// Whether an attribute the expression sets matches the target, or `None`
+/// when either leaves it out
+fn check<T>(
+    want: &Option<String>,
+    have: Option<T>,
+    matches: impl Fn(&str, T) -> bool,
+) -> Option<bool> {
+    Some(matches(want.as_deref()?, have?))
+}
+
+/// Evaluates the conditions of a pack against a target
+pub struct ConditionResolver<'a> {
+    conditions: HashMap<&'a str, &'a Condition>,
+    target: &'a TargetContext,
+}
+
+impl<'a> ConditionResolver<'a> {
+    pub fn new(pdsc: &'a Package, target: &'a TargetContext) -> Self {
+        ConditionResolver {
+            conditions: pdsc.make_condition_lookup(),
+            target,
+        }
+    }
+
+    /// Whether the condition `id` holds for the target
+    ///
+    /// A condition holds when all of its `require` expressions, at least one
+    /// of its `accept` expressions if it has any, and none of its `deny`
+    /// expressions hold. Expressions on other components state dependencies
+    /// rather than facts about the target and are left out. Unknown and
+    /// recursive conditions never hold.
+    pub fn holds(&self, id: &str) -> bool {
+        self.holds_within(id, &mut Vec::new())
+    }
+
+    fn holds_within(&self, id: &str, visiting: &mut Vec<&'a str>) -> bool {
+        let cond = match self.conditions.get(id) {
+            Some(cond) => *cond,
+            None => {
+                tracing::warn!(target: PARSE, "Unknown condition {}", id);
+                return false;
+            }
+        };
+        if visiting.contains(&cond.id.as_str()) {
+            tracing::warn!(target: PARSE, "Condition {} refers to itself", id);
+            return false;
+        }
+        visiting.push(&cond.id);
+        let held = cond
+            .require
+            .iter()
+            .all(|expr| self.matches(expr, visiting).unwrap_or(true))
+            && (cond.accept.is_empty()
+                || cond
+                    .accept
+                    .iter()
+                    .any(|expr| self.matches(expr, visiting).unwrap_or(true)))
+            && !cond
+                .deny
+                .iter()
+                .any(|expr| self.matches(expr, visiting).unwrap_or(false));
+        visiting.pop();
+        held
+    }
+
+    /// Whether `expr` holds for the target, or `None` when it checks nothing
+    /// the target knows
+    fn matches(&self, expr: &ConditionComponent, visiting: &mut Vec<&'a str>) -> Option<bool> {
+        let target = self.target;
+        let vendor = |vendor: &str| vendor.split(':').next().unwrap_or_default().to_string();
+        let same = |want: &str, have: &str| want == have;
+        let checks = [
+            check(
+                &expr.device_vendor,
+                target.device_vendor.as_deref(),
+                |w, h| vendor(w) == vendor(h),
+            ),
+            check(&expr.device_family, target.device_family.as_deref(), same),
+            check(
+                &expr.device_sub_family,
+                target.device_sub_family.as_deref(),
+                same,
+            ),
+            check(
+                &expr.device_name,
+                target.device_name.as_deref(),
+                wildcard_match,
+            ),
+            check(
+                &expr.device_variant,
+                target.device_name.as_deref(),
+                wildcard_match,
+            ),
+            check(&expr.processor, target.processor.as_deref(), same),
+            check(&expr.core, target.core.as_ref(), |w, h| w == h.to_string()),
+            check(&expr.fpu, target.fpu.as_ref(), fpu_matches),
+            check(&expr.mpu, target.mpu.as_ref(), mpu_matches),
+            check(&expr.compiler, target.compiler.as_deref(), |w, h| {
+                w.eq_ignore_ascii_case(h)
+            }),
+            expr.condition
+                .as_deref()
+                .map(|id| self.holds_within(id, visiting)),
+        ];
+        checks
+            .iter()
+            .flatten()
+            .copied()
+            .reduce(|all, one| all && one)
+    }
+
+    /// Whether a component or file with the condition `id`, if any, applies
+    fn applies(&self, id: Option<&str>) -> bool {
+        id.is_none_or(|id| self.holds(id))
+    }
+}
+
+/// The components of `pdsc` whose conditions hold for `target`, each with
+/// only the files whose conditions hold
+pub fn resolve_components(pdsc: &Package, target: &TargetContext) -> Vec<Component> {
+    let resolver = ConditionResolver::new(pdsc, target);
+    pdsc.make_components()
+        .into_iter()
+        .filter(|comp| resolver.applies(comp.condition.as_deref()))
+        .map(|comp| Component {
+            files: comp
+                .files
+                .into_iter()
+                .filter(|file| resolver.applies(file.condition.as_deref()))
+                .collect(),
+            ..comp
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::parse::FromElem;
+
+    const PDSC: &str = r#"<package>
+      <vendor>V</vendor><name>P</name><description/><url>http://example.com/</url>
+      <releases><release version="1.0.0"/></releases>
+      <conditions>
+        <condition id="CM4">
+          <accept Dcore="Cortex-M4"/>
+          <accept Dcore="Cortex-M7"/>
+        </condition>
+        <condition id="CM4 GCC">
+          <require condition="CM4"/>
+          <require Tcompiler="GCC"/>
+          <deny Dname="STM32F4*" Dfpu="NO_FPU"/>
+        </condition>
+        <condition id="Other">
+          <require Dvendor="Other:1"/>
+          <require Cclass="CMSIS" Cgroup="CORE"/>
+        </condition>
+        <condition id="Loop"><require condition="Loop"/></condition>
+      </conditions>
+      <components>
+        <component Cclass="Device" Cgroup="Startup" condition="CM4">
+          <description/>
+          <files>
+            <file category="sourceAsm" name="gcc/startup.s" condition="CM4 GCC"/>
+            <file category="sourceAsm" name="arm/startup.s" condition="Other"/>
+            <file category="header" name="device.h"/>
+          </files>
+        </component>
+        <bundle Cbundle="Drivers" Cclass="Driver" Cversion="2.0.0">
+          <description/><doc/>
+          <component Cgroup="UART" condition="Loop"><description/></component>
+          <component Cgroup="SPI"><description/></component>
+        </bundle>
+      </components>
+    </package>"#;
+
+    #[test]
+    fn conditions_select_components_and_files() {
+        let pdsc = Package::from_string(PDSC).unwrap();
+        assert_eq!(pdsc.bundles()[0].name, "Drivers");
+        let target = TargetContext {
+            device_vendor: Some("V:99".to_string()),
+            device_name: Some("STM32F407".to_string()),
+            core: Some(Core::CortexM4),
+            fpu: Some(FPU::SinglePrecision),
+            compiler: Some("GCC".to_string()),
+            ..Default::default()
+        };
+        let resolver = ConditionResolver::new(&pdsc, &target);
+        assert!(resolver.holds("CM4 GCC"));
+        assert!(!resolver.holds("Other"));
+        assert!(!resolver.holds("Loop"));
+        assert!(!resolver.holds("Missing"));
+
+        let components = resolve_components(&pdsc, &target);
+        let names: Vec<_> = components
+            .iter()
+            .map(|comp| (comp.group.as_str(), comp.bundle.as_deref()))
+            .collect();
+        assert_eq!(names, vec![("Startup", None), ("SPI", Some("Drivers"))]);
+        let files: Vec<_> = components[0]
+            .files
+            .iter()
+            .map(|f| f.path.to_str().unwrap())
+            .collect();
+        assert_eq!(files, vec!["gcc/startup.s", "device.h"]);
+        assert_eq!(components[1].class, "Driver");
+
+        let without_fpu = TargetContext {
+            fpu: Some(FPU::None),
+            ..target
+        };
+        assert!(!ConditionResolver::new(&pdsc, &without_fpu).holds("CM4 GCC"));
+        let unknown_compiler = TargetContext {
+            compiler: None,
+            ..without_fpu
+        };
+        let resolver = ConditionResolver::new(&pdsc, &unknown_compiler);
+        assert!(!resolver.holds("CM4 GCC"));
+        assert!(resolver.holds("CM4"));
+    }
+}
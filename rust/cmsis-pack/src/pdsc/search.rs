@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use super::Package;
+use crate::utils::{compare_versions, pack_id};
+
+/// A pack found by [`search_packages`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PackMatch {
+    pub vendor: String,
+    pub name: String,
+    /// The latest release among the matching PDSC files of the pack
+    pub version: String,
+    /// The first line of the pack's description
+    pub description: String,
+    /// The device names that matched, if any
+    pub devices: Vec<String>,
+}
+
+/// The packs whose name, vendor, description, keywords or device names
+/// contain `query`, ignoring case, in order of their vendor and name
+///
+/// Several PDSC files of one pack are reported once, with the latest
+/// version among them.
+pub fn search_packages<'a, I>(pdscs: I, query: &str) -> Vec<PackMatch>
+where
+    I: IntoIterator<Item = &'a Package>,
+{
+    let query = query.to_lowercase();
+    let found = |text: &str| text.to_lowercase().contains(&query);
+    let mut matches: BTreeMap<String, PackMatch> = BTreeMap::new();
+    for pdsc in pdscs {
+        let mut devices: Vec<String> = pdsc
+            .devices
+            .0
+            .keys()
+            .filter(|name| found(name))
+            .cloned()
+            .collect();
+        devices.sort();
+        let matched = found(&pdsc.name)
+            || found(&pdsc.vendor)
+            || found(&pdsc.description)
+            || pdsc.keywords.iter().any(|keyword| found(keyword))
+            || !devices.is_empty();
+        if !matched {
+            continue;
+        }
+        let version = pdsc.releases.latest_release().version.clone();
+        let candidate = PackMatch {
+            vendor: pdsc.vendor.clone(),
+            name: pdsc.name.clone(),
+            version,
+            description: pdsc
+                .description
+                .trim()
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            devices,
+        };
+        let id = pack_id(&pdsc.vendor, &pdsc.name);
+        match matches.get(&id) {
+            Some(known) if compare_versions(&known.version, &candidate.version).is_ge() => {}
+            _ => {
+                matches.insert(id, candidate);
+            }
+        }
+    }
+    matches.into_values().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::utils::parse::FromElem;
+
+    #[test]
+    fn packs_match_on_names_keywords_and_devices() {
+        let source =
+            std::fs::read_to_string("../../tests/test-pack-index/MyVendor.MyPack.pdsc").unwrap();
+        let pdsc = Package::from_string(&source).unwrap();
+        let device = pdsc.devices.0.keys().next().unwrap().clone();
+        let newer = Package::from_string(
+            &source
+                .replace("version=\"1.1.0\"", "version=\"1.10.0\"")
+                .replace(
+                    "</package>",
+                    "<keywords><keyword>Sensor</keyword></keywords></package>",
+                ),
+        )
+        .unwrap();
+        let pdscs = [pdsc, newer];
+
+        let by_name = search_packages(&pdscs, "mypack");
+        assert_eq!(by_name.len(), 1);
+        assert_eq!(by_name[0].version, "1.10.0");
+        assert!(by_name[0].devices.is_empty());
+
+        let by_device = search_packages(&pdscs, &device.to_uppercase());
+        assert_eq!(by_device[0].devices, vec![device]);
+
+        let by_keyword = search_packages(&pdscs, "sensor");
+        assert_eq!(by_keyword.len(), 1);
+        assert!(search_packages(&pdscs, "no such pack").is_empty());
+    }
+}
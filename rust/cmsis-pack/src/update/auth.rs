@@ -0,0 +1,103 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{format_err, Error};
+use reqwest::RequestBuilder;
+
+/// Credentials sent in the `Authorization` header of requests to a host
+///
+/// Parsed from `bearer:TOKEN` or `basic:USER:PASSWORD`, where the token or
+/// password may be `env:VAR` to read it from the environment variable `VAR`
+/// instead, which keeps secrets out of command lines and config files.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Credentials {
+    Basic {
+        user: String,
+        password: Option<String>,
+    },
+    Bearer(String),
+}
+
+impl Credentials {
+    pub(crate) fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Credentials::Basic { user, password } => request.basic_auth(user, password.as_ref()),
+            Credentials::Bearer(token) => request.bearer_auth(token),
+        }
+    }
+}
+
+/// A secret, or the value of the environment variable it names as `env:VAR`
+fn secret(from: &str) -> Result<String, Error> {
+    match from.strip_prefix("env:") {
+        Some(var) => {
+            std::env::var(var).map_err(|_| format_err!("Environment variable {} is not set", var))
+        }
+        None => Ok(from.to_string()),
+    }
+}
+
+impl FromStr for Credentials {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        match from.split_once(':') {
+            Some(("bearer", token)) if !token.is_empty() => Ok(Credentials::Bearer(secret(token)?)),
+            Some(("basic", user)) => {
+                let (user, password) = match user.split_once(':') {
+                    Some((user, password)) => (user, Some(secret(password)?)),
+                    None => (user, None),
+                };
+                if user.is_empty() {
+                    return Err(format_err!("Basic credentials without a user"));
+                }
+                Ok(Credentials::Basic {
+                    user: user.to_string(),
+                    password,
+                })
+            }
+            _ => Err(format_err!(
+                "Unknown credentials, expected bearer:TOKEN or basic:USER:PASSWORD"
+            )),
+        }
+    }
+}
+
+/// Shows the kind of credentials and the user, never the secrets
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credentials::Basic { user, .. } => write!(f, "Basic({})", user),
+            Credentials::Bearer(_) => write!(f, "Bearer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn credentials_parse_and_read_the_environment() {
+        assert_eq!(
+            "bearer:abc".parse::<Credentials>().unwrap(),
+            Credentials::Bearer("abc".to_string())
+        );
+        std::env::set_var("CMSIS_PACK_AUTH_TEST", "s3cret:with:colons");
+        assert_eq!(
+            "basic:me:env:CMSIS_PACK_AUTH_TEST"
+                .parse::<Credentials>()
+                .unwrap(),
+            Credentials::Basic {
+                user: "me".to_string(),
+                password: Some("s3cret:with:colons".to_string()),
+            }
+        );
+        let basic: Credentials = "basic:me".parse().unwrap();
+        assert_eq!(format!("{:?}", basic), "Basic(me)");
+        assert!("bearer:env:CMSIS_PACK_AUTH_UNSET"
+            .parse::<Credentials>()
+            .is_err());
+        assert!("digest:me".parse::<Credentials>().is_err());
+        assert!("basic::password".parse::<Credentials>().is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+use std::fs::{rename, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::pack_index::{Pidx, Vidx};
+
+const CACHE_FILE: &str = ".index-cache.json";
+
+#[derive(Serialize, Deserialize)]
+struct CachedIndex {
+    timestamp: String,
+    index: Vidx,
+}
+
+/// Vendor indexes fetched by earlier updates, keyed by URL
+///
+/// A vendor index is only fetched again once the timestamp its parent index
+/// lists for it changes. The cache is kept next to the PDSC files in the
+/// pack store; deleting it forces a full update.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct IndexCache {
+    indexes: BTreeMap<String, CachedIndex>,
+}
+
+/// The timestamp a parent index lists for a vendor index, if any
+pub(crate) fn listed_timestamp(pidx: &Pidx) -> Option<&String> {
+    pidx.timestamp.as_ref().or(pidx.date.as_ref())
+}
+
+fn cache_path(pack_store: &Path) -> PathBuf {
+    pack_store.join(CACHE_FILE)
+}
+
+impl IndexCache {
+    /// Load the cache of a pack store, starting afresh if it is missing or
+    /// unreadable
+    pub(crate) fn load(pack_store: &Path) -> Self {
+        File::open(cache_path(pack_store))
+            .ok()
+            .and_then(|fd| serde_json::from_reader(BufReader::new(fd)).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, pack_store: &Path) -> Result<(), Error> {
+        let path = cache_path(pack_store);
+        let temp = path.with_extension("part");
+        std::fs::create_dir_all(pack_store)?;
+        serde_json::to_writer(File::create(&temp)?, self)?;
+        rename(temp, path)?;
+        Ok(())
+    }
+
+    /// The cached contents of `url`, if it was last fetched at `timestamp`
+    pub(crate) fn unchanged(&self, url: &str, timestamp: &str) -> Option<Vidx> {
+        self.indexes
+            .get(url)
+            .filter(|cached| cached.timestamp == timestamp)
+            .map(|cached| cached.index.clone())
+    }
+
+    pub(crate) fn insert(&mut self, url: String, timestamp: String, index: &Vidx) {
+        let index = index.clone();
+        self.indexes.insert(url, CachedIndex { timestamp, index });
+    }
+}
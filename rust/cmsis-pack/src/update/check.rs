@@ -0,0 +1,174 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::pdsc::Package;
+use crate::update::download::is_complete_pdsc;
+use crate::utils::pack_id;
+use crate::utils::parse::FromElem;
+
+/// What is wrong with a PDSC file of the pack store
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum PdscProblem {
+    /// The file is empty
+    Empty,
+    /// The file does not end with the closing tag of its package, as left
+    /// by an interrupted write
+    Truncated,
+    /// The file does not parse as a PDSC file
+    Invalid { error: String },
+    /// The file name does not match the vendor, name or releases in the file
+    Misnamed { expected: String },
+}
+
+impl fmt::Display for PdscProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PdscProblem::Empty => f.write_str("empty file"),
+            PdscProblem::Truncated => f.write_str("truncated file"),
+            PdscProblem::Invalid { error } => write!(f, "invalid PDSC file: {}", error),
+            PdscProblem::Misnamed { expected } => write!(f, "should be named {}", expected),
+        }
+    }
+}
+
+/// A PDSC file of the pack store with a problem
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BrokenPdsc {
+    pub path: PathBuf,
+    pub problem: PdscProblem,
+}
+
+/// The result of [`check_store`]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StoreCheck {
+    /// How many PDSC files were checked
+    pub checked: usize,
+    pub broken: Vec<BrokenPdsc>,
+}
+
+/// The problem of the PDSC file at `path`, named `Vendor.Name.Version.pdsc`
+fn check_pdsc(path: &Path) -> Option<PdscProblem> {
+    match path.metadata() {
+        Ok(meta) if meta.len() == 0 => return Some(PdscProblem::Empty),
+        Ok(_) => {}
+        Err(err) => {
+            return Some(PdscProblem::Invalid {
+                error: err.to_string(),
+            })
+        }
+    }
+    if !is_complete_pdsc(path) {
+        return Some(PdscProblem::Truncated);
+    }
+    let pdsc = match Package::from_path(path) {
+        Ok(pdsc) => pdsc,
+        Err(err) => {
+            return Some(PdscProblem::Invalid {
+                error: err.to_string(),
+            })
+        }
+    };
+    let file_name = path.file_name()?.to_str()?;
+    let prefix = format!("{}.", pack_id(&pdsc.vendor, &pdsc.name));
+    let version = file_name
+        .to_ascii_lowercase()
+        .strip_prefix(&prefix)
+        .and_then(|rest| rest.strip_suffix(".pdsc").map(str::to_string));
+    let released = |version: &str| pdsc.releases.iter().any(|r| r.version == version);
+    match version {
+        Some(version) if released(&version) => None,
+        _ => Some(PdscProblem::Misnamed {
+            expected: format!(
+                "{}.{}.{}.pdsc",
+                pdsc.vendor,
+                pdsc.name,
+                pdsc.releases.latest_release().version
+            ),
+        }),
+    }
+}
+
+/// The PDSC files of the pack store, sorted
+pub(crate) fn pdsc_paths(pack_store: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match pack_store.read_dir() {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "pdsc"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+/// Check the PDSC files of the pack store: that each is complete, parses,
+/// and is named after its vendor, name and one of its releases
+///
+/// Only the PDSC files at the top of the store are checked, not those of
+/// extracted packs. Broken files are listed in the order of their paths.
+pub fn check_store(pack_store: &Path) -> StoreCheck {
+    let paths = pdsc_paths(pack_store);
+    let broken = paths
+        .iter()
+        .filter_map(|path| {
+            check_pdsc(path).map(|problem| BrokenPdsc {
+                path: path.clone(),
+                problem,
+            })
+        })
+        .collect();
+    StoreCheck {
+        checked: paths.len(),
+        broken,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn broken_pdscs_are_reported() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        std::fs::create_dir_all(&store).unwrap();
+        let pdsc =
+            std::fs::read_to_string("../../tests/test-pack-index/MyVendor.MyPack.pdsc").unwrap();
+        std::fs::write(store.join("MyVendor.MyPack.1.1.0.pdsc"), &pdsc).unwrap();
+        std::fs::write(store.join("MyVendor.Other.1.1.0.pdsc"), &pdsc).unwrap();
+        std::fs::write(store.join("MyVendor.MyPack.9.0.0.pdsc"), &pdsc).unwrap();
+        std::fs::write(store.join("V.Cut.1.0.0.pdsc"), &pdsc[..pdsc.len() / 2]).unwrap();
+        std::fs::write(store.join("V.Empty.1.0.0.pdsc"), "").unwrap();
+        std::fs::write(store.join("V.Html.1.0.0.pdsc"), "<html></package>").unwrap();
+
+        let check = check_store(&store);
+        assert_eq!(check.checked, 6);
+        let problems: Vec<(String, &PdscProblem)> = check
+            .broken
+            .iter()
+            .map(|broken| {
+                let name = broken.path.file_name().unwrap().to_string_lossy();
+                (name.into_owned(), &broken.problem)
+            })
+            .collect();
+        let misnamed = PdscProblem::Misnamed {
+            expected: "MyVendor.MyPack.1.1.0.pdsc".to_string(),
+        };
+        assert_eq!(
+            problems[0],
+            ("MyVendor.MyPack.9.0.0.pdsc".to_string(), &misnamed)
+        );
+        assert_eq!(
+            problems[1],
+            ("MyVendor.Other.1.1.0.pdsc".to_string(), &misnamed)
+        );
+        assert_eq!(problems[2].1, &PdscProblem::Truncated);
+        assert_eq!(problems[3].1, &PdscProblem::Empty);
+        assert!(matches!(problems[4].1, PdscProblem::Invalid { .. }));
+        assert_eq!(problems.len(), 5);
+    }
+}
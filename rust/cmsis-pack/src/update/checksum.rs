@@ -0,0 +1,229 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use digest::Digest;
+use sha1::Sha1;
+use sha2::Sha256;
+
+/// The hash function of a [`Checksum`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+impl ChecksumAlgorithm {
+    fn name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha1 => "sha1",
+            ChecksumAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// The extension of the sidecar files holding checksums of this kind
+    pub(crate) fn extension(self) -> &'static str {
+        self.name()
+    }
+}
+
+/// The expected digest of a downloaded file
+///
+/// Parsed from `sha256:HEX` or `sha1:HEX`, or from bare hex digits whose
+/// length tells the algorithm. Sidecar files in the `sha256sum` format,
+/// with a file name after the digest, parse too.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+impl FromStr for Checksum {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.split_whitespace().next().unwrap_or_default();
+        let (named, hex) = match s.split_once(':') {
+            Some((name, hex)) => (Some(name.to_ascii_lowercase()), hex),
+            None => (None, s),
+        };
+        let algorithm = match (named.as_deref(), hex.len()) {
+            (Some("sha1"), _) | (None, 40) => ChecksumAlgorithm::Sha1,
+            (Some("sha256"), _) | (None, 64) => ChecksumAlgorithm::Sha256,
+            (Some(other), _) => return Err(anyhow!("unsupported checksum algorithm {}", other)),
+            (None, _) => return Err(anyhow!("{:?} is not a SHA-1 or SHA-256 digest", s)),
+        };
+        let len = match algorithm {
+            ChecksumAlgorithm::Sha1 => 20,
+            ChecksumAlgorithm::Sha256 => 32,
+        };
+        let digest = (0..hex.len())
+            .step_by(2)
+            .map(|at| {
+                hex.get(at..at + 2)
+                    .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>()
+            .filter(|digest| digest.len() == len && hex.len() == 2 * len)
+            .ok_or_else(|| anyhow!("{:?} is not a {} digest", s, algorithm.name()))?;
+        Ok(Checksum { algorithm, digest })
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.algorithm.name())?;
+        self.digest.iter().try_for_each(|b| write!(f, "{:02x}", b))
+    }
+}
+
+/// A download whose contents do not match its checksum
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumMismatch {
+    pub expected: Checksum,
+    pub actual: Checksum,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Checksum mismatch: expected {}, got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// A download refused because no checksum was published for it, under
+/// [`require_checksum`](crate::update::DownloadConfig::require_checksum)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unverified;
+
+impl fmt::Display for Unverified {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "No checksum was published to verify the download against"
+        )
+    }
+}
+
+impl std::error::Error for Unverified {}
+
+/// The running state of one of the hash functions of [`ChecksumAlgorithm`]
+enum Digests {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+/// Computes the digest of a download as it arrives
+pub(crate) struct Hasher {
+    algorithm: ChecksumAlgorithm,
+    digests: Digests,
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: ChecksumAlgorithm) -> Self {
+        let digests = match algorithm {
+            ChecksumAlgorithm::Sha1 => Digests::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Digests::Sha256(Sha256::new()),
+        };
+        Hasher { algorithm, digests }
+    }
+
+    pub(crate) fn update(&mut self, bytes: &[u8]) {
+        match &mut self.digests {
+            Digests::Sha1(digest) => digest.update(bytes),
+            Digests::Sha256(digest) => digest.update(bytes),
+        }
+    }
+
+    /// Feed the contents of the file at `path`, such as the part of a
+    /// download that an earlier attempt left behind
+    pub(crate) fn update_from(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut file = File::open(path)?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buf)? {
+                0 => return Ok(()),
+                read => self.update(&buf[..read]),
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Checksum {
+        let digest = match self.digests {
+            Digests::Sha1(digest) => digest.finalize().to_vec(),
+            Digests::Sha256(digest) => digest.finalize().to_vec(),
+        };
+        Checksum {
+            algorithm: self.algorithm,
+            digest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn digest(algorithm: ChecksumAlgorithm, chunks: &[&[u8]]) -> String {
+        let mut hasher = Hasher::new(algorithm);
+        chunks.iter().for_each(|chunk| hasher.update(chunk));
+        hasher.finish().to_string()
+    }
+
+    #[test]
+    fn digests_match_known_vectors() {
+        use ChecksumAlgorithm::*;
+        assert_eq!(
+            digest(Sha1, &[b"abc"]),
+            "sha1:a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            digest(Sha256, &[]),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        // 56 bytes, which pad into a second block, fed in uneven chunks
+        let long = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        assert_eq!(
+            digest(Sha256, &[&long[..5], &long[5..]]),
+            "sha256:248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            digest(Sha1, &[&long[..]]),
+            "sha1:84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        let million = vec![b'a'; 1_000_000];
+        assert_eq!(
+            digest(Sha256, &[&million[..100], &million[100..]]),
+            "sha256:cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn checksums_are_parsed() {
+        let sha1: Checksum = "A9993E364706816ABA3E25717850C26C9CD0D89D  V.P.1.0.0.pack\n"
+            .parse()
+            .unwrap();
+        assert_eq!(sha1.algorithm, ChecksumAlgorithm::Sha1);
+        assert_eq!(
+            sha1.to_string(),
+            "sha1:a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        let named: Checksum = "SHA1:a9993e364706816aba3e25717850c26c9cd0d89d"
+            .parse()
+            .unwrap();
+        assert_eq!(named, sha1);
+        assert!("md5:900150983cd24fb0d6963f7d28e17f72"
+            .parse::<Checksum>()
+            .is_err());
+        assert!("sha256:a9993e36".parse::<Checksum>().is_err());
+        assert!("not a digest".parse::<Checksum>().is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use crate::log::STORE;
+use std::fs::{create_dir_all, hard_link, remove_file, rename, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+static TAKEOVERS: AtomicUsize = AtomicUsize::new(0);
+
+/// How often an operation refreshes the claims it holds
+pub(crate) const CLAIM_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Claims not refreshed for this long were left behind by a process that
+/// died, and are taken over
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// The exclusive right to write a file of the pack store, shared between
+/// processes through a `.claim` file next to it
+///
+/// Operations on a shared pack store, such as CI jobs with a common cache
+/// volume, claim each file before writing it, so they split the work instead
+/// of writing the same file at once. The claim is released when dropped.
+pub(crate) struct Claim {
+    path: PathBuf,
+    file: File,
+}
+
+impl Claim {
+    /// Claim `dest`, or `None` while another process holds it
+    pub(crate) fn acquire(dest: &Path) -> std::io::Result<Option<Claim>> {
+        Self::create(dest.with_extension("claim"))
+    }
+
+    /// Create the claim file `path`, or `None` while another process holds
+    /// it
+    pub(crate) fn create(path: PathBuf) -> std::io::Result<Option<Claim>> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        // Once more after a released or stale claim is gone
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let _ = writeln!(file, "{}", std::process::id());
+                    return Ok(Some(Claim { path, file }));
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    match path.metadata().and_then(|meta| meta.modified()) {
+                        Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                        Ok(modified) if is_stale(modified) => {
+                            if !take_over(&path, modified) {
+                                return Ok(None);
+                            }
+                            tracing::warn!(target: STORE, path = ?path, "Taking over a stale claim");
+                        }
+                        _ => return Ok(None),
+                    }
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Mark the claim as still held
+    pub(crate) fn refresh(&self) {
+        if let Err(err) = self.file.set_modified(SystemTime::now()) {
+            tracing::debug!(target: STORE, path = ?self.path, error = %err, "Could not refresh a claim");
+        }
+    }
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let _ = remove_file(&self.path);
+    }
+}
+
+/// Move the stale claim file `path`, last modified at `seen`, out of the
+/// way, returning whether this process did
+///
+/// Of several processes taking over the same claim, only one renames it;
+/// the others find it gone. A claim refreshed or taken over since it was
+/// seen stale is put back instead.
+fn take_over(path: &Path, seen: SystemTime) -> bool {
+    let count = TAKEOVERS.fetch_add(1, Ordering::Relaxed);
+    let aside = path.with_extension(format!("claim.{}.{}.stale", std::process::id(), count));
+    if rename(path, &aside).is_err() {
+        return false;
+    }
+    let unchanged = aside
+        .metadata()
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|modified| modified == seen);
+    if !unchanged {
+        // Linking fails when yet another claim took its place
+        let _ = hard_link(&aside, path);
+    }
+    let _ = remove_file(&aside);
+    unchanged
+}
+
+/// Whether a live claim is held on `dest`
+pub(crate) fn is_claimed(dest: &Path) -> bool {
+    dest.with_extension("claim")
+        .metadata()
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|modified| !is_stale(modified))
+}
+
+pub(crate) fn is_stale(modified: SystemTime) -> bool {
+    modified.elapsed().is_ok_and(|age| age > CLAIM_TIMEOUT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn one_process_takes_over_a_stale_claim() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("Vendor.Pack.claim");
+        File::create(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * CLAIM_TIMEOUT)
+            .unwrap();
+
+        let barrier = Arc::new(Barrier::new(8));
+        let takers: Vec<_> = (0..8)
+            .map(|_| {
+                let (path, barrier) = (path.clone(), barrier.clone());
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    Claim::create(path).unwrap()
+                })
+            })
+            .collect();
+        let claims: Vec<Claim> = takers
+            .into_iter()
+            .filter_map(|taker| taker.join().unwrap())
+            .collect();
+        assert_eq!(claims.len(), 1);
+        assert!(is_claimed(&dir.join("Vendor.Pack.pdsc")));
+        drop(claims);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeMap;
+use std::fs::{rename, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::pack_index::PdscRef;
+use crate::pdsc::Package;
+
+const DEPRECATED_FILE: &str = ".deprecated.json";
+
+/// The deprecation notice of a pack, from a vendor index or its PDSC file
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deprecation {
+    /// When the pack was deprecated, as the notice states it; usually a date
+    pub since: String,
+    /// The pack that replaces it, as `Vendor.Name`
+    pub replacement: Option<String>,
+}
+
+/// Packs the vendor indexes of the last update mark as deprecated, keyed by
+/// `Vendor.Name`
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct DeprecationLog {
+    packs: BTreeMap<String, Deprecation>,
+}
+
+fn log_path(pack_store: &Path) -> PathBuf {
+    pack_store.join(DEPRECATED_FILE)
+}
+
+impl DeprecationLog {
+    pub(crate) fn load(pack_store: &Path) -> Self {
+        File::open(log_path(pack_store))
+            .ok()
+            .and_then(|fd| serde_json::from_reader(BufReader::new(fd)).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, pack_store: &Path) -> Result<(), Error> {
+        let path = log_path(pack_store);
+        if self.packs.is_empty() && !path.exists() {
+            return Ok(());
+        }
+        let temp = path.with_extension("part");
+        std::fs::create_dir_all(pack_store)?;
+        serde_json::to_writer_pretty(File::create(&temp)?, self)?;
+        rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Take in the notices of the index entries `pdscs`; packs they list
+    /// without one are no longer deprecated, while packs they leave out keep
+    /// their notice
+    pub(crate) fn record(&mut self, pdscs: &[PdscRef]) {
+        for pdsc in pdscs {
+            let key = format!("{}.{}", pdsc.vendor, pdsc.name);
+            self.packs
+                .retain(|known, _| !known.eq_ignore_ascii_case(&key));
+            if let Some(since) = &pdsc.deprecated {
+                let notice = Deprecation {
+                    since: since.clone(),
+                    replacement: pdsc.replacement.clone(),
+                };
+                self.packs.insert(key, notice);
+            }
+        }
+    }
+}
+
+/// The packs the vendor indexes mark as deprecated, keyed by `Vendor.Name`
+///
+/// Recorded by every update; PDSC files may carry notices of their own,
+/// which [`outdated_packs`](crate::update::outdated_packs) also reports.
+pub fn deprecated_packs(pack_store: &Path) -> BTreeMap<String, Deprecation> {
+    DeprecationLog::load(pack_store).packs
+}
+
+/// The notice of the pack `vendor`.`name` among those of the vendor indexes
+pub(crate) fn logged(
+    notices: &BTreeMap<String, Deprecation>,
+    vendor: &str,
+    name: &str,
+) -> Option<Deprecation> {
+    let key = format!("{}.{}", vendor, name);
+    notices
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(&key))
+        .map(|(_, notice)| notice.clone())
+}
+
+/// The notice of the pack of `pdsc`, from the vendor indexes or else from
+/// the latest release in the PDSC file
+pub(crate) fn deprecation_of(
+    notices: &BTreeMap<String, Deprecation>,
+    pdsc: &Package,
+) -> Option<Deprecation> {
+    logged(notices, &pdsc.vendor, &pdsc.name).or_else(|| {
+        let latest = pdsc.releases.latest_release();
+        latest.deprecated.map(|date| Deprecation {
+            since: date.to_string(),
+            replacement: latest.replacement.clone(),
+        })
+    })
+}
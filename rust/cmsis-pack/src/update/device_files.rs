@@ -0,0 +1,178 @@
+//! Files of installed packs that devices refer to: SVD files and flash
+//! algorithms
+
+use std::path::{Path, PathBuf};
+
+use anyhow::format_err;
+
+use crate::log::PARSE;
+use crate::pdsc::{Algorithm, Device, Package};
+use crate::update::download::local_pdscs;
+use crate::update::extract::pack_file;
+use crate::update::listing::StoreListing;
+use crate::update::outdated::{entries, installed_versions};
+use crate::utils::parse::from_store_path;
+
+/// The PDSC file of an installed pack version: the one in the store, or
+/// else the one inside the pack
+fn installed_pdsc(
+    listing: &mut StoreListing,
+    pack_store: &Path,
+    pack: &Path,
+    vendor: &str,
+    name: &str,
+    version: &str,
+) -> Option<Package> {
+    let path = local_pdscs(listing, pack_store, vendor, name)
+        .into_iter()
+        .find(|(_, listed)| listed == version)
+        .map(|(path, _)| path)
+        .or_else(|| pack_file(pack, &format!("{}.{}.pdsc", vendor, name)).ok())?;
+    match from_store_path::<Package>(&path) {
+        Ok(pdsc) => Some(pdsc),
+        Err(err) => {
+            tracing::warn!(target: PARSE, path = ?path, "Skipping unparsable PDSC: {}", err);
+            None
+        }
+    }
+}
+
+/// The device named `device` in `pdsc`, ignoring case when no name matches
+/// exactly
+fn find_device<'a>(pdsc: &'a Package, device: &str) -> Option<&'a Device> {
+    let devices = &pdsc.devices.0;
+    devices.get(device).or_else(|| {
+        devices
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(device))
+            .map(|(_, found)| found)
+    })
+}
+
+/// A device of an installed pack version, with the archive of that version
+struct InstalledDevice {
+    pack: PathBuf,
+    pack_name: String,
+    device: Device,
+}
+
+impl InstalledDevice {
+    /// The file `name` of the pack, extracted from its archive if need be
+    fn file(&self, name: &str) -> Result<PathBuf, crate::Error> {
+        pack_file(&self.pack, name).map_err(|err| crate::Error::Pack {
+            pack: self.pack_name.clone(),
+            source: err.into(),
+        })
+    }
+}
+
+/// The definition of `device` in the newest installed version of the first
+/// pack defining it
+fn installed_device(pack_store: &Path, device: &str) -> Result<InstalledDevice, crate::Error> {
+    let mut listing = StoreListing::default();
+    for (vendor_dir, vendor, _) in entries(pack_store).into_iter().filter(|e| e.2) {
+        for (pack_dir, name, _) in entries(&vendor_dir).into_iter().filter(|e| e.2) {
+            for version in installed_versions(&pack_dir) {
+                let pack = pack_dir.join(format!("{}.pack", version));
+                let pdsc =
+                    match installed_pdsc(&mut listing, pack_store, &pack, &vendor, &name, &version)
+                    {
+                        Some(pdsc) => pdsc,
+                        None => continue,
+                    };
+                if let Some(found) = find_device(&pdsc, device) {
+                    return Ok(InstalledDevice {
+                        pack,
+                        pack_name: format!("{}.{}", vendor, name),
+                        device: found.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Err(format_err!("No installed pack defines the device {}", device).into())
+}
+
+/// The CMSIS-SVD file of `device`, as its `<debug svd>` element names it,
+/// from the newest installed version of the first pack defining the device
+///
+/// When the pack is not extracted, the file alone is extracted from its
+/// archive into `Vendor/Name/.files/VERSION/` and found there next time.
+pub fn svd_path(pack_store: &Path, device: &str) -> Result<PathBuf, crate::Error> {
+    let installed = installed_device(pack_store, device)?;
+    let svd = installed
+        .device
+        .processors
+        .iter()
+        .find_map(|processor| processor.svd.as_deref())
+        .ok_or_else(|| crate::Error::Pack {
+            pack: installed.pack_name.clone(),
+            source: format!("device {} names no SVD file", installed.device.name).into(),
+        })?;
+    installed.file(svd)
+}
+
+/// The flash algorithms of `device`, each with the path of its `.FLM` file,
+/// found and extracted like [`svd_path`] finds the SVD file
+pub fn flash_algorithm_paths(
+    pack_store: &Path,
+    device: &str,
+) -> Result<Vec<(Algorithm, PathBuf)>, crate::Error> {
+    let installed = installed_device(pack_store, device)?;
+    installed
+        .device
+        .algorithms
+        .iter()
+        .map(|algo| {
+            let path = installed.file(&algo.file_name.to_string_lossy())?;
+            Ok((algo.clone(), path))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, File};
+    use std::io::Write;
+
+    const PDSC: &str = "<package><name>Pack</name><vendor>Vendor</vendor>\
+        <description/><url>http://example.com/</url>\
+        <releases><release version=\"1.0.0\"/></releases>\
+        <devices><family Dfamily=\"Fam\" Dvendor=\"Vendor:1\">\
+        <processor Dcore=\"Cortex-M4\" Dfpu=\"1\" Dmpu=\"1\" Dendian=\"Little-endian\"/>\
+        <debug svd=\"SVD\\Dev.svd\"/>\
+        <algorithm name=\"Flash\\Dev.FLM\" start=\"0x0\" size=\"0x1000\" default=\"1\"/>\
+        <device Dname=\"DEV1\"/></family></devices></package>";
+
+    #[test]
+    fn device_files_are_extracted_from_installed_packs() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        let pack = store.join("Vendor/Pack/1.0.0.pack");
+        create_dir_all(pack.parent().unwrap()).unwrap();
+        let mut zip = zip::ZipWriter::new(File::create(&pack).unwrap());
+        let files = [
+            ("Vendor.Pack.pdsc", PDSC),
+            ("SVD/Dev.svd", "<device/>"),
+            ("Flash/Dev.FLM", "ELF"),
+        ];
+        for (name, contents) in files {
+            zip.start_file(name, Default::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let svd = svd_path(&store, "dev1").unwrap();
+        assert_eq!(svd, store.join("Vendor/Pack/.files/1.0.0/SVD/Dev.svd"));
+        assert_eq!(std::fs::read_to_string(svd).unwrap(), "<device/>");
+
+        let algorithms = flash_algorithm_paths(&store, "DEV1").unwrap();
+        assert_eq!(algorithms.len(), 1);
+        assert_eq!(algorithms[0].0.size, 0x1000);
+        assert_eq!(std::fs::read_to_string(&algorithms[0].1).unwrap(), "ELF");
+
+        let err = svd_path(&store, "DEV2").unwrap_err();
+        assert_eq!(err.code(), "other");
+    }
+}
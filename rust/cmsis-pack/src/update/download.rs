@@ -1,40 +1,323 @@
-use std::fs::{create_dir_all, rename, OpenOptions};
-use std::io::Write;
+use std::fs::File;
+use std::fs::{create_dir_all, metadata, remove_file, rename, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, Error};
+use chrono::{DateTime, Utc};
 use futures::prelude::*;
 use futures::stream::futures_unordered::FuturesUnordered;
-use reqwest::{redirect, Url};
-use reqwest::{Client, ClientBuilder, Response};
+use minidom::quick_xml::events::{BytesStart, Event};
+use minidom::quick_xml::Reader;
+use reqwest::Url;
+use std::time::Instant;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
+use tracing::Instrument;
 
+use crate::log::{NETWORK, STORE};
 use crate::pack_index::{PdscRef, Vidx};
 use crate::pdsc::Package;
+use crate::update::auth::Credentials;
+use crate::update::cache::{listed_timestamp, IndexCache};
+use crate::update::checksum::{Checksum, ChecksumAlgorithm, ChecksumMismatch, Hasher, Unverified};
+use crate::update::claim::{Claim, CLAIM_HEARTBEAT};
+use crate::update::deprecated::DeprecationLog;
+use crate::update::extract::{extract_dir, extract_pack};
+use crate::update::fetch::{
+    decode_utf8, is_offline, read_to_string, source_url, within, Body, Fetcher, HttpStatus,
+    LocalFiles, Offline, ReqwestFetcher, TlsOptions, Utf8Validator,
+};
+use crate::update::filter::VendorFilter;
+use crate::update::listing::StoreListing;
+use crate::update::mirror::{with_mirrors, Mirror};
+use crate::update::origins::{other_origin, OriginLog};
+use crate::update::plan::{plan_update, PlannedDownload};
+use crate::update::profile::{NetworkProfile, Timeouts};
+use crate::update::rate::{RateLimit, RateLimiter};
+use crate::update::retry::{retry, RetryPolicy};
+use crate::update::shared::{link_object, SharedCache};
+use crate::update::validators::{ValidatorLog, Validators};
+use crate::update::vanished::{VanishedLog, VanishedPolicy};
 use crate::utils::parse::FromElem;
-use futures::StreamExt;
-use std::collections::HashMap;
+use crate::utils::{compare_versions, pack_id, ResultLogExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-const CONCURRENCY : usize = 32;
-const HOST_LIMIT : usize = 6;
-const MAX_RETRIES : usize = 3;
+/// Large enough to hold most PDSC files, which are then written in one go
+const WRITE_BUFFER: usize = 256 * 1024;
 
-fn pdsc_url(pdsc: &mut PdscRef) -> String {
-    if pdsc.url.ends_with('/') {
-        format!("{}{}.{}.pdsc", pdsc.url, pdsc.vendor, pdsc.name)
-    } else {
-        format!("{}/{}.{}.pdsc", pdsc.url, pdsc.vendor, pdsc.name)
+/// Host, source URL, downloaded size and destination of a finished download,
+/// with the URL it was served from when a redirect changed it and the
+/// validators it was served with
+type DownloadResult = (
+    String,
+    Url,
+    usize,
+    Result<(Saved, Option<Url>, Validators), Error>,
+);
+
+/// URL of an index and the index, with whether it was downloaded rather
+/// than taken from the cache
+type IndexFetch = (String, Result<(Vidx, bool), Error>);
+
+/// Source URL, host, destination and expected checksum of a download
+type Pending = (Url, String, PathBuf, Option<Checksum>);
+
+/// Source URL and archive of a pack being extracted, with the task doing it
+type Extraction = (Url, PathBuf, JoinHandle<Result<PathBuf, Error>>);
+
+/// Where a downloaded file ended up
+enum Saved {
+    /// The file was written to this path
+    Written(PathBuf),
+    /// The path already held the same contents and was left untouched
+    Unchanged(PathBuf),
+}
+
+/// Cancels a running update or install from another thread or task
+///
+/// Cancellation aborts the in-flight downloads and discards their partial
+/// files; the operation then returns [`crate::Error::Cancelled`].
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
     }
 }
 
+/// Where and how an update or install stores and retrieves files
+///
+/// Operations keep no state outside their config, so updates of distinct
+/// pack stores may run concurrently in one process. Operations on the same
+/// pack store, also from other processes, claim each file before writing
+/// it: they split the downloads between them and wait for the files
+/// another one is writing.
 pub trait DownloadConfig {
     fn pack_store(&self) -> PathBuf;
+
+    /// The transport used to retrieve files; reqwest unless overridden
+    ///
+    /// Either way, `file:` URLs and plain paths, in the vendor index list
+    /// as well as in the indexes, are read from disk.
+    fn fetcher(&self) -> Option<Arc<dyn Fetcher>> {
+        None
+    }
+
+    /// The URL of a proxy for every download of the default fetcher
+    ///
+    /// Without one, the proxies of the `HTTP_PROXY` and `HTTPS_PROXY`
+    /// environment variables are used; see [`ReqwestFetcher::with_proxy`].
+    fn proxy(&self) -> Option<String> {
+        None
+    }
+
+    /// Credentials of the default fetcher for the hosts that require them,
+    /// keyed by host name
+    fn credentials(&self) -> HashMap<String, Credentials> {
+        HashMap::new()
+    }
+
+    /// PEM files of root certificates the default fetcher trusts besides
+    /// those of the system, such as that of the internal CA of a mirror
+    fn ca_certificates(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Accept any certificate of HTTPS servers in the default fetcher
+    ///
+    /// This leaves downloads open to interception and is logged as a
+    /// warning; only meant for lab environments.
+    fn insecure_skip_verify(&self) -> bool {
+        false
+    }
+
+    /// Concurrency, retries and timeouts of the network
+    fn network_profile(&self) -> NetworkProfile {
+        NetworkProfile::default()
+    }
+
+    /// Download files again even when they are already in the pack store
+    ///
+    /// A file whose contents did not change is not rewritten, which keeps
+    /// its modification time for build systems watching the store. PDSC
+    /// files are requested with the `ETag` and `Last-Modified` headers they
+    /// were last served with, so servers only send the ones that changed.
+    fn refresh(&self) -> bool {
+        false
+    }
+
+    /// Verify and extract pack archives once they are downloaded
+    ///
+    /// A pack is extracted into a directory named after its version, next to
+    /// the archive. Extraction runs on a blocking thread while the other
+    /// packs keep downloading.
+    fn extract_packs(&self) -> bool {
+        false
+    }
+
+    /// Log a warning for every file served from another origin than the one
+    /// its index declares, such as a mirror or CDN a redirect led to
+    ///
+    /// Such files are recorded in the pack store either way; see
+    /// [`foreign_origins`](crate::update::foreign_origins).
+    fn origin_warnings(&self) -> bool {
+        false
+    }
+
+    /// What to do with the local PDSC files of a pack whose PDSC URL
+    /// returns 404
+    ///
+    /// Either way the pack is recorded as vanished in the pack store; see
+    /// [`vanished_packs`](crate::update::vanished_packs).
+    fn vanished_policy(&self) -> VanishedPolicy {
+        VanishedPolicy::default()
+    }
+
+    /// Downloads in flight at once, of indexes as well as of PDSC files and
+    /// packs; that of the network profile unless overridden
+    fn concurrency(&self) -> usize {
+        self.network_profile().concurrency()
+    }
+
+    /// How many response bodies may be read at once
+    ///
+    /// This is independent of the number of requests in flight: the bodies
+    /// of the other responses wait, with their connections paused, until a
+    /// body finishes. Lower it to bound memory use on small machines.
+    fn max_open_bodies(&self) -> usize {
+        self.concurrency()
+    }
+
+    /// Download PDSC files whose index lists an older version than the
+    /// newest one in the pack store
+    ///
+    /// Such a listing comes from a rolled back or misconfigured source, so
+    /// by default the download is skipped and logged.
+    fn allow_downgrade(&self) -> bool {
+        false
+    }
+
+    /// How failed downloads of indexes, PDSC files and packs are retried;
+    /// that of the network profile unless overridden
+    fn retry_policy(&self) -> RetryPolicy {
+        self.network_profile().retry_policy()
+    }
+
+    /// Fail on fetched indexes and PDSC files that are not valid UTF-8
+    ///
+    /// The error lists the byte offsets of the invalid sequences. Otherwise
+    /// they are replaced with U+FFFD and their offsets are logged.
+    fn strict_utf8(&self) -> bool {
+        false
+    }
+
+    /// Connect, read and total timeouts of downloads; those of the network
+    /// profile unless overridden
+    ///
+    /// A download that times out fails with [`TimedOut`](crate::update::TimedOut) or a reqwest
+    /// timeout in its chain, which [`Error::is_timeout`](crate::Error::is_timeout)
+    /// recognizes, and is retried like other transient failures. Only the
+    /// read timeout applies to fetchers other than the default one.
+    fn timeouts(&self) -> Timeouts {
+        self.network_profile().timeouts()
+    }
+
+    /// Refuse downloads without a checksum to verify them against
+    ///
+    /// Downloads are checked against the checksum their index entry lists,
+    /// or else the `.sha256` or `.sha1` file published next to them. Such
+    /// files are looked up for every pack, and for PDSC files only in this
+    /// mode. Downloads that fail their checksum are refused either way.
+    fn require_checksum(&self) -> bool {
+        false
+    }
+
+    /// The vendors whose PDSC files an update downloads; all of them by
+    /// default
+    ///
+    /// The vendor indexes are still fetched in full, as they may list the
+    /// packs of any vendor.
+    fn vendor_filter(&self) -> VendorFilter {
+        VendorFilter::default()
+    }
+
+    /// Mirrors of the indexes, PDSC files and packs under URL prefixes,
+    /// tried in order when a file is missing from its declared URL or its
+    /// server times out or cannot be reached; none by default
+    ///
+    /// A file fetched from a mirror on another origin is reported through
+    /// [`Observer::served_from`] and recorded like one a redirect led to.
+    fn mirrors(&self) -> Vec<Mirror> {
+        Vec::new()
+    }
+
+    /// Requests per second downloads may send, to each host and in all;
+    /// unlimited by default
+    ///
+    /// The limit applies to indexes, PDSC files, packs, their checksum
+    /// files and the `HEAD` requests of dry runs, but not to the hops of a
+    /// redirect.
+    fn rate_limit(&self) -> RateLimit {
+        RateLimit::default()
+    }
+
+    /// A directory of downloads shared by the pack stores of the machine;
+    /// none by default
+    ///
+    /// PDSC files and packs are kept there by digest and hard linked into
+    /// the pack stores, or copied where linking fails. A file already in
+    /// the cache is only requested conditionally, with the validators it
+    /// was served with, or not at all when its index lists its checksum.
+    fn shared_cache(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// Also install the packs that a pack installed by its spec requires,
+    /// at the newest release of the range each requirement accepts
+    ///
+    /// See [`resolve_pack`](crate::update::resolve_pack) for the packs this
+    /// pulls in.
+    fn install_dependencies(&self) -> bool {
+        false
+    }
+
+    /// Forbid network access, so that only the pack store, the shared
+    /// cache and `file:` URLs are read
+    ///
+    /// An operation that needs a download then fails with
+    /// [`crate::Error::Offline`] instead of going on without it.
+    fn offline(&self) -> bool {
+        false
+    }
 }
 
 pub trait IntoDownload {
     fn into_uri(&self) -> Result<Url, Error>;
     fn into_fd<D: DownloadConfig>(&self, _: &D) -> PathBuf;
+
+    /// The checksum the download is listed with, if any
+    fn checksum(&self) -> Option<Checksum> {
+        None
+    }
+
+    /// When the index lists the file as last changed, if it does
+    fn updated(&self) -> Option<DateTime<Utc>> {
+        None
+    }
 }
 
 impl IntoDownload for PdscRef {
@@ -49,9 +332,8 @@ impl IntoDownload for PdscRef {
             format!("{}{}.{}.pdsc", url, vendor, name)
         } else {
             format!("{}/{}.{}.pdsc", url, vendor, name)
-        }
-        .parse()?;
-        Ok(uri)
+        };
+        source_url(&uri)
     }
 
     fn into_fd<D: DownloadConfig>(&self, config: &D) -> PathBuf {
@@ -66,6 +348,14 @@ impl IntoDownload for PdscRef {
         filename.push(pdscname);
         filename
     }
+
+    fn updated(&self) -> Option<DateTime<Utc>> {
+        self.updated
+    }
+
+    fn checksum(&self) -> Option<Checksum> {
+        self.checksum.as_deref()?.parse().ok_warn()
+    }
 }
 
 impl<'a> IntoDownload for &'a Package {
@@ -82,9 +372,8 @@ impl<'a> IntoDownload for &'a Package {
             format!("{}{}.{}.{}.pack", url, vendor, name, version)
         } else {
             format!("{}/{}.{}.{}.pack", url, vendor, name, version)
-        }
-        .parse()?;
-        Ok(uri)
+        };
+        source_url(&uri)
     }
 
     fn into_fd<D: DownloadConfig>(&self, config: &D) -> PathBuf {
@@ -103,22 +392,186 @@ impl<'a> IntoDownload for &'a Package {
     }
 }
 
+/// Whether `path` is a pack archive, whose downloads may be resumed
+fn is_pack(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "pack")
+}
+
+/// Whether two files have the same contents
+fn same_contents(left: &Path, right: &Path) -> std::io::Result<bool> {
+    if left.metadata()?.len() != right.metadata()?.len() {
+        return Ok(false);
+    }
+    let mut left = BufReader::new(File::open(left)?);
+    let mut right = BufReader::new(File::open(right)?);
+    let mut left_buf = [0; 8192];
+    let mut right_buf = [0; 8192];
+    loop {
+        let read = left.read(&mut left_buf)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        right.read_exact(&mut right_buf[..read])?;
+        if left_buf[..read] != right_buf[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+/// Whether a PDSC file in the store ends with its closing tag
+///
+/// Crashes in the middle of writing left empty or truncated files behind in
+/// stores written by older versions, which would otherwise be skipped by
+/// every later update. Only the tail of the file is read.
+pub(crate) fn is_complete_pdsc(path: &Path) -> bool {
+    let read_tail = || -> std::io::Result<Vec<u8>> {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        file.seek(SeekFrom::Start(len.saturating_sub(64)))?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail)?;
+        Ok(tail)
+    };
+    match read_tail() {
+        Ok(tail) => {
+            let tail = tail.trim_ascii_end();
+            tail.ends_with(b"</package>") || tail.ends_with(b"<package/>")
+        }
+        Err(_) => false,
+    }
+}
+
+/// The PDSC files of a pack in the store, with the versions in their names
+pub(crate) fn local_pdscs(
+    listing: &mut StoreListing,
+    pack_store: &Path,
+    vendor: &str,
+    name: &str,
+) -> Vec<(PathBuf, String)> {
+    let prefix = format!("{}.", pack_id(vendor, name));
+    listing
+        .names(pack_store)
+        .iter()
+        .filter_map(|name| name.to_str())
+        .filter(|name| name.to_ascii_lowercase().starts_with(&prefix))
+        .filter_map(|name| {
+            let version = name.get(prefix.len()..)?.strip_suffix(".pdsc")?;
+            version
+                .starts_with(|c: char| c.is_ascii_digit())
+                .then(|| (pack_store.join(name), version.to_string()))
+        })
+        .collect()
+}
+
+/// The newest PDSC file of the pack of `pdsc` in the store, when it has a
+/// higher version than the one `pdsc` lists
+pub(crate) fn newer_local_pdsc(
+    listing: &mut StoreListing,
+    pack_store: &Path,
+    pdsc: &PdscRef,
+) -> Option<(PathBuf, String)> {
+    local_pdscs(listing, pack_store, &pdsc.vendor, &pdsc.name)
+        .into_iter()
+        .filter(|(_, version)| compare_versions(version, &pdsc.version).is_gt())
+        .max_by(|(_, left), (_, right)| compare_versions(left, right))
+}
+
+/// Check that a downloaded file is well-formed XML with a `package` root
+///
+/// Vendor servers sometimes answer with an HTML error page and a 200 status;
+/// such files are rejected before they reach the store.
+fn check_pdsc(path: &Path) -> Result<(), Error> {
+    let mut reader = Reader::from_reader(BufReader::new(File::open(path)?));
+    let mut buf = Vec::new();
+    let mut root = None;
+    // The reader does not fail on elements left open at the end, as in
+    // truncated files
+    let mut open = 0usize;
+    let name = |e: &BytesStart| String::from_utf8_lossy(e.local_name()).into_owned();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(ref e)) => {
+                root.get_or_insert_with(|| name(e));
+                open += 1;
+            }
+            Ok(Event::Empty(ref e)) => {
+                root.get_or_insert_with(|| name(e));
+            }
+            Ok(Event::End(_)) => open = open.saturating_sub(1),
+            Ok(Event::Eof) if open > 0 => return Err(anyhow!("truncated XML")),
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(err) => return Err(anyhow!("not well-formed XML: {}", err)),
+        }
+        buf.clear();
+    }
+    match root.as_deref() {
+        Some("package") => Ok(()),
+        Some(other) => Err(anyhow!("expected a package root element, found {}", other)),
+        None => Err(anyhow!("no root element")),
+    }
+}
+
+/// Check that a downloaded file is UTF-8, transcoding it in place from
+/// UTF-16 or Latin-1 or replacing invalid sequences unless `strict`
+fn check_utf8(path: &Path, dest: &Path, strict: bool) -> Result<(), Error> {
+    let bytes = std::fs::read(path)?;
+    if std::str::from_utf8(&bytes).is_ok() {
+        return Ok(());
+    }
+    let contents = decode_utf8(bytes, &dest.display().to_string(), strict)?;
+    let mut file = File::create(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
 
-async fn save_response(response: Response, dest: PathBuf) -> Result<(usize, PathBuf), Error> {
+async fn save_response(
+    mut body: Body,
+    dest: PathBuf,
+    resumed_at: u64,
+    checksum: Option<&Checksum>,
+    transfer: &Transfer,
+    report: impl Fn(u64, Option<u64>),
+) -> Result<(usize, Saved), Error> {
+    // A partial file left by an interrupted run is overwritten from scratch,
+    // unless the body continues it where it stopped
     let temp = dest.with_extension("part");
-    let file = OpenOptions::new().write(true).create(true).open(&temp);
+    let mut hasher = checksum.map(|checksum| Hasher::new(checksum.algorithm));
+    if let Some(hasher) = hasher.as_mut().filter(|_| resumed_at > 0) {
+        hasher.update_from(&temp)?;
+    }
+    let file = if resumed_at > 0 {
+        OpenOptions::new().append(true).open(&temp)
+    } else {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp)
+    };
 
     let mut file = match file {
         Err(err) => return Err(anyhow!(err.to_string())),
-        Ok(f) => f,
+        Ok(f) => BufWriter::with_capacity(WRITE_BUFFER, f),
     };
 
-    let mut fsize: usize = 0;
-    let mut stream = response.bytes_stream();
-    while let Some(chunk) = stream.next().await {
-        match chunk {
-            Ok(bytes) => {
+    let is_pdsc = dest.extension().and_then(|ext| ext.to_str()) == Some("pdsc");
+    let mut utf8 = Utf8Validator::default();
+    let expected = body.content_length().map(|len| len + resumed_at);
+    let mut fsize = resumed_at as usize;
+    loop {
+        match within(transfer.read_timeout, body.chunk()).await {
+            Ok(None) => break,
+            Ok(Some(bytes)) => {
                 fsize += bytes.len();
+                report(fsize as u64, expected);
+                if is_pdsc {
+                    utf8.feed(&bytes);
+                }
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(&bytes);
+                }
 
                 if let Err(err) = file.write_all(bytes.as_ref()) {
                     let _ = std::fs::remove_file(temp);
@@ -126,20 +579,266 @@ async fn save_response(response: Response, dest: PathBuf) -> Result<(usize, Path
                 }
             }
             Err(err) => {
-                let _ = std::fs::remove_file(temp);
-                return Err(anyhow!(err.to_string()));
+                // Kept as is, so that connection resets are retried. What
+                // arrived of a pack is kept for the retry to continue from.
+                if is_pack(&dest) {
+                    let _ = file.flush();
+                } else {
+                    let _ = std::fs::remove_file(temp);
+                }
+                return Err(err);
+            }
+        }
+    }
+    // The contents must be on disk before the rename makes them visible,
+    // or a crash could leave a renamed but empty file behind
+    if let Err(err) = file.flush().and_then(|_| file.get_ref().sync_all()) {
+        let _ = std::fs::remove_file(temp);
+        return Err(anyhow!(err.to_string()));
+    }
+    if let Some(expected) = expected.filter(|&expected| expected != fsize as u64) {
+        let _ = std::fs::remove_file(temp);
+        return Err(anyhow!(
+            "received {} bytes, but the response announced {}",
+            fsize,
+            expected
+        ));
+    }
+    drop(file);
+    // Checked before invalid UTF-8 is replaced, which changes the contents
+    if let (Some(expected), Some(hasher)) = (checksum, hasher) {
+        let actual = hasher.finish();
+        if &actual != expected {
+            let _ = std::fs::remove_file(temp);
+            return Err(ChecksumMismatch {
+                expected: expected.clone(),
+                actual,
             }
+            .into());
         }
     }
+    if is_pdsc {
+        // Only files with invalid UTF-8 are read back, to be rewritten
+        let checked = match utf8.is_valid() {
+            true => Ok(()),
+            false => check_utf8(&temp, &dest, transfer.strict_utf8),
+        };
+        if let Err(err) = checked {
+            let _ = std::fs::remove_file(temp);
+            return Err(err);
+        }
+        if let Err(err) = check_pdsc(&temp) {
+            let _ = std::fs::remove_file(temp);
+            return Err(err.context(format!("{} is not a PDSC file", dest.display())));
+        }
+    }
+    if dest.exists() && same_contents(&temp, &dest).unwrap_or(false) {
+        let _ = std::fs::remove_file(temp);
+        return Ok((fsize, Saved::Unchanged(dest)));
+    }
     if let Err(err) = rename(&temp, &dest) {
         let _ = std::fs::remove_file(temp);
         return Err(anyhow!(err.to_string()));
     }
-    Ok((fsize, dest))
+    Ok((fsize, Saved::Written(dest)))
+}
+
+/// What the downloads of an update or install share
+struct Transfer {
+    fetcher: Arc<dyn Fetcher>,
+    bodies: Arc<Semaphore>,
+    strict_utf8: bool,
+    require_checksum: bool,
+    read_timeout: Duration,
+    mirrors: Arc<[Mirror]>,
+    rate: Arc<RateLimiter>,
+    shared: Option<SharedCache>,
+}
+
+/// The checksum published next to `source`, in a file named after it with
+/// a `.sha256` or `.sha1` extension
+///
+/// A sidecar that cannot be fetched counts as not published; only offline
+/// mode, which refuses every download, is an error.
+async fn published_checksum(transfer: &Transfer, source: &Url) -> Result<Option<Checksum>, Error> {
+    for algorithm in [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Sha1] {
+        let url = format!("{}.{}", source, algorithm.extension());
+        let text = async {
+            let url = source_url(&url)?;
+            transfer.rate.acquire(&url).await;
+            let body = within(transfer.read_timeout, transfer.fetcher.get(url.clone())).await?;
+            read_to_string(body, url.as_str(), false, transfer.read_timeout).await
+        };
+        let text = match text.await {
+            Ok(text) => text,
+            Err(err) if is_offline(&err) => return Err(err),
+            Err(err) if matches!(err.downcast_ref(), Some(HttpStatus(400..=499))) => continue,
+            Err(err) => {
+                tracing::warn!(target: NETWORK, url = %url, "Ignoring a checksum file: {}", err);
+                continue;
+            }
+        };
+        match text.parse::<Checksum>() {
+            Ok(checksum) if checksum.algorithm == algorithm => return Ok(Some(checksum)),
+            _ => tracing::warn!(target: NETWORK, url = %url, "Ignoring a malformed checksum file"),
+        }
+    }
+    Ok(None)
+}
+
+/// Start fetching `source`, conditionally when there are validators, with
+/// the checksum to verify it against and the offset it resumes at; `None`
+/// when it was not modified
+async fn fetch(
+    transfer: &Transfer,
+    source: &Url,
+    dest: &Path,
+    sent: &Validators,
+    listed: Option<&Checksum>,
+) -> Result<Option<(Body, u64, Option<Checksum>)>, Error> {
+    let fetcher = &transfer.fetcher;
+    let checksum = match listed {
+        Some(listed) => Some(listed.clone()),
+        None if is_pack(dest) || transfer.require_checksum => {
+            published_checksum(transfer, source).await?
+        }
+        None => None,
+    };
+    if checksum.is_none() && transfer.require_checksum {
+        return Err(Unverified.into());
+    }
+    let partial = match metadata(dest.with_extension("part")) {
+        Ok(partial) if is_pack(dest) => partial.len(),
+        _ => 0,
+    };
+    // The read timeout also bounds the wait for the response to start
+    let read_timeout = transfer.read_timeout;
+    transfer.rate.acquire(source).await;
+    let fetched = if partial > 0 {
+        within(read_timeout, fetcher.get_from(source.clone(), partial))
+            .map_ok(|(body, resumed)| Some((body, if resumed { partial } else { 0 })))
+            .await
+    } else if sent.is_empty() {
+        within(read_timeout, fetcher.get(source.clone()))
+            .map_ok(|body| Some((body, 0)))
+            .await
+    } else {
+        within(read_timeout, fetcher.get_if_modified(source.clone(), sent))
+            .map_ok(|body| body.map(|body| (body, 0)))
+            .await
+    };
+    Ok(fetched?.map(|(body, resumed_at)| (body, resumed_at, checksum)))
+}
+
+/// Fetch `source`, or one of its mirrors, and save it to `dest`, with the
+/// URL it was served from and the validators it was served with
+async fn fetch_and_save(
+    transfer: &Transfer,
+    source: &Url,
+    dest: &Path,
+    sent: &Validators,
+    listed: Option<&Checksum>,
+    report: &impl Fn(u64, Option<u64>),
+) -> Result<(usize, Saved, Option<Url>, Validators), Error> {
+    // Files the pack store already has validators for are not looked up
+    let shared = transfer.shared.as_ref().filter(|_| sent.is_empty());
+    if let Some(object) = shared
+        .zip(listed)
+        .and_then(|(cache, listed)| cache.listed(listed))
+    {
+        tracing::debug!(target: NETWORK, url = %source, object = ?object, "Linked from the shared cache");
+        return Ok((0, link_into(&object, dest)?, None, Validators::default()));
+    }
+    let cached = shared.and_then(|cache| cache.revalidate(source, listed));
+    let sent = cached.as_ref().map_or(sent, |(_, validators)| validators);
+    let fetched = with_mirrors(&transfer.mirrors, source, |url| async move {
+        fetch(transfer, &url, dest, sent, listed).await
+    })
+    .await
+    .map_err(|err| crate::Error::with_url(err, source.as_str()))?;
+    let (body, resumed_at, checksum) = match (fetched, &cached) {
+        (Some(fetched), _) => fetched,
+        (None, Some((object, validators))) => {
+            tracing::debug!(target: NETWORK, url = %source, object = ?object, "Not modified, linked from the shared cache");
+            return Ok((0, link_into(object, dest)?, None, validators.clone()));
+        }
+        (None, None) => {
+            tracing::debug!(target: NETWORK, url = %source, "Not modified");
+            return Ok((0, Saved::Unchanged(dest.to_path_buf()), None, sent.clone()));
+        }
+    };
+    if resumed_at > 0 {
+        tracing::debug!(target: NETWORK, url = %source, from = resumed_at, "Resuming download");
+    }
+    let actual = body.url().cloned();
+    let served_with = body.validators();
+    let _permit = transfer.bodies.acquire().await?;
+    let (size, saved) = save_response(
+        body,
+        dest.to_path_buf(),
+        resumed_at,
+        checksum.as_ref(),
+        transfer,
+        report,
+    )
+    .await
+    .map_err(|err| crate::Error::with_url(err, source.as_str()))?;
+    if let Some(cache) = &transfer.shared {
+        let (Saved::Written(path) | Saved::Unchanged(path)) = &saved;
+        cache.insert(source, path, &served_with).ok_warn();
+    }
+    Ok((size, saved, actual, served_with))
 }
 
+/// Place an object of the shared cache at `dest`, unless it already holds
+/// the same contents
+fn link_into(object: &Path, dest: &Path) -> Result<Saved, Error> {
+    if dest.exists() && same_contents(object, dest).unwrap_or(false) {
+        return Ok(Saved::Unchanged(dest.to_path_buf()));
+    }
+    link_object(object, dest)?;
+    Ok(Saved::Written(dest.to_path_buf()))
+}
 
-pub trait DownloadProgress: Send {
+/// Notifications about individual steps of an update or install
+///
+/// Every method has an empty default implementation, so implementors only
+/// need to override the events they care about.
+pub trait Observer {
+    /// A vendor index (vidx or pidx) was downloaded and parsed
+    fn source_fetched(&self, _url: &str) {}
+    /// A download was started
+    fn download_started(&self, _url: &str) {}
+    /// A PDSC file was downloaded into the pack store
+    fn pdsc_downloaded(&self, _url: &str, _dest: &Path) {}
+    /// A PDSC file was already present in the pack store and was not downloaded
+    fn pdsc_skipped(&self, _dest: &Path) {}
+    /// Downloading a file failed; the operation continues with the other files
+    fn download_failed(&self, _url: &str, _error: &Error) {}
+    /// A pack archive was downloaded into the pack store
+    fn pack_installed(&self, _url: &str, _dest: &Path) {}
+    /// A pack archive was verified and extracted into `dir`
+    fn pack_extracted(&self, _url: &str, _dir: &Path) {}
+    /// A file declared at `url` was served from `actual`, another origin
+    fn served_from(&self, _url: &str, _actual: &str) {}
+    /// The PDSC file at `url` returned 404; `local` are the PDSC files of
+    /// the same pack in the store, kept or deleted per the vanished policy
+    fn pack_vanished(&self, _url: &str, _local: &[PathBuf]) {}
+    /// `bytes` of the file at `url` were received, out of `total` when the
+    /// server announced its size
+    ///
+    /// Reported while the body is read, at most every 100ms for each file.
+    fn file_progress(&self, _url: &str, _bytes: u64, _total: Option<u64>) {}
+}
+
+impl Observer for () {}
+
+/// Progress of a whole update or install
+///
+/// `size` announces how many files the operation handles, then `progress`
+/// and `complete` follow as each of them finishes. Per-file byte counts are
+/// reported through [`Observer::file_progress`].
+pub trait DownloadProgress: Observer + Send {
     fn size(&self, files: usize);
     fn progress(&self, bytes: usize);
     fn complete(&self);
@@ -160,7 +859,14 @@ where
 {
     config: &'a Conf,
     prog: Prog,
-    client: Client,
+    fetcher: Arc<dyn Fetcher>,
+    profile: NetworkProfile,
+    bodies: Arc<Semaphore>,
+    mirrors: Arc<[Mirror]>,
+    rate: Arc<RateLimiter>,
+    shared: Option<SharedCache>,
+    cancel: CancellationToken,
+    extract_packs: bool,
 }
 
 impl<'a, Conf, Prog> DownloadContext<'a, Conf, Prog>
@@ -168,105 +874,335 @@ where
     Conf: DownloadConfig,
     Prog: DownloadProgress + 'a,
 {
-    pub fn new(config: &'a Conf, prog: Prog) -> Result<Self, Error> {
-        let client = ClientBuilder::new()
-            .redirect(redirect::Policy::limited(5))
-            .build()?;
+    pub fn new(config: &'a Conf, prog: Prog, cancel: CancellationToken) -> Result<Self, Error> {
+        let profile = config.network_profile();
+        let fetcher = match config.fetcher() {
+            _ if config.offline() => Arc::new(Offline),
+            Some(fetcher) => fetcher,
+            None => {
+                let proxy = config.proxy();
+                let tls = TlsOptions {
+                    ca_certificates: config.ca_certificates(),
+                    insecure_skip_verify: config.insecure_skip_verify(),
+                };
+                let fetcher =
+                    ReqwestFetcher::with_tls(profile, config.timeouts(), proxy.as_deref(), &tls)
+                        .map_err(|err| crate::Error::Config(err.into()))?;
+                Arc::new(fetcher.with_credentials(config.credentials()))
+            }
+        };
+        let fetcher = Arc::new(LocalFiles(fetcher));
 
         Ok(DownloadContext {
             config,
             prog,
-            client,
+            fetcher,
+            profile,
+            bodies: Arc::new(Semaphore::new(config.max_open_bodies().max(1))),
+            mirrors: config.mirrors().into(),
+            rate: Arc::new(RateLimiter::new(config.rate_limit())),
+            shared: config.shared_cache().map(SharedCache::new),
+            cancel,
+            extract_packs: config.extract_packs(),
         })
     }
 
-    pub async fn download_iterator<I>(&'a self, iter: I) -> Vec<PathBuf>
+    /// Extract pack archives whatever the config says
+    pub(crate) fn extracting(self) -> Self {
+        DownloadContext {
+            extract_packs: true,
+            ..self
+        }
+    }
+
+    pub async fn download_iterator<I>(&'a self, iter: I) -> Result<Vec<PathBuf>, Error>
     where
         I: IntoIterator + 'a,
         <I as IntoIterator>::Item: IntoDownload,
     {
-        let mut to_dl: Vec<(Url, String, PathBuf)> = iter
+        let mut listing = StoreListing::default();
+        let pack_store = self.config.pack_store();
+        // When the indexes list the files as last changed
+        let mut updated: HashMap<PathBuf, DateTime<Utc>> = HashMap::new();
+        let mut to_dl: Vec<Pending> = iter
             .into_iter()
             .filter_map(|i| {
                 if let Ok(uri) = i.into_uri() {
                     let c = uri.clone();
-                    if let Some(host) = c.host_str() {
-                        Some((uri, host.to_string(), i.into_fd(self.config)))
-                    } else {
-                        None
+                    // Local files share the empty host and its limit
+                    let host = match c.host_str() {
+                        Some(host) => host,
+                        None if c.scheme() == "file" => "",
+                        None => return None,
+                    };
+                    let dest = listing.resolve(&pack_store, &i.into_fd(self.config));
+                    if let Some(at) = i.updated() {
+                        updated.insert(dest.clone(), at);
                     }
+                    Some((uri, host.to_string(), dest, i.checksum()))
                 } else {
                     None
                 }
             })
             .collect();
         self.prog.size(to_dl.len());
+        let mut origins = OriginLog::load(&pack_store);
+        let mut vanished = VanishedLog::load(&pack_store);
+        let mut validators = ValidatorLog::load(&pack_store);
 
+        let concurrency = self.config.concurrency().max(1);
         let mut hosts: HashMap<String, usize> = HashMap::new();
-        let mut results : Vec<PathBuf> = vec![];
-        let mut started : usize = 0;
-        let mut handles: Vec<JoinHandle<(String, usize, Option<PathBuf>)>> = vec![];
+        let mut results: Vec<PathBuf> = vec![];
+        let mut started: usize = 0;
+        let mut handles: Vec<(JoinHandle<DownloadResult>, PathBuf)> = vec![];
+        let mut extracting: Vec<Extraction> = vec![];
+        // Files this operation writes, and files it found claimed by another
+        let mut claims: HashMap<PathBuf, Claim> = HashMap::new();
+        let mut deferred: HashSet<PathBuf> = HashSet::new();
+        let mut heartbeat = Instant::now();
+        // Byte counts of the running downloads, reported once per round
+        let (received_tx, mut received_rx) = unbounded_channel::<(String, u64, Option<u64>)>();
+        let extract = |source: Url, pack: PathBuf, extracting: &mut Vec<Extraction>| {
+            let archive = pack.clone();
+            let handle = tokio::task::spawn_blocking(move || extract_pack(&archive));
+            extracting.push((source, pack, handle));
+        };
+
+        // The first download refused for want of network access, which ends
+        // the operation
+        let mut offline: Option<Error> = None;
+        while !to_dl.is_empty() || !handles.is_empty() || !extracting.is_empty() {
+            if self.cancel.is_cancelled() || offline.is_some() {
+                // Only completed files are renamed into place, so removing
+                // the partial downloads leaves the store consistent. Those of
+                // packs are kept for the next install to continue.
+                for (handle, dest) in handles {
+                    handle.abort();
+                    let _ = handle.await;
+                    if !is_pack(&dest) {
+                        let _ = remove_file(dest.with_extension("part"));
+                    }
+                }
+                // Extractions cannot be interrupted, but clean up after
+                // themselves when they fail
+                for (_, _, handle) in extracting {
+                    let _ = handle.await;
+                }
+                let _ = origins.save(&pack_store);
+                let _ = vanished.save(&pack_store);
+                let _ = validators.save(&pack_store);
+                return Err(offline.unwrap_or_else(|| crate::Error::Cancelled.into()));
+            }
+
+            let mut received = HashMap::new();
+            while let Ok((url, bytes, total)) = received_rx.try_recv() {
+                received.insert(url, (bytes, total));
+            }
+            for (url, (bytes, total)) in received {
+                self.prog.file_progress(&url, bytes, total);
+            }
+
+            if heartbeat.elapsed() >= CLAIM_HEARTBEAT {
+                claims.values().for_each(Claim::refresh);
+                heartbeat = Instant::now();
+            }
+
+            let mut still_extracting = vec![];
+            for (source, pack, handle) in extracting {
+                if !handle.is_finished() {
+                    still_extracting.push((source, pack, handle));
+                    continue;
+                }
+                claims.remove(&pack);
+                match handle.await.map_err(Error::from).and_then(|res| res) {
+                    Ok(dir) => {
+                        self.prog.pack_installed(source.as_str(), &pack);
+                        self.prog.pack_extracted(source.as_str(), &dir);
+                        results.push(pack);
+                    }
+                    Err(err) => {
+                        // Remove the archive so that the next install fetches
+                        // it again
+                        tracing::warn!(target: STORE, url = %source, error = %err, "Extraction failed");
+                        let _ = remove_file(&pack);
+                        self.prog.download_failed(source.as_str(), &err);
+                    }
+                }
+            }
+            extracting = still_extracting;
 
-        while !to_dl.is_empty() || !handles.is_empty() {
-            let mut wait_list: Vec<(Url, String, PathBuf)> = vec![];
-            let mut next: Vec<JoinHandle<(String, usize, Option<PathBuf>)>> = vec![];
+            let mut wait_list: Vec<Pending> = vec![];
+            let mut next: Vec<(JoinHandle<DownloadResult>, PathBuf)> = vec![];
 
-            while let Some(handle) = handles.pop() {
+            while let Some((handle, dest)) = handles.pop() {
                 if handle.is_finished() {
-                    let r = handle.await.unwrap();
-                    *hosts.entry(r.0).or_insert(1) -= 1;
+                    let (host, source, size, res) = handle.await.unwrap();
+                    *hosts.entry(host).or_insert(1) -= 1;
                     started -= 1;
-                    self.prog.progress(r.1);
+                    self.prog.progress(size);
                     self.prog.complete();
-                    if let Some(path) = r.2 {
-                        results.push(path);
+                    let res = res.map(|(saved, actual, served_with)| {
+                        if let (Saved::Written(path), Some(actual)) = (&saved, actual) {
+                            self.served_from(&mut origins, &pack_store, path, &source, &actual);
+                        }
+                        if dest.extension().is_some_and(|ext| ext == "pdsc") {
+                            validators.record(&pack_store, &dest, served_with);
+                            if let Some(at) = updated.get(&dest) {
+                                validators.record_listed(&pack_store, &dest, *at);
+                            }
+                        }
+                        saved
+                    });
+                    match res {
+                        Ok(Saved::Unchanged(path)) => {
+                            if path.extension().is_some_and(|ext| ext == "pdsc") {
+                                self.prog.pdsc_skipped(&path);
+                            }
+                            results.push(path);
+                        }
+                        Ok(Saved::Written(path)) => {
+                            let is_pack = is_pack(&path);
+                            if is_pack && self.extract_packs {
+                                extract(source, path, &mut extracting);
+                                continue;
+                            }
+                            if is_pack {
+                                self.prog.pack_installed(source.as_str(), &path);
+                            } else {
+                                vanished.reappeared(&source);
+                                self.prog.pdsc_downloaded(source.as_str(), &path);
+                            }
+                            results.push(path);
+                        }
+                        Err(err) if is_offline(&err) => offline = Some(err),
+                        Err(err) => {
+                            let is_pdsc = dest.extension().is_some_and(|ext| ext == "pdsc");
+                            if is_pdsc && err.downcast_ref() == Some(&HttpStatus(404)) {
+                                let policy = self.config.vanished_policy();
+                                let local =
+                                    vanished.vanished(&pack_store, &mut listing, &source, policy);
+                                tracing::warn!(target: STORE, url = %source, local = ?local, "PDSC file vanished upstream");
+                                self.prog.pack_vanished(source.as_str(), &local);
+                            }
+                            self.prog.download_failed(source.as_str(), &err);
+                        }
                     }
+                    claims.remove(&dest);
                 } else {
-                    next.push(handle);
+                    next.push((handle, dest));
                 }
             }
 
-            while ! to_dl.is_empty() && started < CONCURRENCY {
+            while !to_dl.is_empty() && started < concurrency {
                 let from = to_dl.pop().unwrap();
                 let host = from.1.clone();
                 let entry = hosts.entry(host).or_insert(0);
-                if *entry >= HOST_LIMIT  {
+                if *entry >= self.profile.host_limit() {
                     wait_list.push(from);
                 } else {
                     let source = from.0.clone();
                     let host = from.1.clone();
                     let dest = from.2.clone();
-                    if dest.exists() {
+                    let checksum = from.3.clone();
+                    let is_pdsc = dest.extension().is_some_and(|ext| ext == "pdsc");
+                    let is_pack = is_pack(&dest);
+                    let mut listed = !self.config.refresh() && listing.contains(&dest);
+                    if listed && is_pdsc && !is_complete_pdsc(&dest) {
+                        tracing::warn!(target: STORE, path = ?dest, "Downloading an incomplete PDSC file again");
+                        listed = false;
+                    }
+                    let stale = listed
+                        && is_pdsc
+                        && updated
+                            .get(&dest)
+                            .is_some_and(|at| validators.is_stale(&pack_store, &dest, at));
+                    if stale {
+                        tracing::info!(target: STORE, path = ?dest, "The index lists a newer PDSC file");
+                        listed = false;
+                    }
+                    let needs_extract =
+                        |dest: &Path| is_pack && self.extract_packs && !extract_dir(dest).exists();
+                    let was_deferred = deferred.contains(&dest);
+                    if !listed || was_deferred || needs_extract(&dest) {
+                        match Claim::acquire(&dest) {
+                            Ok(Some(claim)) => {
+                                claims.insert(dest.clone(), claim);
+                            }
+                            Ok(None) => {
+                                tracing::debug!(target: STORE, path = ?dest, "Another process is writing the file");
+                                deferred.insert(dest);
+                                wait_list.push(from);
+                                continue;
+                            }
+                            Err(err) => {
+                                self.prog.download_failed(source.as_str(), &err.into());
+                                continue;
+                            }
+                        }
+                    }
+                    // Another process may have written the file while this
+                    // one waited for its claim
+                    let present = listed
+                        || (was_deferred && dest.exists() && (!is_pdsc || is_complete_pdsc(&dest)));
+                    if present {
+                        if needs_extract(&dest) {
+                            extract(source, dest, &mut extracting);
+                            continue;
+                        }
+                        claims.remove(&dest);
+                        if is_pdsc {
+                            self.prog.pdsc_skipped(&dest);
+                        }
                         results.push(dest);
                     } else {
-                        let client = self.client.clone();
-                        let handle: JoinHandle<(String, usize, Option<PathBuf>)> = tokio::spawn(async move {
+                        self.prog.download_started(source.as_str());
+                        let transfer = Transfer {
+                            fetcher: self.fetcher.clone(),
+                            bodies: self.bodies.clone(),
+                            strict_utf8: self.config.strict_utf8(),
+                            require_checksum: self.config.require_checksum(),
+                            read_timeout: self.config.timeouts().read,
+                            mirrors: self.mirrors.clone(),
+                            rate: self.rate.clone(),
+                            shared: self.shared.clone(),
+                        };
+                        let part_dest = dest.clone();
+                        let policy = self.config.retry_policy();
+                        let sent = if is_pdsc && (self.config.refresh() || stale) {
+                            validators.get(&pack_store, &dest).unwrap_or_default()
+                        } else {
+                            Validators::default()
+                        };
+                        let received_tx = received_tx.clone();
+                        let url = source.to_string();
+                        let report = move |bytes, total| {
+                            let _ = received_tx.send((url.clone(), bytes, total));
+                        };
+                        let span = tracing::debug_span!(target: NETWORK, "download", host = %host, url = %source);
+                        let handle: JoinHandle<DownloadResult> = tokio::spawn(async move {
                             dest.parent().map(create_dir_all);
-                            let res = client.get(source.clone()).send().await;
-                            let res: Result<(usize, PathBuf), Error> = match res {
-                                Ok(r) => {
-                                    let rc = r.status().as_u16();
-                                    if rc >= 400 {
-                                        Err(anyhow!(format!("Response code in invalid range: {}", rc).to_string()))
-                                    } else {
-                                        save_response(r, dest).await
-                                    }
-                                },
-                                Err(err) => {
-                                    Err(anyhow!(err.to_string()))
-                                },
-                            };
+                            let res = retry(policy, source.as_str(), || {
+                                fetch_and_save(
+                                    &transfer,
+                                    &source,
+                                    &dest,
+                                    &sent,
+                                    checksum.as_ref(),
+                                    &report,
+                                )
+                            })
+                            .await;
                             match res {
                                 Ok(r) => {
-                                    (host, r.0, Some(r.1))
+                                    (host, source, r.0, Ok((r.1, r.2, r.3)))
                                 },
                                 Err(err) => {
-                                    log::warn!("Download of {} failed: {}", source.to_string(), err);
-                                    (host, 0, None)
+                                    tracing::warn!(target: NETWORK, url = %source, error = %err, "Download failed");
+                                    (host, source, 0, Err(err))
                                 }
                             }
-                        });
-                        handles.push(handle);
+                        }.instrument(span));
+                        handles.push((handle, part_dest));
                         started += 1;
                         *entry += 1;
                     }
@@ -283,18 +1219,150 @@ where
             sleep(Duration::from_millis(100)).await;
         }
 
-        results
+        if let Err(err) = origins.save(&pack_store) {
+            tracing::warn!(target: STORE, error = %err, "Could not save the origins of downloads");
+        }
+        if let Err(err) = vanished.save(&pack_store) {
+            tracing::warn!(target: STORE, error = %err, "Could not save the vanished packs");
+        }
+        if let Err(err) = validators.save(&pack_store) {
+            tracing::warn!(target: STORE, error = %err, "Could not save the validators of downloads");
+        }
+        match offline {
+            Some(err) => Err(err),
+            None => Ok(results),
+        }
+    }
+
+    fn served_from(
+        &self,
+        origins: &mut OriginLog,
+        pack_store: &Path,
+        path: &Path,
+        declared: &Url,
+        actual: &Url,
+    ) {
+        origins.record(pack_store, path, declared, actual);
+        if !other_origin(declared, actual) {
+            return;
+        }
+        self.prog.served_from(declared.as_str(), actual.as_str());
+        if self.config.origin_warnings() {
+            tracing::warn!(target: NETWORK, url = %declared, actual = %actual, "Served from another origin");
+        } else {
+            tracing::debug!(target: NETWORK, url = %declared, actual = %actual, "Served from another origin");
+        }
+    }
+
+    pub(crate) async fn update_vidx<I>(&'a self, list: I) -> Result<(), Error>
+    where
+        I: IntoIterator + 'a,
+        <I as IntoIterator>::Item: Into<String>,
+    {
+        let mut pdscs = self.fetch_pdsc_refs(list, true).await?;
+        let pack_store = self.config.pack_store();
+        if !self.config.allow_downgrade() {
+            let mut listing = StoreListing::default();
+            pdscs.retain(
+                |pdsc| match newer_local_pdsc(&mut listing, &pack_store, pdsc) {
+                    Some((path, version)) => {
+                        tracing::warn!(
+                            target: NETWORK,
+                            pack = %format!("{}.{}", pdsc.vendor, pdsc.name),
+                            local = %version,
+                            listed = %pdsc.version,
+                            "Not downgrading a PDSC file"
+                        );
+                        self.prog.pdsc_skipped(&path);
+                        false
+                    }
+                    None => true,
+                },
+            );
+        }
+
+        self.download_iterator(pdscs).await?;
+        Ok(())
+    }
+
+    /// The PDSC downloads an update from `list` would perform, without
+    /// writing anything to the pack store
+    ///
+    /// Sizes come from the indexes where they list them, and otherwise from
+    /// `HEAD` requests.
+    pub(crate) async fn plan_vidx<I>(&'a self, list: I) -> Result<Vec<PlannedDownload>, Error>
+    where
+        I: IntoIterator + 'a,
+        <I as IntoIterator>::Item: Into<String>,
+    {
+        let pdscs = self.fetch_pdsc_refs(list, false).await?;
+        let listed: HashMap<String, u64> = pdscs
+            .iter()
+            .filter_map(|pdsc| {
+                let size = pdsc.size.as_deref()?.parse().ok()?;
+                Some((pdsc.into_uri().ok()?.to_string(), size))
+            })
+            .collect();
+        let planned = plan_update(self.config, &pdscs)
+            .into_iter()
+            .map(|download| PlannedDownload {
+                size: listed.get(&download.url).copied(),
+                ..download
+            })
+            .collect();
+        Ok(self.measure(planned).await)
+    }
+
+    /// Fill in the sizes of the planned downloads that would be performed,
+    /// where not known yet, with `HEAD` requests
+    ///
+    /// Files whose size a server does not tell are left without one.
+    pub(crate) async fn measure(&self, planned: Vec<PlannedDownload>) -> Vec<PlannedDownload> {
+        let concurrency = self.config.concurrency().max(1);
+        stream::iter(planned)
+            .map(|mut download| async move {
+                if download.size.is_some() || !download.reason.downloads() {
+                    return download;
+                }
+                let url = match source_url(&download.url) {
+                    Ok(url) => url,
+                    Err(_) => return download,
+                };
+                let measured = with_mirrors(&self.mirrors, &url, |url| async move {
+                    self.rate.acquire(&url).await;
+                    self.fetcher.content_length(url).await
+                });
+                match measured.await {
+                    Ok(size) => download.size = size,
+                    Err(err) => {
+                        tracing::warn!(target: NETWORK, url = %download.url, "Could not get the size: {}", err)
+                    }
+                }
+                download
+            })
+            .buffered(concurrency)
+            .collect()
+            .await
     }
 
-    pub(crate) async fn update_vidx<I>(&'a self, list: I) -> Result<Vec<PathBuf>, Error>
+    /// The PDSC entries of the indexes reachable from `list`, each pack
+    /// once and only those of the vendors the config allows
+    ///
+    /// With `record`, the fetched indexes are cached and their deprecation
+    /// notices logged in the pack store; without it nothing is written.
+    async fn fetch_pdsc_refs<I>(&'a self, list: I, record: bool) -> Result<Vec<PdscRef>, Error>
     where
         I: IntoIterator + 'a,
         <I as IntoIterator>::Item: Into<String>,
     {
+        let concurrency = self.config.concurrency().max(1);
         let mut downloaded: HashMap<String, bool> = HashMap::new();
-        let mut failures: HashMap<String, usize> = HashMap::new();
         let mut urls: Vec<String> = list.into_iter().map(|x| x.into()).collect();
         let mut vidxs: Vec<Vidx> = Vec::new();
+        let pack_store = self.config.pack_store();
+        let mut cache = IndexCache::load(&pack_store);
+        // Timestamps the parent indexes list for the vendor indexes
+        let mut listed: HashMap<String, String> = HashMap::new();
         loop {
             // Remove from list all duplicate URLs and those already downloaded
             urls.dedup();
@@ -303,28 +1371,62 @@ where
                 .filter(|u| !*downloaded.get(u).unwrap_or(&false))
                 .collect();
 
-            // TODO: Make this section asynchronous
+            // The indexes of a round are fetched concurrently, and handled in
+            // order so that the first listing of a pack wins
+            let fetches: Vec<IndexFetch> = stream::iter(urls)
+                .map(|url| {
+                    let cached = listed.get(&url).and_then(|ts| cache.unchanged(&url, ts));
+                    async move {
+                        let fetched = match cached {
+                            Some(t) => Ok((t, false)),
+                            None if self.cancel.is_cancelled() => {
+                                Err(crate::Error::Cancelled.into())
+                            }
+                            None => retry(self.config.retry_policy(), &url, || {
+                                self.download_vidx(url.clone())
+                            })
+                            .await
+                            .map(|t| (t, true)),
+                        };
+                        (url, fetched)
+                    }
+                })
+                .buffered(concurrency)
+                .collect()
+                .await;
+            if self.cancel.is_cancelled() {
+                return Err(crate::Error::Cancelled.into());
+            }
+
             let mut next: Vec<String> = Vec::new();
-            for url in urls {
-                match self.download_vidx(url.clone()).await {
-                    Ok(t) => {
-                        log::info!("Downloaded {}", url);
+            for (url, fetched) in fetches {
+                match fetched {
+                    Ok((t, downloaded_now)) => {
+                        if downloaded_now {
+                            tracing::debug!(target: NETWORK, url = %url, "Downloaded index");
+                            self.prog.source_fetched(&url);
+                            if let Some(ts) = listed.get(&url) {
+                                cache.insert(url.clone(), ts.clone(), &t);
+                            }
+                        } else {
+                            tracing::debug!(target: NETWORK, url = %url, "Index unchanged since the last update");
+                        }
                         downloaded.insert(url, true);
                         for v in &t.vendor_index {
                             let u = format!("{}{}.pidx", v.url, v.vendor);
                             if !downloaded.contains_key(&u) {
+                                if let Some(ts) = listed_timestamp(v) {
+                                    listed.insert(u.clone(), ts.clone());
+                                }
                                 downloaded.insert(u.clone(), false);
                                 next.push(u);
                             }
                         }
                         vidxs.push(t);
                     }
-                    Err(_err) => {
-                        let tries = failures.entry(url.clone()).or_insert(0);
-                        *tries += 1;
-                        if *tries < MAX_RETRIES {
-                            next.push(url);
-                        }
+                    Err(err) if is_offline(&err) => return Err(err),
+                    Err(err) => {
+                        self.prog.download_failed(&url, &err);
                     }
                 }
             }
@@ -338,11 +1440,32 @@ where
         for mut v in vidxs {
             pdscs.append(&mut v.pdsc_index);
         }
+        if record {
+            if let Err(err) = cache.save(&pack_store) {
+                tracing::warn!(target: STORE, error = %err, "Could not save the index cache");
+            }
+            let mut deprecations = DeprecationLog::load(&pack_store);
+            deprecations.record(&pdscs);
+            if let Err(err) = deprecations.save(&pack_store) {
+                tracing::warn!(target: STORE, error = %err, "Could not save the deprecation notices");
+            }
+        }
 
-        pdscs.dedup_by_key(|pdsc| pdsc_url(pdsc));
-        log::info!("Found {} Pdsc entries", pdscs.len());
-
-        Ok(self.download_iterator(pdscs.into_iter()).await)
+        // Vendor and pack names are case-insensitive, so `Keil.X` and
+        // `KEIL.X` are downloaded once
+        let mut seen = HashSet::new();
+        pdscs.retain(|pdsc| seen.insert(pack_id(&pdsc.vendor, &pdsc.name)));
+        tracing::info!(target: NETWORK, count = pdscs.len(), "Found Pdsc entries");
+        let filter = self.config.vendor_filter();
+        if !filter.is_empty() {
+            pdscs.retain(|pdsc| filter.allows(&pdsc.vendor));
+            tracing::info!(
+                target: NETWORK,
+                count = pdscs.len(),
+                "Kept the Pdsc entries of allowed vendors"
+            );
+        }
+        Ok(pdscs)
     }
 
     pub(crate) async fn download_vidx<I: Into<String>>(
@@ -350,10 +1473,19 @@ where
         vidx_ref: I,
     ) -> Result<Vidx, Error> {
         let vidx = vidx_ref.into();
-        let uri = vidx.parse::<Url>().unwrap();
+        let uri = source_url(&vidx)?;
 
-        let req: reqwest::Response = self.client.get(uri).send().await?;
-        Vidx::from_string(req.text().await?.as_str())
+        let read_timeout = self.config.timeouts().read;
+        let body = with_mirrors(&self.mirrors, &uri, |url| async move {
+            self.rate.acquire(&url).await;
+            within(read_timeout, self.fetcher.get(url)).await
+        })
+        .await?;
+        let contents = {
+            let _permit = self.bodies.acquire().await?;
+            read_to_string(body, &vidx, self.config.strict_utf8(), read_timeout).await?
+        };
+        Vidx::from_string(contents.as_str())
     }
 
     #[allow(dead_code)]
@@ -365,15 +1497,15 @@ where
         list.into_iter()
             .map(|vidx_ref| {
                 let vidx = vidx_ref.into();
-                println!("{}", vidx);
+                tracing::debug!(target: NETWORK, url = %vidx, "Downloading index");
                 self.download_vidx(vidx.clone()).then(|r| async move {
                     match r {
                         Ok(v) => {
-                            println!("{} success", vidx);
+                            tracing::debug!(target: NETWORK, url = %vidx, "Downloaded index");
                             Some(v)
                         }
                         Err(e) => {
-                            log::error!("{}", format!("{}", e).replace("uri", &vidx));
+                            tracing::error!(target: NETWORK, "{}", format!("{}", e).replace("uri", &vidx));
                             None
                         }
                     }
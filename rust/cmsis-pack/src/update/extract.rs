@@ -0,0 +1,262 @@
+use std::fs::{create_dir_all, remove_dir_all, remove_file, rename, File};
+use std::io::{copy, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{format_err, Error};
+use zip::read::ZipFile;
+
+/// How many times its own size an archive may unpack to; zip bombs reach
+/// thousands
+const MAX_RATIO: u64 = 100;
+/// How much any archive may unpack to, so that small archives of highly
+/// compressible files are not mistaken for zip bombs
+const MIN_ALLOWANCE: u64 = 16 << 20;
+
+/// The most the archive `pack` may unpack to
+fn allowance(pack: &Path) -> Result<u64, Error> {
+    let size = pack.metadata()?.len();
+    Ok(size.saturating_mul(MAX_RATIO).max(MIN_ALLOWANCE))
+}
+
+/// Copy `entry` to `dest`, reading no more than the size it declares so that
+/// an archive cannot understate the size of its entries
+fn copy_entry<W: Write>(entry: &mut ZipFile, dest: W) -> Result<(), Error> {
+    let declared = entry.size();
+    let name = entry.name().to_string();
+    let copied = copy(
+        &mut entry.take(declared.saturating_add(1)),
+        &mut BufWriter::new(dest),
+    )?;
+    if copied > declared {
+        return Err(format_err!("{} is larger than it declares", name));
+    }
+    Ok(())
+}
+
+/// The directory a pack archive is extracted into: `Vendor/Name/1.0.0.pack`
+/// goes to `Vendor/Name/1.0.0/`
+pub(crate) fn extract_dir(pack: &Path) -> PathBuf {
+    pack.with_extension("")
+}
+
+/// The directory single files of a pack archive are extracted into:
+/// `Vendor/Name/1.0.0.pack` keeps them in `Vendor/Name/.files/1.0.0/`
+pub(crate) fn files_dir(pack: &Path) -> PathBuf {
+    let version = pack.file_stem().unwrap_or_default();
+    pack.with_file_name(".files").join(version)
+}
+
+/// The file `name` of an installed pack, as a PDSC file refers to it
+///
+/// The file is taken from the extracted pack when there is one. Otherwise
+/// it alone is extracted from the archive `pack` into [`files_dir`], where
+/// later calls find it.
+pub(crate) fn pack_file(pack: &Path, name: &str) -> Result<PathBuf, Error> {
+    // PDSC files often separate directories with backslashes
+    let name = name.replace('\\', "/");
+    let relative = Path::new(&name);
+    if !relative
+        .components()
+        .all(|part| matches!(part, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(format_err!("Unsafe path {:?} in {:?}", name, pack));
+    }
+    let extracted = extract_dir(pack).join(relative);
+    if extracted.is_file() {
+        return Ok(extracted);
+    }
+    let cached = files_dir(pack).join(relative);
+    if cached.is_file() {
+        return Ok(cached);
+    }
+
+    let mut archive = zip::ZipArchive::new(File::open(pack)?)?;
+    let wanted = relative
+        .to_string_lossy()
+        .trim_start_matches("./")
+        .to_string();
+    // Pack authors test on Windows, where the case of a path does not matter
+    let found = archive
+        .file_names()
+        .find(|entry| entry.eq_ignore_ascii_case(&wanted))
+        .map(str::to_string)
+        .ok_or_else(|| format_err!("No {} in {:?}", name, pack))?;
+    let mut entry = archive.by_name(&found)?;
+    if entry.size() > allowance(pack)? {
+        return Err(format_err!("{} in {:?} is too large", name, pack));
+    }
+    if let Some(parent) = cached.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut scratch = cached.clone().into_os_string();
+    scratch.push(".part");
+    let scratch = PathBuf::from(scratch);
+    let res = copy_entry(&mut entry, File::create(&scratch)?)
+        .and_then(|()| Ok(rename(&scratch, &cached)?));
+    match res {
+        Ok(()) => Ok(cached),
+        Err(err) => {
+            let _ = remove_file(&scratch);
+            Err(err)
+        }
+    }
+}
+
+/// Verify and extract a downloaded pack archive next to it
+///
+/// Entries are decompressed into a scratch directory, and the CRC of each is
+/// checked as it is read. Only once every entry checked out is the scratch
+/// directory renamed into place, so a corrupt archive leaves nothing behind.
+/// Entries outside of the directory, such as `../x` or `/x`, fail the
+/// extraction, as do archives unpacking to more than [`MAX_RATIO`] times
+/// their size.
+pub(crate) fn extract_pack(pack: &Path) -> Result<PathBuf, Error> {
+    let dest = extract_dir(pack);
+    let mut scratch = dest.clone().into_os_string();
+    scratch.push(".extracting");
+    let scratch = PathBuf::from(scratch);
+    let _ = remove_dir_all(&scratch);
+    let res = extract_into(pack, &scratch).and_then(|()| {
+        if dest.exists() {
+            remove_dir_all(&dest)?;
+        }
+        rename(&scratch, &dest)?;
+        Ok(())
+    });
+    match res {
+        Ok(()) => Ok(dest),
+        Err(err) => {
+            let _ = remove_dir_all(&scratch);
+            Err(err)
+        }
+    }
+}
+
+fn extract_into(pack: &Path, dir: &Path) -> Result<(), Error> {
+    let mut archive = zip::ZipArchive::new(File::open(pack)?)?;
+    let mut unpacked: u64 = 0;
+    for i in 0..archive.len() {
+        unpacked = unpacked.saturating_add(archive.by_index_raw(i)?.size());
+    }
+    if unpacked > allowance(pack)? {
+        return Err(format_err!(
+            "{:?} would unpack to {} bytes, too many for its size",
+            pack,
+            unpacked
+        ));
+    }
+    create_dir_all(dir)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let path = match entry.enclosed_name() {
+            Some(path) => dir.join(path),
+            None => return Err(format_err!("Unsafe path {:?} in {:?}", entry.name(), pack)),
+        };
+        if entry.is_dir() {
+            create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        // Reading an entry to its end fails when its CRC does not match
+        copy_entry(&mut entry, File::create(&path)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    fn write_pack(path: &Path, files: &[(&str, &str)]) {
+        // Stored, so that the contents can be corrupted in place
+        let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        write_zip(path, files, options);
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &str)], options: FileOptions) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn packs_are_extracted_next_to_the_archive() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+
+        let pack = dir.join("1.0.0.pack");
+        write_pack(&pack, &[("V.P.pdsc", "<package/>"), ("inc/p.h", "int p;")]);
+        let extracted = extract_pack(&pack).unwrap();
+        assert_eq!(extracted, dir.join("1.0.0"));
+        let read = |path: &str| std::fs::read_to_string(extracted.join(path)).unwrap();
+        assert_eq!(read("V.P.pdsc"), "<package/>");
+        assert_eq!(read("inc/p.h"), "int p;");
+
+        // A corrupt archive leaves neither a scratch nor a final directory
+        let corrupt = dir.join("2.0.0.pack");
+        let mut bytes = std::fs::read(&pack).unwrap();
+        let at = bytes.windows(6).position(|w| w == b"int p;").unwrap();
+        bytes[at] = b'x';
+        std::fs::write(&corrupt, bytes).unwrap();
+        assert!(extract_pack(&corrupt).is_err());
+        assert!(!dir.join("2.0.0").exists());
+        assert!(!dir.join("2.0.0.extracting").exists());
+    }
+
+    #[test]
+    fn unsafe_archives_are_not_extracted() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(dir.join("store")).unwrap();
+
+        for (version, name) in [("1.0.0", "../escape.h"), ("2.0.0", "/abs.h")] {
+            let pack = dir.join("store").join(version).with_extension("pack");
+            write_pack(&pack, &[("V.P.pdsc", "<package/>"), (name, "int x;")]);
+            assert!(extract_pack(&pack).is_err(), "{}", name);
+            assert!(!extract_dir(&pack).exists());
+        }
+        assert!(!dir.join("escape.h").exists());
+
+        // Zeros compress a thousandfold, too much for a pack archive
+        let zeros = "\0".repeat(MIN_ALLOWANCE as usize + 1);
+        let deflated = FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        let bomb = dir.join("store/3.0.0.pack");
+        write_zip(&bomb, &[("zeros.bin", &zeros)], deflated);
+        assert!(extract_pack(&bomb).is_err());
+        assert!(pack_file(&bomb, "zeros.bin").is_err());
+        assert!(!extract_dir(&bomb).exists());
+    }
+
+    #[test]
+    fn single_files_are_extracted_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+
+        let pack = dir.join("1.0.0.pack");
+        write_pack(&pack, &[("SVD/Dev.svd", "<device/>")]);
+        let svd = pack_file(&pack, "svd\\dev.svd").unwrap();
+        assert_eq!(svd, dir.join(".files/1.0.0/svd/dev.svd"));
+        assert_eq!(std::fs::read_to_string(&svd).unwrap(), "<device/>");
+
+        // The cached file is used once the archive is gone, and an extracted
+        // pack takes precedence over it
+        remove_file(&pack).unwrap();
+        assert_eq!(pack_file(&pack, "svd/dev.svd").unwrap(), svd);
+        create_dir_all(dir.join("1.0.0/svd")).unwrap();
+        std::fs::write(dir.join("1.0.0/svd/dev.svd"), "<device/>").unwrap();
+        assert_eq!(
+            pack_file(&pack, "svd/dev.svd").unwrap(),
+            dir.join("1.0.0/svd/dev.svd")
+        );
+        assert!(pack_file(&pack, "../escape.svd").is_err());
+    }
+}
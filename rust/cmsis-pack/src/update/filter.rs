@@ -0,0 +1,45 @@
+/// The vendors whose PDSC files an update downloads
+///
+/// Vendor names are compared ignoring case. An empty `allow` list allows
+/// every vendor, and `deny` wins over `allow`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VendorFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
+
+impl VendorFilter {
+    /// Whether the PDSC files of `vendor` are downloaded
+    pub fn allows(&self, vendor: &str) -> bool {
+        let listed = |list: &[String]| list.iter().any(|v| v.eq_ignore_ascii_case(vendor));
+        (self.allow.is_empty() || listed(&self.allow)) && !listed(&self.deny)
+    }
+
+    /// Whether every vendor is allowed
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vendors_are_allowed_and_denied() {
+        assert!(VendorFilter::default().allows("Keil"));
+
+        let filter = VendorFilter {
+            allow: vec!["ST".into(), "NordicSemiconductor".into()],
+            deny: Vec::new(),
+        };
+        assert!(filter.allows("st") && filter.allows("NordicSemiconductor"));
+        assert!(!filter.allows("Keil") && !filter.allows("STM"));
+
+        let filter = VendorFilter {
+            deny: vec!["st".into()],
+            ..filter
+        };
+        assert!(!filter.allows("ST") && filter.allows("NordicSemiconductor"));
+    }
+}
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{format_err, Error};
+use reqwest::Url;
+
+use crate::log::STORE;
+use crate::pdsc::{PackRequirement, Package};
+use crate::update::deprecated::{deprecated_packs, deprecation_of};
+use crate::update::download::{
+    local_pdscs, DownloadConfig, DownloadContext, DownloadProgress, IntoDownload,
+};
+use crate::update::extract::extract_dir;
+use crate::update::fetch::source_url;
+use crate::update::listing::StoreListing;
+use crate::update::plan::{plan, PlannedDownload};
+use crate::update::CancellationToken;
+use crate::utils::parse::from_store_path;
+use crate::utils::{compare_versions, pack_id, VersionReq};
+
+/// A pack to install: `Vendor::Name` for its latest release,
+/// `Vendor::Name@1.2.0` for a given one, or `Vendor::Name@^1.2` for the
+/// newest release meeting a [`VersionReq`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackSpec {
+    pub vendor: String,
+    pub name: String,
+    pub version: Option<String>,
+}
+
+impl PackSpec {
+    /// The requirement of the version, if any; one that does not parse
+    /// names a release literally
+    pub fn version_req(&self) -> Option<VersionReq> {
+        let version = self.version.as_deref()?;
+        Some(
+            version
+                .parse()
+                .unwrap_or_else(|_| VersionReq::exact(version)),
+        )
+    }
+}
+
+/// Whether `part` of a pack spec names a single file in a directory, so
+/// joining it to a path in the pack store stays in that directory
+pub(crate) fn is_file_name(part: &str) -> bool {
+    let mut components = Path::new(part).components();
+    !part.contains(['/', '\\'])
+        && !part.contains("..")
+        && matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+}
+
+impl FromStr for PackSpec {
+    type Err = Error;
+
+    fn from_str(from: &str) -> Result<Self, Self::Err> {
+        let (pack, version) = match from.split_once('@') {
+            Some((pack, version)) => (pack, Some(version)),
+            None => (from, None),
+        };
+        if let Some(version) = version {
+            if !is_file_name(version) {
+                return Err(format_err!("Invalid version in {}", from));
+            }
+            version.parse::<VersionReq>()?;
+        }
+        match pack.split_once("::") {
+            Some((vendor, name)) if !is_file_name(vendor) || !is_file_name(name) => {
+                Err(format_err!("Invalid vendor or pack name in {}", from))
+            }
+            Some((vendor, name)) => Ok(PackSpec {
+                vendor: vendor.to_string(),
+                name: name.to_string(),
+                version: version.map(String::from),
+            }),
+            _ => Err(format_err!(
+                "Expected Vendor::Pack or Vendor::Pack@version, found {}",
+                from
+            )),
+        }
+    }
+}
+
+impl fmt::Display for PackSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}::{}", self.vendor, self.name)?;
+        match &self.version {
+            Some(version) => write!(f, "@{}", version),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The pack archive of one release of a package, which need not be the
+/// latest one
+struct PackRelease<'a> {
+    pdsc: &'a Package,
+    version: String,
+}
+
+impl<'a> IntoDownload for PackRelease<'a> {
+    fn into_uri(&self) -> Result<Url, Error> {
+        let Package {
+            name, vendor, url, ..
+        } = self.pdsc;
+        let uri = if url.ends_with('/') {
+            format!("{}{}.{}.{}.pack", url, vendor, name, self.version)
+        } else {
+            format!("{}/{}.{}.{}.pack", url, vendor, name, self.version)
+        };
+        source_url(&uri)
+    }
+
+    fn into_fd<D: DownloadConfig>(&self, config: &D) -> PathBuf {
+        let mut filename = config.pack_store();
+        filename.push(Path::new(&self.pdsc.vendor));
+        filename.push(Path::new(&self.pdsc.name));
+        filename.push(format!("{}.pack", self.version));
+        filename
+    }
+}
+
+/// A pack [`resolve_pack`] chose, at the release to install, with the pack
+/// whose requirement pulled it in
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedPack {
+    pub spec: PackSpec,
+    pub required_by: Option<PackSpec>,
+}
+
+/// A pack found while resolving, with the requirements on it and the packs
+/// stating them
+struct Found {
+    pdsc: Package,
+    required_by: Option<PackSpec>,
+    requirements: Vec<(PackRequirement, PackSpec)>,
+}
+
+fn unavailable(spec: &PackSpec, reason: String) -> crate::Error {
+    crate::Error::Pack {
+        pack: spec.to_string(),
+        source: reason.into(),
+    }
+}
+
+/// The newest PDSC file of the pack of `spec` in the store
+fn newest_pdsc(pack_store: &Path, spec: &PackSpec) -> Result<Package, crate::Error> {
+    let mut listing = StoreListing::default();
+    let (path, _) = local_pdscs(&mut listing, pack_store, &spec.vendor, &spec.name)
+        .into_iter()
+        .max_by(|(_, left), (_, right)| compare_versions(left, right))
+        .ok_or_else(|| unavailable(spec, "no PDSC file in the pack store; update first".into()))?;
+    from_store_path::<Package>(&path).map_err(|err| crate::Error::with_path(err, path))
+}
+
+/// The release of `found` to install: the newest one that `spec` and every
+/// requirement on the pack accept
+fn choose_release(found: &Found, spec: &PackSpec) -> Result<String, crate::Error> {
+    let listed = || {
+        let listed: Vec<&str> = found
+            .pdsc
+            .releases
+            .iter()
+            .map(|r| r.version.as_str())
+            .collect();
+        listed.join(", ")
+    };
+    let wanted = spec.version_req();
+    if let Some(wanted) = &wanted {
+        if !found
+            .pdsc
+            .releases
+            .iter()
+            .any(|r| wanted.matches(&r.version))
+        {
+            let reason = match wanted.is_exact() {
+                true => format!("no release {}; the PDSC lists {}", wanted, listed()),
+                false => format!("no release matches {}; the PDSC lists {}", wanted, listed()),
+            };
+            return Err(unavailable(spec, reason));
+        }
+    }
+    let accepted = |version: &String| {
+        wanted.as_ref().is_none_or(|wanted| wanted.matches(version))
+            && found
+                .requirements
+                .iter()
+                .all(|(req, _)| req.accepts(version))
+    };
+    let releases = found.pdsc.releases.iter().map(|release| &release.version);
+    match releases
+        .filter(|version| accepted(version))
+        .max_by(|left, right| compare_versions(left, right))
+    {
+        Some(version) => Ok(version.clone()),
+        None if found.requirements.is_empty() => {
+            Err(unavailable(spec, "the PDSC lists no release".into()))
+        }
+        None => {
+            let ranges: Vec<String> = found
+                .requirements
+                .iter()
+                .map(|(req, by)| {
+                    let range = req.version.as_deref().unwrap_or("any");
+                    format!("{} by {}", range, by)
+                })
+                .collect();
+            let reason = format!(
+                "no release is accepted by the requirements {}; the PDSC lists {}",
+                ranges.join(", "),
+                listed()
+            );
+            Err(unavailable(spec, reason))
+        }
+    }
+}
+
+/// `spec` and, when `dependencies`, the packs it requires and those they
+/// require in turn, in the order they were found, with their PDSC files
+fn resolve(
+    pack_store: &Path,
+    spec: &PackSpec,
+    dependencies: bool,
+) -> Result<Vec<(ResolvedPack, Package)>, crate::Error> {
+    let mut found = vec![Found {
+        pdsc: newest_pdsc(pack_store, spec)?,
+        required_by: None,
+        requirements: Vec::new(),
+    }];
+    let mut known = HashMap::from([(pack_id(&spec.vendor, &spec.name), 0)]);
+    let mut next = 0;
+    // The requirements of a pack come from its newest PDSC file whatever
+    // release is chosen, so they are all known before any is chosen
+    while dependencies && next < found.len() {
+        let pdsc = &found[next].pdsc;
+        let requirer = PackSpec {
+            vendor: pdsc.vendor.clone(),
+            name: pdsc.name.clone(),
+            version: None,
+        };
+        for requirement in pdsc.requirements.clone() {
+            let id = pack_id(&requirement.vendor, &requirement.name);
+            let at = match known.get(&id) {
+                Some(&at) => at,
+                None => {
+                    let required = PackSpec {
+                        vendor: requirement.vendor.clone(),
+                        name: requirement.name.clone(),
+                        version: None,
+                    };
+                    let pdsc = newest_pdsc(pack_store, &required).map_err(|err| match err {
+                        crate::Error::Pack { pack, source } => crate::Error::Pack {
+                            pack,
+                            source: format!("required by {}: {}", requirer, source).into(),
+                        },
+                        err => err,
+                    })?;
+                    found.push(Found {
+                        pdsc,
+                        required_by: Some(requirer.clone()),
+                        requirements: Vec::new(),
+                    });
+                    known.insert(id, found.len() - 1);
+                    found.len() - 1
+                }
+            };
+            found[at].requirements.push((requirement, requirer.clone()));
+        }
+        next += 1;
+    }
+    found
+        .into_iter()
+        .enumerate()
+        .map(|(at, found)| {
+            let wanted = match at {
+                0 => spec.clone(),
+                _ => PackSpec {
+                    vendor: found.pdsc.vendor.clone(),
+                    name: found.pdsc.name.clone(),
+                    version: None,
+                },
+            };
+            let version = choose_release(&found, &wanted)?;
+            let resolved = ResolvedPack {
+                spec: PackSpec {
+                    version: Some(version),
+                    ..wanted
+                },
+                required_by: found.required_by,
+            };
+            Ok((resolved, found.pdsc))
+        })
+        .collect()
+}
+
+/// The newest PDSC file of the pack of `spec` in the store, with the
+/// version of the release `spec` names
+fn find_release(pack_store: &Path, spec: &PackSpec) -> Result<(Package, String), crate::Error> {
+    let (resolved, pdsc) = resolve(pack_store, spec, false)?.swap_remove(0);
+    Ok((pdsc, resolved.spec.version.unwrap_or_default()))
+}
+
+/// The packs [`install_pack`] installs for `spec`, each at the release it
+/// installs: `spec` first, then, under
+/// [`DownloadConfig::install_dependencies`], the packs it requires
+///
+/// The packs required by a pack are those its newest PDSC file in the pack
+/// store lists in its `<requirements>`, and those they require in turn.
+/// Each is installed at the newest release that all the requirements on it
+/// accept. A required pack missing from the pack store, or whose releases
+/// the requirements all rule out, fails the resolution.
+pub fn resolve_pack<D: DownloadConfig>(
+    config: &D,
+    spec: &PackSpec,
+) -> Result<Vec<ResolvedPack>, crate::Error> {
+    let resolved = resolve(&config.pack_store(), spec, config.install_dependencies())?;
+    Ok(resolved.into_iter().map(|(resolved, _)| resolved).collect())
+}
+
+/// The pack download [`install_pack`] would perform for `spec`
+pub fn plan_install_pack<D: DownloadConfig>(
+    config: &D,
+    spec: &PackSpec,
+) -> Result<PlannedDownload, crate::Error> {
+    let (pdsc, version) = find_release(&config.pack_store(), spec)?;
+    let release = PackRelease {
+        pdsc: &pdsc,
+        version,
+    };
+    plan(config, Some(release), |_, name| name.ends_with(".pack"))
+        .pop()
+        .ok_or_else(|| crate::Error::Pack {
+            pack: spec.to_string(),
+            source: "the PDSC gives no valid pack URL".into(),
+        })
+}
+
+/// Download and extract the pack archive of `spec`, returning the directory
+/// it was extracted into
+///
+/// The pack URL and its releases come from the newest PDSC file of the pack
+/// in the pack store, so run an update first. The archive is checked
+/// against the size the server announced, and is extracted into a directory
+/// named after its version next to it, whatever
+/// [`DownloadConfig::extract_packs`] says. An archive already installed is
+/// not downloaded again. Under [`DownloadConfig::install_dependencies`],
+/// the packs [`resolve_pack`] finds are installed alongside.
+pub async fn install_pack_async<P, D>(
+    config: &D,
+    spec: &PackSpec,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<PathBuf, crate::Error>
+where
+    P: DownloadProgress,
+    D: DownloadConfig,
+{
+    let pack_store = config.pack_store();
+    let resolved = resolve(&pack_store, spec, config.install_dependencies())?;
+    let deprecated = deprecated_packs(&pack_store);
+    let mut releases = Vec::new();
+    let mut listing = StoreListing::default();
+    let mut dirs = Vec::new();
+    for (resolved, pdsc) in resolved.iter() {
+        if let Some(notice) = deprecation_of(&deprecated, pdsc) {
+            tracing::warn!(
+                target: STORE,
+                pack = %resolved.spec,
+                since = %notice.since,
+                replacement = ?notice.replacement,
+                "Installing a deprecated pack"
+            );
+        }
+        if let Some(by) = &resolved.required_by {
+            tracing::debug!(target: STORE, pack = %resolved.spec, required_by = %by, "Installing a required pack");
+        }
+        let release = PackRelease {
+            pdsc,
+            version: resolved.spec.version.clone().unwrap_or_default(),
+        };
+        let url = release.into_uri()?.to_string();
+        dirs.push((
+            url,
+            extract_dir(&listing.resolve(&pack_store, &release.into_fd(config))),
+        ));
+        releases.push(release);
+    }
+    let dl_cntx = DownloadContext::new(config, progress, cancel)?.extracting();
+    dl_cntx.download_iterator(releases).await?;
+    if let Some((url, _)) = dirs.iter().find(|(_, dir)| !dir.exists()) {
+        return Err(crate::Error::Download {
+            url: url.clone(),
+            source: "pack could not be downloaded and extracted".into(),
+        });
+    }
+    Ok(dirs.swap_remove(0).1)
+}
+
+/// Download and extract the pack archive of `spec`
+///
+/// Blocking version of [`install_pack_async`].
+pub fn install_pack<P, D>(
+    config: &D,
+    spec: &PackSpec,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<PathBuf, crate::Error>
+where
+    P: DownloadProgress,
+    D: DownloadConfig,
+{
+    super::block_on(install_pack_async(config, spec, progress, cancel))?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pack_specs() {
+        let spec: PackSpec = "ARM::CMSIS@5.9.0".parse().unwrap();
+        assert_eq!(spec.vendor, "ARM");
+        assert_eq!(spec.name, "CMSIS");
+        assert_eq!(spec.version.as_deref(), Some("5.9.0"));
+        assert_eq!(spec.to_string(), "ARM::CMSIS@5.9.0");
+        let latest: PackSpec = "ARM::CMSIS".parse().unwrap();
+        assert_eq!(latest.version, None);
+        let range: PackSpec = "ARM::CMSIS@^5.7".parse().unwrap();
+        assert!(range.version_req().unwrap().matches("5.9.0"));
+        for bad in [
+            "ARM.CMSIS",
+            "::CMSIS",
+            "ARM::",
+            "ARM::CMSIS@",
+            "ARM::CMSIS@^five",
+            "..::../victim",
+            "/::etc",
+            "ARM::CMSIS/../..",
+            "ARM\\..::CMSIS",
+            "ARM::CMSIS@1.0.0/../..",
+        ] {
+            assert!(bad.parse::<PackSpec>().is_err(), "{}", bad);
+        }
+    }
+
+    fn pdsc(name: &str, versions: &[&str], requires: &str) -> String {
+        let releases: String = versions
+            .iter()
+            .map(|version| format!("<release version=\"{}\"/>", version))
+            .collect();
+        format!(
+            "<package><vendor>V</vendor><name>{}</name><description/><url>http://example.com/</url>\
+             <releases>{}</releases><requirements><packages>{}</packages></requirements>\
+             <apis><api Cclass=\"C\" Cgroup=\"G\" Capiversion=\"1.0.0\" exclusive=\"0\"/></apis>\
+             </package>",
+            name, releases, requires
+        )
+    }
+
+    #[test]
+    fn requirements_are_resolved_to_accepted_releases() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        std::fs::create_dir_all(&store).unwrap();
+        let write = |file: &str, contents: String| std::fs::write(store.join(file), contents);
+        let requires = |name: &str, version: &str| {
+            format!(
+                "<package vendor=\"V\" name=\"{}\" version=\"{}\"/>",
+                name, version
+            )
+        };
+        let a = format!("{}{}", requires("B", "1.0.0:1.9.9"), requires("C", "1.0.0"));
+        write("V.A.1.0.0.pdsc", pdsc("A", &["1.0.0"], &a)).unwrap();
+        write(
+            "V.B.2.0.0.pdsc",
+            pdsc("B", &["2.0.0", "1.5.0", "1.0.0"], ""),
+        )
+        .unwrap();
+        write(
+            "V.C.1.0.0.pdsc",
+            pdsc("C", &["1.0.0"], &requires("B", "1.2.0")),
+        )
+        .unwrap();
+
+        let parsed = from_store_path::<Package>(&store.join("V.A.1.0.0.pdsc")).unwrap();
+        assert_eq!(parsed.requirements.len(), 2);
+        assert_eq!(
+            parsed.requirements[0].version.as_deref(),
+            Some("1.0.0:1.9.9")
+        );
+        assert!(!parsed.apis[0].exclusive);
+
+        let spec = |pack: &str| pack.parse::<PackSpec>().unwrap();
+        let resolved: Vec<_> = resolve(&store, &spec("V::A"), true)
+            .unwrap()
+            .into_iter()
+            .map(|(resolved, _)| resolved)
+            .collect();
+        let a_spec = PackSpec {
+            version: None,
+            ..spec("V::A")
+        };
+        assert_eq!(
+            resolved,
+            vec![
+                ResolvedPack {
+                    spec: spec("V::A@1.0.0"),
+                    required_by: None
+                },
+                ResolvedPack {
+                    spec: spec("V::B@1.5.0"),
+                    required_by: Some(a_spec.clone())
+                },
+                ResolvedPack {
+                    spec: spec("V::C@1.0.0"),
+                    required_by: Some(a_spec)
+                },
+            ]
+        );
+        assert_eq!(resolve(&store, &spec("V::A"), false).unwrap().len(), 1);
+
+        // Requirements that rule out every release fail, as do missing packs
+        write(
+            "V.C.1.0.0.pdsc",
+            pdsc("C", &["1.0.0"], &requires("B", "1.6.0")),
+        )
+        .unwrap();
+        let err = resolve(&store, &spec("V::A"), true).err().unwrap();
+        assert_eq!(err.pack(), Some("V::B"));
+        write(
+            "V.C.1.0.0.pdsc",
+            pdsc("C", &["1.0.0"], &requires("D", "1.0.0")),
+        )
+        .unwrap();
+        let err = resolve(&store, &spec("V::A"), true).err().unwrap();
+        assert_eq!(err.pack(), Some("V::D"));
+        assert!(err.to_string().contains("required by V::C"));
+    }
+}
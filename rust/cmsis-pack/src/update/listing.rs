@@ -0,0 +1,62 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+/// The file names of pack store directories, each read at most once
+///
+/// Checking many destinations against a listing replaces a `stat` per file
+/// with a single read of every directory involved, which is much cheaper
+/// on network filesystems.
+#[derive(Default)]
+pub(crate) struct StoreListing {
+    dirs: HashMap<PathBuf, HashSet<OsString>>,
+}
+
+impl StoreListing {
+    /// The names of the files in `dir`; empty when it does not exist
+    pub(crate) fn names(&mut self, dir: &Path) -> &HashSet<OsString> {
+        self.dirs.entry(dir.to_path_buf()).or_insert_with(|| {
+            read_dir(dir)
+                .map(|entries| entries.flatten().map(|entry| entry.file_name()).collect())
+                .unwrap_or_default()
+        })
+    }
+
+    pub(crate) fn contains(&mut self, path: &Path) -> bool {
+        match (path.parent(), path.file_name()) {
+            (Some(dir), Some(name)) => self.names(dir).contains(name),
+            _ => false,
+        }
+    }
+
+    /// `path` below `root`, with every component that names an existing
+    /// entry in another case spelled like that entry
+    ///
+    /// Vendor and pack names are case-insensitive, so a `KEIL.X` download
+    /// lands on the `Keil.X` file already in the store.
+    pub(crate) fn resolve(&mut self, root: &Path, path: &Path) -> PathBuf {
+        let relative = match path.strip_prefix(root) {
+            Ok(relative) => relative,
+            Err(_) => return path.to_path_buf(),
+        };
+        let mut resolved = root.to_path_buf();
+        for component in relative.iter() {
+            let names = self.names(&resolved);
+            let existing = if names.contains(component) {
+                None
+            } else {
+                let wanted = component.to_str();
+                names
+                    .iter()
+                    .find(|name| match (name.to_str(), wanted) {
+                        (Some(name), Some(wanted)) => name.eq_ignore_ascii_case(wanted),
+                        _ => false,
+                    })
+                    .cloned()
+            };
+            resolved.push(existing.as_deref().unwrap_or(component));
+        }
+        resolved
+    }
+}
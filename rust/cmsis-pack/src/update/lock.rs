@@ -0,0 +1,151 @@
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::log::STORE;
+use crate::update::claim::{Claim, CLAIM_HEARTBEAT};
+
+pub(crate) const LOCK_FILE: &str = ".lock";
+
+/// How often a waiting operation checks whether the lock was released
+const LOCK_POLL: Duration = Duration::from_secs(1);
+
+/// Exclusive use of a pack store by one process, held through a `.lock`
+/// file in the store until dropped
+///
+/// Updates, installs and other operations that change the store take it so
+/// that processes sharing a store do not run them at once. The lock is
+/// advisory: functions of this crate do not take it themselves. A lock not
+/// refreshed for two minutes was left by a process that died, and is taken
+/// over by one of the processes waiting for it.
+pub struct StoreLock {
+    path: PathBuf,
+    stop: Option<Sender<()>>,
+    heartbeat: Option<JoinHandle<()>>,
+}
+
+impl StoreLock {
+    /// Lock `pack_store`, waiting for the process holding it to release it
+    /// if `wait`, or else failing with [`Error::Locked`](crate::Error::Locked)
+    pub fn acquire(pack_store: &Path, wait: bool) -> Result<StoreLock, crate::Error> {
+        let path = pack_store.join(LOCK_FILE);
+        let mut waiting = false;
+        loop {
+            let claim = Claim::create(path.clone()).map_err(|source| crate::Error::Io {
+                path: Some(path.clone()),
+                source,
+            })?;
+            if let Some(claim) = claim {
+                return Ok(StoreLock::hold(path, claim));
+            }
+            let pid = read_to_string(&path)
+                .ok()
+                .and_then(|pid| pid.trim().parse().ok());
+            if !wait {
+                return Err(crate::Error::Locked { path, pid });
+            }
+            if !waiting {
+                tracing::info!(target: STORE, path = ?path, pid, "Waiting for another process to unlock the pack store");
+                waiting = true;
+            }
+            thread::sleep(LOCK_POLL);
+        }
+    }
+
+    /// Refresh `claim` in the background until the lock is dropped
+    fn hold(path: PathBuf, claim: Claim) -> StoreLock {
+        let claim = Arc::new(claim);
+        let (stop, stopped) = channel();
+        let heartbeat = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(CLAIM_HEARTBEAT) {
+                claim.refresh();
+            }
+        });
+        StoreLock {
+            path,
+            stop: Some(stop),
+            heartbeat: Some(heartbeat),
+        }
+    }
+
+    /// The lock file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        // The heartbeat thread owns the claim, and removes the lock file
+        // when it ends
+        self.stop.take();
+        if let Some(heartbeat) = self.heartbeat.take() {
+            let _ = heartbeat.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn the_store_is_locked_by_one_holder_at_a_time() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        let lock = StoreLock::acquire(&store, false).unwrap();
+        match StoreLock::acquire(&store, false) {
+            Err(crate::Error::Locked { path, pid }) => {
+                assert_eq!(path, lock.path());
+                assert_eq!(pid, Some(std::process::id()));
+            }
+            other => panic!("expected a locked store, got {:?}", other.err()),
+        }
+
+        let waiter = {
+            let store = store.clone();
+            thread::spawn(move || StoreLock::acquire(&store, true).map(|_| ()))
+        };
+        thread::sleep(Duration::from_millis(100));
+        drop(lock);
+        waiter.join().unwrap().unwrap();
+        assert!(!store.join(LOCK_FILE).exists());
+    }
+
+    #[test]
+    fn a_stale_lock_is_taken_over_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        std::fs::create_dir_all(&store).unwrap();
+        std::fs::write(store.join(LOCK_FILE), "1\n").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(store.join(LOCK_FILE))
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - Duration::from_secs(3600))
+            .unwrap();
+
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+        let lockers: Vec<_> = (0..8)
+            .map(|_| {
+                let (store, barrier) = (store.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    StoreLock::acquire(&store, false).ok()
+                })
+            })
+            .collect();
+        let locks: Vec<StoreLock> = lockers
+            .into_iter()
+            .filter_map(|locker| locker.join().unwrap())
+            .collect();
+        assert_eq!(locks.len(), 1);
+        assert_eq!(
+            read_to_string(locks[0].path()).unwrap().trim(),
+            std::process::id().to_string()
+        );
+    }
+}
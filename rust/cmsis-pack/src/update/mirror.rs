@@ -0,0 +1,117 @@
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use reqwest::Url;
+
+use crate::log::NETWORK;
+use crate::update::fetch::{is_offline, source_url, HttpStatus};
+
+/// A mirror of the files under a URL prefix, tried when they cannot be
+/// fetched from there
+///
+/// Parsed from `PREFIX=MIRROR`, such as
+/// `https://www.keil.com/pack/=https://artifactory.example.com/keil-packs/`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mirror {
+    pub prefix: String,
+    pub mirror: String,
+}
+
+impl Mirror {
+    /// `url` with its prefix replaced by that of the mirror, if it has it
+    pub fn rewrite(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.prefix)
+            .map(|rest| format!("{}{}", self.mirror, rest))
+    }
+}
+
+impl FromStr for Mirror {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.split_once('=') {
+            Some((prefix, mirror)) if !prefix.is_empty() && !mirror.is_empty() => Ok(Mirror {
+                prefix: prefix.to_string(),
+                mirror: mirror.to_string(),
+            }),
+            _ => Err(anyhow!("Expected PREFIX=MIRROR, got {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Mirror {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.prefix, self.mirror)
+    }
+}
+
+/// Whether a failure of the primary URL is worth trying a mirror for: the
+/// file is missing, or the server timed out or could not be reached
+fn falls_back(err: &Error) -> bool {
+    if let Some(HttpStatus(code)) = err.downcast_ref() {
+        return *code == 404;
+    }
+    // Mirrors on disk still serve files offline
+    is_offline(err)
+        || crate::Error::is_timeout_of(err)
+        || err
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<reqwest::Error>())
+            .any(|err| err.is_connect())
+}
+
+/// Run `attempt` on `source`, then on each of its mirrors in order while it
+/// fails with a missing file, a timeout or an unreachable server
+///
+/// When every mirror fails too, the error of `source` is returned, so that
+/// a 404 still marks the pack as vanished.
+pub(crate) async fn with_mirrors<T, F, Fut>(
+    mirrors: &[Mirror],
+    source: &Url,
+    mut attempt: F,
+) -> Result<T, Error>
+where
+    F: FnMut(Url) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let first = match attempt(source.clone()).await {
+        Err(err) if falls_back(&err) => err,
+        done => return done,
+    };
+    let rewrites = mirrors
+        .iter()
+        .filter_map(|mirror| mirror.rewrite(source.as_str()))
+        .filter_map(|url| source_url(&url).ok());
+    for url in rewrites {
+        tracing::debug!(target: NETWORK, url = %source, mirror = %url, error = %first, "Trying a mirror");
+        match attempt(url.clone()).await {
+            Err(err) if falls_back(&err) => {
+                tracing::warn!(target: NETWORK, mirror = %url, error = %err, "Mirror failed")
+            }
+            done => return done,
+        }
+    }
+    Err(first)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mirrors_are_parsed_and_rewrite_prefixes() {
+        let mirror: Mirror = "https://www.keil.com/pack/=https://mirror.example.com/keil/"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            mirror.rewrite("https://www.keil.com/pack/Keil.A.pdsc"),
+            Some("https://mirror.example.com/keil/Keil.A.pdsc".to_string())
+        );
+        assert_eq!(mirror.rewrite("https://example.com/pack/Keil.A.pdsc"), None);
+        assert_eq!(mirror.to_string().parse::<Mirror>().unwrap(), mirror);
+        assert!("https://www.keil.com/pack/".parse::<Mirror>().is_err());
+        assert!("=https://mirror.example.com/".parse::<Mirror>().is_err());
+    }
+}
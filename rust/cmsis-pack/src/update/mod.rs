@@ -1,42 +1,1696 @@
-use anyhow::Error;
+use std::future::Future;
 use std::path::PathBuf;
 use tokio::runtime;
 
+use crate::log::STORE;
 use crate::pdsc::Package;
 
+mod auth;
+mod cache;
+mod check;
+mod checksum;
+mod claim;
+mod deprecated;
+mod device_files;
 mod download;
+mod extract;
+mod fetch;
+mod filter;
+mod install;
+mod listing;
+mod lock;
+mod mirror;
+mod origins;
+mod outdated;
+mod plan;
+mod profile;
+mod progress;
+mod prune;
+mod rate;
+mod report;
+mod retry;
+mod shared;
+mod snapshot;
+mod store;
+#[cfg(test)]
+mod test_server;
+mod usage;
+mod validators;
+mod vanished;
 
+pub use crate::update::auth::Credentials;
+pub use crate::update::check::{check_store, BrokenPdsc, PdscProblem, StoreCheck};
+pub use crate::update::checksum::{Checksum, ChecksumAlgorithm, ChecksumMismatch, Unverified};
+pub use crate::update::deprecated::{deprecated_packs, Deprecation};
+pub use crate::update::device_files::{flash_algorithm_paths, svd_path};
 use crate::update::download::DownloadContext;
-pub use crate::update::download::{DownloadConfig, DownloadProgress};
+pub use crate::update::download::{CancellationToken, DownloadConfig, DownloadProgress, Observer};
+pub use crate::update::fetch::{
+    Body, ByteStream, Fetcher, HttpStatus, RedirectError, ReqwestFetcher, TimedOut, TlsOptions,
+    MAX_REDIRECTS,
+};
+pub use crate::update::filter::VendorFilter;
+pub use crate::update::install::{
+    install_pack, install_pack_async, plan_install_pack, resolve_pack, PackSpec, ResolvedPack,
+};
+pub use crate::update::lock::StoreLock;
+pub use crate::update::mirror::Mirror;
+pub use crate::update::origins::{foreign_origins, ServedFrom};
+pub use crate::update::outdated::{outdated_packs, Outdated};
+pub use crate::update::plan::{plan_install, plan_update, PlanReason, PlannedDownload};
+pub use crate::update::profile::{NetworkProfile, Timeouts};
+pub use crate::update::progress::{FileState, ProgressSnapshot, ProgressTracker};
+pub use crate::update::prune::{clean_store, collect_garbage, remove_pack, Reclaimed};
+pub use crate::update::rate::RateLimit;
+use crate::update::report::Reporting;
+pub use crate::update::report::{FailedDownload, UpdateReport};
+pub use crate::update::retry::RetryPolicy;
+pub use crate::update::snapshot::{
+    capture_snapshot, restore_snapshot, restore_snapshot_async, SnapshotEntry, StoreSnapshot,
+};
+pub use crate::update::store::{Cache, DEFAULT_VIDX_LIST};
+pub use crate::update::usage::{store_usage, CleanScope, StoreUsage, VendorUsage};
+pub use crate::update::validators::Validators;
+pub use crate::update::vanished::{vanished_packs, Vanished, VanishedPolicy};
+use crate::Error;
 
 type Result<T> = std::result::Result<T, Error>;
 
-/// Flatten a list of Vidx Urls into a list of updated CMSIS packs
-pub fn update<I, P, D>(config: &D, vidx_list: I, progress: P) -> Result<Vec<PathBuf>>
+/// Download the PDSC files listed by a list of Vidx Urls into the pack store
+///
+/// Failed downloads do not fail the update; they are listed in the
+/// returned report, which is also saved in the pack store.
+///
+/// Downloads are spawned onto the current Tokio runtime, so the returned
+/// future must be polled from within one.
+pub async fn update_async<I, P, D>(
+    config: &D,
+    vidx_list: I,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<UpdateReport>
 where
     I: IntoIterator<Item = String>,
     P: DownloadProgress,
     D: DownloadConfig,
 {
+    let progress = Reporting::new(progress);
+    let report = progress.report();
+    let dl_cntx = DownloadContext::new(config, progress, cancel)?;
+    dl_cntx.update_vidx(vidx_list).await?;
+    let report = match report.lock() {
+        Ok(report) => report.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+    if let Err(err) = report.save(&config.pack_store()) {
+        tracing::warn!(target: STORE, "Could not save the update report: {}", err);
+    }
+    Ok(report)
+}
+
+/// Download the pack archive of the latest release of each package
+///
+/// Downloads are spawned onto the current Tokio runtime, so the returned
+/// future must be polled from within one.
+pub async fn install_async<'a, I, P, D>(
+    config: &'a D,
+    pdsc_list: I,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<Vec<PathBuf>>
+where
+    I: IntoIterator<Item = &'a Package> + 'a,
+    P: DownloadProgress + 'a,
+    D: DownloadConfig,
+{
+    let dl_cntx = DownloadContext::new(config, progress, cancel)?;
+    Ok(dl_cntx.download_iterator(pdsc_list).await?)
+}
+
+/// The PDSC downloads an update from `vidx_list` would perform, without
+/// writing anything to the pack store
+///
+/// The indexes are fetched, but not cached. The size of each file that
+/// would be downloaded comes from its index entry, or else from a `HEAD`
+/// request, and is left out when neither tells it.
+pub async fn dry_run_update_async<I, D>(config: &D, vidx_list: I) -> Result<Vec<PlannedDownload>>
+where
+    I: IntoIterator<Item = String>,
+    D: DownloadConfig,
+{
+    let dl_cntx = DownloadContext::new(config, (), CancellationToken::new())?;
+    Ok(dl_cntx.plan_vidx(vidx_list).await?)
+}
+
+/// Fill in the sizes of the files `planned` would download, with `HEAD`
+/// requests
+pub async fn measure_downloads_async<D: DownloadConfig>(
+    config: &D,
+    planned: Vec<PlannedDownload>,
+) -> Result<Vec<PlannedDownload>> {
+    let dl_cntx = DownloadContext::new(config, (), CancellationToken::new())?;
+    Ok(dl_cntx.measure(planned).await)
+}
+
+fn block_on<F: Future>(future: F) -> Result<F::Output> {
     let rt = runtime::Builder::new_current_thread()
         .enable_all()
         .build()?;
+    Ok(rt.block_on(future))
+}
+
+/// Download the PDSC files listed by a list of Vidx Urls into the pack store
+///
+/// Blocking version of [`update_async`].
+pub fn update<I, P, D>(
+    config: &D,
+    vidx_list: I,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<UpdateReport>
+where
+    I: IntoIterator<Item = String>,
+    P: DownloadProgress,
+    D: DownloadConfig,
+{
+    block_on(update_async(config, vidx_list, progress, cancel))?
+}
+
+/// The PDSC downloads an update from `vidx_list` would perform
+///
+/// Blocking version of [`dry_run_update_async`].
+pub fn dry_run_update<I, D>(config: &D, vidx_list: I) -> Result<Vec<PlannedDownload>>
+where
+    I: IntoIterator<Item = String>,
+    D: DownloadConfig,
+{
+    block_on(dry_run_update_async(config, vidx_list))?
+}
 
-    let dl_cntx = DownloadContext::new(config, progress)?;
-    rt.block_on(dl_cntx.update_vidx(vidx_list))
+/// Fill in the sizes of the files `planned` would download
+///
+/// Blocking version of [`measure_downloads_async`].
+pub fn measure_downloads<D: DownloadConfig>(
+    config: &D,
+    planned: Vec<PlannedDownload>,
+) -> Result<Vec<PlannedDownload>> {
+    block_on(measure_downloads_async(config, planned))?
 }
 
-/// Flatten a list of Vidx Urls into a list of updated CMSIS packs
-pub fn install<'a, I: 'a, P, D>(config: &'a D, pdsc_list: I, progress: P) -> Result<Vec<PathBuf>>
+/// Download the pack archive of the latest release of each package
+///
+/// Blocking version of [`install_async`].
+pub fn install<'a, I, P, D>(
+    config: &'a D,
+    pdsc_list: I,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<Vec<PathBuf>>
 where
-    I: IntoIterator<Item = &'a Package>,
+    I: IntoIterator<Item = &'a Package> + 'a,
     P: DownloadProgress + 'a,
     D: DownloadConfig,
 {
-    let rt = runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()?;
+    block_on(install_async(config, pdsc_list, progress, cancel))?
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_server::{host, ok, redirect, serve, status};
+    use super::*;
+    use futures::future::{self, BoxFuture, FutureExt};
+    use futures::stream::{self, StreamExt};
+    use reqwest::Url;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    /// A pack store in a temporary directory of its own, with every setting
+    /// a test may change
+    ///
+    /// Tests take the defaults of [`TestConfig::new`] and override fields,
+    /// such as `TestConfig { refresh: true, ..TestConfig::new() }`.
+    #[derive(Clone)]
+    struct TestConfig {
+        /// Deleted along with the last clone of the config
+        dir: Arc<tempfile::TempDir>,
+        store: PathBuf,
+        fetcher: Option<Arc<dyn Fetcher>>,
+        proxy: Option<String>,
+        credentials: HashMap<String, Credentials>,
+        ca_certificates: Vec<PathBuf>,
+        refresh: bool,
+        extract_packs: bool,
+        origin_warnings: bool,
+        vanished_policy: VanishedPolicy,
+        max_open_bodies: usize,
+        allow_downgrade: bool,
+        retry_policy: RetryPolicy,
+        timeouts: Timeouts,
+        require_checksum: bool,
+        mirrors: Vec<Mirror>,
+        shared_cache: Option<PathBuf>,
+        offline: bool,
+    }
+
+    /// A config that keeps every default of [`DownloadConfig`]
+    struct Defaults;
+
+    impl DownloadConfig for Defaults {
+        fn pack_store(&self) -> PathBuf {
+            PathBuf::new()
+        }
+    }
+
+    impl TestConfig {
+        /// The defaults of [`DownloadConfig`], for a pack store that does not
+        /// exist yet in a new temporary directory
+        fn new() -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            TestConfig {
+                store: dir.path().join("store"),
+                dir: Arc::new(dir),
+                fetcher: Defaults.fetcher(),
+                proxy: Defaults.proxy(),
+                credentials: Defaults.credentials(),
+                ca_certificates: Defaults.ca_certificates(),
+                refresh: Defaults.refresh(),
+                extract_packs: Defaults.extract_packs(),
+                origin_warnings: Defaults.origin_warnings(),
+                vanished_policy: Defaults.vanished_policy(),
+                max_open_bodies: Defaults.max_open_bodies(),
+                allow_downgrade: Defaults.allow_downgrade(),
+                retry_policy: Defaults.retry_policy(),
+                timeouts: Defaults.timeouts(),
+                require_checksum: Defaults.require_checksum(),
+                mirrors: Defaults.mirrors(),
+                shared_cache: Defaults.shared_cache(),
+                offline: Defaults.offline(),
+            }
+        }
+
+        /// The temporary directory of the store, for files next to it
+        fn dir(&self) -> &std::path::Path {
+            self.dir.path()
+        }
+    }
+
+    impl DownloadConfig for TestConfig {
+        fn pack_store(&self) -> PathBuf {
+            self.store.clone()
+        }
+        fn fetcher(&self) -> Option<Arc<dyn Fetcher>> {
+            self.fetcher.clone()
+        }
+        fn proxy(&self) -> Option<String> {
+            self.proxy.clone()
+        }
+        fn credentials(&self) -> HashMap<String, Credentials> {
+            self.credentials.clone()
+        }
+        fn ca_certificates(&self) -> Vec<PathBuf> {
+            self.ca_certificates.clone()
+        }
+        fn refresh(&self) -> bool {
+            self.refresh
+        }
+        fn extract_packs(&self) -> bool {
+            self.extract_packs
+        }
+        fn origin_warnings(&self) -> bool {
+            self.origin_warnings
+        }
+        fn vanished_policy(&self) -> VanishedPolicy {
+            self.vanished_policy
+        }
+        fn max_open_bodies(&self) -> usize {
+            self.max_open_bodies
+        }
+        fn allow_downgrade(&self) -> bool {
+            self.allow_downgrade
+        }
+        fn retry_policy(&self) -> RetryPolicy {
+            self.retry_policy
+        }
+        fn timeouts(&self) -> Timeouts {
+            self.timeouts
+        }
+        fn require_checksum(&self) -> bool {
+            self.require_checksum
+        }
+        fn mirrors(&self) -> Vec<Mirror> {
+            self.mirrors.clone()
+        }
+        fn shared_cache(&self) -> Option<PathBuf> {
+            self.shared_cache.clone()
+        }
+        fn offline(&self) -> bool {
+            self.offline
+        }
+    }
+
+    /// Serves files from memory, recording the requested URLs
+    struct MemoryFetcher(HashMap<String, &'static str>, Mutex<Vec<String>>);
+
+    impl Fetcher for MemoryFetcher {
+        fn get(&self, url: Url) -> BoxFuture<'static, anyhow::Result<Body>> {
+            self.1.lock().unwrap().push(url.to_string());
+            let found = self.0.get(url.as_str()).copied();
+            async move {
+                let contents = found.ok_or(HttpStatus(404))?;
+                Ok(bytes::Bytes::from_static(contents.as_bytes()).into())
+            }
+            .boxed()
+        }
+    }
+
+    /// An index listing a single pack, V.P, whose PDSC file is `pdsc`
+    fn index_files(pdsc: &'static str) -> HashMap<String, &'static str> {
+        HashMap::from([
+            (
+                "http://example.com/index.pidx".to_string(),
+                "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+                 <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+            ),
+            ("http://example.com/V.P.pdsc".to_string(), pdsc),
+        ])
+    }
+
+    /// A store that fetches `files` from memory, with the fetcher to look
+    /// at its requests
+    fn fetching(files: HashMap<String, &'static str>) -> (TestConfig, Arc<MemoryFetcher>) {
+        let fetcher = Arc::new(MemoryFetcher(files, Mutex::default()));
+        let config = TestConfig {
+            fetcher: Some(fetcher.clone()),
+            ..TestConfig::new()
+        };
+        (config, fetcher)
+    }
+
+    /// A store whose index lists a single pack, V.P
+    fn memory_store(pdsc: &'static str) -> (TestConfig, Arc<MemoryFetcher>) {
+        fetching(index_files(pdsc))
+    }
+
+    fn vidx() -> Vec<String> {
+        vec!["http://example.com/index.pidx".to_string()]
+    }
+
+    #[test]
+    fn update_through_custom_fetcher() {
+        let (config, _) = memory_store("<package/>");
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("V.P.1.0.0.pdsc")]);
+        assert_eq!(std::fs::read_to_string(&updated[0]).unwrap(), "<package/>");
+    }
+
+    #[test]
+    fn async_entry_points_through_custom_fetcher() {
+        use crate::utils::parse::FromElem;
+
+        let pdsc = "<package><name>P</name><vendor>V</vendor><description>Pack</description>\
+                    <url>http://example.com/</url>\
+                    <releases><release version=\"1.0.0\"/></releases></package>";
+        let mut files = index_files(pdsc);
+        files.insert("http://example.com/V.P.1.0.0.pack".to_string(), "PK");
+        let (config, _) = fetching(files);
+
+        let report = block_on(update_async(&config, vidx(), (), CancellationToken::new()))
+            .unwrap()
+            .unwrap();
+        let updated = report.pdsc_files();
+        assert_eq!(updated, vec![config.store.join("V.P.1.0.0.pdsc")]);
+
+        let package = Package::from_path(&updated[0]).unwrap();
+        let installed = block_on(install_async(
+            &config,
+            [&package],
+            (),
+            CancellationToken::new(),
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            installed,
+            vec![config.store.join("V").join("P").join("1.0.0.pack")]
+        );
+        assert_eq!(std::fs::read_to_string(&installed[0]).unwrap(), "PK");
+    }
+
+    #[test]
+    fn unchanged_vendor_index_is_not_fetched() {
+        let store = TestConfig::new();
+        let files = |timestamp: &'static str| {
+            HashMap::from([
+                ("http://example.com/index.vidx".to_string(), timestamp),
+                (
+                    "http://example.com/V.pidx".to_string(),
+                    "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+                     <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                     </pindex></index>",
+                ),
+                ("http://example.com/V.P.pdsc".to_string(), "<package/>"),
+            ])
+        };
+        let vidx = |ts| {
+            if ts == 1 {
+                "<index><vendor>All</vendor><url>http://example.com/</url><vindex>\
+                 <pidx vendor=\"V\" url=\"http://example.com/\" timestamp=\"1\"/>\
+                 </vindex></index>"
+            } else {
+                "<index><vendor>All</vendor><url>http://example.com/</url><vindex>\
+                 <pidx vendor=\"V\" url=\"http://example.com/\" timestamp=\"2\"/>\
+                 </vindex></index>"
+            }
+        };
+        let run = |ts| {
+            let fetcher = Arc::new(MemoryFetcher(files(vidx(ts)), Mutex::default()));
+            let config = TestConfig {
+                fetcher: Some(fetcher.clone()),
+                ..store.clone()
+            };
+            let list = vec!["http://example.com/index.vidx".to_string()];
+            let updated = update(&config, list, (), CancellationToken::new())
+                .unwrap()
+                .pdsc_files();
+            assert_eq!(updated, vec![store.store.join("V.P.1.0.0.pdsc")]);
+            let requests = fetcher.1.lock().unwrap().clone();
+            requests
+        };
+        assert!(run(1).contains(&"http://example.com/V.pidx".to_string()));
+        assert_eq!(run(1), vec!["http://example.com/index.vidx".to_string()]);
+        assert!(run(2).contains(&"http://example.com/V.pidx".to_string()));
+    }
+
+    /// The URL, bytes received and announced length of a progress report
+    type FileProgress = (String, u64, Option<u64>);
+
+    /// Records the per-file progress of downloads
+    #[derive(Clone, Default)]
+    struct Received(Arc<Mutex<Vec<FileProgress>>>);
+
+    impl Observer for Received {
+        fn file_progress(&self, url: &str, bytes: u64, total: Option<u64>) {
+            self.0.lock().unwrap().push((url.to_string(), bytes, total));
+        }
+    }
+
+    impl DownloadProgress for Received {
+        fn size(&self, _: usize) {}
+        fn progress(&self, _: usize) {}
+        fn complete(&self) {}
+        fn for_file(&self, _: &str) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn file_progress_is_reported() {
+        let (config, _) = memory_store("<package/>");
+        let received = Received::default();
+        update(&config, vidx(), received.clone(), CancellationToken::new()).unwrap();
+        let received = received.0.lock().unwrap().clone();
+        assert_eq!(
+            received,
+            vec![("http://example.com/V.P.pdsc".to_string(), 10, None)]
+        );
+    }
+
+    /// Answers every URL with a 503 the first time it is requested
+    struct FlakyFetcher(MemoryFetcher);
+
+    impl Fetcher for FlakyFetcher {
+        fn get(&self, url: Url) -> BoxFuture<'static, anyhow::Result<Body>> {
+            let seen = self.0 .1.lock().unwrap().contains(&url.to_string());
+            let get = self.0.get(url);
+            async move {
+                if !seen {
+                    return Err(HttpStatus(503).into());
+                }
+                get.await
+            }
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn transient_failures_are_retried() {
+        let files = index_files("<package/>");
+        let fetcher = Arc::new(FlakyFetcher(MemoryFetcher(files, Mutex::default())));
+        let config = TestConfig {
+            fetcher: Some(fetcher.clone()),
+            retry_policy: RetryPolicy {
+                backoff: std::time::Duration::from_millis(1),
+                ..RetryPolicy::default()
+            },
+            ..TestConfig::new()
+        };
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("V.P.1.0.0.pdsc")]);
+        let requests = fetcher.0 .1.lock().unwrap().len();
+        assert_eq!(requests, 4);
+    }
+
+    /// Serves the index, then stalls after the first chunk of any PDSC file
+    struct StallingFetcher(MemoryFetcher);
+
+    impl Fetcher for StallingFetcher {
+        fn get(&self, url: Url) -> BoxFuture<'static, anyhow::Result<Body>> {
+            if !url.path().ends_with(".pdsc") {
+                return self.0.get(url);
+            }
+            let first = stream::iter([Ok(bytes::Bytes::from_static(b"<package"))]);
+            let stream: ByteStream = Box::pin(first.chain(stream::pending()));
+            future::ready(Ok(stream.into())).boxed()
+        }
+    }
+
+    /// Records whether each failed download timed out
+    #[derive(Clone, Default)]
+    struct TimedOutFailures(Arc<Mutex<Vec<bool>>>);
+
+    impl Observer for TimedOutFailures {
+        fn download_failed(&self, _: &str, err: &anyhow::Error) {
+            self.0.lock().unwrap().push(Error::is_timeout_of(err));
+        }
+    }
+
+    impl DownloadProgress for TimedOutFailures {
+        fn size(&self, _: usize) {}
+        fn progress(&self, _: usize) {}
+        fn complete(&self) {}
+        fn for_file(&self, _: &str) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn stalled_downloads_time_out() {
+        let files = index_files("<package/>");
+        let config = TestConfig {
+            fetcher: Some(Arc::new(StallingFetcher(MemoryFetcher(
+                files,
+                Mutex::default(),
+            )))),
+            retry_policy: RetryPolicy {
+                attempts: 1,
+                ..RetryPolicy::default()
+            },
+            timeouts: Timeouts {
+                read: std::time::Duration::from_millis(50),
+                ..Defaults.timeouts()
+            },
+            ..TestConfig::new()
+        };
+        let failures = TimedOutFailures::default();
+        let updated = update(&config, vidx(), failures.clone(), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert!(updated.is_empty());
+        assert_eq!(*failures.0.lock().unwrap(), vec![true]);
+        assert!(!config.store.join("V.P.1.0.0.part").exists());
+    }
+
+    /// Records the observer and progress events of an update
+    #[derive(Clone, Default)]
+    struct Recording(Arc<Mutex<Vec<String>>>);
+
+    impl Recording {
+        fn record(&self, event: String) {
+            self.0.lock().unwrap().push(event);
+        }
+
+        /// The events that mention `url`, in order
+        fn about(&self, url: &str) -> Vec<String> {
+            let events = self.0.lock().unwrap();
+            events.iter().filter(|e| e.contains(url)).cloned().collect()
+        }
+    }
+
+    impl Observer for Recording {
+        fn download_started(&self, url: &str) {
+            self.record(format!("started {}", url));
+        }
+        fn pdsc_downloaded(&self, url: &str, _: &std::path::Path) {
+            self.record(format!("downloaded {}", url));
+        }
+        fn download_failed(&self, url: &str, _: &anyhow::Error) {
+            self.record(format!("failed {}", url));
+        }
+    }
+
+    impl DownloadProgress for Recording {
+        fn size(&self, files: usize) {
+            self.record(format!("size {}", files));
+        }
+        fn progress(&self, _: usize) {}
+        fn complete(&self) {
+            self.record("complete".to_string());
+        }
+        fn for_file(&self, _: &str) -> Self {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn observer_sees_downloads_succeed_and_fail() {
+        let mut files = index_files("<package/>");
+        files.insert(
+            "http://example.com/index.pidx".to_string(),
+            "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+             <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+             <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"Gone\" version=\"1.0.0\"/>\
+             </pindex></index>",
+        );
+        let (config, _) = fetching(files);
+        let recording = Recording::default();
+        let report = update(&config, vidx(), recording.clone(), CancellationToken::new()).unwrap();
+        assert_eq!(report.failed.len(), 1);
+
+        let pdsc = "http://example.com/V.P.pdsc";
+        let gone = "http://example.com/V.Gone.pdsc";
+        assert_eq!(
+            recording.about(pdsc),
+            vec![format!("started {}", pdsc), format!("downloaded {}", pdsc)]
+        );
+        assert_eq!(
+            recording.about(gone),
+            vec![format!("started {}", gone), format!("failed {}", gone)]
+        );
+        let events = recording.0.lock().unwrap();
+        assert_eq!(events[0], "size 2");
+        assert_eq!(events.iter().filter(|e| *e == "complete").count(), 2);
+    }
+
+    #[test]
+    fn claimed_files_are_left_to_their_claimant() {
+        let (config, fetcher) = memory_store("<package/>");
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        let claim = config.store.join("V.P.1.0.0.claim");
+        std::fs::create_dir_all(&config.store).unwrap();
+        std::fs::write(&claim, "").unwrap();
+        let other = {
+            let (dest, claim) = (dest.clone(), claim.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(300));
+                std::fs::write(dest, "<package>other</package>").unwrap();
+                std::fs::remove_file(claim).unwrap();
+            })
+        };
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        other.join().unwrap();
+        assert_eq!(updated, vec![dest.clone()]);
+        assert_eq!(
+            std::fs::read_to_string(&dest).unwrap(),
+            "<package>other</package>"
+        );
+        let requests = fetcher.1.lock().unwrap().clone();
+        assert_eq!(requests, vec!["http://example.com/index.pidx".to_string()]);
+
+        // Claims of processes that died are taken over
+        std::fs::remove_file(&dest).unwrap();
+        let stale = std::fs::File::create(&claim).unwrap();
+        let an_hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        stale.set_modified(an_hour_ago).unwrap();
+        update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "<package/>");
+        assert!(!claim.exists());
+    }
+
+    #[test]
+    fn partial_files_of_interrupted_runs_are_overwritten() {
+        let (config, _) = memory_store("<package/>");
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        std::fs::create_dir_all(&config.store).unwrap();
+        std::fs::write(dest.with_extension("part"), "<package><name>Interrupted").unwrap();
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![dest.clone()]);
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "<package/>");
+        assert!(!dest.with_extension("part").exists());
+    }
+
+    #[test]
+    fn refresh_keeps_unchanged_files() {
+        let config = TestConfig {
+            refresh: true,
+            ..memory_store("<package/>").0
+        };
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        let past = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        let fd = std::fs::File::options().write(true).open(&dest).unwrap();
+        fd.set_modified(past).unwrap();
+
+        // The same contents are downloaded again, but not written
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![dest.clone()]);
+        assert_eq!(dest.metadata().unwrap().modified().unwrap(), past);
+        assert!(!dest.with_extension("part").exists());
+
+        // Different contents replace the file
+        std::fs::write(&dest, "<package>old</package>").unwrap();
+        update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "<package/>");
+    }
+
+    #[test]
+    fn newer_local_pdscs_are_not_downgraded() {
+        let (config, _) = memory_store("<package/>");
+        let newer = config.store.join("V.P.2.0.0.pdsc");
+        let older = config.store.join("V.P.1.0.0.pdsc");
+        std::fs::create_dir_all(&config.store).unwrap();
+        std::fs::write(&newer, "<package/>").unwrap();
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![newer]);
+        assert!(!older.exists());
+
+        let config = TestConfig {
+            allow_downgrade: true,
+            ..config
+        };
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![older]);
+    }
+
+    #[test]
+    fn truncated_pdscs_are_downloaded_again() {
+        let (config, fetcher) = memory_store("<package>\n</package>\n");
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        for leftover in ["", "<package>\n</pack"] {
+            std::fs::create_dir_all(&config.store).unwrap();
+            std::fs::write(&dest, leftover).unwrap();
+            update(&config, vidx(), (), CancellationToken::new()).unwrap();
+            let contents = std::fs::read_to_string(&dest).unwrap();
+            assert_eq!(contents, "<package>\n</package>\n");
+        }
+        // Complete files are still skipped
+        fetcher.1.lock().unwrap().clear();
+        update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        let requests = fetcher.1.lock().unwrap().clone();
+        assert_eq!(requests, vec!["http://example.com/index.pidx".to_string()]);
+    }
+
+    #[test]
+    fn pack_names_are_case_insensitive() {
+        let files = HashMap::from([
+            (
+                "http://example.com/index.pidx".to_string(),
+                "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+                 <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://example.com/\" vendor=\"v\" name=\"P\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+            ),
+            ("http://example.com/V.P.pdsc".to_string(), "<package/>"),
+            ("http://example.com/v.P.pdsc".to_string(), "<package/>"),
+        ]);
+        let (config, fetcher) = fetching(files);
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated.len(), 1);
+        let requests = fetcher.1.lock().unwrap().clone();
+        assert_eq!(requests.len(), 2);
+
+        // A file spelled differently in the store is the same pack
+        std::fs::rename(&updated[0], config.store.join("v.p.1.0.0.pdsc")).unwrap();
+        fetcher.1.lock().unwrap().clear();
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("v.p.1.0.0.pdsc")]);
+        let requests = fetcher.1.lock().unwrap().clone();
+        assert_eq!(requests, vec!["http://example.com/index.pidx".to_string()]);
+    }
+
+    #[test]
+    fn downloads_are_verified_against_checksums() {
+        let listed = |checksum: &'static str| {
+            let index = match checksum {
+                "good" => {
+                    "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+                     <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"P\" version=\"1.0.0\" \
+                     checksum=\"sha256:02f1f5031ddb1a8f73ee7cf723b6196e850a3fd7e929e1f5fb9fe0c4d5aafcdf\"/>\
+                     </pindex></index>"
+                }
+                _ => {
+                    "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+                     <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"P\" version=\"1.0.0\" \
+                     checksum=\"cdf4786292db141334640f8d982467997f1c3574\"/>\
+                     </pindex></index>"
+                }
+            };
+            let files = HashMap::from([
+                ("http://example.com/index.pidx".to_string(), index),
+                ("http://example.com/V.P.pdsc".to_string(), "<package/>"),
+            ]);
+            fetching(files).0
+        };
+        let config = listed("good");
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        assert_eq!(
+            update(&config, vidx(), (), CancellationToken::new())
+                .unwrap()
+                .pdsc_files(),
+            vec![dest.clone()]
+        );
+        let config = listed("bad");
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        let report = update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(report.failed[0].code, "checksum");
+        assert!(report.failed[0]
+            .error
+            .starts_with("http://example.com/V.P.pdsc: "));
+        assert!(!dest.exists());
+        assert!(!config.store.join("V.P.1.0.0.part").exists());
+
+        // Strict mode refuses files without a checksum, unless one is
+        // published next to them
+        let config = TestConfig {
+            require_checksum: true,
+            ..memory_store("<package/>").0
+        };
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        let report = update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(report.failed[0].code, "checksum");
+        assert!(!dest.exists());
+        let mut files = index_files("<package/>");
+        files.insert(
+            "http://example.com/V.P.pdsc.sha1".to_string(),
+            "cdf4786292db141334640f8d982467997f1c3573  V.P.pdsc\n",
+        );
+        let fetcher = Arc::new(MemoryFetcher(files.clone(), Mutex::default()));
+        let config = TestConfig {
+            fetcher: Some(fetcher.clone()),
+            ..config
+        };
+        update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "<package/>");
+        let requests = fetcher.1.lock().unwrap().clone();
+        assert!(requests.contains(&"http://example.com/V.P.pdsc.sha256".to_string()));
+
+        // A sidecar that fails to download is skipped like a missing one
+        std::fs::remove_dir_all(&config.store).unwrap();
+        let fetcher = BrokenSha256(MemoryFetcher(files, Mutex::default()));
+        let config = TestConfig {
+            fetcher: Some(Arc::new(fetcher)),
+            ..config
+        };
+        let report = update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert!(report.failed.is_empty());
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "<package/>");
+    }
+
+    /// A [`MemoryFetcher`] whose `.sha256` files fail with a server error
+    struct BrokenSha256(MemoryFetcher);
+
+    impl Fetcher for BrokenSha256 {
+        fn get(&self, url: Url) -> BoxFuture<'static, anyhow::Result<Body>> {
+            match url.as_str().ends_with(".sha256") {
+                true => future::ready(Err(HttpStatus(500).into())).boxed(),
+                false => self.0.get(url),
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_config_errors() {
+        let config = TestConfig::new();
+        let config = TestConfig {
+            ca_certificates: vec![config.dir().join("missing.pem")],
+            ..config
+        };
+        let err = update(&config, vidx(), (), CancellationToken::new()).unwrap_err();
+        assert_eq!(err.code(), "config");
+        assert!(matches!(err, Error::Config(_)));
+        assert!(err.to_string().starts_with("Invalid configuration: "));
+    }
+
+    #[test]
+    fn html_error_pages_are_not_stored() {
+        let (config, _) = memory_store("<!DOCTYPE html><html><body>Not found</body></html>");
+        update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert!(!config.store.join("V.P.1.0.0.pdsc").exists());
+        assert!(!config.store.join("V.P.1.0.0.part").exists());
+    }
+
+    #[test]
+    fn other_documents_do_not_replace_stored_pdscs() {
+        let config = TestConfig {
+            refresh: true,
+            ..memory_store("<index><vendor>V</vendor></index>").0
+        };
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+        std::fs::create_dir_all(&config.store).unwrap();
+        std::fs::write(&dest, "<package/>").unwrap();
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert!(updated.is_empty());
+        assert_eq!(std::fs::read_to_string(&dest).unwrap(), "<package/>");
+        assert!(!dest.with_extension("part").exists());
+    }
+
+    #[test]
+    fn vanished_packs_are_recorded() {
+        let (config, _) = memory_store("<package/>");
+        let old = config.store.join("V.P.0.9.0.pdsc");
+        let mut files = index_files("<package/>");
+        files.remove("http://example.com/V.P.pdsc");
+        let gone = TestConfig {
+            fetcher: Some(Arc::new(MemoryFetcher(files, Mutex::default()))),
+            ..config.clone()
+        };
+        std::fs::create_dir_all(&config.store).unwrap();
+        std::fs::write(&old, "<package/>").unwrap();
+
+        // Kept by default, but recorded
+        update(&gone, vidx(), (), CancellationToken::new()).unwrap();
+        let vanished = vanished_packs(&config.store);
+        assert_eq!(vanished["V.P"].files, vec!["V.P.0.9.0.pdsc".to_string()]);
+        assert!(!vanished["V.P"].deleted);
+        assert!(old.exists());
+
+        let delete = TestConfig {
+            vanished_policy: VanishedPolicy::Delete,
+            ..gone
+        };
+        update(&delete, vidx(), (), CancellationToken::new()).unwrap();
+        assert!(vanished_packs(&config.store)["V.P"].deleted);
+        assert!(!old.exists());
+
+        // Published again
+        update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert!(vanished_packs(&config.store).is_empty());
+    }
+
+    #[test]
+    fn parallel_updates_to_distinct_stores() {
+        let stores = [
+            memory_store("<package>a</package>").0,
+            memory_store("<package>b</package>").0,
+        ];
+        std::thread::scope(|scope| {
+            for config in &stores {
+                scope.spawn(move || update(config, vidx(), (), CancellationToken::new()).unwrap());
+            }
+        });
+        let read = |config: &TestConfig| {
+            std::fs::read_to_string(config.store.join("V.P.1.0.0.pdsc")).unwrap()
+        };
+        assert_eq!(read(&stores[0]), "<package>a</package>");
+        assert_eq!(read(&stores[1]), "<package>b</package>");
+
+        // Updates driven concurrently from a single runtime stay apart too,
+        // and cancelling one leaves the other running
+        let stores = [
+            memory_store("<package>c</package>").0,
+            memory_store("<package>d</package>").0,
+        ];
+        let cancelled = CancellationToken::new();
+        cancelled.cancel();
+        let (c, d) = block_on(futures::future::join(
+            update_async(&stores[0], vidx(), (), CancellationToken::new()),
+            update_async(&stores[1], vidx(), (), cancelled),
+        ))
+        .unwrap();
+        assert_eq!(
+            c.unwrap().pdsc_files(),
+            vec![stores[0].store.join("V.P.1.0.0.pdsc")]
+        );
+        assert!(matches!(d, Err(Error::Cancelled)));
+        assert!(!stores[1].store.exists());
+    }
+
+    /// A body that counts itself as open from its first poll until dropped
+    struct OpenBody {
+        inner: ByteStream,
+        counts: Arc<(AtomicUsize, AtomicUsize)>,
+        opened: bool,
+    }
+
+    impl futures::Stream for OpenBody {
+        type Item = anyhow::Result<bytes::Bytes>;
+
+        fn poll_next(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Self::Item>> {
+            if !self.opened {
+                // Yield once so that other bodies get a chance to open
+                self.opened = true;
+                let open = self.counts.0.fetch_add(1, Ordering::SeqCst) + 1;
+                self.counts.1.fetch_max(open, Ordering::SeqCst);
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
+            }
+            self.inner.as_mut().poll_next(cx)
+        }
+    }
+
+    impl Drop for OpenBody {
+        fn drop(&mut self) {
+            if self.opened {
+                self.counts.0.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
+
+    struct CountingFetcher(MemoryFetcher, Arc<(AtomicUsize, AtomicUsize)>);
+
+    impl Fetcher for CountingFetcher {
+        fn get(&self, url: Url) -> BoxFuture<'static, anyhow::Result<Body>> {
+            let counts = self.1.clone();
+            self.0
+                .get(url)
+                .map(|body| {
+                    let body: ByteStream = Box::pin(OpenBody {
+                        inner: body?.into_stream(),
+                        counts,
+                        opened: false,
+                    });
+                    Ok(body.into())
+                })
+                .boxed()
+        }
+    }
+
+    #[test]
+    fn open_bodies_are_bounded() {
+        let files = HashMap::from([
+            (
+                "http://example.com/index.pidx".to_string(),
+                "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+                 <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"A\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"B\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"C\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+            ),
+            ("http://example.com/V.A.pdsc".to_string(), "<package/>"),
+            ("http://example.com/V.B.pdsc".to_string(), "<package/>"),
+            ("http://example.com/V.C.pdsc".to_string(), "<package/>"),
+        ]);
+        let counts = Arc::new((AtomicUsize::new(0), AtomicUsize::new(0)));
+        let fetcher = CountingFetcher(MemoryFetcher(files, Mutex::default()), counts.clone());
+        let config = TestConfig {
+            fetcher: Some(Arc::new(fetcher)),
+            max_open_bodies: 1,
+            ..TestConfig::new()
+        };
+        let updated = update(&config, vidx(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated.len(), 3);
+        assert_eq!(counts.1.load(Ordering::SeqCst), 1);
+        assert_eq!(counts.0.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn installed_packs_are_extracted() {
+        use crate::update::download::IntoDownload;
+        use crate::utils::parse::FromElem;
+        use std::io::Write;
+
+        let config = TestConfig {
+            extract_packs: true,
+            ..fetching(HashMap::new()).0
+        };
+        let pdsc = "../../tests/test-pack-index/MyVendor.MyPack.pdsc";
+        let pdsc = Package::from_path(std::path::Path::new(pdsc)).unwrap();
+
+        // An archive already in the store is extracted without a download
+        let pack = (&pdsc).into_fd(&config);
+        std::fs::create_dir_all(pack.parent().unwrap()).unwrap();
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&pack).unwrap());
+        zip.start_file("MyVendor.MyPack.pdsc", Default::default())
+            .unwrap();
+        zip.write_all(b"<package/>").unwrap();
+        zip.finish().unwrap();
+
+        let installed = install(&config, [&pdsc], (), CancellationToken::new()).unwrap();
+        assert_eq!(installed, vec![pack.clone()]);
+        let extracted = extract::extract_dir(&pack).join("MyVendor.MyPack.pdsc");
+        assert_eq!(std::fs::read_to_string(extracted).unwrap(), "<package/>");
+    }
+
+    #[test]
+    fn packs_are_installed_by_spec() {
+        use std::io::Write;
+
+        let config = TestConfig::new();
+        std::fs::create_dir_all(&config.store).unwrap();
+        std::fs::copy(
+            "../../tests/test-pack-index/MyVendor.MyPack.pdsc",
+            config.store.join("MyVendor.MyPack.1.1.0.pdsc"),
+        )
+        .unwrap();
+        let pack = config
+            .store
+            .join("MyVendor")
+            .join("MyPack")
+            .join("1.1.0.pack");
+        std::fs::create_dir_all(pack.parent().unwrap()).unwrap();
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&pack).unwrap());
+        zip.start_file("MyVendor.MyPack.pdsc", Default::default())
+            .unwrap();
+        zip.write_all(b"<package/>").unwrap();
+        zip.finish().unwrap();
+
+        let spec = "MyVendor::MyPack@1.1.0".parse().unwrap();
+        let dir = install_pack(&config, &spec, (), CancellationToken::new()).unwrap();
+        assert_eq!(
+            dir,
+            config.store.join("MyVendor").join("MyPack").join("1.1.0")
+        );
+        assert!(dir.join("MyVendor.MyPack.pdsc").exists());
+
+        let spec = "MyVendor::MyPack@9.9.9".parse().unwrap();
+        let err = install_pack(&config, &spec, (), CancellationToken::new()).unwrap_err();
+        assert_eq!(err.code(), "pack");
+    }
+
+    #[test]
+    fn redirects_to_other_origins_are_recorded() {
+        let mirror = serve(move |_, _| ok("<package/>"));
+        let redirector = serve(move |path, _| {
+            redirect("302 Found", &format!("http://127.0.0.1:{}{}", mirror, path))
+        });
+        let index = serve(move |_, _| {
+            ok(&format!(
+                "<index><vendor>V</vendor><url>http://127.0.0.1/</url><pindex>\
+                 <pdsc url=\"http://127.0.0.1:{}/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+                redirector
+            ))
+        });
+        let config = TestConfig {
+            origin_warnings: true,
+            ..TestConfig::new()
+        };
+        let vidx = vec![format!("http://127.0.0.1:{}/index.pidx", index)];
+        update(&config, vidx, (), CancellationToken::new()).unwrap();
+
+        let origins = foreign_origins(&config.store);
+        let served = &origins["V.P.1.0.0.pdsc"];
+        let declared = format!("http://127.0.0.1:{}/V.P.pdsc", redirector);
+        assert_eq!(served.declared, declared);
+        assert_eq!(
+            served.actual,
+            format!("http://127.0.0.1:{}/V.P.pdsc", mirror)
+        );
+    }
+
+    #[test]
+    fn redirects_are_followed_to_relative_locations() {
+        let port = serve(|path, headers| match path {
+            "/index.pidx" => ok(&format!(
+                "<index><vendor>V</vendor><url>http://{0}/</url><pindex>\
+                 <pdsc url=\"http://{0}/old/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://{0}/loop/\" vendor=\"V\" name=\"Loop\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+                host(headers)
+            )),
+            "/old/V.P.pdsc" => redirect("301 Moved Permanently", "../new/V.P.pdsc"),
+            "/new/V.P.pdsc" => redirect("307 Temporary Redirect", "/files/V.P.pdsc"),
+            "/files/V.P.pdsc" => ok("<package/>"),
+            "/loop/V.Loop.pdsc" => redirect("302 Found", "/loop/V.Loop.pdsc"),
+            _ => status("404 Not Found"),
+        });
+        let config = TestConfig::new();
+        let vidx = vec![format!("http://127.0.0.1:{}/index.pidx", port)];
+        let updated = update(&config, vidx, (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("V.P.1.0.0.pdsc")]);
+        assert_eq!(std::fs::read_to_string(&updated[0]).unwrap(), "<package/>");
+        // Redirects within the origin are not recorded, and endless ones
+        // leave nothing behind
+        assert!(foreign_origins(&config.store).is_empty());
+        assert!(!config.store.join("V.Loop.1.0.0.pdsc").exists());
+        assert!(!config.store.join("V.Loop.1.0.0.part").exists());
+    }
+
+    #[test]
+    fn missing_files_are_fetched_from_mirrors() {
+        let port = serve(|path, headers| match path {
+            "/mirror/index.pidx" => ok(&format!(
+                "<index><vendor>V</vendor><url>http://{0}/primary/</url><pindex>\
+                 <pdsc url=\"http://{0}/primary/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://{0}/primary/\" vendor=\"V\" name=\"Gone\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+                host(headers)
+            )),
+            "/mirror/V.P.pdsc" => ok("<package/>"),
+            _ => status("404 Not Found"),
+        });
+        let base = format!("http://127.0.0.1:{}", port);
+        let mirror = |to: &str| format!("{0}/primary/={0}/{1}/", base, to).parse().unwrap();
+        let config = TestConfig {
+            mirrors: vec![mirror("missing"), mirror("mirror")],
+            ..TestConfig::new()
+        };
+        let vidx = vec![format!("{}/primary/index.pidx", base)];
+        let report = update(&config, vidx, (), CancellationToken::new()).unwrap();
+        assert_eq!(report.downloaded, vec![config.store.join("V.P.1.0.0.pdsc")]);
+        // Files missing from every mirror still vanish
+        assert_eq!(report.failed.len(), 1);
+        assert!(vanished_packs(&config.store).contains_key("V.Gone"));
+    }
+
+    #[test]
+    fn pack_stores_share_downloads() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let not_modified = Arc::new(AtomicUsize::new(0));
+        let port = {
+            let (sent, not_modified) = (sent.clone(), not_modified.clone());
+            serve(move |path, headers| match path {
+                "/index.pidx" => ok(&format!(
+                    "<index><vendor>V</vendor><url>http://{0}/</url><pindex>\
+                     <pdsc url=\"http://{0}/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                     </pindex></index>",
+                    host(headers)
+                )),
+                _ if headers.contains("if-none-match: \"1\"") => {
+                    not_modified.fetch_add(1, Ordering::SeqCst);
+                    "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_string()
+                }
+                _ => {
+                    sent.fetch_add(1, Ordering::SeqCst);
+                    "HTTP/1.1 200 OK\r\nETag: \"1\"\r\nContent-Length: 10\r\n\
+                     Connection: close\r\n\r\n<package/>"
+                        .to_string()
+                }
+            })
+        };
+        let shared = TestConfig::new();
+        let shared = TestConfig {
+            shared_cache: Some(shared.dir().join("cache")),
+            ..shared
+        };
+        let vidx = vec![format!("http://127.0.0.1:{}/index.pidx", port)];
+        for store in ["first", "second"] {
+            let config = TestConfig {
+                store: shared.dir().join(store),
+                ..shared.clone()
+            };
+            let report = update(&config, vidx.clone(), (), CancellationToken::new()).unwrap();
+            let dest = config.store.join("V.P.1.0.0.pdsc");
+            assert_eq!(report.downloaded, vec![dest.clone()]);
+            assert_eq!(std::fs::read_to_string(dest).unwrap(), "<package/>");
+        }
+        // The second store only asked whether the file changed
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn newer_index_timestamps_refresh_pdscs() {
+        let served = Arc::new(Mutex::new(("2020-01-01T00:00:00Z", "<package/>")));
+        let state = served.clone();
+        let port = serve(move |path, headers| {
+            let (timestamp, pdsc) = *state.lock().unwrap();
+            match path {
+                "/index.pidx" => ok(&format!(
+                    "<index schemaVersion=\"1.7\"><vendor>V</vendor><url>http://{0}/</url><pindex>\
+                     <pdsc url=\"http://{0}/\" vendor=\"V\" name=\"P\" version=\"1.0.0\" \
+                     timestamp=\"{1}\"/></pindex></index>",
+                    host(headers),
+                    timestamp
+                )),
+                "/V.P.pdsc" => ok(pdsc),
+                _ => status("404 Not Found"),
+            }
+        });
+        let config = TestConfig::new();
+        let vidx = || vec![format!("http://127.0.0.1:{}/index.pidx", port)];
+        let pdsc = config.store.join("V.P.1.0.0.pdsc");
+        let report = update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(report.downloaded, vec![pdsc.clone()]);
+
+        *served.lock().unwrap() = ("2020-01-01T00:00:00Z", "<package><name>P</name></package>");
+        let report = update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(report.skipped, vec![pdsc.clone()]);
+        assert_eq!(std::fs::read_to_string(&pdsc).unwrap(), "<package/>");
+
+        // A timestamp past the file's modification time refreshes it
+        *served.lock().unwrap() = ("2999-01-01T00:00:00Z", "<package><name>P</name></package>");
+        let report = update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(report.downloaded, vec![pdsc.clone()]);
+        let report = update(&config, vidx(), (), CancellationToken::new()).unwrap();
+        assert_eq!(report.skipped, vec![pdsc]);
+    }
+
+    #[test]
+    fn dry_runs_write_nothing() {
+        let port = serve(|path, headers| {
+            match path {
+            "/index.pidx" => ok(&format!(
+                "<index><vendor>V</vendor><url>http://{0}/</url><pindex>\
+                 <pdsc url=\"http://{0}/\" vendor=\"V\" name=\"Listed\" version=\"1.0.0\" size=\"123\"/>\
+                 <pdsc url=\"http://{0}/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+                host(headers)
+            )),
+            "/V.P.pdsc" => ok("<package/>"),
+            _ => status("404 Not Found"),
+        }
+        });
+        let config = TestConfig::new();
+        let vidx = vec![format!("http://127.0.0.1:{}/index.pidx", port)];
+        let planned = dry_run_update(&config, vidx).unwrap();
+        let sizes: Vec<_> = planned.iter().map(|p| (p.reason, p.size)).collect();
+        assert_eq!(
+            sizes,
+            vec![(PlanReason::New, Some(123)), (PlanReason::New, Some(10))]
+        );
+        assert!(!config.store.exists());
+    }
+
+    #[test]
+    fn broken_indexes_and_pdscs_are_skipped() {
+        let port = serve(|path, headers| match path {
+            "/index.vidx" => ok(&format!(
+                "<index><vendor>All</vendor><url>http://{0}/</url><vindex>\
+                 <pidx vendor=\"A\" url=\"http://{0}/\"/>\
+                 <pidx vendor=\"Missing\" url=\"http://{0}/\"/>\
+                 <pidx vendor=\"Malformed\" url=\"http://{0}/\"/>\
+                 </vindex></index>",
+                host(headers)
+            )),
+            "/A.pidx" => ok(&format!(
+                "<index><vendor>A</vendor><url>http://{0}/</url><pindex>\
+                 <pdsc url=\"http://{0}/\" vendor=\"A\" name=\"P\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://{0}/\" vendor=\"A\" name=\"Gone\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://{0}/\" vendor=\"A\" name=\"Html\" version=\"1.0.0\"/>\
+                 <pdsc url=\"http://{0}/\" vendor=\"A\" name=\"Cut\" version=\"1.0.0\"/>\
+                 <pdsc vendor=\"A\" name=\"NoUrl\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+                host(headers)
+            )),
+            "/Malformed.pidx" => ok("<index><vendor>Malformed</vendor><pindex>"),
+            "/A.P.pdsc" => ok("<package/>"),
+            "/A.Html.pdsc" => ok("<html><body>Oops</body></html>"),
+            "/A.Cut.pdsc" => ok("<package><name>Cut</name>"),
+            _ => status("404 Not Found"),
+        });
+        let config = TestConfig::new();
+        let vidx = vec![format!("http://127.0.0.1:{}/index.vidx", port)];
+        let updated = update(&config, vidx, (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("A.P.1.0.0.pdsc")]);
+        assert!(vanished_packs(&config.store).contains_key("A.Gone"));
+        let leftovers: Vec<_> = std::fs::read_dir(&config.store)
+            .unwrap()
+            .flatten()
+            .map(|entry| entry.file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".pdsc") || name.ends_with(".part"))
+            .collect();
+        assert_eq!(leftovers, vec!["A.P.1.0.0.pdsc"]);
+    }
+
+    #[test]
+    fn downloads_go_through_the_proxy() {
+        let proxy = serve(|url, _| {
+            let body = match url {
+                "http://packs.invalid/index.pidx" => {
+                    "<index><vendor>V</vendor><url>http://packs.invalid/</url><pindex>\
+                     <pdsc url=\"http://packs.invalid/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                     </pindex></index>"
+                }
+                "http://packs.invalid/V.P.pdsc" => "<package/>",
+                _ => return "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string(),
+            };
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        });
+        let config = TestConfig {
+            proxy: Some(format!("http://127.0.0.1:{}", proxy)),
+            ..TestConfig::new()
+        };
+        let vidx = vec!["http://packs.invalid/index.pidx".to_string()];
+        let updated = update(&config, vidx, (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("V.P.1.0.0.pdsc")]);
+    }
+
+    #[test]
+    fn unmodified_pdscs_are_not_downloaded_again() {
+        let ok = |body: &str, etag: &str| {
+            format!(
+                "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                etag,
+                body.len(),
+                body
+            )
+        };
+        let contents = Arc::new(Mutex::new("<package/>"));
+        let not_modified = Arc::new(AtomicUsize::new(0));
+        let packs = {
+            let (contents, not_modified) = (contents.clone(), not_modified.clone());
+            serve(move |_, headers| {
+                let body = *contents.lock().unwrap();
+                let etag = format!("\"{}\"", body.len());
+                if headers.contains(&format!("if-none-match: {}", etag)) {
+                    not_modified.fetch_add(1, Ordering::SeqCst);
+                    return "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_string();
+                }
+                ok(body, &etag)
+            })
+        };
+        let index = serve(move |_, _| {
+            let body = format!(
+                "<index><vendor>V</vendor><url>http://127.0.0.1/</url><pindex>\
+                 <pdsc url=\"http://127.0.0.1:{}/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                 </pindex></index>",
+                packs
+            );
+            ok(&body, "\"index\"")
+        });
+        let config = TestConfig {
+            refresh: true,
+            ..TestConfig::new()
+        };
+        let vidx = vec![format!("http://127.0.0.1:{}/index.pidx", index)];
+        let dest = config.store.join("V.P.1.0.0.pdsc");
+
+        update(&config, vidx.clone(), (), CancellationToken::new()).unwrap();
+        assert_eq!(not_modified.load(Ordering::SeqCst), 0);
+        let updated = update(&config, vidx.clone(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![dest.clone()]);
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+
+        // A file changed in place is downloaded again
+        *contents.lock().unwrap() = "<package>new</package>";
+        update(&config, vidx, (), CancellationToken::new()).unwrap();
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            std::fs::read_to_string(&dest).unwrap(),
+            "<package>new</package>"
+        );
+    }
+
+    #[test]
+    fn credentials_are_sent_to_their_host() {
+        let port = serve(|path, headers| {
+            if !headers.contains("authorization: bearer secret") {
+                return status("401 Unauthorized");
+            }
+            let port = host(headers).trim_start_matches("localhost:");
+            let body = match path {
+                "/index.pidx" => format!(
+                    "<index><vendor>V</vendor><url>http://localhost/</url><pindex>\
+                     <pdsc url=\"http://LOCALHOST:{}/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+                     </pindex></index>",
+                    port
+                ),
+                _ => "<package/>".to_string(),
+            };
+            ok(&body)
+        });
+        let vidx = vec![format!("http://localhost:{}/index.pidx", port)];
+        let authorized =
+            |host: &str| HashMap::from([(host.to_string(), "bearer:secret".parse().unwrap())]);
+
+        let config = TestConfig {
+            credentials: authorized("127.0.0.1"),
+            ..TestConfig::new()
+        };
+        let updated = update(&config, vidx.clone(), (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert!(updated.is_empty());
+
+        let config = TestConfig {
+            credentials: authorized("LocalHost"),
+            ..config
+        };
+        let updated = update(&config, vidx, (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("V.P.1.0.0.pdsc")]);
+    }
+
+    #[test]
+    fn interrupted_pack_downloads_are_resumed() {
+        use crate::update::download::IntoDownload;
+        use crate::utils::parse::FromElem;
+
+        const PACK: &str = "0123456789";
+        let respond = |honour_ranges: bool| {
+            move |_: &str, headers: &str| {
+                let ranged = headers.contains("range: bytes=4-");
+                if honour_ranges && !ranged {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                } else if honour_ranges {
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 4-9/10\r\n\
+                         Content-Length: 6\r\nConnection: close\r\n\r\n{}",
+                        &PACK[4..]
+                    )
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n{}",
+                        PACK
+                    )
+                }
+            }
+        };
+        let source =
+            std::fs::read_to_string("../../tests/test-pack-index/MyVendor.MyPack.pdsc").unwrap();
+        let config = TestConfig::new();
+
+        for (honour_ranges, partial) in [(true, "0123"), (false, "abcd")] {
+            let _ = std::fs::remove_dir_all(&config.store);
+            let port = serve(respond(honour_ranges));
+            let pdsc = Package::from_string(&source.replace(
+                "http://localhost:8001/tests/test-pack-index/",
+                &format!("http://127.0.0.1:{}/", port),
+            ))
+            .unwrap();
+            let pack = (&pdsc).into_fd(&config);
+            std::fs::create_dir_all(pack.parent().unwrap()).unwrap();
+            std::fs::write(pack.with_extension("part"), partial).unwrap();
+
+            let installed = install(&config, [&pdsc], (), CancellationToken::new()).unwrap();
+            assert_eq!(installed, vec![pack.clone()]);
+            assert_eq!(std::fs::read_to_string(&pack).unwrap(), PACK);
+            assert!(!pack.with_extension("part").exists());
+        }
+    }
+
+    #[test]
+    fn updates_read_local_mirrors() {
+        let config = TestConfig::new();
+        let mirror = config.dir().join("mirror");
+        std::fs::create_dir_all(&mirror).unwrap();
+        let mirror_url = Url::from_directory_path(&mirror).unwrap();
+        let index = format!(
+            "<index><vendor>V</vendor><url>{0}</url><pindex>\
+             <pdsc url=\"{0}\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+             <pdsc url=\"{0}\" vendor=\"V\" name=\"Gone\" version=\"1.0.0\"/>\
+             </pindex></index>",
+            mirror_url
+        );
+        std::fs::write(mirror.join("index.pidx"), index).unwrap();
+        std::fs::write(mirror.join("V.P.pdsc"), "<package/>").unwrap();
+
+        let list = vec![mirror.join("index.pidx").display().to_string()];
+        let updated = update(&config, list, (), CancellationToken::new())
+            .unwrap()
+            .pdsc_files();
+        assert_eq!(updated, vec![config.store.join("V.P.1.0.0.pdsc")]);
+        assert_eq!(std::fs::read_to_string(&updated[0]).unwrap(), "<package/>");
+        assert!(vanished_packs(&config.store).contains_key("V.Gone"));
+    }
+
+    #[test]
+    fn update_reports_are_saved() {
+        let config = TestConfig::new();
+        let mirror = config.dir().join("mirror");
+        std::fs::create_dir_all(&mirror).unwrap();
+        let mirror_url = Url::from_directory_path(&mirror).unwrap();
+        let index = format!(
+            "<index><vendor>V</vendor><url>{0}</url><pindex>\
+             <pdsc url=\"{0}\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+             <pdsc url=\"{0}\" vendor=\"V\" name=\"Gone\" version=\"1.0.0\"/>\
+             </pindex></index>",
+            mirror_url
+        );
+        std::fs::write(mirror.join("index.pidx"), index).unwrap();
+        std::fs::write(mirror.join("V.P.pdsc"), "<package/>").unwrap();
+        let list = || vec![mirror.join("index.pidx").display().to_string()];
+
+        let report = update(&config, list(), (), CancellationToken::new()).unwrap();
+        assert_eq!(report.downloaded, vec![config.store.join("V.P.1.0.0.pdsc")]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].url, format!("{}V.Gone.pdsc", mirror_url));
+        assert_eq!(report.failed[0].code, "download");
+        assert_eq!(UpdateReport::load(&config.store), Some(report));
+
+        let report = update(&config, list(), (), CancellationToken::new()).unwrap();
+        assert!(report.downloaded.is_empty());
+        assert_eq!(report.skipped, vec![config.store.join("V.P.1.0.0.pdsc")]);
+        assert_eq!(UpdateReport::load(&config.store), Some(report));
+    }
+
+    #[test]
+    fn cancelled_update_stops_before_fetching() {
+        let config = TestConfig::new();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let vidx = vec!["http://localhost:1/index.vidx".to_string()];
+        let err = update(&config, vidx, (), cancel).unwrap_err();
+        assert!(matches!(err, Error::Cancelled));
+        assert_eq!(err.code(), "cancelled");
+    }
+
+    #[test]
+    fn offline_updates_fail_without_requests() {
+        let (config, fetcher) = memory_store("<package/>");
+        let config = TestConfig {
+            offline: true,
+            ..config
+        };
+        let err = update(&config, vidx(), (), CancellationToken::new()).unwrap_err();
+        assert_eq!(err.url(), Some("http://example.com/index.pidx"));
+        assert_eq!(err.code(), "offline");
 
-    let dl_cntx = DownloadContext::new(config, progress)?;
-    rt.block_on(async { Ok(dl_cntx.download_iterator(pdsc_list).await) })
+        // A local index is read, and the PDSC file it lists is refused
+        std::fs::create_dir_all(&config.store).unwrap();
+        let index = config.store.join("local.pidx");
+        std::fs::write(
+            &index,
+            "<index><vendor>V</vendor><url>http://example.com/</url><pindex>\
+             <pdsc url=\"http://example.com/\" vendor=\"V\" name=\"P\" version=\"1.0.0\"/>\
+             </pindex></index>",
+        )
+        .unwrap();
+        let local = vec![Url::from_file_path(&index).unwrap().to_string()];
+        let err = update(&config, local, (), CancellationToken::new()).unwrap_err();
+        assert!(matches!(&err, Error::Offline { url } if url == "http://example.com/V.P.pdsc"));
+        assert!(fetcher.1.lock().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::fs::{rename, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+const ORIGINS_FILE: &str = ".origins.json";
+
+/// The URL a file was declared at and the URL it was actually served from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServedFrom {
+    pub declared: String,
+    pub actual: String,
+}
+
+/// Files of the pack store that redirects fetched from another origin,
+/// keyed by their path relative to the store
+///
+/// Files that were served by the origin their index declares have no entry.
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct OriginLog {
+    files: BTreeMap<String, ServedFrom>,
+}
+
+/// Whether two URLs differ in scheme, host or port
+pub(crate) fn other_origin(declared: &Url, actual: &Url) -> bool {
+    declared.origin() != actual.origin()
+}
+
+fn log_path(pack_store: &Path) -> PathBuf {
+    pack_store.join(ORIGINS_FILE)
+}
+
+/// The path of `file` relative to the pack store, with forward slashes
+pub(crate) fn key(pack_store: &Path, file: &Path) -> String {
+    let relative = file.strip_prefix(pack_store).unwrap_or(file);
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+impl OriginLog {
+    pub(crate) fn load(pack_store: &Path) -> Self {
+        File::open(log_path(pack_store))
+            .ok()
+            .and_then(|fd| serde_json::from_reader(BufReader::new(fd)).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, pack_store: &Path) -> Result<(), Error> {
+        let path = log_path(pack_store);
+        if self.files.is_empty() && !path.exists() {
+            return Ok(());
+        }
+        let temp = path.with_extension("part");
+        std::fs::create_dir_all(pack_store)?;
+        serde_json::to_writer_pretty(File::create(&temp)?, self)?;
+        rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Record where `file` was served from, forgetting earlier records of it
+    /// when that was its declared origin
+    pub(crate) fn record(&mut self, pack_store: &Path, file: &Path, declared: &Url, actual: &Url) {
+        let key = key(pack_store, file);
+        if other_origin(declared, actual) {
+            let served = ServedFrom {
+                declared: declared.to_string(),
+                actual: actual.to_string(),
+            };
+            self.files.insert(key, served);
+        } else {
+            self.files.remove(&key);
+        }
+    }
+}
+
+/// The files of a pack store that were served from another origin than the
+/// one declared for them, keyed by their path relative to the store
+pub fn foreign_origins(pack_store: &Path) -> BTreeMap<String, ServedFrom> {
+    OriginLog::load(pack_store).files
+}
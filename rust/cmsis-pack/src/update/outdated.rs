@@ -0,0 +1,179 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::pdsc::Package;
+use crate::update::deprecated::{deprecated_packs, deprecation_of, logged, Deprecation};
+use crate::update::download::local_pdscs;
+use crate::update::listing::StoreListing;
+use crate::utils::compare_versions;
+use crate::utils::parse::from_store_path;
+
+/// An installed pack with a newer version or a deprecation notice
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Outdated {
+    pub vendor: String,
+    pub name: String,
+    /// The newest installed version
+    pub installed: String,
+    /// The newest version the PDSC files of the store list, when newer than
+    /// the installed one
+    pub latest: Option<String>,
+    pub deprecation: Option<Deprecation>,
+}
+
+pub(crate) fn entries(dir: &Path) -> Vec<(PathBuf, String, bool)> {
+    let mut entries: Vec<_> = read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            let name = entry.file_name().into_string().ok()?;
+            Some((entry.path(), name, is_dir))
+        })
+        .collect();
+    entries.sort();
+    entries
+}
+
+/// The installed versions of the pack in `dir`, from its archives and
+/// extracted directories, newest first
+pub(crate) fn installed_versions(dir: &Path) -> Vec<String> {
+    let mut versions: Vec<String> = entries(dir)
+        .into_iter()
+        .filter_map(|(_, name, is_dir)| {
+            if is_dir {
+                (!name.ends_with(".extracting")).then_some(name)
+            } else {
+                name.strip_suffix(".pack").map(str::to_string)
+            }
+        })
+        .filter(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+        .collect();
+    versions.sort_by(|left, right| compare_versions(right, left));
+    versions.dedup();
+    versions
+}
+
+/// The installed packs that have a newer version in the PDSC files of the
+/// store, or that their vendor index or PDSC file marks as deprecated, in
+/// order of their vendor and name
+///
+/// Newer versions are only known once an update downloaded their PDSC files.
+pub fn outdated_packs(pack_store: &Path) -> Vec<Outdated> {
+    let notices = deprecated_packs(pack_store);
+    let mut listing = StoreListing::default();
+    let mut outdated = Vec::new();
+    for (vendor_dir, vendor, _) in entries(pack_store).into_iter().filter(|e| e.2) {
+        for (pack_dir, name, _) in entries(&vendor_dir).into_iter().filter(|e| e.2) {
+            let installed = match installed_versions(&pack_dir).into_iter().next() {
+                Some(installed) => installed,
+                None => continue,
+            };
+            let newest = local_pdscs(&mut listing, pack_store, &vendor, &name)
+                .into_iter()
+                .max_by(|(_, left), (_, right)| compare_versions(left, right));
+            let (latest, deprecation) = match newest {
+                Some((path, version)) => {
+                    let deprecation = logged(&notices, &vendor, &name).or_else(|| {
+                        let pdsc = from_store_path::<Package>(&path).ok()?;
+                        deprecation_of(&notices, &pdsc)
+                    });
+                    let newer = compare_versions(&version, &installed).is_gt();
+                    (newer.then_some(version), deprecation)
+                }
+                None => (None, logged(&notices, &vendor, &name)),
+            };
+            if latest.is_some() || deprecation.is_some() {
+                outdated.push(Outdated {
+                    vendor: vendor.clone(),
+                    name,
+                    installed,
+                    latest,
+                    deprecation,
+                });
+            }
+        }
+    }
+    outdated
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::pack_index::PdscRef;
+    use crate::update::deprecated::DeprecationLog;
+    use std::fs::{create_dir_all, write};
+
+    fn pdsc(name: &str, version: &str, deprecated: &str) -> String {
+        format!(
+            "<package><name>{}</name><vendor>Vendor</vendor>\
+             <description/><url>http://example.com/</url>\
+             <releases><release version=\"{}\" {}/></releases></package>",
+            name, version, deprecated
+        )
+    }
+
+    #[test]
+    fn newer_and_deprecated_packs_are_outdated() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        for pack in ["Vendor/Pack/1.0.0", "Vendor/Old/2.0.0", "Vendor/Current"] {
+            create_dir_all(store.join(pack)).unwrap();
+        }
+        write(store.join("Vendor/Current/1.0.0.pack"), "").unwrap();
+        write(
+            store.join("Vendor.Pack.1.10.0.pdsc"),
+            pdsc("Pack", "1.10.0", ""),
+        )
+        .unwrap();
+        write(
+            store.join("Vendor.Current.1.0.0.pdsc"),
+            pdsc("Current", "1.0.0", ""),
+        )
+        .unwrap();
+        let retired = pdsc(
+            "Old",
+            "2.0.0",
+            "deprecated=\"2023-06-01\" replacement=\"Vendor.Pack\"",
+        );
+        write(store.join("Vendor.Old.2.0.0.pdsc"), retired).unwrap();
+
+        let outdated = outdated_packs(&store);
+        assert_eq!(outdated.len(), 2);
+        assert_eq!(outdated[0].name, "Old");
+        assert_eq!(outdated[0].latest, None);
+        let notice = Deprecation {
+            since: "2023-06-01".to_string(),
+            replacement: Some("Vendor.Pack".to_string()),
+        };
+        assert_eq!(outdated[0].deprecation, Some(notice));
+        assert_eq!(outdated[1].installed, "1.0.0");
+        assert_eq!(outdated[1].latest.as_deref(), Some("1.10.0"));
+        assert_eq!(outdated[1].deprecation, None);
+
+        // A notice of the vendor index is reported without one in the PDSC
+        let mut log = DeprecationLog::default();
+        log.record(&[PdscRef {
+            url: "http://example.com/".to_string(),
+            vendor: "vendor".to_string(),
+            name: "current".to_string(),
+            version: "1.0.0".to_string(),
+            date: None,
+            deprecated: Some("true".to_string()),
+            replacement: None,
+            size: None,
+            released: None,
+            deprecated_on: None,
+            checksum: None,
+            timestamp: None,
+            updated: None,
+        }]);
+        log.save(&store).unwrap();
+        let outdated = outdated_packs(&store);
+        assert_eq!(outdated[0].name, "Current");
+        assert_eq!(outdated[0].deprecation.as_ref().unwrap().since, "true");
+    }
+}
@@ -0,0 +1,181 @@
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::pack_index::PdscRef;
+use crate::pdsc::Package;
+use crate::update::download::{is_complete_pdsc, newer_local_pdsc, DownloadConfig, IntoDownload};
+use crate::update::listing::StoreListing;
+use crate::update::validators::ValidatorLog;
+use crate::utils::pack_id;
+
+/// Why a file appears in a [`PlannedDownload`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanReason {
+    /// No version of the file is in the pack store
+    New,
+    /// Another version of the file is in the pack store
+    Updated,
+    /// This version is already in the pack store and will not be downloaded
+    Skipped,
+    /// A newer version is in the pack store, so this older one will not be
+    /// downloaded
+    Downgrade,
+}
+
+impl PlanReason {
+    /// Whether the file would be downloaded
+    pub fn downloads(&self) -> bool {
+        matches!(self, PlanReason::New | PlanReason::Updated)
+    }
+}
+
+/// A single download an update or install would perform
+#[derive(Clone, Debug, Serialize)]
+pub struct PlannedDownload {
+    pub url: String,
+    pub dest: PathBuf,
+    pub reason: PlanReason,
+    /// The size of the file, when a dry run learned it from the index or
+    /// the server
+    pub size: Option<u64>,
+}
+
+pub(crate) fn plan<I, F, D>(config: &D, items: I, same_file: F) -> Vec<PlannedDownload>
+where
+    I: IntoIterator,
+    I::Item: IntoDownload,
+    F: Fn(&I::Item, &str) -> bool,
+    D: DownloadConfig,
+{
+    let mut listing = StoreListing::default();
+    let store = config.pack_store();
+    let validators = ValidatorLog::load(&store);
+    items
+        .into_iter()
+        .filter_map(|item| {
+            let url = item.into_uri().ok()?;
+            let dest = listing.resolve(&store, &item.into_fd(config));
+            let has_sibling = |listing: &mut StoreListing| {
+                dest.parent().is_some_and(|dir| {
+                    listing
+                        .names(dir)
+                        .iter()
+                        .any(|name| name.to_str().is_some_and(|name| same_file(&item, name)))
+                })
+            };
+            let is_pdsc = dest.extension().is_some_and(|ext| ext == "pdsc");
+            let stale = |at| validators.is_stale(&store, &dest, &at);
+            let present = listing.contains(&dest)
+                && (!is_pdsc || is_complete_pdsc(&dest) && !item.updated().is_some_and(stale));
+            let reason = if present {
+                PlanReason::Skipped
+            } else if has_sibling(&mut listing) {
+                PlanReason::Updated
+            } else {
+                PlanReason::New
+            };
+            Some(PlannedDownload {
+                url: url.to_string(),
+                dest,
+                reason,
+                size: None,
+            })
+        })
+        .collect()
+}
+
+/// The PDSC downloads `update` would perform for an already fetched index
+pub fn plan_update<'a, I, D>(config: &D, index: I) -> Vec<PlannedDownload>
+where
+    I: IntoIterator<Item = &'a PdscRef>,
+    D: DownloadConfig,
+{
+    let filter = config.vendor_filter();
+    let index = index
+        .into_iter()
+        .filter(|pdsc| filter.allows(&pdsc.vendor))
+        .cloned();
+    let (downgrades, index): (Vec<PdscRef>, Vec<PdscRef>) = if config.allow_downgrade() {
+        (Vec::new(), index.collect())
+    } else {
+        let mut listing = StoreListing::default();
+        let store = config.pack_store();
+        index.partition(|pdsc| newer_local_pdsc(&mut listing, &store, pdsc).is_some())
+    };
+    let mut planned = plan(config, index, |pdsc, name| {
+        let prefix = format!("{}.", pack_id(&pdsc.vendor, &pdsc.name));
+        name.to_ascii_lowercase().starts_with(&prefix) && name.ends_with(".pdsc")
+    });
+    planned.extend(downgrades.iter().filter_map(|pdsc| {
+        Some(PlannedDownload {
+            url: pdsc.into_uri().ok()?.to_string(),
+            dest: pdsc.into_fd(config),
+            reason: PlanReason::Downgrade,
+            size: None,
+        })
+    }));
+    planned
+}
+
+/// The pack downloads `install` would perform for these packages
+pub fn plan_install<'a, I, D>(config: &D, pdscs: I) -> Vec<PlannedDownload>
+where
+    I: IntoIterator<Item = &'a Package>,
+    D: DownloadConfig,
+{
+    plan(config, pdscs, |_, name| name.ends_with(".pack"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    struct Store(PathBuf);
+
+    impl DownloadConfig for Store {
+        fn pack_store(&self) -> PathBuf {
+            self.0.clone()
+        }
+    }
+
+    fn pdsc_ref(version: &str) -> PdscRef {
+        PdscRef {
+            url: "http://example.com/".into(),
+            vendor: "Vendor".into(),
+            name: "Pack".into(),
+            version: version.into(),
+            date: None,
+            deprecated: None,
+            replacement: None,
+            size: None,
+            released: None,
+            deprecated_on: None,
+            checksum: None,
+            timestamp: None,
+            updated: None,
+        }
+    }
+
+    #[test]
+    fn plan_update_reasons() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = Store(temp.path().join("store"));
+        create_dir_all(&store.0).unwrap();
+        write(store.0.join("Vendor.Pack.1.0.0.pdsc"), "<package/>").unwrap();
+        let index = [pdsc_ref("1.0.0"), pdsc_ref("1.1.0")];
+        let planned = plan_update(&store, &index);
+        assert_eq!(planned[0].reason, PlanReason::Skipped);
+        assert_eq!(planned[1].reason, PlanReason::Updated);
+        assert_eq!(planned[1].url, "http://example.com/Vendor.Pack.pdsc");
+        let other = PdscRef {
+            name: "Other".into(),
+            ..pdsc_ref("1.0.0")
+        };
+        assert_eq!(plan_update(&store, Some(&other))[0].reason, PlanReason::New);
+        let older = plan_update(&store, Some(&pdsc_ref("0.9.0")));
+        assert_eq!(older[0].reason, PlanReason::Downgrade);
+    }
+}
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{format_err, Error};
+
+use crate::update::retry::RetryPolicy;
+
+/// How long downloads may wait on the network
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Time allowed for establishing a connection
+    pub connect: Duration,
+    /// Time allowed for the response to start, and then between any two
+    /// parts of its body, so that a stalled server fails the download
+    pub read: Duration,
+    /// Time allowed for a whole request, body included; none by default, as
+    /// large packs take long on slow links
+    pub total: Option<Duration>,
+}
+
+/// Presets for how hard updates and installs use the network
+///
+/// A profile sets the number of concurrent downloads, the number of
+/// concurrent downloads per host, how often a failed index is retried and
+/// how long to wait for a connection, so that these are tuned together.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NetworkProfile {
+    /// Few connections, patient timeouts and more retries, for slow or
+    /// unreliable links
+    Conservative,
+    /// The defaults
+    #[default]
+    Balanced,
+    /// Many connections and short timeouts, for fast and reliable links
+    Aggressive,
+}
+
+impl NetworkProfile {
+    pub const NAMES: &'static [&'static str] = &["conservative", "balanced", "aggressive"];
+
+    /// Downloads in flight at once
+    pub fn concurrency(self) -> usize {
+        match self {
+            NetworkProfile::Conservative => 4,
+            NetworkProfile::Balanced => 32,
+            NetworkProfile::Aggressive => 128,
+        }
+    }
+
+    /// Downloads in flight at once from a single host
+    pub fn host_limit(self) -> usize {
+        match self {
+            NetworkProfile::Conservative => 2,
+            NetworkProfile::Balanced => 6,
+            NetworkProfile::Aggressive => 16,
+        }
+    }
+
+    /// Attempts at each download before giving up on it
+    pub fn retries(self) -> usize {
+        match self {
+            NetworkProfile::Conservative => 5,
+            NetworkProfile::Balanced => 3,
+            NetworkProfile::Aggressive => 2,
+        }
+    }
+
+    /// How failed downloads are retried, with as many attempts as
+    /// [`retries`](Self::retries) and slower backoff for slower profiles
+    pub fn retry_policy(self) -> RetryPolicy {
+        let backoff = match self {
+            NetworkProfile::Conservative => Duration::from_secs(1),
+            NetworkProfile::Balanced => Duration::from_millis(500),
+            NetworkProfile::Aggressive => Duration::from_millis(250),
+        };
+        RetryPolicy {
+            attempts: self.retries(),
+            backoff,
+            ..RetryPolicy::default()
+        }
+    }
+
+    /// Time allowed for establishing a connection
+    pub fn connect_timeout(self) -> Duration {
+        match self {
+            NetworkProfile::Conservative => Duration::from_secs(60),
+            NetworkProfile::Balanced => Duration::from_secs(30),
+            NetworkProfile::Aggressive => Duration::from_secs(10),
+        }
+    }
+
+    /// Timeouts of downloads, with the [`connect_timeout`](Self::connect_timeout)
+    /// of the profile
+    pub fn timeouts(self) -> Timeouts {
+        let read = match self {
+            NetworkProfile::Conservative => Duration::from_secs(120),
+            NetworkProfile::Balanced => Duration::from_secs(60),
+            NetworkProfile::Aggressive => Duration::from_secs(20),
+        };
+        Timeouts {
+            connect: self.connect_timeout(),
+            read,
+            total: None,
+        }
+    }
+}
+
+impl FromStr for NetworkProfile {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        match from {
+            "conservative" => Ok(NetworkProfile::Conservative),
+            "balanced" => Ok(NetworkProfile::Balanced),
+            "aggressive" => Ok(NetworkProfile::Aggressive),
+            unknown => Err(format_err!("Unknown network profile {}", unknown)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn profiles_parse_and_scale() {
+        let profiles: Vec<NetworkProfile> = NetworkProfile::NAMES
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+        assert_eq!(profiles[1], NetworkProfile::default());
+        for pair in profiles.windows(2) {
+            assert!(pair[0].concurrency() < pair[1].concurrency());
+            assert!(pair[0].host_limit() < pair[1].host_limit());
+            assert!(pair[0].connect_timeout() > pair[1].connect_timeout());
+            assert!(pair[0].timeouts().read > pair[1].timeouts().read);
+        }
+        assert!("reckless".parse::<NetworkProfile>().is_err());
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use serde::Serialize;
+
+use crate::update::download::{DownloadProgress, Observer};
+
+/// The state of a single file of an update or install
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "state", content = "error")]
+pub enum FileState {
+    Downloading,
+    Done,
+    Skipped,
+    Failed(String),
+}
+
+/// A point-in-time view of an update or install
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ProgressSnapshot {
+    pub total: usize,
+    /// Finished downloads, including failed ones
+    pub completed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub bytes: usize,
+    /// Keyed by URL, or by destination for skipped files
+    pub files: BTreeMap<String, FileState>,
+    pub elapsed: Duration,
+    /// Average download rate in bytes per second
+    pub rate: f64,
+    /// Estimated time until every file is handled, once one has completed
+    pub eta: Option<Duration>,
+}
+
+#[derive(Default)]
+struct Tracked {
+    started: Option<Instant>,
+    snapshot: ProgressSnapshot,
+}
+
+/// Aggregates progress events into a [`ProgressSnapshot`]
+///
+/// Pass a clone as the progress argument of `update` or `install` and call
+/// [`ProgressTracker::snapshot`] from any other thread.
+#[derive(Clone, Default)]
+pub struct ProgressTracker(Arc<Mutex<Tracked>>);
+
+impl ProgressTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let tracked = match self.0.lock() {
+            Ok(tracked) => tracked,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let mut snapshot = tracked.snapshot.clone();
+        if let Some(started) = tracked.started {
+            snapshot.elapsed = started.elapsed();
+            let secs = snapshot.elapsed.as_secs_f64();
+            if secs > 0.0 {
+                snapshot.rate = snapshot.bytes as f64 / secs;
+            }
+            let handled = snapshot.completed + snapshot.skipped;
+            if handled > 0 {
+                let remaining = snapshot.total.saturating_sub(handled) as u32;
+                snapshot.eta = Some(snapshot.elapsed / handled as u32 * remaining);
+            }
+        }
+        snapshot
+    }
+
+    fn update<F: FnOnce(&mut ProgressSnapshot)>(&self, f: F) {
+        if let Ok(mut tracked) = self.0.lock() {
+            f(&mut tracked.snapshot);
+        }
+    }
+}
+
+impl Observer for ProgressTracker {
+    fn download_started(&self, url: &str) {
+        self.update(|s| {
+            s.files.insert(url.to_string(), FileState::Downloading);
+        });
+    }
+    fn pdsc_downloaded(&self, url: &str, _: &Path) {
+        self.update(|s| {
+            s.files.insert(url.to_string(), FileState::Done);
+        });
+    }
+    fn pdsc_skipped(&self, dest: &Path) {
+        self.update(|s| {
+            s.skipped += 1;
+            s.files
+                .insert(dest.display().to_string(), FileState::Skipped);
+        });
+    }
+    fn download_failed(&self, url: &str, error: &Error) {
+        self.update(|s| {
+            s.failed += 1;
+            s.files
+                .insert(url.to_string(), FileState::Failed(error.to_string()));
+        });
+    }
+    fn pack_installed(&self, url: &str, _: &Path) {
+        self.update(|s| {
+            s.files.insert(url.to_string(), FileState::Done);
+        });
+    }
+}
+
+impl DownloadProgress for ProgressTracker {
+    fn size(&self, files: usize) {
+        if let Ok(mut tracked) = self.0.lock() {
+            tracked.started.get_or_insert_with(Instant::now);
+            tracked.snapshot.total = files;
+        }
+    }
+    fn progress(&self, bytes: usize) {
+        self.update(|s| s.bytes += bytes);
+    }
+    fn complete(&self) {
+        self.update(|s| s.completed += 1);
+    }
+    fn for_file(&self, _: &str) -> Self {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn tracker_aggregates_events() {
+        let tracker = ProgressTracker::new();
+        let pipeline = tracker.clone();
+        pipeline.size(3);
+        pipeline.download_started("http://a/1");
+        pipeline.download_started("http://a/2");
+        pipeline.progress(100);
+        pipeline.complete();
+        pipeline.pdsc_downloaded("http://a/1", Path::new("1"));
+        pipeline.complete();
+        pipeline.download_failed("http://a/2", &anyhow!("404"));
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.total, 3);
+        assert_eq!(snapshot.bytes, 100);
+        assert_eq!(snapshot.files["http://a/1"], FileState::Done);
+        assert_eq!(
+            snapshot.files["http://a/2"],
+            FileState::Failed("404".into())
+        );
+        assert!(snapshot.eta.is_some());
+    }
+}
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::fs::{read_dir, remove_dir, remove_dir_all, remove_file, symlink_metadata};
+use std::path::{Component, Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::update::claim::{is_claimed, is_stale};
+use crate::update::download::local_pdscs;
+use crate::update::extract::{extract_dir, files_dir};
+use crate::update::install::PackSpec;
+use crate::update::listing::StoreListing;
+use crate::update::outdated::entries;
+use crate::update::usage::{CleanScope, StorePart};
+use crate::utils::{compare_versions, pack_id};
+
+/// The files and directories deleted from the pack store by [`remove_pack`]
+/// or [`collect_garbage`]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Reclaimed {
+    pub paths: Vec<PathBuf>,
+    /// The bytes they took up
+    pub bytes: u64,
+}
+
+impl Reclaimed {
+    /// Delete the file or directory at `path`, if there is one
+    fn delete(&mut self, path: &Path) -> Result<(), crate::Error> {
+        let meta = match symlink_metadata(path) {
+            Ok(meta) => meta,
+            Err(_) => return Ok(()),
+        };
+        let bytes = disk_usage(path);
+        let res = if meta.is_dir() {
+            remove_dir_all(path)
+        } else {
+            remove_file(path)
+        };
+        res.map_err(|source| crate::Error::Io {
+            path: Some(path.to_path_buf()),
+            source,
+        })?;
+        self.paths.push(path.to_path_buf());
+        self.bytes += bytes;
+        Ok(())
+    }
+}
+
+/// The size of the file at `path`, or of all files below the directory
+pub(crate) fn disk_usage(path: &Path) -> u64 {
+    match symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => read_dir(path)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| disk_usage(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or_default(),
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Whether `path` lies below `dir`, and is not `dir` itself, without
+/// leaving it through `..`
+fn is_below(dir: &Path, path: &Path) -> bool {
+    match path.strip_prefix(dir) {
+        Ok(rest) => {
+            rest.components().next().is_some()
+                && rest.components().all(|c| matches!(c, Component::Normal(_)))
+        }
+        Err(_) => false,
+    }
+}
+
+/// Remove a pack from the pack store: the PDSC files, archive and extracted
+/// directory of the version `spec` names, or of every version without one
+///
+/// Fails when the store holds nothing of the pack.
+pub fn remove_pack(pack_store: &Path, spec: &PackSpec) -> Result<Reclaimed, crate::Error> {
+    let mut listing = StoreListing::default();
+    let pack_dir = listing.resolve(pack_store, &pack_store.join(&spec.vendor).join(&spec.name));
+    let mut doomed: Vec<PathBuf> = local_pdscs(&mut listing, pack_store, &spec.vendor, &spec.name)
+        .into_iter()
+        .filter(|(_, version)| spec.version.as_ref().is_none_or(|wanted| wanted == version))
+        .map(|(path, _)| path)
+        .collect();
+    match &spec.version {
+        Some(version) => {
+            let pack = pack_dir.join(format!("{}.pack", version));
+            doomed.push(extract_dir(&pack));
+            doomed.push(files_dir(&pack));
+            doomed.push(pack.with_extension("part"));
+            doomed.push(pack);
+        }
+        None => doomed.push(pack_dir.clone()),
+    }
+    if let Some(outside) = doomed.iter().find(|path| !is_below(pack_store, path)) {
+        return Err(crate::Error::Pack {
+            pack: spec.to_string(),
+            source: format!("{} is outside the pack store", outside.display()).into(),
+        });
+    }
+    let mut reclaimed = Reclaimed::default();
+    for path in doomed {
+        reclaimed.delete(&path)?;
+    }
+    if reclaimed.paths.is_empty() {
+        return Err(crate::Error::Pack {
+            pack: spec.to_string(),
+            source: "not in the pack store".into(),
+        });
+    }
+    // Only succeeds on directories left empty
+    let _ = remove_dir(pack_dir.join(".files"));
+    let _ = remove_dir(&pack_dir);
+    if let Some(vendor_dir) = pack_dir.parent() {
+        let _ = remove_dir(vendor_dir);
+    }
+    Ok(reclaimed)
+}
+
+/// The PDSC files of the store with a newer version of their pack next to
+/// them, unless the archive or extraction of their own version is installed
+fn superseded_pdscs(pack_store: &Path) -> Vec<PathBuf> {
+    let mut packs: HashMap<String, Vec<(PathBuf, String, String, String)>> = HashMap::new();
+    for entry in read_dir(pack_store).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let stem = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => match name.strip_suffix(".pdsc") {
+                Some(stem) => stem.to_string(),
+                None => continue,
+            },
+            None => continue,
+        };
+        // Vendor and pack names hold no dots, so the version is the rest
+        let mut parts = stem.splitn(3, '.');
+        if let (Some(vendor), Some(name), Some(version)) =
+            (parts.next(), parts.next(), parts.next())
+        {
+            packs.entry(pack_id(vendor, name)).or_default().push((
+                path.clone(),
+                vendor.to_string(),
+                name.to_string(),
+                version.to_string(),
+            ));
+        }
+    }
+    let mut listing = StoreListing::default();
+    let mut superseded = Vec::new();
+    for versions in packs.into_values() {
+        let newest = match versions
+            .iter()
+            .map(|(_, _, _, version)| version)
+            .max_by(|left, right| compare_versions(left, right))
+        {
+            Some(newest) => newest.clone(),
+            None => continue,
+        };
+        for (path, vendor, name, version) in versions {
+            if compare_versions(&version, &newest).is_ge() {
+                continue;
+            }
+            let pack = pack_store
+                .join(vendor)
+                .join(name)
+                .join(format!("{}.pack", version));
+            let pack = listing.resolve(pack_store, &pack);
+            if !pack.exists() && !extract_dir(&pack).exists() {
+                superseded.push(path);
+            }
+        }
+    }
+    superseded.sort();
+    superseded
+}
+
+/// Partial downloads, extraction scratch directories and claims in the top
+/// three levels of the store, unchanged for a while and not claimed by a
+/// running operation
+fn orphaned_temp_files(pack_store: &Path) -> Vec<PathBuf> {
+    let is_temp = |path: &Path| {
+        path.extension()
+            .is_some_and(|ext| ext == "part" || ext == "extracting" || ext == "claim")
+    };
+    let is_orphaned = |path: &Path| {
+        let unchanged = path
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .is_ok_and(is_stale);
+        unchanged && !is_claimed(path)
+    };
+    let mut orphaned = Vec::new();
+    let mut dirs = vec![pack_store.to_path_buf()];
+    // The store, vendor and pack directories; not the extracted packs
+    for _ in 0..3 {
+        let mut next = Vec::new();
+        for dir in dirs {
+            for entry in read_dir(&dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if is_temp(&path) {
+                    if is_orphaned(&path) {
+                        orphaned.push(path);
+                    }
+                } else if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                    next.push(path);
+                }
+            }
+        }
+        dirs = next;
+    }
+    orphaned.sort();
+    orphaned
+}
+
+/// Prune the pack store of PDSC files superseded by a newer version of
+/// their pack, and of temporary files left behind by interrupted operations
+///
+/// The PDSC files of installed pack versions are kept. Temporary files are
+/// only pruned once no operation has touched or claimed them for a while,
+/// so a store can be pruned while others use it.
+pub fn collect_garbage(pack_store: &Path) -> Result<Reclaimed, crate::Error> {
+    let mut reclaimed = Reclaimed::default();
+    for path in superseded_pdscs(pack_store)
+        .into_iter()
+        .chain(orphaned_temp_files(pack_store))
+    {
+        reclaimed.delete(&path)?;
+    }
+    Ok(reclaimed)
+}
+
+/// Delete the parts of the pack store `scope` names
+///
+/// Files of the user kept next to the store, such as `vendors.list`, and
+/// the lock of the store are left alone; take the lock before cleaning a
+/// store others may use.
+pub fn clean_store(pack_store: &Path, scope: CleanScope) -> Result<Reclaimed, crate::Error> {
+    let mut reclaimed = Reclaimed::default();
+    for (path, name, is_dir) in entries(pack_store) {
+        if StorePart::of(&name, is_dir).is_some_and(|part| scope.covers(&part)) {
+            reclaimed.delete(&path)?;
+        }
+    }
+    Ok(reclaimed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, write, File};
+    use std::time::{Duration, SystemTime};
+
+    fn age(path: &Path) {
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+    }
+
+    #[test]
+    fn packs_outside_the_store_are_not_removed() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().to_path_buf();
+        let store = root.join("store");
+        let victim = root.join("victim");
+        create_dir_all(&store).unwrap();
+        create_dir_all(&victim).unwrap();
+        write(victim.join("file"), "kept").unwrap();
+
+        for (vendor, name) in [("..", "victim"), ("", ""), ("/", "victim")] {
+            let spec = PackSpec {
+                vendor: vendor.to_string(),
+                name: name.to_string(),
+                version: None,
+            };
+            let err = remove_pack(&store, &spec).unwrap_err();
+            assert_eq!(err.code(), "pack");
+        }
+        assert!("..::../victim".parse::<PackSpec>().is_err());
+        assert!(victim.join("file").exists());
+        assert!(store.exists());
+    }
+
+    #[test]
+    fn packs_are_removed_and_garbage_collected() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        let pack_dir = store.join("Vendor").join("Pack");
+        create_dir_all(pack_dir.join("1.0.0")).unwrap();
+        for version in ["1.0.0", "1.2.0", "1.10.0"] {
+            write(
+                store.join(format!("Vendor.Pack.{}.pdsc", version)),
+                "<package/>",
+            )
+            .unwrap();
+        }
+        write(store.join("Other.Pack.1.0.0.pdsc"), "<package/>").unwrap();
+        write(pack_dir.join("1.0.0.pack"), [0; 100]).unwrap();
+        write(pack_dir.join("1.0.0").join("Vendor.Pack.pdsc"), [0; 50]).unwrap();
+        write(pack_dir.join("2.0.0.part"), [0; 30]).unwrap();
+        write(store.join("Vendor.Pack.2.0.0.part"), [0; 20]).unwrap();
+        write(store.join("Vendor.Pack.2.0.0.claim"), "1").unwrap();
+        write(store.join("Other.Pack.2.0.0.part"), [0; 10]).unwrap();
+        age(&pack_dir.join("2.0.0.part"));
+        age(&store.join("Vendor.Pack.2.0.0.part"));
+
+        let gc = collect_garbage(&store).unwrap();
+        // The claimed partial download and the recent one are kept
+        assert_eq!(
+            gc.paths,
+            vec![
+                store.join("Vendor.Pack.1.2.0.pdsc"),
+                pack_dir.join("2.0.0.part"),
+            ]
+        );
+        assert_eq!(gc.bytes, 10 + 30);
+        assert!(store.join("Vendor.Pack.1.0.0.pdsc").exists());
+        assert!(store.join("Other.Pack.2.0.0.part").exists());
+
+        let spec = "VENDOR::Pack@1.0.0".parse().unwrap();
+        let removed = remove_pack(&store, &spec).unwrap();
+        assert_eq!(removed.bytes, 10 + 50 + 100);
+        assert!(!pack_dir.join("1.0.0").exists());
+        assert!(store.join("Vendor.Pack.1.10.0.pdsc").exists());
+
+        let all = remove_pack(&store, &"Vendor::Pack".parse().unwrap()).unwrap();
+        assert_eq!(all.paths, vec![store.join("Vendor.Pack.1.10.0.pdsc")]);
+        assert!(!store.join("Vendor").exists());
+        assert!(remove_pack(&store, &spec).is_err());
+    }
+
+    #[test]
+    fn stores_are_cleaned_by_part() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        create_dir_all(store.join("Vendor/Pack/1.0.0")).unwrap();
+        write(store.join("Vendor.Pack.1.0.0.pdsc"), [0; 10]).unwrap();
+        write(store.join("Vendor/Pack/1.0.0.pack"), [0; 100]).unwrap();
+        write(store.join(".device-cache.bin"), [0; 7]).unwrap();
+        write(store.join("Vendor.Pack.2.0.0.part"), [0; 3]).unwrap();
+        write(store.join(".lock"), "1").unwrap();
+        write(store.join("vendors.list"), "").unwrap();
+
+        let index = clean_store(&store, CleanScope::Index).unwrap();
+        assert_eq!(
+            index.paths,
+            vec![
+                store.join(".device-cache.bin"),
+                store.join("Vendor.Pack.1.0.0.pdsc")
+            ]
+        );
+        assert!(store.join("Vendor/Pack/1.0.0.pack").exists());
+        let packs = clean_store(&store, CleanScope::Packs).unwrap();
+        assert_eq!(
+            (packs.paths, packs.bytes),
+            (vec![store.join("Vendor")], 100)
+        );
+        let all = clean_store(&store, CleanScope::All).unwrap();
+        assert_eq!(all.paths, vec![store.join("Vendor.Pack.2.0.0.part")]);
+        assert!(store.join(".lock").exists() && store.join("vendors.list").exists());
+    }
+}
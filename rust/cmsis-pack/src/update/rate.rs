@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::log::NETWORK;
+use reqwest::Url;
+use tokio::time::sleep;
+
+/// How many requests per second downloads may send, so that servers that
+/// throttle aggressive clients are not tripped
+///
+/// Each request waits for a token of a bucket that holds a second's worth
+/// of them and refills at the given rate. Local files are not limited.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RateLimit {
+    /// Requests per second to any one host
+    pub per_host: Option<f64>,
+    /// Requests per second to all hosts together
+    pub global: Option<f64>,
+}
+
+/// A token bucket, whose tokens may be reserved ahead of their arrival
+struct Bucket {
+    rate: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, now: Instant) -> Self {
+        Bucket {
+            rate,
+            tokens: rate.max(1.0),
+            updated: now,
+        }
+    }
+
+    /// Take a token, returning how long to wait for it
+    fn take(&mut self, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate.max(1.0));
+        self.updated = now;
+        self.tokens -= 1.0;
+        match self.tokens < 0.0 {
+            true => Duration::from_secs_f64(-self.tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+/// The buckets of a [`RateLimit`], shared by the downloads of an operation
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    global: Mutex<Option<Bucket>>,
+    hosts: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        let valid = |rate: Option<f64>| rate.filter(|rate| *rate > 0.0);
+        RateLimiter {
+            limit: RateLimit {
+                per_host: valid(limit.per_host),
+                global: valid(limit.global),
+            },
+            global: Mutex::new(None),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long a request to `url` sent at `now` waits for its tokens
+    fn reserve(&self, url: &Url, now: Instant) -> Duration {
+        let host = match url.host_str() {
+            Some(host) if url.scheme() != "file" => host,
+            _ => return Duration::ZERO,
+        };
+        let mut wait = Duration::ZERO;
+        if let (Some(rate), Ok(mut global)) = (self.limit.global, self.global.lock()) {
+            let bucket = global.get_or_insert_with(|| Bucket::new(rate, now));
+            wait = wait.max(bucket.take(now));
+        }
+        if let (Some(rate), Ok(mut hosts)) = (self.limit.per_host, self.hosts.lock()) {
+            let bucket = hosts
+                .entry(host.to_string())
+                .or_insert_with(|| Bucket::new(rate, now));
+            wait = wait.max(bucket.take(now));
+        }
+        wait
+    }
+
+    /// Wait until a request to `url` may be sent
+    pub(crate) async fn acquire(&self, url: &Url) {
+        let wait = self.reserve(url, Instant::now());
+        if !wait.is_zero() {
+            tracing::debug!(target: NETWORK, url = %url, wait = ?wait, "Rate limited");
+            sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requests_wait_for_their_tokens() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_host: Some(2.0),
+            global: None,
+        });
+        let a = Url::parse("https://a.example.com/index.pidx").unwrap();
+        let b = Url::parse("https://b.example.com/index.pidx").unwrap();
+        let now = Instant::now();
+        let waits: Vec<_> = (0..4).map(|_| limiter.reserve(&a, now)).collect();
+        let ms = |ms| Duration::from_millis(ms);
+        assert_eq!(waits, [ms(0), ms(0), ms(500), ms(1000)]);
+        // Hosts have buckets of their own, which refill over time
+        assert_eq!(limiter.reserve(&b, now), ms(0));
+        assert_eq!(limiter.reserve(&a, now + ms(2500)), ms(0));
+
+        let limiter = RateLimiter::new(RateLimit {
+            per_host: None,
+            global: Some(1.0),
+        });
+        assert_eq!(limiter.reserve(&a, now), ms(0));
+        assert_eq!(limiter.reserve(&b, now), ms(1000));
+        let local = Url::parse("file:///mirror/index.pidx").unwrap();
+        assert_eq!(limiter.reserve(&local, now), ms(0));
+    }
+}
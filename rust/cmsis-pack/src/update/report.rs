@@ -0,0 +1,146 @@
+use std::fs::{rename, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Error;
+use serde::{Deserialize, Serialize};
+
+use crate::update::download::{DownloadProgress, Observer};
+
+const REPORT_FILE: &str = ".last-update.json";
+
+/// A download of an update that failed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedDownload {
+    /// The URL of the vendor index or PDSC file
+    pub url: String,
+    /// The [`code`](crate::Error::code) of the error
+    pub code: String,
+    pub error: String,
+}
+
+/// The outcome of an update, file by file
+///
+/// Every update records its report in `.last-update.json` in the pack store.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UpdateReport {
+    /// PDSC files written to the pack store
+    pub downloaded: Vec<PathBuf>,
+    /// PDSC files already in the pack store, including those kept instead
+    /// of a downgrade
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<FailedDownload>,
+}
+
+impl UpdateReport {
+    /// The PDSC files of the pack store the update listed, downloaded or not
+    pub fn pdsc_files(&self) -> Vec<PathBuf> {
+        self.downloaded
+            .iter()
+            .chain(&self.skipped)
+            .cloned()
+            .collect()
+    }
+
+    /// The report of the last update of `pack_store`, if any
+    pub fn load(pack_store: &Path) -> Option<Self> {
+        let fd = File::open(pack_store.join(REPORT_FILE)).ok()?;
+        serde_json::from_reader(BufReader::new(fd)).ok()
+    }
+
+    pub(crate) fn save(&self, pack_store: &Path) -> Result<(), Error> {
+        let path = pack_store.join(REPORT_FILE);
+        let temp = path.with_extension("part");
+        std::fs::create_dir_all(pack_store)?;
+        serde_json::to_writer_pretty(File::create(&temp)?, self)?;
+        rename(temp, path)?;
+        Ok(())
+    }
+}
+
+/// Forwards progress events to `inner` while collecting an [`UpdateReport`]
+pub(crate) struct Reporting<P> {
+    inner: P,
+    report: Arc<Mutex<UpdateReport>>,
+}
+
+impl<P> Reporting<P> {
+    pub(crate) fn new(inner: P) -> Self {
+        Reporting {
+            inner,
+            report: Default::default(),
+        }
+    }
+
+    /// A handle on the report, which outlives the progress
+    pub(crate) fn report(&self) -> Arc<Mutex<UpdateReport>> {
+        self.report.clone()
+    }
+
+    fn record<F: FnOnce(&mut UpdateReport)>(&self, f: F) {
+        if let Ok(mut report) = self.report.lock() {
+            f(&mut report);
+        }
+    }
+}
+
+impl<P: Observer> Observer for Reporting<P> {
+    fn source_fetched(&self, url: &str) {
+        self.inner.source_fetched(url)
+    }
+    fn download_started(&self, url: &str) {
+        self.inner.download_started(url)
+    }
+    fn pdsc_downloaded(&self, url: &str, dest: &Path) {
+        self.record(|r| r.downloaded.push(dest.to_path_buf()));
+        self.inner.pdsc_downloaded(url, dest)
+    }
+    fn pdsc_skipped(&self, dest: &Path) {
+        self.record(|r| r.skipped.push(dest.to_path_buf()));
+        self.inner.pdsc_skipped(dest)
+    }
+    fn download_failed(&self, url: &str, error: &Error) {
+        self.record(|r| {
+            r.failed.push(FailedDownload {
+                url: url.to_string(),
+                code: crate::Error::code_of(error).to_string(),
+                error: error.to_string(),
+            })
+        });
+        self.inner.download_failed(url, error)
+    }
+    fn pack_installed(&self, url: &str, dest: &Path) {
+        self.inner.pack_installed(url, dest)
+    }
+    fn pack_extracted(&self, url: &str, dir: &Path) {
+        self.inner.pack_extracted(url, dir)
+    }
+    fn served_from(&self, url: &str, actual: &str) {
+        self.inner.served_from(url, actual)
+    }
+    fn pack_vanished(&self, url: &str, local: &[PathBuf]) {
+        self.inner.pack_vanished(url, local)
+    }
+    fn file_progress(&self, url: &str, bytes: u64, total: Option<u64>) {
+        self.inner.file_progress(url, bytes, total)
+    }
+}
+
+impl<P: DownloadProgress> DownloadProgress for Reporting<P> {
+    fn size(&self, files: usize) {
+        self.inner.size(files)
+    }
+    fn progress(&self, bytes: usize) {
+        self.inner.progress(bytes)
+    }
+    fn complete(&self) {
+        self.inner.complete()
+    }
+    fn for_file(&self, file: &str) -> Self {
+        Reporting {
+            inner: self.inner.for_file(file),
+            report: self.report.clone(),
+        }
+    }
+}
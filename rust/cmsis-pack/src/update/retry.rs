@@ -0,0 +1,149 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::time::Duration;
+
+use anyhow::Error;
+use tokio::time::sleep;
+
+use crate::log::NETWORK;
+use crate::update::fetch::{HttpStatus, TimedOut};
+
+/// How failed downloads are retried
+///
+/// Only transient failures are retried: server errors, timeouts, throttling
+/// and connections that failed or were reset. Missing files and invalid
+/// contents fail at once.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts at each download, including the first
+    pub attempts: usize,
+    /// Delay before the first retry, doubled for every further one
+    pub backoff: Duration,
+    /// Upper bound of the delay between attempts
+    pub max_backoff: Duration,
+    /// Fraction of each delay, from 0 to 1, randomly added or taken away
+    /// so that downloads failing together are not retried together
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry`, counting from 1
+    pub fn delay(&self, retry: usize) -> Duration {
+        let doublings = retry.saturating_sub(1).min(31) as u32;
+        let delay = self
+            .backoff
+            .saturating_mul(1 << doublings)
+            .min(self.max_backoff);
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        delay.mul_f64(1.0 + jitter * (2.0 * random - 1.0))
+    }
+}
+
+/// Whether a failed download may succeed when tried again
+pub(crate) fn is_transient(err: &Error) -> bool {
+    if let Some(HttpStatus(code)) = err.downcast_ref() {
+        return *code >= 500 || *code == 408 || *code == 429;
+    }
+    if err.is::<TimedOut>() {
+        return true;
+    }
+    if let Some(err) = err.downcast_ref::<reqwest::Error>() {
+        return err.is_timeout() || err.is_connect() || err.is_request() || err.is_body();
+    }
+    // Local failures, such as a full disk or a read-only pack store, fail
+    // again however often they are tried
+    err.downcast_ref::<std::io::Error>().is_some_and(|err| {
+        matches!(
+            err.kind(),
+            ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::TimedOut
+                | ErrorKind::UnexpectedEof
+        )
+    })
+}
+
+/// Run `attempt` until it succeeds, fails for good or runs out of attempts,
+/// waiting between attempts as `policy` says
+pub(crate) async fn retry<T, F, Fut>(
+    policy: RetryPolicy,
+    url: &str,
+    mut attempt: F,
+) -> Result<T, Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut tries = 1;
+    loop {
+        match attempt().await {
+            Err(err) if tries < policy.attempts && is_transient(&err) => {
+                let delay = policy.delay(tries);
+                tracing::warn!(target: NETWORK, url = %url, error = %err, delay = ?delay, "Retrying download");
+                sleep(delay).await;
+                tries += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn delays_double_up_to_the_bound() {
+        let policy = RetryPolicy {
+            attempts: 10,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            jitter: 0.0,
+        };
+        let delays: Vec<_> = (1..5)
+            .map(|retry| policy.delay(retry).as_millis())
+            .collect();
+        assert_eq!(delays, vec![100, 200, 350, 350]);
+
+        let jittered = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..20 {
+            let delay = jittered.delay(1).as_millis();
+            assert!((50..=150).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn only_transient_failures_are_retried() {
+        assert!(is_transient(&HttpStatus(503).into()));
+        assert!(is_transient(&HttpStatus(429).into()));
+        assert!(!is_transient(&HttpStatus(404).into()));
+        assert!(is_transient(&TimedOut(Duration::from_secs(1)).into()));
+        assert!(!is_transient(&anyhow::anyhow!("not a PDSC file")));
+        let reset = std::io::Error::from(ErrorKind::ConnectionReset);
+        assert!(is_transient(&reset.into()));
+        let eof = std::io::Error::from(ErrorKind::UnexpectedEof);
+        assert!(is_transient(&eof.into()));
+        let denied = std::io::Error::from(ErrorKind::PermissionDenied);
+        assert!(!is_transient(&denied.into()));
+        let full = std::io::Error::other("No space left on device");
+        assert!(!is_transient(&full.into()));
+    }
+}
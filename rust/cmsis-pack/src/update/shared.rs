@@ -0,0 +1,206 @@
+use std::fs::{create_dir_all, hard_link, remove_file, rename, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Error;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::log::STORE;
+use crate::update::checksum::{Checksum, ChecksumAlgorithm, Hasher};
+use crate::update::validators::Validators;
+
+/// Distinguishes the temporary files of concurrent writers in one process
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// What the shared cache knows about a URL: the object it was last served
+/// as, and the validators it was served with
+#[derive(Serialize, Deserialize)]
+struct UrlEntry {
+    url: String,
+    sha256: String,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+/// A content-addressed cache of downloads, shared by the pack stores of a
+/// machine
+///
+/// Files are kept under `objects/`, named after their SHA-256 digest, and
+/// hard linked into the pack stores that download them, or copied where
+/// linking fails. `urls/` records which object each URL was last served as,
+/// with its validators: a pack store then only sends a conditional request
+/// and links the object when the server did not change it, and a download
+/// whose index lists its checksum is linked without any request at all.
+/// Objects are verified against their digest before every use.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedCache {
+    dir: PathBuf,
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn digest_of(algorithm: ChecksumAlgorithm, path: &Path) -> std::io::Result<Checksum> {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update_from(path)?;
+    Ok(hasher.finish())
+}
+
+/// A temporary path next to `path`, unique to this process and call
+fn temp_path(path: &Path) -> PathBuf {
+    let count = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    path.with_extension(format!("{}.{}.part", std::process::id(), count))
+}
+
+/// Make `to` a hard link of `from`, or a copy of it when linking fails,
+/// such as across file systems
+fn link_or_copy(from: &Path, to: &Path) -> std::io::Result<()> {
+    let _ = remove_file(to);
+    if hard_link(from, to).is_err() {
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+impl SharedCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        SharedCache { dir }
+    }
+
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        let prefix = sha256.get(..2).unwrap_or("00");
+        self.dir.join("objects").join(prefix).join(sha256)
+    }
+
+    fn url_path(&self, url: &Url) -> PathBuf {
+        let mut hasher = Hasher::new(ChecksumAlgorithm::Sha256);
+        hasher.update(url.as_str().as_bytes());
+        let name = hex(&hasher.finish().digest);
+        self.dir.join("urls").join(name).with_extension("json")
+    }
+
+    /// The object with digest `sha256`, if it is cached and intact
+    ///
+    /// A corrupt object, such as one a pack store changed in place through
+    /// its link, is removed.
+    fn object(&self, sha256: &str) -> Option<PathBuf> {
+        let path = self.object_path(sha256);
+        let digest = digest_of(ChecksumAlgorithm::Sha256, &path).ok()?;
+        if hex(&digest.digest) == sha256 {
+            return Some(path);
+        }
+        tracing::warn!(target: STORE, path = ?path, "Removing a corrupt object of the shared cache");
+        let _ = remove_file(&path);
+        None
+    }
+
+    /// The cached file `checksum` lists, without asking the server
+    pub(crate) fn listed(&self, checksum: &Checksum) -> Option<PathBuf> {
+        match checksum.algorithm {
+            ChecksumAlgorithm::Sha256 => self.object(&hex(&checksum.digest)),
+            ChecksumAlgorithm::Sha1 => None,
+        }
+    }
+
+    /// The object `url` was last served as and the validators to ask the
+    /// server whether it changed, when they match `listed`
+    pub(crate) fn revalidate(
+        &self,
+        url: &Url,
+        listed: Option<&Checksum>,
+    ) -> Option<(PathBuf, Validators)> {
+        let fd = File::open(self.url_path(url)).ok()?;
+        let entry: UrlEntry = serde_json::from_reader(BufReader::new(fd)).ok()?;
+        if entry.url != url.as_str() || entry.validators.is_empty() {
+            return None;
+        }
+        let object = self.object(&entry.sha256)?;
+        if let Some(listed) = listed {
+            if digest_of(listed.algorithm, &object).ok().as_ref() != Some(listed) {
+                return None;
+            }
+        }
+        Some((object, entry.validators))
+    }
+
+    /// Add the downloaded `file` as the object `url` was served as
+    pub(crate) fn insert(
+        &self,
+        url: &Url,
+        file: &Path,
+        validators: &Validators,
+    ) -> Result<(), Error> {
+        let sha256 = hex(&digest_of(ChecksumAlgorithm::Sha256, file)?.digest);
+        let object = self.object_path(&sha256);
+        if !object.exists() {
+            if let Some(parent) = object.parent() {
+                create_dir_all(parent)?;
+            }
+            let temp = temp_path(&object);
+            link_or_copy(file, &temp)?;
+            rename(&temp, &object)?;
+        }
+        let entry = UrlEntry {
+            url: url.to_string(),
+            sha256,
+            validators: validators.clone(),
+        };
+        let path = self.url_path(url);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let temp = temp_path(&path);
+        serde_json::to_writer(File::create(&temp)?, &entry)?;
+        rename(&temp, &path)?;
+        Ok(())
+    }
+}
+
+/// Place `object` at `dest`, through its `.part` file so that readers never
+/// see it half copied
+pub(crate) fn link_object(object: &Path, dest: &Path) -> std::io::Result<()> {
+    let temp = dest.with_extension("part");
+    link_or_copy(object, &temp)?;
+    rename(&temp, dest).inspect_err(|_| {
+        let _ = remove_file(&temp);
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn objects_are_found_by_url_and_checksum() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        create_dir_all(&dir).unwrap();
+        let file = dir.join("V.P.pdsc");
+        std::fs::write(&file, "<package/>").unwrap();
+        let cache = SharedCache::new(dir.join("cache"));
+        let url = Url::parse("https://example.com/V.P.pdsc").unwrap();
+        let validators = Validators {
+            etag: Some("\"1\"".to_string()),
+            last_modified: None,
+        };
+        cache.insert(&url, &file, &validators).unwrap();
+
+        let checksum = digest_of(ChecksumAlgorithm::Sha256, &file).unwrap();
+        let object = cache.listed(&checksum).unwrap();
+        assert_eq!(std::fs::read_to_string(&object).unwrap(), "<package/>");
+        let (found, sent) = cache.revalidate(&url, None).unwrap();
+        assert_eq!((&found, &sent), (&object, &validators));
+        let sha1 = digest_of(ChecksumAlgorithm::Sha1, &file).unwrap();
+        assert!(cache.revalidate(&url, Some(&sha1)).is_some());
+        let wrong: Checksum = format!("sha256:{}", "0".repeat(64)).parse().unwrap();
+        assert!(cache.revalidate(&url, Some(&wrong)).is_none());
+
+        // Objects changed through a link are dropped
+        std::fs::write(&object, "<package></package>").unwrap();
+        assert!(cache.listed(&checksum).is_none());
+        assert!(cache.revalidate(&url, None).is_none());
+    }
+}
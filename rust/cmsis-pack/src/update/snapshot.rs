@@ -0,0 +1,536 @@
+use std::fs::{create_dir_all, read, read_dir, remove_file, rename, write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::log::STORE;
+use crate::pack_index::PdscRef;
+use crate::pdsc::Package;
+use crate::update::checksum::{Checksum, ChecksumAlgorithm, ChecksumMismatch, Hasher};
+use crate::update::download::{DownloadConfig, DownloadContext, DownloadProgress, IntoDownload};
+use crate::update::extract::pack_file;
+use crate::update::fetch::source_url;
+use crate::update::CancellationToken;
+use crate::utils::pack_id;
+use crate::utils::parse::{from_store_path, FromElem};
+
+/// Format 2 added the digests and contents of files; snapshots of format 1
+/// still restore, unverified
+const SNAPSHOT_FORMAT: u32 = 2;
+
+/// A pack at a specific version, as recorded in a [`StoreSnapshot`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    pub vendor: String,
+    pub name: String,
+    pub version: String,
+    pub url: String,
+    /// The digest of the file, as `sha256:HEX`, which a restore checks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// The contents of a PDSC file, so that a restore does not depend on
+    /// the version its vendor publishes at the time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pdsc: Option<String>,
+}
+
+/// The complete state of a pack store
+///
+/// Restoring a snapshot into an empty pack store reproduces the same index
+/// and installed packs, byte for byte, or fails if any of them can no longer
+/// be retrieved at the recorded version.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreSnapshot {
+    pub format: u32,
+    /// The vendor index URLs the store was updated from
+    pub sources: Vec<String>,
+    /// PDSC files in the store
+    pub index: Vec<SnapshotEntry>,
+    /// Pack archives in the store
+    pub installed: Vec<SnapshotEntry>,
+}
+
+impl StoreSnapshot {
+    pub fn from_reader<R: std::io::Read>(reader: R) -> Result<Self, crate::Error> {
+        let snapshot: Self = serde_json::from_reader(reader).map_err(|e| crate::Error::Parse {
+            path: None,
+            source: e.into(),
+        })?;
+        if !(1..=SNAPSHOT_FORMAT).contains(&snapshot.format) {
+            return Err(crate::Error::Parse {
+                path: None,
+                source: format!("Unsupported snapshot format {}", snapshot.format).into(),
+            });
+        }
+        Ok(snapshot)
+    }
+
+    pub fn to_writer<W: std::io::Write>(&self, writer: W) -> Result<(), crate::Error> {
+        serde_json::to_writer_pretty(writer, self).map_err(|e| crate::Error::Other(e.into()))
+    }
+}
+
+impl SnapshotEntry {
+    fn pdsc_ref(&self) -> PdscRef {
+        PdscRef {
+            url: self.url.clone(),
+            vendor: self.vendor.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            date: None,
+            deprecated: None,
+            replacement: None,
+            size: None,
+            released: None,
+            deprecated_on: None,
+            checksum: None,
+            timestamp: None,
+            updated: None,
+        }
+    }
+
+    fn pack(&self) -> String {
+        format!("{}.{}", self.vendor, self.name)
+    }
+}
+
+/// A pack archive at the version recorded in a snapshot, which need not be
+/// the latest release
+struct PackAtVersion<'a>(&'a SnapshotEntry);
+
+impl<'a> IntoDownload for PackAtVersion<'a> {
+    fn into_uri(&self) -> Result<Url, Error> {
+        let SnapshotEntry {
+            vendor,
+            name,
+            version,
+            url,
+            ..
+        } = self.0;
+        let uri = if url.ends_with('/') {
+            format!("{}{}.{}.{}.pack", url, vendor, name, version)
+        } else {
+            format!("{}/{}.{}.{}.pack", url, vendor, name, version)
+        };
+        source_url(&uri)
+    }
+
+    fn into_fd<D: DownloadConfig>(&self, config: &D) -> PathBuf {
+        let mut filename = config.pack_store();
+        filename.push(Path::new(&self.0.vendor));
+        filename.push(Path::new(&self.0.name));
+        filename.push(format!("{}.pack", self.0.version));
+        filename
+    }
+}
+
+fn sha256_of(bytes: &[u8]) -> Checksum {
+    let mut hasher = Hasher::new(ChecksumAlgorithm::Sha256);
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+fn file_sha256(path: &Path) -> Result<String, crate::Error> {
+    let mut hasher = Hasher::new(ChecksumAlgorithm::Sha256);
+    hasher
+        .update_from(path)
+        .map_err(|e| crate::Error::with_path(e.into(), path.to_path_buf()))?;
+    Ok(hasher.finish().to_string())
+}
+
+fn files_with_extension(dir: &Path, ext: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = read_dir(dir)
+        .map(|rd| {
+            rd.flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|e| e == ext))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = read_dir(dir)
+        .map(|rd| {
+            rd.flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect()
+        })
+        .unwrap_or_default();
+    dirs.sort();
+    dirs
+}
+
+/// Record the state of the pack store of `config`
+///
+/// `sources` are the vendor index URLs the store is updated from; they are
+/// recorded as given so a restore can update from the same list.
+pub fn capture_snapshot<D, I>(config: &D, sources: I) -> Result<StoreSnapshot, crate::Error>
+where
+    D: DownloadConfig,
+    I: IntoIterator<Item = String>,
+{
+    let store = config.pack_store();
+    let mut index = Vec::new();
+    for path in files_with_extension(&store, "pdsc") {
+        let pkg = from_store_path::<Package>(&path)
+            .map_err(|e| crate::Error::with_path(e, path.clone()))?;
+        let contents = read(&path).map_err(|e| crate::Error::with_path(e.into(), path.clone()))?;
+        // The file name carries the version listed in the index, which is
+        // what a restore has to ask for
+        let prefix = format!("{}.", pack_id(&pkg.vendor, &pkg.name));
+        let version = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .filter(|stem| stem.to_ascii_lowercase().starts_with(&prefix))
+            .and_then(|stem| stem.get(prefix.len()..))
+            .map(String::from)
+            .or_else(|| pkg.releases.iter().next().map(|r| r.version.clone()));
+        match version {
+            Some(version) => index.push(SnapshotEntry {
+                vendor: pkg.vendor,
+                name: pkg.name,
+                version,
+                url: pkg.url,
+                sha256: Some(sha256_of(&contents).to_string()),
+                // A PDSC that is not UTF-8 is taken from its pack archive
+                // or the vendor instead
+                pdsc: String::from_utf8(contents).ok(),
+            }),
+            None => tracing::warn!(target: STORE, path = ?path, "Skipping PDSC without a version"),
+        }
+    }
+
+    let mut installed = Vec::new();
+    for vendor_dir in subdirs(&store) {
+        for name_dir in subdirs(&vendor_dir) {
+            let vendor = vendor_dir.file_name().and_then(|n| n.to_str());
+            let name = name_dir.file_name().and_then(|n| n.to_str());
+            let (vendor, name) = match (vendor, name) {
+                (Some(vendor), Some(name)) => (vendor, name),
+                _ => continue,
+            };
+            let url = index
+                .iter()
+                .find(|entry| {
+                    entry.vendor.eq_ignore_ascii_case(vendor)
+                        && entry.name.eq_ignore_ascii_case(name)
+                })
+                .map(|entry| entry.url.clone());
+            let url = match url {
+                Some(url) => url,
+                None => {
+                    tracing::warn!(target: STORE, vendor, name, "Skipping packs without a PDSC in the store");
+                    continue;
+                }
+            };
+            for pack in files_with_extension(&name_dir, "pack") {
+                if let Some(version) = pack.file_stem().and_then(|stem| stem.to_str()) {
+                    installed.push(SnapshotEntry {
+                        vendor: vendor.to_string(),
+                        name: name.to_string(),
+                        version: version.to_string(),
+                        url: url.clone(),
+                        sha256: Some(file_sha256(&pack)?),
+                        pdsc: None,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(StoreSnapshot {
+        format: SNAPSHOT_FORMAT,
+        sources: sources.into_iter().collect(),
+        index,
+        installed,
+    })
+}
+
+/// Check `contents`, the file of `entry` from `url`, against the digest
+/// the snapshot records for it
+fn verify(entry: &SnapshotEntry, url: String, contents: &[u8]) -> Result<(), crate::Error> {
+    let expected: Checksum = match &entry.sha256 {
+        Some(sha256) => sha256.parse().map_err(|e: Error| crate::Error::Parse {
+            path: None,
+            source: e.into(),
+        })?,
+        None => return Ok(()),
+    };
+    let mut hasher = Hasher::new(expected.algorithm);
+    hasher.update(contents);
+    let actual = hasher.finish();
+    if actual == expected {
+        return Ok(());
+    }
+    Err(crate::Error::Checksum {
+        url,
+        source: Box::new(ChecksumMismatch { expected, actual }),
+    })
+}
+
+/// Check the file of `entry` at `dest` against the snapshot, removing it
+/// when it differs
+fn verify_file(entry: &SnapshotEntry, url: String, dest: &Path) -> Result<(), crate::Error> {
+    let contents = read(dest).map_err(|e| crate::Error::with_path(e.into(), dest.to_path_buf()))?;
+    let res = verify(entry, url, &contents);
+    if res.is_err() {
+        let _ = remove_file(dest);
+    }
+    res
+}
+
+/// Write `contents` to `dest`, through a temporary file so that `dest` is
+/// never left incomplete
+fn write_file(dest: &Path, contents: &[u8]) -> Result<(), crate::Error> {
+    let temp = dest.with_extension("pdsc.part");
+    dest.parent()
+        .map_or(Ok(()), create_dir_all)
+        .and_then(|()| write(&temp, contents))
+        .and_then(|()| rename(&temp, dest))
+        .map_err(|e| {
+            let _ = remove_file(&temp);
+            crate::Error::with_path(e.into(), dest.to_path_buf())
+        })
+}
+
+/// Put the PDSC of `entry` into the store without the network, from the
+/// contents the snapshot embeds or from its pack archive at the recorded
+/// version, returning whether it did
+fn restore_pdsc<D: DownloadConfig>(
+    config: &D,
+    entry: &SnapshotEntry,
+    snapshot: &StoreSnapshot,
+) -> Result<bool, crate::Error> {
+    let pdsc = entry.pdsc_ref();
+    let dest = pdsc.into_fd(config);
+    let url = pdsc.into_uri().map(|u| u.to_string()).unwrap_or_default();
+    if let Some(contents) = &entry.pdsc {
+        verify(entry, url, contents.as_bytes())?;
+        write_file(&dest, contents.as_bytes())?;
+        return Ok(true);
+    }
+    let archive = snapshot.installed.iter().find(|pack| {
+        pack.vendor.eq_ignore_ascii_case(&entry.vendor)
+            && pack.name.eq_ignore_ascii_case(&entry.name)
+            && pack.version == entry.version
+    });
+    let name = format!("{}.{}.pdsc", entry.vendor, entry.name);
+    let from_archive = archive
+        .map(|pack| PackAtVersion(pack).into_fd(config))
+        .and_then(|pack| pack_file(&pack, &name).ok())
+        .and_then(|path| read(path).ok());
+    match from_archive {
+        Some(contents) => {
+            verify(entry, url, &contents)?;
+            write_file(&dest, &contents)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+/// Check that the PDSC of `entry` is in the store at the recorded version,
+/// removing it otherwise
+fn check_pdsc<D: DownloadConfig>(config: &D, entry: &SnapshotEntry) -> Result<(), crate::Error> {
+    let pdsc = entry.pdsc_ref();
+    let dest = pdsc.into_fd(config);
+    let url = pdsc.into_uri().map(|u| u.to_string()).unwrap_or_default();
+    if !dest.exists() {
+        return Err(crate::Error::Download {
+            url,
+            source: "PDSC could not be downloaded".into(),
+        });
+    }
+    let found = Package::from_path(&dest)
+        .ok()
+        .and_then(|pkg| pkg.releases.iter().next().map(|r| r.version.clone()));
+    if found.as_deref() == Some(entry.version.as_str()) {
+        return verify_file(entry, url, &dest);
+    }
+    let _ = remove_file(&dest);
+    Err(crate::Error::Pack {
+        pack: entry.pack(),
+        source: format!(
+            "snapshot has version {}, but {} is published",
+            entry.version,
+            found.as_deref().unwrap_or("no valid PDSC")
+        )
+        .into(),
+    })
+}
+
+/// Restore a snapshot into the pack store of `config`
+///
+/// Downloads every recorded pack archive that is not already in the store,
+/// and writes the PDSC files the snapshot embeds. A PDSC it does not embed
+/// is taken from its pack archive at the recorded version, or else
+/// downloaded, which fails once the vendor publishes another version. Every
+/// file is checked against the digest the snapshot records. Restore into an
+/// empty pack store: files the snapshot does not mention are left alone.
+pub async fn restore_snapshot_async<P, D>(
+    config: &D,
+    snapshot: &StoreSnapshot,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<Vec<PathBuf>, crate::Error>
+where
+    P: DownloadProgress,
+    D: DownloadConfig,
+{
+    let dl_cntx = DownloadContext::new(config, progress, cancel)?;
+    let mut restored = dl_cntx
+        .download_iterator(snapshot.installed.iter().map(PackAtVersion))
+        .await?;
+    for entry in &snapshot.installed {
+        let pack = PackAtVersion(entry);
+        let dest = pack.into_fd(config);
+        let url = pack.into_uri().map(|u| u.to_string()).unwrap_or_default();
+        if !dest.exists() {
+            return Err(crate::Error::Download {
+                url,
+                source: "pack could not be downloaded".into(),
+            });
+        }
+        verify_file(entry, url, &dest)?;
+    }
+
+    let mut published = Vec::new();
+    for entry in &snapshot.index {
+        if restore_pdsc(config, entry, snapshot)? {
+            restored.push(entry.pdsc_ref().into_fd(config));
+        } else {
+            published.push(entry);
+        }
+    }
+    restored.extend(
+        dl_cntx
+            .download_iterator(published.iter().map(|entry| entry.pdsc_ref()))
+            .await?,
+    );
+    for entry in published {
+        check_pdsc(config, entry)?;
+    }
+    Ok(restored)
+}
+
+/// Restore a snapshot into the pack store of `config`
+///
+/// Blocking version of [`restore_snapshot_async`].
+pub fn restore_snapshot<P, D>(
+    config: &D,
+    snapshot: &StoreSnapshot,
+    progress: P,
+    cancel: CancellationToken,
+) -> Result<Vec<PathBuf>, crate::Error>
+where
+    P: DownloadProgress,
+    D: DownloadConfig,
+{
+    super::block_on(restore_snapshot_async(config, snapshot, progress, cancel))?
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::update::test_server::{ok, serve, status};
+
+    struct Store(PathBuf);
+
+    impl DownloadConfig for Store {
+        fn pack_store(&self) -> PathBuf {
+            self.0.clone()
+        }
+    }
+
+    #[test]
+    fn capture_and_reload() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = Store(temp.path().join("store"));
+        create_dir_all(store.0.join("Vendor").join("Pack")).unwrap();
+        write(
+            store.0.join("Vendor.Pack.1.1.0.pdsc"),
+            "<package><name>Pack</name><vendor>Vendor</vendor>\
+             <description/><url>http://example.com/</url>\
+             <releases><release version=\"1.1.0\"/></releases></package>",
+        )
+        .unwrap();
+        write(store.0.join("Vendor").join("Pack").join("1.0.0.pack"), "").unwrap();
+
+        let sources = vec!["http://example.com/index.pidx".to_string()];
+        let snapshot = capture_snapshot(&store, sources.clone()).unwrap();
+        assert_eq!(snapshot.sources, sources);
+        assert_eq!(snapshot.index.len(), 1);
+        assert_eq!(snapshot.index[0].version, "1.1.0");
+        assert_eq!(snapshot.installed[0].version, "1.0.0");
+        assert_eq!(
+            PackAtVersion(&snapshot.installed[0])
+                .into_uri()
+                .unwrap()
+                .as_str(),
+            "http://example.com/Vendor.Pack.1.0.0.pack"
+        );
+
+        let mut buf = Vec::new();
+        snapshot.to_writer(&mut buf).unwrap();
+        assert_eq!(
+            StoreSnapshot::from_reader(buf.as_slice()).unwrap(),
+            snapshot
+        );
+    }
+
+    const PDSC: &str = "<package><name>Pack</name><vendor>Vendor</vendor>\
+                        <description/><url>http://127.0.0.1:{}/</url>\
+                        <releases><release version=\"{}\"/></releases></package>";
+
+    fn pdsc(port: u16, version: &str) -> String {
+        PDSC.replacen("{}", &port.to_string(), 1)
+            .replacen("{}", version, 1)
+    }
+
+    #[test]
+    fn restore_embeds_pdsc_and_verifies_packs() {
+        let archive = std::sync::Arc::new(std::sync::Mutex::new("archive"));
+        let served = archive.clone();
+        let port = serve(move |path, _| match path {
+            // The vendor has moved on since the snapshot was taken
+            "/Vendor.Pack.pdsc" => ok(&pdsc(0, "1.2.0")),
+            "/Vendor.Pack.1.1.0.pack" => ok(*served.lock().unwrap()),
+            _ => status("404 Not Found"),
+        });
+        let from_temp = tempfile::tempdir().unwrap();
+        let from = Store(from_temp.path().join("store"));
+        create_dir_all(from.0.join("Vendor").join("Pack")).unwrap();
+        write(from.0.join("Vendor.Pack.1.1.0.pdsc"), pdsc(port, "1.1.0")).unwrap();
+        write(from.0.join("Vendor/Pack/1.1.0.pack"), "archive").unwrap();
+        let snapshot = capture_snapshot(&from, vec![]).unwrap();
+        assert!(snapshot.index[0].pdsc.is_some());
+        assert!(snapshot.installed[0].sha256.is_some());
+
+        let to_temp = tempfile::tempdir().unwrap();
+        let to = Store(to_temp.path().join("store"));
+        create_dir_all(&to.0).unwrap();
+        restore_snapshot(&to, &snapshot, (), CancellationToken::new()).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(to.0.join("Vendor.Pack.1.1.0.pdsc")).unwrap(),
+            pdsc(port, "1.1.0")
+        );
+        assert_eq!(
+            std::fs::read_to_string(to.0.join("Vendor/Pack/1.1.0.pack")).unwrap(),
+            "archive"
+        );
+
+        // An archive republished with other contents fails the restore
+        *archive.lock().unwrap() = "tampered";
+        let _ = std::fs::remove_dir_all(&to.0);
+        create_dir_all(&to.0).unwrap();
+        let err = restore_snapshot(&to, &snapshot, (), CancellationToken::new()).unwrap_err();
+        assert_eq!(err.code(), "checksum");
+        assert!(!to.0.join("Vendor/Pack/1.1.0.pack").exists());
+    }
+}
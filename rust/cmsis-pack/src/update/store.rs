@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+
+use crate::pdsc::{DatabaseDevice, DeviceDatabase};
+use crate::update::check::pdsc_paths;
+use crate::update::download::{CancellationToken, DownloadConfig};
+use crate::update::install::{install_pack, PackSpec};
+use crate::update::lock::StoreLock;
+use crate::update::report::UpdateReport;
+use crate::Error;
+
+/// The vendor index list of a [`Cache`] unless it is given another one
+pub const DEFAULT_VIDX_LIST: &[&str] = &["http://www.keil.com/pack/index.pidx"];
+
+/// The file the device database of a pack store is cached in, shared with
+/// the command line interface
+const DEVICE_CACHE: &str = ".device-cache.bin";
+
+/// A pack store, for using the crate as a library
+///
+/// A `Cache` downloads with the defaults of [`DownloadConfig`]; implement
+/// that trait and call [`update`](super::update) or
+/// [`install_pack`](super::install_pack) for more control. Commands that
+/// write to the store lock it, and fail with [`Error::Locked`] while
+/// another process holds the lock.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    pack_store: PathBuf,
+    vidx_list: Vec<String>,
+}
+
+impl DownloadConfig for Cache {
+    fn pack_store(&self) -> PathBuf {
+        self.pack_store.clone()
+    }
+}
+
+impl Cache {
+    /// The pack store at `path`, which is created by the first update
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Cache {
+            pack_store: path.into(),
+            vidx_list: DEFAULT_VIDX_LIST
+                .iter()
+                .map(|url| url.to_string())
+                .collect(),
+        }
+    }
+
+    /// Update from the vendor indexes of `vidx_list` instead of the
+    /// [default ones](DEFAULT_VIDX_LIST)
+    pub fn with_vidx_list<I: IntoIterator<Item = String>>(self, vidx_list: I) -> Self {
+        Cache {
+            vidx_list: vidx_list.into_iter().collect(),
+            ..self
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.pack_store
+    }
+
+    /// Download the PDSC files of the vendor indexes into the store
+    pub fn update(&self) -> Result<UpdateReport, Error> {
+        let _lock = StoreLock::acquire(&self.pack_store, false)?;
+        super::update(self, self.vidx_list.clone(), (), CancellationToken::new())
+    }
+
+    /// The PDSC files of the store, sorted
+    pub fn installed_pdscs(&self) -> Vec<PathBuf> {
+        pdsc_paths(&self.pack_store)
+    }
+
+    /// The devices, boards and components of the PDSC files of the store,
+    /// from the binary cache the command line interface keeps up to date
+    pub fn database(&self) -> DeviceDatabase {
+        DeviceDatabase::load_or_build(&self.installed_pdscs(), &self.pack_store.join(DEVICE_CACHE))
+    }
+
+    /// The device called `name`, as [`DeviceDatabase::lookup`] finds it
+    pub fn device(&self, name: &str) -> Option<DatabaseDevice> {
+        self.database().lookup(name).cloned()
+    }
+
+    /// Download and extract the pack `id`, as `Vendor::Name` for its latest
+    /// release or `Vendor::Name@1.2.0`, returning its directory
+    pub fn install_pack(&self, id: &str) -> Result<PathBuf, Error> {
+        let spec: PackSpec = id.parse()?;
+        let _lock = StoreLock::acquire(&self.pack_store, false)?;
+        install_pack(self, &spec, (), CancellationToken::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use reqwest::Url;
+
+    #[test]
+    fn caches_update_and_query_their_store() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().to_path_buf();
+        let mirror = root.join("mirror");
+        std::fs::create_dir_all(&mirror).unwrap();
+        let index = format!(
+            "<index><vendor>V</vendor><url>{0}</url><pindex>\
+             <pdsc url=\"{0}\" vendor=\"MyVendor\" name=\"MyPack\" version=\"1.1.0\"/>\
+             </pindex></index>",
+            Url::from_directory_path(&mirror).unwrap()
+        );
+        std::fs::write(mirror.join("index.pidx"), index).unwrap();
+        std::fs::copy(
+            "../../tests/test-pack-index/MyVendor.MyPack.pdsc",
+            mirror.join("MyVendor.MyPack.pdsc"),
+        )
+        .unwrap();
+
+        let cache = Cache::new(root.join("store"))
+            .with_vidx_list([mirror.join("index.pidx").display().to_string()]);
+        assert!(cache.installed_pdscs().is_empty());
+        assert!(cache.device("MyDevice").is_none());
+
+        let report = cache.update().unwrap();
+        assert!(report.failed.is_empty());
+        let pdsc = root.join("store").join("MyVendor.MyPack.1.1.0.pdsc");
+        assert_eq!(cache.installed_pdscs(), vec![pdsc]);
+        let device = cache.device("mydevice").unwrap();
+        assert_eq!(device.pack.name, "MyPack");
+        assert!(cache.path().join(DEVICE_CACHE).exists());
+
+        assert_eq!(cache.install_pack("MyPack").unwrap_err().code(), "other");
+    }
+}
@@ -0,0 +1,66 @@
+//! An in-process HTTP server, so that tests of downloads cover redirects and
+//! error responses without reaching vendor servers
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+
+/// Serve HTTP on a local port, one request per connection, answering
+/// each path and its request headers with the status line, headers and
+/// body `respond` returns
+///
+/// Header names are lower case. The server runs until the test ends.
+pub(crate) fn serve(respond: impl Fn(&str, &str) -> String + Send + 'static) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(&stream);
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            let path = request.split(' ').nth(1).unwrap_or("/").to_string();
+            let mut headers = String::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok() && line.trim() != "" {
+                headers.push_str(&line.to_ascii_lowercase());
+                line.clear();
+            }
+            let _ = (&stream).write_all(respond(&path, &headers).as_bytes());
+        }
+    });
+    port
+}
+
+/// A 200 response with `body`
+pub(crate) fn ok(body: &str) -> String {
+    format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+/// An empty response with `status`, such as `404 Not Found`
+pub(crate) fn status(status: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )
+}
+
+/// A redirect with `status` to `location`, which may be relative
+pub(crate) fn redirect(status: &str, location: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status, location
+    )
+}
+
+/// The `Host` header of a request, to build absolute URLs back to the
+/// server from within `respond`
+pub(crate) fn host(headers: &str) -> &str {
+    headers
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .unwrap_or_default()
+        .trim()
+}
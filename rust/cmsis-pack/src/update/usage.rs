@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{format_err, Error};
+use serde::Serialize;
+
+use crate::update::lock::LOCK_FILE;
+use crate::update::outdated::entries;
+use crate::update::prune::disk_usage;
+
+/// What a top-level entry of the pack store is part of
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum StorePart {
+    /// A PDSC file downloaded by an update, of the vendor
+    Index(String),
+    /// The directory of the archives and extracted packs of the vendor
+    Packs(String),
+    /// A cache or record kept by the pack store, such as the device cache
+    Cache,
+    /// A partial download or claim of an operation
+    Temporary,
+}
+
+impl StorePart {
+    /// The part the entry `name` of the store is, if it belongs to the
+    /// store; the lock and files of the user such as `vendors.list` do not
+    pub(crate) fn of(name: &str, is_dir: bool) -> Option<Self> {
+        let ext = Path::new(name).extension().and_then(|ext| ext.to_str());
+        if matches!(ext, Some("part" | "claim" | "extracting")) {
+            return Some(StorePart::Temporary);
+        }
+        if name == LOCK_FILE {
+            return None;
+        }
+        if name.starts_with('.') {
+            return Some(StorePart::Cache);
+        }
+        let vendor = name.split('.').next().unwrap_or(name).to_string();
+        match (is_dir, ext) {
+            (true, _) => Some(StorePart::Packs(vendor)),
+            (false, Some("pdsc")) => Some(StorePart::Index(vendor)),
+            (false, _) => None,
+        }
+    }
+}
+
+/// The disk space a vendor takes up in the pack store, in bytes
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VendorUsage {
+    /// Its PDSC files
+    pub index: u64,
+    /// Its pack archives and extracted packs
+    pub packs: u64,
+}
+
+/// The disk space the pack store takes up, in bytes, as [`store_usage`]
+/// measures it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct StoreUsage {
+    pub vendors: BTreeMap<String, VendorUsage>,
+    /// Caches and records such as the device cache and the update report
+    pub caches: u64,
+    /// Partial downloads and claims
+    pub temporary: u64,
+}
+
+impl StoreUsage {
+    pub fn total(&self) -> u64 {
+        let vendors: u64 = self
+            .vendors
+            .values()
+            .map(|usage| usage.index + usage.packs)
+            .sum();
+        vendors + self.caches + self.temporary
+    }
+}
+
+/// Measure the disk space of the pack store, by vendor
+///
+/// Files of the user kept next to the store, such as `vendors.list`, are
+/// not counted.
+pub fn store_usage(pack_store: &Path) -> StoreUsage {
+    let mut usage = StoreUsage::default();
+    for (path, name, is_dir) in entries(pack_store) {
+        let bytes = disk_usage(&path);
+        match StorePart::of(&name, is_dir) {
+            Some(StorePart::Index(vendor)) => {
+                usage.vendors.entry(vendor).or_default().index += bytes
+            }
+            Some(StorePart::Packs(vendor)) => {
+                usage.vendors.entry(vendor).or_default().packs += bytes
+            }
+            Some(StorePart::Cache) => usage.caches += bytes,
+            Some(StorePart::Temporary) => usage.temporary += bytes,
+            None => {}
+        }
+    }
+    usage
+}
+
+/// The parts of the pack store [`clean_store`](crate::update::clean_store)
+/// deletes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CleanScope {
+    /// The PDSC files and the caches built from them, which the next update
+    /// downloads again
+    Index,
+    /// The pack archives and extracted packs
+    Packs,
+    /// Everything the pack store holds, including partial downloads
+    All,
+}
+
+impl CleanScope {
+    pub const NAMES: &'static [&'static str] = &["index", "packs", "all"];
+
+    pub(crate) fn covers(self, part: &StorePart) -> bool {
+        matches!(
+            (self, part),
+            (CleanScope::All, _)
+                | (CleanScope::Index, StorePart::Index(_) | StorePart::Cache)
+                | (CleanScope::Packs, StorePart::Packs(_))
+        )
+    }
+}
+
+impl FromStr for CleanScope {
+    type Err = Error;
+    fn from_str(from: &str) -> Result<Self, Error> {
+        match from {
+            "index" => Ok(CleanScope::Index),
+            "packs" => Ok(CleanScope::Packs),
+            "all" => Ok(CleanScope::All),
+            unknown => Err(format_err!("Unknown part of the pack store {}", unknown)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn usage_is_broken_down_by_vendor() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        create_dir_all(store.join("Vendor/Pack/1.0.0")).unwrap();
+        write(store.join("Vendor.Pack.1.0.0.pdsc"), [0; 10]).unwrap();
+        write(store.join("Other.Pack.1.0.0.pdsc"), [0; 20]).unwrap();
+        write(store.join("Vendor/Pack/1.0.0.pack"), [0; 100]).unwrap();
+        write(store.join("Vendor/Pack/1.0.0/Vendor.Pack.pdsc"), [0; 50]).unwrap();
+        write(store.join(".device-cache.bin"), [0; 7]).unwrap();
+        write(store.join("Other.Pack.2.0.0.part"), [0; 3]).unwrap();
+        write(store.join(LOCK_FILE), "1").unwrap();
+        write(store.join("vendors.list"), "https://example.com/index.pidx").unwrap();
+
+        let usage = store_usage(&store);
+        let vendor = |index, packs| VendorUsage { index, packs };
+        assert_eq!(usage.vendors["Vendor"], vendor(10, 150));
+        assert_eq!(usage.vendors["Other"], vendor(20, 0));
+        assert_eq!((usage.caches, usage.temporary), (7, 3));
+        assert_eq!(usage.total(), 190);
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::fs::{rename, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::update::origins::key;
+
+const VALIDATORS_FILE: &str = ".validators.json";
+
+/// The `ETag` and `Last-Modified` headers a file was served with, which a
+/// later request sends back to only fetch it again when it changed
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// Validators of the PDSC files of the pack store, and the index timestamps
+/// they were downloaded for, keyed by their path relative to the store
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct ValidatorLog {
+    files: BTreeMap<String, Validators>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    listed: BTreeMap<String, DateTime<Utc>>,
+}
+
+fn log_path(pack_store: &Path) -> PathBuf {
+    pack_store.join(VALIDATORS_FILE)
+}
+
+impl ValidatorLog {
+    pub(crate) fn load(pack_store: &Path) -> Self {
+        File::open(log_path(pack_store))
+            .ok()
+            .and_then(|fd| serde_json::from_reader(BufReader::new(fd)).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, pack_store: &Path) -> Result<(), Error> {
+        let path = log_path(pack_store);
+        if self.files.is_empty() && self.listed.is_empty() && !path.exists() {
+            return Ok(());
+        }
+        let temp = path.with_extension("part");
+        std::fs::create_dir_all(pack_store)?;
+        serde_json::to_writer_pretty(File::create(&temp)?, self)?;
+        rename(temp, path)?;
+        Ok(())
+    }
+
+    /// The validators `file` was last served with, if it still exists
+    pub(crate) fn get(&self, pack_store: &Path, file: &Path) -> Option<Validators> {
+        if !file.exists() {
+            return None;
+        }
+        self.files.get(&key(pack_store, file)).cloned()
+    }
+
+    /// Record the validators `file` was served with, forgetting earlier
+    /// ones when the server sent none
+    pub(crate) fn record(&mut self, pack_store: &Path, file: &Path, validators: Validators) {
+        let key = key(pack_store, file);
+        if validators.is_empty() {
+            self.files.remove(&key);
+        } else {
+            self.files.insert(key, validators);
+        }
+    }
+
+    /// Whether the index lists `file` as changed at `updated`, after the
+    /// timestamp it was last downloaded for or, without one, after it was
+    /// written
+    pub(crate) fn is_stale(&self, pack_store: &Path, file: &Path, updated: &DateTime<Utc>) -> bool {
+        match self.listed.get(&key(pack_store, file)) {
+            Some(listed) => updated > listed,
+            None => std::fs::metadata(file)
+                .and_then(|meta| meta.modified())
+                .is_ok_and(|modified| *updated > DateTime::<Utc>::from(modified)),
+        }
+    }
+
+    /// Record the index timestamp `file` was downloaded for
+    pub(crate) fn record_listed(&mut self, pack_store: &Path, file: &Path, updated: DateTime<Utc>) {
+        self.listed.insert(key(pack_store, file), updated);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn newer_index_timestamps_make_files_stale() {
+        let temp = tempfile::tempdir().unwrap();
+        let store = temp.path().join("store");
+        std::fs::create_dir_all(&store).unwrap();
+        let file = store.join("V.P.1.0.0.pdsc");
+        std::fs::write(&file, "<package/>").unwrap();
+        let now = DateTime::<Utc>::from(std::time::SystemTime::now());
+
+        let mut log = ValidatorLog::default();
+        assert!(!log.is_stale(&store, &file, &(now - Duration::days(1))));
+        assert!(log.is_stale(&store, &file, &(now + Duration::days(1))));
+        log.record_listed(&store, &file, now + Duration::days(1));
+        log.save(&store).unwrap();
+        let log = ValidatorLog::load(&store);
+        assert!(!log.is_stale(&store, &file, &(now + Duration::days(1))));
+        assert!(log.is_stale(&store, &file, &(now + Duration::days(2))));
+    }
+}
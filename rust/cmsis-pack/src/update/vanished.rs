@@ -0,0 +1,145 @@
+use std::collections::BTreeMap;
+use std::fs::{remove_file, rename, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Error;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::update::listing::StoreListing;
+
+const VANISHED_FILE: &str = ".vanished.json";
+
+/// What happens to the local PDSC files of a pack whose PDSC URL returns 404
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VanishedPolicy {
+    /// Keep the files, marked as stale
+    #[default]
+    Keep,
+    /// Delete the files
+    Delete,
+}
+
+/// A pack whose PDSC file is no longer published
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Vanished {
+    /// The URL that returned 404
+    pub url: String,
+    /// Seconds since the Unix epoch when the 404 was first seen
+    pub since: u64,
+    /// The local PDSC files of the pack, relative to the pack store
+    pub files: Vec<String>,
+    /// Whether the files were deleted
+    pub deleted: bool,
+}
+
+/// Packs of the pack store whose PDSC URL returned 404, keyed by
+/// `Vendor.Name`
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct VanishedLog {
+    packs: BTreeMap<String, Vanished>,
+}
+
+fn log_path(pack_store: &Path) -> PathBuf {
+    pack_store.join(VANISHED_FILE)
+}
+
+/// `Vendor.Name` of a PDSC URL, which ends in `Vendor.Name.pdsc`
+pub(crate) fn pack_key(url: &Url) -> Option<String> {
+    let file = url.path_segments()?.next_back()?;
+    file.strip_suffix(".pdsc").map(str::to_string)
+}
+
+impl VanishedLog {
+    pub(crate) fn load(pack_store: &Path) -> Self {
+        File::open(log_path(pack_store))
+            .ok()
+            .and_then(|fd| serde_json::from_reader(BufReader::new(fd)).ok())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn save(&self, pack_store: &Path) -> Result<(), Error> {
+        let path = log_path(pack_store);
+        if self.packs.is_empty() && !path.exists() {
+            return Ok(());
+        }
+        let temp = path.with_extension("part");
+        std::fs::create_dir_all(pack_store)?;
+        serde_json::to_writer_pretty(File::create(&temp)?, self)?;
+        rename(temp, path)?;
+        Ok(())
+    }
+
+    /// Mark the pack of `url` as vanished, applying `policy` to its local
+    /// PDSC files, which are returned
+    pub(crate) fn vanished(
+        &mut self,
+        pack_store: &Path,
+        listing: &mut StoreListing,
+        url: &Url,
+        policy: VanishedPolicy,
+    ) -> Vec<PathBuf> {
+        let key = match pack_key(url) {
+            Some(key) => key,
+            None => return Vec::new(),
+        };
+        // Names are case-insensitive, and so is the key of an entry
+        let key = self
+            .packs
+            .keys()
+            .find(|known| known.eq_ignore_ascii_case(&key))
+            .cloned()
+            .unwrap_or(key);
+        let prefix = format!("{}.", key.to_ascii_lowercase());
+        let mut files: Vec<String> = listing
+            .names(pack_store)
+            .iter()
+            .filter_map(|name| name.to_str())
+            .filter(|name| name.ends_with(".pdsc"))
+            .filter(|name| {
+                name.to_ascii_lowercase()
+                    .strip_prefix(&prefix)
+                    .is_some_and(|version| version.starts_with(|c: char| c.is_ascii_digit()))
+            })
+            .map(str::to_string)
+            .collect();
+        files.sort();
+        let deleted = policy == VanishedPolicy::Delete;
+        let paths: Vec<PathBuf> = files.iter().map(|file| pack_store.join(file)).collect();
+        if deleted {
+            for path in &paths {
+                let _ = remove_file(path);
+            }
+        }
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let entry = self.packs.entry(key).or_insert(Vanished {
+            url: url.to_string(),
+            since,
+            files: Vec::new(),
+            deleted,
+        });
+        if !files.is_empty() {
+            entry.files = files;
+        }
+        entry.deleted = deleted;
+        paths
+    }
+
+    /// Forget the pack of `url`, which was downloaded again
+    pub(crate) fn reappeared(&mut self, url: &Url) {
+        if let Some(key) = pack_key(url) {
+            self.packs
+                .retain(|known, _| !known.eq_ignore_ascii_case(&key));
+        }
+    }
+}
+
+/// The packs whose PDSC files vanished upstream, keyed by `Vendor.Name`
+pub fn vanished_packs(pack_store: &Path) -> BTreeMap<String, Vanished> {
+    VanishedLog::load(pack_store).packs
+}
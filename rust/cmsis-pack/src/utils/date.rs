@@ -0,0 +1,145 @@
+use crate::log::PARSE;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+
+const DATE_FORMATS: &[&str] = &["%Y-%m-%d", "%Y/%m/%d", "%Y.%m.%d", "%d.%m.%Y", "%Y%m%d"];
+
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M",
+];
+
+/// UTC offsets, in minutes, of the zone abbreviations `date` prints
+///
+/// Abbreviations that name several zones, like IST for India, Ireland and
+/// Israel, are left out and read as unknown.
+const ZONES: &[(&str, i32)] = &[
+    ("UTC", 0),
+    ("GMT", 0),
+    ("WET", 0),
+    ("BST", 60),
+    ("CET", 60),
+    ("CEST", 120),
+    ("EET", 120),
+    ("EEST", 180),
+    ("CST", -360),
+    ("CDT", -300),
+    ("EST", -300),
+    ("EDT", -240),
+    ("MST", -420),
+    ("MDT", -360),
+    ("PST", -480),
+    ("PDT", -420),
+];
+
+/// Parse a release date such as `2021-03-04`
+///
+/// Vendors also write `2021/03/04`, `04.03.2021`, unpadded fields or a full
+/// timestamp, of which the date is kept.
+pub(crate) fn parse_date(s: &str) -> Option<NaiveDate> {
+    let s = s.trim();
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(s, format).ok())
+        .or_else(|| parse_timestamp(s).map(|t| t.date_naive()))
+}
+
+/// Parse an index timestamp such as `2017-01-08T10:30:00`
+///
+/// RFC 3339, RFC 2822 and the output of `date`, like
+/// `Fri Sep  1 13:26:41 CDT 2017`, are accepted. Timestamps without a zone
+/// are taken to be UTC.
+pub(crate) fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    let s = s.trim();
+    let zoned = DateTime::parse_from_rfc3339(s).or_else(|_| DateTime::parse_from_rfc2822(s));
+    if let Ok(t) = zoned {
+        return Some(t.with_timezone(&Utc));
+    }
+    let naive = DATETIME_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(s, format).ok());
+    match naive {
+        Some(t) => Some(Utc.from_utc_datetime(&t)),
+        None => parse_unix_date(s),
+    }
+}
+
+/// Parse the output of `date`: weekday, month, day, time, zone and year,
+/// where the zone may be missing
+fn parse_unix_date(s: &str) -> Option<DateTime<Utc>> {
+    let words: Vec<&str> = s.split_whitespace().collect();
+    let (month, day, time, zone, year) = match words.as_slice() {
+        [_, month, day, time, zone, year] => (month, day, time, Some(*zone), year),
+        [_, month, day, time, year] => (month, day, time, None, year),
+        _ => return None,
+    };
+    let naive = NaiveDateTime::parse_from_str(
+        &format!("{} {} {} {}", year, month, day, time),
+        "%Y %b %d %H:%M:%S",
+    )
+    .ok()?;
+    let minutes = match zone {
+        Some(zone) => ZONES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(zone))
+            .map(|(_, minutes)| *minutes)
+            .unwrap_or_else(|| {
+                tracing::debug!(target: PARSE, zone, "Unknown time zone, assuming UTC");
+                0
+            }),
+        None => 0,
+    };
+    FixedOffset::east_opt(minutes * 60)?
+        .from_local_datetime(&naive)
+        .single()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn vendor_dates() {
+        let expected = NaiveDate::from_ymd_opt(2021, 3, 4);
+        for s in [
+            "2021-03-04",
+            " 2021-3-4 ",
+            "2021/03/04",
+            "04.03.2021",
+            "2021-03-04T12:00:00",
+        ] {
+            assert_eq!(parse_date(s), expected, "{}", s);
+        }
+        assert_eq!(parse_date("A-Date"), None);
+        assert_eq!(parse_date("2021-02-30"), None);
+    }
+
+    #[test]
+    fn index_timestamps() {
+        let expected = Utc.with_ymd_and_hms(2017, 9, 1, 18, 26, 41).single();
+        for s in [
+            "Fri Sep  1 13:26:41 CDT 2017",
+            "2017-09-01T18:26:41Z",
+            "2017-09-01T20:26:41+02:00",
+            "2017-09-01T18:26:41",
+            "Fri, 01 Sep 2017 18:26:41 +0000",
+        ] {
+            assert_eq!(parse_timestamp(s), expected, "{}", s);
+        }
+        assert_eq!(parse_timestamp("yesterday"), None);
+    }
+
+    #[test]
+    fn ambiguous_zones_are_utc() {
+        assert_eq!(
+            parse_timestamp("Fri Sep  1 18:26:41 IST 2017"),
+            Utc.with_ymd_and_hms(2017, 9, 1, 18, 26, 41).single()
+        );
+        assert_eq!(
+            parse_timestamp("Fri Sep  1 20:26:41 CEST 2017"),
+            Utc.with_ymd_and_hms(2017, 9, 1, 18, 26, 41).single()
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::ops::Range;
+
+/// Characters of Windows-1252 from 0x80 to 0x9F; the five bytes it leaves
+/// undefined map to the C1 controls, as in ISO-8859-1
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+/// A document transcoded into UTF-8 from the encoding it is in
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Decoded {
+    pub(crate) text: String,
+    /// The name of the encoding the document was in
+    pub(crate) encoding: &'static str,
+    /// How many invalid sequences were replaced with U+FFFD
+    pub(crate) replaced: usize,
+}
+
+/// The byte range of the value of the `encoding` declaration in the XML
+/// prolog `head` starts with
+fn declared_encoding(head: &[u8]) -> Option<Range<usize>> {
+    let prolog = head.strip_prefix(b"<?xml")?;
+    let end = prolog.windows(2).position(|w| w == b"?>")?;
+    let prolog = &prolog[..end];
+    let at = prolog.windows(8).position(|w| w == b"encoding")? + 8;
+    let rest = &prolog[at..];
+    let skip = |bytes: &[u8], from: usize| {
+        from + bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_whitespace())
+            .count()
+    };
+    let eq = skip(rest, 0);
+    if rest.get(eq) != Some(&b'=') {
+        return None;
+    }
+    let open = skip(rest, eq + 1);
+    let quote = *rest.get(open).filter(|q| **q == b'"' || **q == b'\'')?;
+    let len = rest[open + 1..].iter().position(|b| *b == quote)?;
+    let start = 5 + at + open + 1;
+    Some(start..start + len)
+}
+
+fn utf16(bytes: &[u8], big_endian: bool) -> Decoded {
+    let units = bytes.chunks_exact(2).map(|pair| match big_endian {
+        true => u16::from_be_bytes([pair[0], pair[1]]),
+        false => u16::from_le_bytes([pair[0], pair[1]]),
+    });
+    let mut replaced = bytes.len() % 2;
+    let mut text: String = char::decode_utf16(units)
+        .map(|unit| {
+            unit.unwrap_or_else(|_| {
+                replaced += 1;
+                char::REPLACEMENT_CHARACTER
+            })
+        })
+        .collect();
+    if bytes.len() % 2 == 1 {
+        text.push(char::REPLACEMENT_CHARACTER);
+    }
+    let encoding = match big_endian {
+        true => "UTF-16BE",
+        false => "UTF-16LE",
+    };
+    Decoded {
+        text,
+        encoding,
+        replaced,
+    }
+}
+
+fn windows_1252(bytes: &[u8]) -> Decoded {
+    let text = bytes
+        .iter()
+        .map(|&b| match b {
+            0x80..=0x9F => WINDOWS_1252[usize::from(b - 0x80)],
+            _ => char::from(b),
+        })
+        .collect();
+    Decoded {
+        text,
+        encoding: "windows-1252",
+        replaced: 0,
+    }
+}
+
+/// Transcode an XML document that is not UTF-8 from the encoding its byte
+/// order mark or prolog gives, if it is UTF-16 or Latin-1
+///
+/// Like web browsers, Latin-1 is read as its superset Windows-1252, which
+/// vendors mean when their descriptions hold quotes or a trademark sign.
+/// The `encoding` declaration of the prolog is changed to UTF-8 to match
+/// the text. Documents in other encodings, or declaring none, are left to
+/// be read as UTF-8.
+pub(crate) fn decode_declared(bytes: &[u8]) -> Option<Decoded> {
+    let mut decoded = match bytes {
+        [0xFF, 0xFE, rest @ ..] => utf16(rest, false),
+        [0xFE, 0xFF, rest @ ..] => utf16(rest, true),
+        [b'<', 0, b'?', 0, ..] => utf16(bytes, false),
+        [0, b'<', 0, b'?', ..] => utf16(bytes, true),
+        _ => {
+            let range = declared_encoding(&bytes[..bytes.len().min(256)])?;
+            let label = String::from_utf8_lossy(&bytes[range]).to_ascii_lowercase();
+            match label.as_str() {
+                "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1"
+                | "windows-1252" | "cp1252" => windows_1252(bytes),
+                _ => return None,
+            }
+        }
+    };
+    if let Some(range) = declared_encoding(decoded.text.as_bytes()) {
+        decoded.text.replace_range(range, "UTF-8");
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn declared_encodings_are_transcoded() {
+        let latin1 = b"<?xml version=\"1.0\" encoding='ISO-8859-1'?><d>Caf\xe9 \x99</d>";
+        let decoded = decode_declared(latin1).unwrap();
+        assert_eq!(
+            decoded.text,
+            "<?xml version=\"1.0\" encoding='UTF-8'?><d>Café ™</d>"
+        );
+        assert_eq!((decoded.encoding, decoded.replaced), ("windows-1252", 0));
+
+        let text = "<?xml version=\"1.0\" encoding=\"UTF-16\"?><d>€</d>";
+        let mut le = vec![0xFF, 0xFE];
+        le.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        let be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        let expected = text.replace("UTF-16", "UTF-8");
+        assert_eq!(decode_declared(&le).unwrap().text, expected);
+        assert_eq!(decode_declared(&be).unwrap().encoding, "UTF-16BE");
+
+        // An unpaired surrogate is replaced
+        let mut broken = le.clone();
+        broken.extend([0x00, 0xD8]);
+        let decoded = decode_declared(&broken).unwrap();
+        assert_eq!(decoded.replaced, 1);
+        assert!(decoded.text.ends_with("</d>\u{fffd}"));
+
+        assert!(decode_declared(b"<?xml version=\"1.0\"?><d>\xe9</d>").is_none());
+        assert!(decode_declared(b"<?xml encoding=\"Shift_JIS\"?><d/>").is_none());
+    }
+
+    #[test]
+    fn local_pdsc_files_are_transcoded() {
+        use crate::pdsc::Package;
+        use crate::utils::parse::FromElem;
+
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("latin1.pdsc");
+        let pdsc = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\
+            <package><name>Pack</name><vendor>Vendor</vendor>\
+            <description>Fa\xe7ade\xae</description><url>http://example.com/</url>\
+            <releases><release version=\"1.0.0\"/></releases></package>";
+        std::fs::write(&path, pdsc).unwrap();
+        let package = Package::from_path(&path).unwrap();
+        assert_eq!(package.description, "Façade®");
+    }
+}
@@ -1,10 +1,36 @@
+pub(crate) mod date;
+pub(crate) mod encoding;
 pub(crate) mod parse;
 pub(crate) mod prelude;
+mod serialize;
+mod version;
 
 pub use self::parse::FromElem;
+pub use self::serialize::Serialization;
+pub use self::version::VersionReq;
 
+use std::cmp::Ordering;
 use std::fmt::Display;
 
+/// The key identifying a pack, `vendor.name` in lower case
+///
+/// CMSIS compares vendor and pack names case-insensitively, so `Keil.X`
+/// and `KEIL.X` name the same pack.
+pub(crate) fn pack_id(vendor: &str, name: &str) -> String {
+    format!("{}.{}", vendor, name).to_ascii_lowercase()
+}
+
+/// Order versions by their numeric components, so that 1.10.0 follows 1.9.0
+pub(crate) fn compare_versions(left: &str, right: &str) -> Ordering {
+    let parts = |version: &str| -> Vec<u64> {
+        version
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|part| part.parse().ok())
+            .collect()
+    };
+    parts(left).cmp(&parts(right))
+}
+
 pub trait ResultLogExt<T, E> {
     fn ok_warn(self) -> Option<T>;
     fn ok_error(self) -> Option<T>;
@@ -18,7 +44,7 @@ where
         match self {
             Ok(x) => Some(x),
             Err(e) => {
-                log::warn!("{}", e);
+                tracing::warn!("{}", e);
                 None
             }
         }
@@ -27,7 +53,7 @@ where
         match self {
             Ok(x) => Some(x),
             Err(e) => {
-                log::error!("{}", e);
+                tracing::error!("{}", e);
                 None
             }
         }
@@ -3,6 +3,8 @@ use std::io::BufRead;
 use std::path::Path;
 use std::str::FromStr;
 
+use crate::log::PARSE;
+use crate::utils::encoding::decode_declared;
 use crate::utils::ResultLogExt;
 use minidom::quick_xml::Reader;
 use minidom::{Children, Element};
@@ -86,6 +88,26 @@ pub fn assert_root_name(from: &Element, name: &str) -> Result<(), Error> {
     }
 }
 
+/// The file `p` transcoded into UTF-8, when it is not UTF-8 but declares
+/// an encoding [`decode_declared`] reads
+fn transcoded(p: &Path) -> Option<String> {
+    let bytes = std::fs::read(p).ok()?;
+    if std::str::from_utf8(&bytes).is_ok() {
+        return None;
+    }
+    let decoded = decode_declared(&bytes)?;
+    if decoded.replaced > 0 {
+        tracing::warn!(
+            target: PARSE,
+            path = ?p,
+            encoding = decoded.encoding,
+            replaced = decoded.replaced,
+            "Replaced invalid sequences"
+        );
+    }
+    Some(decoded.text)
+}
+
 pub trait FromElem: Sized {
     fn from_elem(e: &Element) -> Result<Self, Error>;
 
@@ -99,11 +121,42 @@ pub trait FromElem: Sized {
         Self::from_reader(&mut r)
     }
     fn from_path(p: &Path) -> Result<Self, Error> {
-        let mut r = Reader::from_file(p)?;
-        Self::from_reader(&mut r)
+        let parsed = Reader::from_file(p)
+            .map_err(Error::from)
+            .and_then(|mut r| Self::from_reader(&mut r));
+        parsed.or_else(|err| match transcoded(p) {
+            Some(text) => Self::from_string(&text),
+            None => Err(err),
+        })
     }
     fn vec_from_children(clds: Children) -> Vec<Self> {
         clds.flat_map(move |cld| Self::from_elem(cld).ok_warn().into_iter())
             .collect()
     }
 }
+
+/// Files in the pack store at least this large are memory mapped instead
+/// of read through a buffer, which keeps the biggest vendor PDSC files
+/// from being copied
+#[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+const MMAP_THRESHOLD: u64 = 1 << 20;
+
+/// Parse a file of the pack store, memory mapping it when it is large
+///
+/// Only for files the store writes itself: those are renamed into place
+/// once complete and never modified after, so a mapping of one stays
+/// valid. Other files go through [`FromElem::from_path`].
+#[cfg(all(feature = "network", not(target_arch = "wasm32")))]
+pub(crate) fn from_store_path<T: FromElem>(p: &Path) -> Result<T, Error> {
+    let fd = std::fs::File::open(p)?;
+    if fd.metadata()?.len() < MMAP_THRESHOLD {
+        return T::from_path(p);
+    }
+    // Safety: files of the pack store are never modified once in place
+    let map = unsafe { memmap2::Mmap::map(&fd)? };
+    let mut r = Reader::from_reader(&map[..]);
+    T::from_reader(&mut r).or_else(|err| match transcoded(p) {
+        Some(text) => T::from_string(&text),
+        None => Err(err),
+    })
+}
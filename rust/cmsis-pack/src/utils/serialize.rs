@@ -0,0 +1,23 @@
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// JSON and YAML representations of the public data types
+///
+/// The documents use the Rust field names of the implementing type as keys,
+/// and every type that implements this trait round-trips through both
+/// formats.
+pub trait Serialization: Serialize + DeserializeOwned {
+    fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+    fn from_json(s: &str) -> Result<Self, Error> {
+        Ok(serde_json::from_str(s)?)
+    }
+    fn to_yaml(&self) -> Result<String, Error> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+    fn from_yaml(s: &str) -> Result<Self, Error> {
+        Ok(serde_yaml::from_str(s)?)
+    }
+}
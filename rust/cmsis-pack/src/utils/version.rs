@@ -0,0 +1,160 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{format_err, Error};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+}
+
+/// A requirement on the version of a pack, in the syntax of Cargo
+///
+/// Comparators are separated by commas and must all match: `^5.7` for
+/// 5.7.0 up to but excluding 6.0.0, `~5.7.1` for 5.7.1 up to 5.8.0,
+/// `>=5.7, <5.9`, `5.9.*`, or `*` for any version. Versions are compared
+/// by their numeric components, so that 5.10.0 follows 5.9.0. A bare
+/// version such as `5.9.0` or `1.0.0-beta` names that release alone.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionReq {
+    text: String,
+    comparators: Vec<(Op, Vec<u64>)>,
+}
+
+fn parts(version: &str) -> Vec<u64> {
+    version
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|part| part.parse().ok())
+        .collect()
+}
+
+/// Compare the leading components of `version` with `to`, reading missing
+/// components as 0
+fn compare_prefix(version: &[u64], to: &[u64]) -> Ordering {
+    let padded = version.iter().copied().chain(std::iter::repeat(0));
+    padded.take(to.len()).cmp(to.iter().copied())
+}
+
+impl VersionReq {
+    /// Whether the release `version` meets the requirement
+    pub fn matches(&self, version: &str) -> bool {
+        if self.is_exact() {
+            return version == self.text;
+        }
+        let version = parts(version);
+        self.comparators.iter().all(|(op, to)| {
+            let order = compare_prefix(&version, to);
+            let same_up_to = |len: usize| compare_prefix(&version, &to[..len]).is_eq();
+            match op {
+                Op::Exact => order.is_eq(),
+                Op::Greater => order.is_gt(),
+                Op::GreaterEq => order.is_ge(),
+                Op::Less => order.is_lt(),
+                Op::LessEq => order.is_le(),
+                Op::Tilde => order.is_ge() && same_up_to(to.len().min(2)),
+                Op::Caret => {
+                    let nonzero = to.iter().position(|&part| part != 0);
+                    let len = nonzero.map_or(to.len(), |at| at + 1).min(to.len());
+                    order.is_ge() && same_up_to(len)
+                }
+            }
+        })
+    }
+
+    /// The requirement naming the release `version` alone
+    pub fn exact(version: &str) -> Self {
+        VersionReq {
+            text: version.to_string(),
+            comparators: Vec::new(),
+        }
+    }
+
+    /// Whether the requirement is a bare version, naming one release
+    pub fn is_exact(&self) -> bool {
+        self.comparators.is_empty() && self.text.starts_with(|c: char| c.is_ascii_digit())
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let text = s.trim().to_string();
+        // Bare versions are kept whole, as release names may carry
+        // suffixes such as `-beta`
+        let is_bare = text.starts_with(|c: char| c.is_ascii_digit())
+            && !text.contains([',', '*', '<', '>', '=', '~', '^']);
+        if is_bare {
+            let comparators = Vec::new();
+            return Ok(VersionReq { text, comparators });
+        }
+        let comparators = s
+            .split(',')
+            .map(str::trim)
+            .filter(|comparator| *comparator != "*")
+            .map(|comparator| {
+                let (op, version) = [
+                    (">=", Op::GreaterEq),
+                    ("<=", Op::LessEq),
+                    (">", Op::Greater),
+                    ("<", Op::Less),
+                    ("=", Op::Exact),
+                    ("~", Op::Tilde),
+                    ("^", Op::Caret),
+                ]
+                .iter()
+                .find_map(|(prefix, op)| Some((*op, comparator.strip_prefix(prefix)?)))
+                .unwrap_or((Op::Exact, comparator));
+                let version = version.trim();
+                let version = version.strip_suffix(".*").unwrap_or(version);
+                let valid = version
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()));
+                match valid {
+                    true => Ok((op, parts(version))),
+                    false => Err(format_err!("{:?} is not a version requirement", s)),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(VersionReq { text, comparators })
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn requirements_match_like_cargo() {
+        let matches =
+            |req: &str, version: &str| req.parse::<VersionReq>().unwrap().matches(version);
+        assert!(matches("^5.7", "5.7.0") && matches("^5.7", "5.10.1"));
+        assert!(!matches("^5.7", "5.6.9") && !matches("^5.7", "6.0.0"));
+        assert!(matches("^0.2.3", "0.2.9") && !matches("^0.2.3", "0.3.0"));
+        assert!(matches("~5.7.1", "5.7.4") && !matches("~5.7.1", "5.8.0"));
+        assert!(matches(">=5.7, <5.9", "5.8.2") && !matches(">=5.7, <5.9", "5.9.0"));
+        assert!(matches("<=5.7", "5.7.3") && !matches("<=5.7", "5.8.0"));
+        assert!(matches("5.9.0", "5.9.0") && !matches("5.9.0", "5.9.1"));
+        assert!(matches("1.0.0-beta", "1.0.0-beta") && !matches("5.9", "5.9.0"));
+        assert!(matches("5.9.*", "5.9.1") && matches("*", "1.0.0"));
+
+        assert!("5.9.0".parse::<VersionReq>().unwrap().is_exact());
+        assert!(!"=5.9.0".parse::<VersionReq>().unwrap().is_exact());
+        for bad in ["", "^", "latest", ">=5.7,", ">=5..1"] {
+            assert!(bad.parse::<VersionReq>().is_err(), "{}", bad);
+        }
+    }
+}
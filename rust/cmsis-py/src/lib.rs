@@ -0,0 +1,199 @@
+//! Native Python bindings for the pack store of `cmsis-pack`
+//!
+//! Built by maturin into the `cmsis_pack_native` extension module:
+//!
+//! ```python
+//! from cmsis_pack_native import Cache
+//!
+//! cache = Cache("/path/to/store")
+//! cache.update()
+//! device = cache.device("STM32F407VGTx")
+//! ```
+//!
+//! Devices are returned as the dictionaries `index_json` holds, one per
+//! device name, as in the `index.json` of the cffi based package.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use serde::Serialize;
+
+use cmsis_pack::pdsc::{parse_packages, DeviceDatabase};
+use cmsis_pack::update::{Cache as PackCache, DEFAULT_VIDX_LIST};
+
+create_exception!(
+    cmsis_pack_native,
+    CmsisPackError,
+    PyException,
+    "An error of the pack store; `args` holds its message and its code, such as \"network\" or \"locked\""
+);
+
+fn pack_error(err: cmsis_pack::Error) -> PyErr {
+    CmsisPackError::new_err((err.to_string(), err.code()))
+}
+
+/// `value` as the Python object its JSON parses into
+fn to_python<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let text = serde_json::to_string(value)
+        .map_err(|err| CmsisPackError::new_err((err.to_string(), "json")))?;
+    Ok(py.import("json")?.call_method1("loads", (text,))?.unbind())
+}
+
+/// The devices of a set of PDSC files, to look up by name
+#[pyclass(module = "cmsis_pack_native", frozen)]
+struct Index {
+    database: DeviceDatabase,
+}
+
+#[pymethods]
+impl Index {
+    /// Parse the PDSC files at `paths`; files that do not parse are skipped
+    #[staticmethod]
+    fn from_pdscs(py: Python<'_>, paths: Vec<PathBuf>) -> Index {
+        let database = py.allow_threads(|| {
+            let packages: Vec<_> = parse_packages(paths)
+                .into_iter()
+                .filter_map(|(_, parsed)| parsed.ok())
+                .collect();
+            DeviceDatabase::from_packages(&packages)
+        });
+        Index { database }
+    }
+
+    fn __len__(&self) -> usize {
+        self.database.devices.len()
+    }
+
+    fn __contains__(&self, name: &str) -> bool {
+        self.database.lookup(name).is_some()
+    }
+
+    /// The names of the devices, of those starting with `prefix` if given
+    #[pyo3(signature = (prefix=None))]
+    fn devices(&self, prefix: Option<&str>) -> Vec<String> {
+        match prefix {
+            Some(prefix) => self
+                .database
+                .with_prefix(prefix)
+                .map(String::from)
+                .collect(),
+            None => self.database.devices.keys().cloned().collect(),
+        }
+    }
+
+    /// The device called `name`, or `None`
+    fn device(&self, py: Python<'_>, name: &str) -> PyResult<Option<PyObject>> {
+        self.database
+            .dump_device(name)
+            .map(|device| to_python(py, &device))
+            .transpose()
+    }
+
+    /// Every device, keyed by name, as JSON
+    fn to_json(&self) -> PyResult<String> {
+        let devices: BTreeMap<_, _> = self
+            .database
+            .devices
+            .keys()
+            .filter_map(|name| Some((name, self.database.dump_device(name)?)))
+            .collect();
+        serde_json::to_string(&devices)
+            .map_err(|err| CmsisPackError::new_err((err.to_string(), "json")))
+    }
+}
+
+/// A pack store, updated from the vendor indexes of `vidx_list`, or the
+/// default ones
+#[pyclass(module = "cmsis_pack_native", frozen)]
+struct Cache {
+    inner: PackCache,
+}
+
+impl Cache {
+    fn database(&self, py: Python<'_>) -> DeviceDatabase {
+        py.allow_threads(|| self.inner.database())
+    }
+}
+
+#[pymethods]
+impl Cache {
+    #[new]
+    #[pyo3(signature = (pack_store, vidx_list=None))]
+    fn new(pack_store: PathBuf, vidx_list: Option<Vec<String>>) -> Self {
+        let inner = PackCache::new(pack_store);
+        let inner = match vidx_list {
+            Some(vidx_list) => inner.with_vidx_list(vidx_list),
+            None => inner,
+        };
+        Cache { inner }
+    }
+
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.inner.path().to_path_buf()
+    }
+
+    /// Download the PDSC files of the vendor indexes, returning the paths
+    /// of those in the store
+    fn update(&self, py: Python<'_>) -> PyResult<Vec<PathBuf>> {
+        py.allow_threads(|| self.inner.update())
+            .map(|report| report.pdsc_files())
+            .map_err(pack_error)
+    }
+
+    /// The PDSC files of the store, sorted
+    fn installed_pdscs(&self) -> Vec<PathBuf> {
+        self.inner.installed_pdscs()
+    }
+
+    /// The devices of the PDSC files of the store
+    fn index(&self, py: Python<'_>) -> Index {
+        Index {
+            database: self.database(py),
+        }
+    }
+
+    /// Every device of the store, keyed by name, as JSON
+    fn index_json(&self, py: Python<'_>) -> PyResult<String> {
+        self.index(py).to_json()
+    }
+
+    /// The device called `name`, or `None`
+    fn device(&self, py: Python<'_>, name: &str) -> PyResult<Option<PyObject>> {
+        self.index(py).device(py, name)
+    }
+
+    /// The pack archive in the store that describes the device called
+    /// `name`, or `None` when the device is unknown or its pack is not
+    /// downloaded
+    fn pack_from_cache(&self, py: Python<'_>, name: &str) -> Option<PathBuf> {
+        let database = self.database(py);
+        let pack = &database.lookup(name)?.pack;
+        let path = self
+            .inner
+            .path()
+            .join(&pack.vendor)
+            .join(&pack.name)
+            .join(format!("{}.pack", pack.version));
+        path.exists().then_some(path)
+    }
+
+    /// Download and extract the pack `id`, as `Vendor::Name` for its latest
+    /// release or `Vendor::Name@1.2.0`, returning its directory
+    fn install_pack(&self, py: Python<'_>, id: &str) -> PyResult<PathBuf> {
+        py.allow_threads(|| self.inner.install_pack(id))
+            .map_err(pack_error)
+    }
+}
+
+#[pymodule]
+fn cmsis_pack_native(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Cache>()?;
+    m.add_class::<Index>()?;
+    m.add("CmsisPackError", m.py().get_type::<CmsisPackError>())?;
+    m.add("DEFAULT_VIDX_LIST", DEFAULT_VIDX_LIST.to_vec())?;
+    Ok(())
+}
@@ -0,0 +1,72 @@
+import contextlib
+import http.server
+import json
+import os
+import shutil
+import socketserver
+import tempfile
+import threading
+from os.path import dirname, exists, join
+
+import pytest
+
+import cmsis_pack_native
+
+
+TEST_PACK_INDEX = join(dirname(__file__), "..", "..", "..", "tests", "test-pack-index")
+
+
+@contextlib.contextmanager
+def pack_server():
+    """Serve the test pack index on localhost:8001 as tests/integration.py does"""
+    root = join(TEST_PACK_INDEX, "..", "..")
+    handler = lambda *args: http.server.SimpleHTTPRequestHandler(*args, directory=root)
+    socketserver.TCPServer.allow_reuse_address = True
+    httpd = socketserver.TCPServer(("", 8001), handler)
+    thread = threading.Thread(target=httpd.serve_forever, daemon=True)
+    thread.start()
+    try:
+        yield ["http://localhost:8001/tests/test-pack-index/index.pidx"]
+    finally:
+        httpd.shutdown()
+        httpd.server_close()
+
+
+def test_index_from_pdscs():
+    index = cmsis_pack_native.Index.from_pdscs(
+        [join(TEST_PACK_INDEX, "MyVendor.MyPack.pdsc"), "missing.pdsc"])
+    assert len(index) == 1
+    assert "MyDevice" in index
+    assert index.devices(prefix="My") == ["MyDevice"]
+    assert index.device("MyDevice")["family"] == "MyFamily"
+    assert index.device("NotADevice") is None
+    assert set(json.loads(index.to_json())) == {"MyDevice"}
+
+
+def test_cache_updates_and_looks_up_devices():
+    store = tempfile.mkdtemp()
+    try:
+        with pack_server() as vidx_list:
+            cache = cmsis_pack_native.Cache(store, vidx_list)
+            updated = cache.update()
+            assert [os.path.basename(p) for p in updated] == ["MyVendor.MyPack.1.1.0.pdsc"]
+            assert cache.installed_pdscs() == updated
+            assert cache.device("MyDevice")["from_pack"]["pack"] == "MyPack"
+            assert "MyDevice" in json.loads(cache.index_json())
+            assert cache.pack_from_cache("MyDevice") is None
+
+            pack_dir = cache.install_pack("MyVendor::MyPack")
+            assert exists(pack_dir)
+            assert cache.pack_from_cache("MyDevice") == join(
+                store, "MyVendor", "MyPack", "1.1.0.pack")
+    finally:
+        shutil.rmtree(store)
+
+
+def test_errors_carry_their_code():
+    cache = cmsis_pack_native.Cache(tempfile.mkdtemp(), [])
+    with pytest.raises(cmsis_pack_native.CmsisPackError) as raised:
+        cache.install_pack("not a pack spec")
+    message, code = raised.value.args
+    assert code == "other"
+    assert message.startswith("Expected Vendor::Pack")
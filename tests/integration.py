@@ -162,6 +162,28 @@ def test_panic_handling():
     except:
         pass
 
+def test_device_lookup():
+    with cmsis_server():
+        c = cmsis_pack_manager.Cache(
+            True, True, json_path=tempfile.mkdtemp(), data_path=tempfile.mkdtemp(),
+            vidx_list=join(dirname(__file__), 'test-pack-index', 'vendors.list'))
+        c.cache_descriptors()
+        assert("MyFamily" == c.device("MyDevice")["family"])
+        try:
+            c.device("NotADevice")
+            assert False
+        except cmsis_pack_manager.RustNotFoundError:
+            pass
+
+
+def test_pyocd_pack():
+    import cmsis_pack_manager.pyocd
+    pack = cmsis_pack_manager.pyocd.CmsisPack(
+        join(dirname(__file__), 'test-pack-index', 'MyVendor.MyPack.pdsc'))
+    assert(["MyDevice"] == [dev.part_number for dev in pack.devices])
+    assert("MyFamily" in pack.devices[0].families)
+
+
 def test_print_cache_dir_cli(capsys):
     sys.argv = ["pack-manager", "print-cache-dir"]
     cmsis_pack_manager.pack_manager.main()